@@ -47,6 +47,9 @@ magick icon.png -define icon:auto-resize=256,128,64,48,32,16 icon.ico
 
 # Tray icon (monochrome, 44x44 @2x for Retina)
 rsvg-convert -w 44 -h 44 tray-icon.svg | magick png:- -type TrueColorAlpha -depth 8 -define png:color-type=6 tray-icon.png
+# Themed tray icons (not template images): white for dark, violet for light
+rsvg-convert -w 44 -h 44 tray-icon-dark.svg | magick png:- -type TrueColorAlpha -depth 8 -define png:color-type=6 tray-icon-dark.png
+rsvg-convert -w 44 -h 44 tray-icon-color.svg | magick png:- -type TrueColorAlpha -depth 8 -define png:color-type=6 tray-icon-color.png
 
 echo "All icons regenerated successfully!"
 file icon.png 32x32.png
@@ -0,0 +1,131 @@
+//! Validation for files attached to messages.
+//!
+//! The caller only supplies a path; size and (when not given) MIME type are
+//! taken from the file itself so the stored metadata can be trusted.
+
+use std::path::Path;
+
+/// Default for the `max_attachment_bytes` setting (20 MiB).
+pub const DEFAULT_MAX_ATTACHMENT_BYTES: i64 = 20 * 1024 * 1024;
+
+/// Facts about an attachment that passed validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckedFile {
+    pub file_name: String,
+    pub file_size: i64,
+    pub mime_type: Option<String>,
+}
+
+/// MIME type for common attachment extensions.
+pub fn guess_mime_type(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    Some(match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "heic" => "image/heic",
+        "pdf" => "application/pdf",
+        "txt" | "log" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "html" | "htm" => "text/html",
+        _ => return None,
+    })
+}
+
+/// Check that `path` is a readable file within `max_bytes` (`<= 0` means no
+/// limit) and that images are only attached when the model can see them.
+pub fn check_file(
+    path: &Path,
+    mime_type: Option<&str>,
+    max_bytes: i64,
+    supports_vision: bool,
+) -> Result<CheckedFile, String> {
+    let metadata = std::fs::metadata(path)
+        .map_err(|e| format!("Cannot read attachment {}: {}", path.display(), e))?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", path.display()));
+    }
+
+    let file_size = i64::try_from(metadata.len()).unwrap_or(i64::MAX);
+    if max_bytes > 0 && file_size > max_bytes {
+        return Err(format!(
+            "{} is {} bytes, over the {} byte attachment limit",
+            path.display(),
+            file_size,
+            max_bytes
+        ));
+    }
+
+    let mime_type = mime_type
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(str::to_lowercase)
+        .or_else(|| guess_mime_type(path).map(str::to_string));
+    if !supports_vision
+        && mime_type
+            .as_deref()
+            .is_some_and(|m| m.starts_with("image/"))
+    {
+        return Err(
+            "The selected model cannot read images; choose a vision model to attach them"
+                .to_string(),
+        );
+    }
+
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+
+    Ok(CheckedFile {
+        file_name,
+        file_size,
+        mime_type,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(name: &str, len: usize) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("zitong-attachments-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, vec![b'x'; len]).unwrap();
+        path
+    }
+
+    #[test]
+    fn oversized_files_are_rejected() {
+        let path = write_file("big.txt", 2048);
+
+        let err = check_file(&path, None, 1024, true).unwrap_err();
+        assert!(err.contains("over the 1024 byte attachment limit"), "{err}");
+
+        let checked = check_file(&path, None, 0, true).unwrap();
+        assert_eq!(checked.file_size, 2048);
+        assert_eq!(checked.file_name, "big.txt");
+        assert_eq!(checked.mime_type.as_deref(), Some("text/plain"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn images_need_a_vision_model() {
+        let path = write_file("photo.PNG", 16);
+
+        let err = check_file(&path, None, 1024, false).unwrap_err();
+        assert!(err.contains("cannot read images"), "{err}");
+        // A caller-supplied type is checked too, whatever the extension.
+        let renamed = write_file("photo.bin", 16);
+        assert!(check_file(&renamed, Some(" Image/JPEG "), 1024, false).is_err());
+
+        let checked = check_file(&path, None, 1024, true).unwrap();
+        assert_eq!(checked.mime_type.as_deref(), Some("image/png"));
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_file(renamed);
+    }
+}
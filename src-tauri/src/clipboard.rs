@@ -1,8 +1,12 @@
 //! Clipboard helpers — cross-platform read/write via arboard,
 //! plus macOS-specific CGEvent ⌘C simulation and accessibility checks.
 
+use crate::db::Database;
+use base64::Engine as _;
+use tauri::State;
+
 /// Combined permissions check — returns detailed status for the frontend.
-#[derive(serde::Serialize)]
+#[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PermissionsStatus {
     pub accessibility_ok: bool,
@@ -20,26 +24,247 @@ pub struct PermissionsStatus {
 /// Read plain text from the system clipboard.
 #[tauri::command]
 pub fn read_clipboard_text() -> Result<String, String> {
-    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Clipboard init failed: {e}"))?;
-    let text = clipboard.get_text().map_err(|e| format!("Clipboard read failed: {e}"))?;
+    let mut clipboard =
+        arboard::Clipboard::new().map_err(|e| format!("Clipboard init failed: {e}"))?;
+    let text = clipboard
+        .get_text()
+        .map_err(|e| format!("Clipboard read failed: {e}"))?;
     Ok(text)
 }
 
 /// Write plain text to the system clipboard.
 #[tauri::command]
 pub fn write_clipboard_text(text: String) -> Result<(), String> {
-    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Clipboard init failed: {e}"))?;
-    clipboard.set_text(text).map_err(|e| format!("Clipboard write failed: {e}"))?;
+    let mut clipboard =
+        arboard::Clipboard::new().map_err(|e| format!("Clipboard init failed: {e}"))?;
+    clipboard
+        .set_text(text)
+        .map_err(|e| format!("Clipboard write failed: {e}"))?;
+    Ok(())
+}
+
+/// An image from the clipboard, ready to attach to a vision-capable chat.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClipboardImage {
+    /// Base64-encoded PNG.
+    pub data: String,
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ClipboardImage {
+    /// Wrap PNG bytes, taking the dimensions from the PNG header.
+    pub fn from_png(png: &[u8]) -> Result<Self, String> {
+        let (width, height) = png_dimensions(png)?;
+        Ok(Self {
+            data: base64::engine::general_purpose::STANDARD.encode(png),
+            mime_type: "image/png".to_string(),
+            width,
+            height,
+        })
+    }
+}
+
+/// Width and height from a PNG's IHDR chunk, which must come first.
+pub fn png_dimensions(png: &[u8]) -> Result<(u32, u32), String> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if png.len() < 24 || !png.starts_with(SIGNATURE) || &png[12..16] != b"IHDR" {
+        return Err("Image is not a valid PNG".to_string());
+    }
+    let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+    let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+    Ok((width, height))
+}
+
+/// Read an image from the clipboard as PNG. macOS reads the pasteboard's
+/// PNG or TIFF data directly; elsewhere arboard's RGBA image is re-encoded.
+#[tauri::command]
+pub fn read_clipboard_image() -> Result<ClipboardImage, String> {
+    let png = read_image_png()?.ok_or("The clipboard has no image")?;
+    ClipboardImage::from_png(&png)
+}
+
+/// Put a base64-encoded PNG on the clipboard.
+#[tauri::command]
+pub fn write_clipboard_image(data: String) -> Result<(), String> {
+    let png = base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|e| format!("Image is not valid base64: {e}"))?;
+    png_dimensions(&png)?;
+    write_image_png(&png)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn read_image_png() -> Result<Option<Vec<u8>>, String> {
+    let mut clipboard =
+        arboard::Clipboard::new().map_err(|e| format!("Clipboard init failed: {e}"))?;
+    let image = match clipboard.get_image() {
+        Ok(image) => image,
+        Err(arboard::Error::ContentNotAvailable) => return Ok(None),
+        Err(e) => return Err(format!("Clipboard read failed: {e}")),
+    };
+    let width = u32::try_from(image.width).map_err(|_| "Clipboard image is too large")?;
+    let height = u32::try_from(image.height).map_err(|_| "Clipboard image is too large")?;
+    encode_png(width, height, &image.bytes).map(Some)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn write_image_png(png: &[u8]) -> Result<(), String> {
+    let (width, height, rgba) = decode_png(png)?;
+    let mut clipboard =
+        arboard::Clipboard::new().map_err(|e| format!("Clipboard init failed: {e}"))?;
+    clipboard
+        .set_image(arboard::ImageData {
+            width: width as usize,
+            height: height as usize,
+            bytes: rgba.into(),
+        })
+        .map_err(|e| format!("Clipboard write failed: {e}"))
+}
+
+/// Encode 8-bit RGBA pixels as PNG.
+#[cfg(not(target_os = "macos"))]
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("PNG encoding failed: {e}"))?;
+    writer
+        .write_image_data(rgba)
+        .map_err(|e| format!("PNG encoding failed: {e}"))?;
+    writer
+        .finish()
+        .map_err(|e| format!("PNG encoding failed: {e}"))?;
+    Ok(png)
+}
+
+/// Decode a PNG of any color type to 8-bit RGBA pixels.
+#[cfg(not(target_os = "macos"))]
+pub fn decode_png(png: &[u8]) -> Result<(u32, u32, Vec<u8>), String> {
+    let mut decoder = png::Decoder::new(std::io::Cursor::new(png));
+    decoder.set_transformations(
+        png::Transformations::normalize_to_color8() | png::Transformations::ALPHA,
+    );
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| format!("PNG decoding failed: {e}"))?;
+    let size = reader
+        .output_buffer_size()
+        .ok_or("PNG image is too large")?;
+    let mut buf = vec![0; size];
+    let info = reader
+        .next_frame(&mut buf)
+        .map_err(|e| format!("PNG decoding failed: {e}"))?;
+    buf.truncate(info.buffer_size());
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buf,
+        // With ALPHA, grayscale images come out as gray + alpha pairs.
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        other => return Err(format!("Unsupported PNG color type {:?}", other)),
+    };
+    Ok((info.width, info.height, rgba))
+}
+
+/// Push an AI response to the clipboard. With the `auto_paste` setting on
+/// (macOS/Windows), also hides the overlay, pastes into the originating app
+/// and then restores whatever was on the clipboard before.
+#[tauri::command]
+pub async fn copy_response_to_clipboard(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    text: String,
+) -> Result<(), String> {
+    let auto_paste = db.get_settings().map(|s| s.auto_paste).unwrap_or(false);
+    if !pastes_into_origin(auto_paste) {
+        return write_clipboard_text(text);
+    }
+
+    let previous = read_clipboard_text().ok();
+    paste_into_origin(&app, text, false).await?;
+
+    // The target app reads the clipboard asynchronously after the keystroke.
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    if let Some(previous) = previous {
+        write_clipboard_text(previous)?;
+    }
     Ok(())
 }
 
+/// Whether `copy_response_to_clipboard` pastes into the originating app
+/// rather than only writing the clipboard. Pasting needs macOS or Windows.
+fn pastes_into_origin(auto_paste: bool) -> bool {
+    auto_paste && cfg!(any(target_os = "macos", target_os = "windows"))
+}
+
+/// Put `text` on the clipboard, hide the overlay and paste into the app
+/// that had focus before it. With `at_selection_end`, the selection is
+/// collapsed to its end first so the text is inserted after it.
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub async fn paste_into_origin(
+    app: &tauri::AppHandle,
+    text: String,
+    at_selection_end: bool,
+) -> Result<(), String> {
+    use std::time::Duration;
+
+    write_clipboard_text(text)?;
+
+    // On macOS the panel is hidden on the main thread asynchronously; the
+    // originating app only becomes key again once that has happened, so
+    // keystrokes sent earlier would land in the overlay.
+    crate::hide_overlay(app.clone()).await?;
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    if at_selection_end {
+        tokio::task::spawn_blocking(simulate_move_to_selection_end_sync)
+            .await
+            .map_err(|e| format!("spawn_blocking failed: {}", e))??;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    simulate_paste().await
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+pub async fn paste_into_origin(
+    _app: &tauri::AppHandle,
+    _text: String,
+    _at_selection_end: bool,
+) -> Result<(), String> {
+    Err("Pasting into other apps is not supported on this platform".to_string())
+}
+
+/// Paste `text` into whichever app has focus, at its caret. Used after
+/// `paste_into_origin` has already handed focus back to that app.
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub async fn paste_into_focused(text: String) -> Result<(), String> {
+    write_clipboard_text(text)?;
+    simulate_paste().await
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+pub async fn paste_into_focused(_text: String) -> Result<(), String> {
+    Err("Pasting into other apps is not supported on this platform".to_string())
+}
+
 // ============================================================================
 // macOS implementation
 // ============================================================================
 #[cfg(target_os = "macos")]
 mod macos {
     use super::PermissionsStatus;
-    use objc2_app_kit::NSPasteboard;
+    use objc2_app_kit::{
+        NSBitmapImageFileType, NSBitmapImageRep, NSPasteboard, NSPasteboardTypePNG,
+        NSPasteboardTypeTIFF,
+    };
+    use objc2_foundation::{NSData, NSDictionary};
 
     // FFI binding for macOS Accessibility API
     extern "C" {
@@ -98,7 +323,7 @@ mod macos {
         std::thread::sleep(std::time::Duration::from_millis(200));
 
         let change_count_after = NSPasteboard::generalPasteboard().changeCount();
-        eprintln!(
+        tracing::debug!(
             "[clipboard] ⌘C via CGEvent: changeCount {} -> {} (changed={})",
             change_count_before,
             change_count_after,
@@ -132,6 +357,27 @@ mod macos {
         Ok(())
     }
 
+    /// Press → to collapse the current selection to its end.
+    pub fn simulate_move_to_selection_end_sync() -> Result<(), String> {
+        use core_graphics::event::{CGEvent, CGEventTapLocation, CGKeyCode};
+        use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
+
+        let source = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
+            .map_err(|_| "Failed to create CGEventSource")?;
+
+        let key_right: CGKeyCode = 124;
+
+        let key_down = CGEvent::new_keyboard_event(source.clone(), key_right, true)
+            .map_err(|_| "Failed to create key down event")?;
+        let key_up = CGEvent::new_keyboard_event(source, key_right, false)
+            .map_err(|_| "Failed to create key up event")?;
+
+        key_down.post(CGEventTapLocation::HID);
+        key_up.post(CGEventTapLocation::HID);
+
+        Ok(())
+    }
+
     /// Tauri command wrapper for simulate_copy
     #[tauri::command]
     pub async fn simulate_copy() -> Result<(), String> {
@@ -156,7 +402,7 @@ mod macos {
         let is_bundled = exe_path.contains(".app/Contents/MacOS/");
 
         let accessibility_ok = check_accessibility_permission(false);
-        eprintln!("[permissions] accessibility_ok: {}", accessibility_ok);
+        tracing::debug!("[permissions] accessibility_ok: {}", accessibility_ok);
 
         let automation = std::process::Command::new("osascript")
             .args([
@@ -180,9 +426,10 @@ mod macos {
             }
             Err(e) => (false, Some(format!("Automation check failed: {e}"))),
         };
-        eprintln!(
+        tracing::debug!(
             "[permissions] automation_ok: {}, is_bundled: {}",
-            automation_ok, is_bundled
+            automation_ok,
+            is_bundled
         );
 
         let can_copy = accessibility_ok && automation_ok;
@@ -208,10 +455,10 @@ mod macos {
 
     #[tauri::command]
     pub fn request_permissions() {
-        eprintln!("[permissions] triggering Accessibility prompt...");
+        tracing::debug!("[permissions] triggering Accessibility prompt...");
         let _ = check_accessibility_permission(true);
 
-        eprintln!("[permissions] triggering Automation (System Events) prompt...");
+        tracing::debug!("[permissions] triggering Automation (System Events) prompt...");
         let _ = std::process::Command::new("osascript")
             .args([
                 "-e",
@@ -238,10 +485,53 @@ mod macos {
         Ok(())
     }
 
+    /// The pasteboard image as PNG: PNG data when an app put it there,
+    /// otherwise TIFF (what screenshots and most apps copy) converted.
+    pub fn read_image_png() -> Result<Option<Vec<u8>>, String> {
+        let pasteboard = NSPasteboard::generalPasteboard();
+        unsafe {
+            if let Some(png) = pasteboard.dataForType(NSPasteboardTypePNG) {
+                return Ok(Some(png.to_vec()));
+            }
+            let Some(tiff) = pasteboard.dataForType(NSPasteboardTypeTIFF) else {
+                return Ok(None);
+            };
+            let rep = NSBitmapImageRep::imageRepWithData(&tiff)
+                .ok_or("Clipboard image could not be decoded")?;
+            let png = rep
+                .representationUsingType_properties(
+                    NSBitmapImageFileType::PNG,
+                    &NSDictionary::new(),
+                )
+                .ok_or("Clipboard image could not be converted to PNG")?;
+            Ok(Some(png.to_vec()))
+        }
+    }
+
+    /// Put PNG bytes on the pasteboard as both PNG and TIFF, since many
+    /// apps only read TIFF.
+    pub fn write_image_png(png: &[u8]) -> Result<(), String> {
+        let data = NSData::with_bytes(png);
+        let pasteboard = NSPasteboard::generalPasteboard();
+        unsafe {
+            let rep =
+                NSBitmapImageRep::imageRepWithData(&data).ok_or("Image could not be decoded")?;
+            let tiff = rep
+                .TIFFRepresentation()
+                .ok_or("Image could not be converted to TIFF")?;
+            pasteboard.clearContents();
+            if !pasteboard.setData_forType(Some(&data), NSPasteboardTypePNG)
+                || !pasteboard.setData_forType(Some(&tiff), NSPasteboardTypeTIFF)
+            {
+                return Err("Clipboard write failed".to_string());
+            }
+        }
+        Ok(())
+    }
+
     #[tauri::command]
     pub fn relaunch_app(app: tauri::AppHandle) -> Result<(), String> {
-        let exe =
-            std::env::current_exe().map_err(|e| format!("Failed to get exe path: {}", e))?;
+        let exe = std::env::current_exe().map_err(|e| format!("Failed to get exe path: {}", e))?;
         let app_bundle = exe
             .parent()
             .and_then(|p| p.parent())
@@ -289,9 +579,11 @@ mod windows_impl {
     /// This is faster and more reliable than simulating Ctrl+C — it reads
     /// the selection directly without touching the clipboard.
     /// Try to read selected text from a single UIA element via TextPattern.
-    fn try_text_pattern(element: &windows::Win32::UI::Accessibility::IUIAutomationElement) -> Option<String> {
-        use windows::Win32::UI::Accessibility::*;
+    fn try_text_pattern(
+        element: &windows::Win32::UI::Accessibility::IUIAutomationElement,
+    ) -> Option<String> {
         use windows::core::Interface;
+        use windows::Win32::UI::Accessibility::*;
 
         unsafe {
             let pattern_unknown = element.GetCurrentPattern(UIA_TextPatternId).ok()?;
@@ -304,18 +596,23 @@ mod windows_impl {
             let range = selection.GetElement(0).ok()?;
             let text = range.GetText(-1).ok()?;
             let result = text.to_string();
-            if result.trim().is_empty() { None } else { Some(result) }
+            if result.trim().is_empty() {
+                None
+            } else {
+                Some(result)
+            }
         }
     }
 
     fn get_selected_text_uia() -> Option<String> {
-        use windows::Win32::UI::Accessibility::*;
         use windows::Win32::System::Com::*;
+        use windows::Win32::UI::Accessibility::*;
 
         unsafe {
             let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
 
-            let uia: IUIAutomation = CoCreateInstance(&CUIAutomation, None, CLSCTX_INPROC_SERVER).ok()?;
+            let uia: IUIAutomation =
+                CoCreateInstance(&CUIAutomation, None, CLSCTX_INPROC_SERVER).ok()?;
             let focused = uia.GetFocusedElement().ok()?;
 
             // Try TextPattern on the focused element itself
@@ -348,9 +645,9 @@ mod windows_impl {
     /// (which opens DevTools in browsers).
     fn simulate_copy_via_sendinput() -> Result<(), String> {
         use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
-            GetAsyncKeyState, SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT,
-            KEYEVENTF_KEYUP, VK_CONTROL, VK_C, VK_SHIFT, VK_LSHIFT, VK_RSHIFT,
-            VK_MENU, VK_LMENU, VK_RMENU, VK_LWIN, VK_RWIN,
+            GetAsyncKeyState, SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, VK_C,
+            VK_CONTROL, VK_LMENU, VK_LSHIFT, VK_LWIN, VK_MENU, VK_RMENU, VK_RSHIFT, VK_RWIN,
+            VK_SHIFT,
         };
 
         std::thread::sleep(std::time::Duration::from_millis(80));
@@ -358,9 +655,7 @@ mod windows_impl {
         unsafe {
             // Check which modifier keys are currently held down
             let modifiers_to_release: &[u16] = &[
-                VK_SHIFT, VK_LSHIFT, VK_RSHIFT,
-                VK_MENU, VK_LMENU, VK_RMENU,
-                VK_LWIN, VK_RWIN,
+                VK_SHIFT, VK_LSHIFT, VK_RSHIFT, VK_MENU, VK_LMENU, VK_RMENU, VK_LWIN, VK_RWIN,
             ];
 
             let mut release_inputs: Vec<INPUT> = Vec::new();
@@ -370,7 +665,11 @@ mod windows_impl {
                     let mut release: INPUT = std::mem::zeroed();
                     release.r#type = INPUT_KEYBOARD;
                     release.Anonymous.ki = KEYBDINPUT {
-                        wVk: vk, wScan: 0, dwFlags: KEYEVENTF_KEYUP, time: 0, dwExtraInfo: 0,
+                        wVk: vk,
+                        wScan: 0,
+                        dwFlags: KEYEVENTF_KEYUP,
+                        time: 0,
+                        dwExtraInfo: 0,
                     };
                     release_inputs.push(release);
                 }
@@ -391,22 +690,38 @@ mod windows_impl {
 
             inputs[0].r#type = INPUT_KEYBOARD;
             inputs[0].Anonymous.ki = KEYBDINPUT {
-                wVk: VK_CONTROL, wScan: 0, dwFlags: 0, time: 0, dwExtraInfo: 0,
+                wVk: VK_CONTROL,
+                wScan: 0,
+                dwFlags: 0,
+                time: 0,
+                dwExtraInfo: 0,
             };
 
             inputs[1].r#type = INPUT_KEYBOARD;
             inputs[1].Anonymous.ki = KEYBDINPUT {
-                wVk: VK_C, wScan: 0, dwFlags: 0, time: 0, dwExtraInfo: 0,
+                wVk: VK_C,
+                wScan: 0,
+                dwFlags: 0,
+                time: 0,
+                dwExtraInfo: 0,
             };
 
             inputs[2].r#type = INPUT_KEYBOARD;
             inputs[2].Anonymous.ki = KEYBDINPUT {
-                wVk: VK_C, wScan: 0, dwFlags: KEYEVENTF_KEYUP, time: 0, dwExtraInfo: 0,
+                wVk: VK_C,
+                wScan: 0,
+                dwFlags: KEYEVENTF_KEYUP,
+                time: 0,
+                dwExtraInfo: 0,
             };
 
             inputs[3].r#type = INPUT_KEYBOARD;
             inputs[3].Anonymous.ki = KEYBDINPUT {
-                wVk: VK_CONTROL, wScan: 0, dwFlags: KEYEVENTF_KEYUP, time: 0, dwExtraInfo: 0,
+                wVk: VK_CONTROL,
+                wScan: 0,
+                dwFlags: KEYEVENTF_KEYUP,
+                time: 0,
+                dwExtraInfo: 0,
             };
 
             let sent = SendInput(4, inputs.as_ptr(), std::mem::size_of::<INPUT>() as i32);
@@ -422,8 +737,7 @@ mod windows_impl {
     /// Simulate Ctrl+V on Windows using SendInput.
     fn simulate_paste_sync() -> Result<(), String> {
         use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
-            SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT,
-            KEYEVENTF_KEYUP, VK_CONTROL, VK_V,
+            SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, VK_CONTROL, VK_V,
         };
 
         unsafe {
@@ -431,22 +745,38 @@ mod windows_impl {
 
             inputs[0].r#type = INPUT_KEYBOARD;
             inputs[0].Anonymous.ki = KEYBDINPUT {
-                wVk: VK_CONTROL, wScan: 0, dwFlags: 0, time: 0, dwExtraInfo: 0,
+                wVk: VK_CONTROL,
+                wScan: 0,
+                dwFlags: 0,
+                time: 0,
+                dwExtraInfo: 0,
             };
 
             inputs[1].r#type = INPUT_KEYBOARD;
             inputs[1].Anonymous.ki = KEYBDINPUT {
-                wVk: VK_V, wScan: 0, dwFlags: 0, time: 0, dwExtraInfo: 0,
+                wVk: VK_V,
+                wScan: 0,
+                dwFlags: 0,
+                time: 0,
+                dwExtraInfo: 0,
             };
 
             inputs[2].r#type = INPUT_KEYBOARD;
             inputs[2].Anonymous.ki = KEYBDINPUT {
-                wVk: VK_V, wScan: 0, dwFlags: KEYEVENTF_KEYUP, time: 0, dwExtraInfo: 0,
+                wVk: VK_V,
+                wScan: 0,
+                dwFlags: KEYEVENTF_KEYUP,
+                time: 0,
+                dwExtraInfo: 0,
             };
 
             inputs[3].r#type = INPUT_KEYBOARD;
             inputs[3].Anonymous.ki = KEYBDINPUT {
-                wVk: VK_CONTROL, wScan: 0, dwFlags: KEYEVENTF_KEYUP, time: 0, dwExtraInfo: 0,
+                wVk: VK_CONTROL,
+                wScan: 0,
+                dwFlags: KEYEVENTF_KEYUP,
+                time: 0,
+                dwExtraInfo: 0,
             };
 
             let sent = SendInput(4, inputs.as_ptr(), std::mem::size_of::<INPUT>() as i32);
@@ -458,6 +788,42 @@ mod windows_impl {
         Ok(())
     }
 
+    /// Press Right to collapse the current selection to its end.
+    pub fn simulate_move_to_selection_end_sync() -> Result<(), String> {
+        use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+            SendInput, INPUT, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, VK_RIGHT,
+        };
+
+        unsafe {
+            let mut inputs: [INPUT; 2] = std::mem::zeroed();
+
+            inputs[0].r#type = INPUT_KEYBOARD;
+            inputs[0].Anonymous.ki = KEYBDINPUT {
+                wVk: VK_RIGHT,
+                wScan: 0,
+                dwFlags: 0,
+                time: 0,
+                dwExtraInfo: 0,
+            };
+
+            inputs[1].r#type = INPUT_KEYBOARD;
+            inputs[1].Anonymous.ki = KEYBDINPUT {
+                wVk: VK_RIGHT,
+                wScan: 0,
+                dwFlags: KEYEVENTF_KEYUP,
+                time: 0,
+                dwExtraInfo: 0,
+            };
+
+            let sent = SendInput(2, inputs.as_ptr(), std::mem::size_of::<INPUT>() as i32);
+            if sent != 2 {
+                return Err(format!("SendInput returned {}, expected 2", sent));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Tauri command wrapper for simulate_paste
     #[tauri::command]
     pub async fn simulate_paste() -> Result<(), String> {
@@ -472,16 +838,16 @@ mod windows_impl {
     pub fn simulate_copy_sync() -> Result<(), String> {
         // Try UI Automation first — reads selection directly
         if let Some(text) = get_selected_text_uia() {
-            let mut clipboard = arboard::Clipboard::new()
-                .map_err(|e| format!("Clipboard init failed: {e}"))?;
-            clipboard.set_text(text)
+            let mut clipboard =
+                arboard::Clipboard::new().map_err(|e| format!("Clipboard init failed: {e}"))?;
+            clipboard
+                .set_text(text)
                 .map_err(|e| format!("Clipboard write failed: {e}"))?;
             return Ok(());
         }
         simulate_copy_via_sendinput()
     }
 
-
     #[tauri::command]
     pub fn check_permissions() -> PermissionsStatus {
         let exe_path = std::env::current_exe()
@@ -515,8 +881,7 @@ mod windows_impl {
 
     #[tauri::command]
     pub fn relaunch_app(app: tauri::AppHandle) -> Result<(), String> {
-        let exe =
-            std::env::current_exe().map_err(|e| format!("Failed to get exe path: {}", e))?;
+        let exe = std::env::current_exe().map_err(|e| format!("Failed to get exe path: {}", e))?;
         let _ = std::process::Command::new(&exe).spawn();
         app.exit(0);
         Ok(())
@@ -531,3 +896,68 @@ pub use macos::*;
 
 #[cfg(target_os = "windows")]
 pub use windows_impl::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_only_writes_the_clipboard_without_auto_paste() {
+        assert!(!pastes_into_origin(false));
+        assert_eq!(
+            pastes_into_origin(true),
+            cfg!(any(target_os = "macos", target_os = "windows"))
+        );
+    }
+
+    #[test]
+    fn written_text_reads_back() {
+        // Headless machines have no clipboard to write to.
+        if arboard::Clipboard::new().is_err() {
+            return;
+        }
+        let previous = read_clipboard_text().ok();
+        write_clipboard_text("zitong clipboard test".to_string()).unwrap();
+        assert_eq!(read_clipboard_text().unwrap(), "zitong clipboard test");
+        if let Some(previous) = previous {
+            let _ = write_clipboard_text(previous);
+        }
+    }
+
+    /// A 2×1 RGBA PNG: one red pixel, one blue.
+    const FIXTURE_PNG: &[u8] = &[
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0xf4,
+        0x22, 0x7f, 0x8a, 0x00, 0x00, 0x00, 0x0e, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0xf8,
+        0xcf, 0xc0, 0x00, 0x42, 0xff, 0x01, 0x0f, 0xf9, 0x03, 0xfd, 0x85, 0x11, 0x99, 0x76, 0x00,
+        0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+    ];
+
+    #[test]
+    fn clipboard_images_are_base64_png() {
+        let image = ClipboardImage::from_png(FIXTURE_PNG).unwrap();
+        assert_eq!(
+            image,
+            ClipboardImage {
+                data: "iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAYAAAD0In+KAAAADklEQVR4nGP4z8AAQv8BD/kD/YURmXYAAAAASUVORK5CYII="
+                    .to_string(),
+                mime_type: "image/png".to_string(),
+                width: 2,
+                height: 1,
+            }
+        );
+        assert!(ClipboardImage::from_png(b"GIF89a not a png").is_err());
+        assert!(write_clipboard_image("not base64!".to_string()).is_err());
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn png_pixels_round_trip() {
+        let pixels = [255, 0, 0, 255, 0, 0, 255, 255];
+        assert_eq!(decode_png(FIXTURE_PNG).unwrap(), (2, 1, pixels.to_vec()));
+
+        let png = encode_png(2, 1, &pixels).unwrap();
+        assert_eq!(png_dimensions(&png).unwrap(), (2, 1));
+        assert_eq!(decode_png(&png).unwrap(), (2, 1, pixels.to_vec()));
+    }
+}
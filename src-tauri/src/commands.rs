@@ -1,5 +1,15 @@
+use crate::attachments;
 use crate::db::{self, Database};
-use crate::providers::{self, ChatMessage, DeviceCodeResponse, ModelInfo, ProviderConfig, StreamEvent};
+use crate::events;
+use crate::export;
+use crate::local_api;
+use crate::logging;
+use crate::providers::{
+    self, ChatMessage, DeviceCodeResponse, ModelInfo, ProviderConfig, StreamErrorKind, StreamEvent,
+};
+use crate::shortcuts;
+use crate::transcript;
+use crate::tray;
 use serde::{Deserialize, Serialize};
 use tauri::{ipc::Channel, Emitter, Manager, State};
 
@@ -11,7 +21,9 @@ use tauri::{ipc::Channel, Emitter, Manager, State};
 #[serde(rename_all = "camelCase")]
 pub struct CreateConversationRequest {
     pub title: Option<String>,
+    #[serde(default)]
     pub model: String,
+    #[serde(default)]
     pub provider_id: String,
     pub system_prompt: Option<String>,
     pub folder_id: Option<String>,
@@ -25,6 +37,347 @@ pub struct SendMessageRequest {
     pub model: String,
     pub provider_id: String,
     pub system_prompt: Option<String>,
+    /// Tools the model may call; honoured by OpenAI-compatible and
+    /// Anthropic providers.
+    #[serde(default)]
+    pub tools: Option<Vec<providers::ToolDef>>,
+    /// Id the client chose for the user message. Becomes the message's id,
+    /// and a second send with the same id is not sent again.
+    #[serde(default)]
+    pub client_message_id: Option<String>,
+    /// Text the reply must start with (Anthropic only). Sent as a trailing
+    /// assistant message and saved as the beginning of the reply.
+    #[serde(default)]
+    pub prefill: Option<String>,
+}
+
+/// What `send_message` did with a request. A repeated `client_message_id`
+/// sends nothing: `duplicate` is set, and `in_progress` says whether the
+/// original send is still streaming.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendMessageStatus {
+    pub user_message_id: String,
+    pub duplicate: bool,
+    pub in_progress: bool,
+}
+
+/// Build the runtime provider config for `model` from a stored provider row,
+/// applying app-wide streaming settings. Providers with rotated keys get the
+/// next key that isn't cooling down in place of `api_key`.
+fn provider_config(
+    db: &Database,
+    provider: db::Provider,
+    model: String,
+    settings: &db::AppSettings,
+) -> ProviderConfig {
+    let (api_key, key_id) =
+        match db.next_provider_key(&provider.id, &providers::cooling_down_keys()) {
+            Ok(Some(key)) => (Some(key.key), Some(key.id)),
+            Ok(None) => (provider.api_key, None),
+            Err(e) => {
+                tracing::warn!("[providers] key rotation failed for {}: {}", provider.id, e);
+                (provider.api_key, None)
+            }
+        };
+    ProviderConfig {
+        provider_type: provider.provider_type,
+        api_key,
+        base_url: provider.base_url,
+        model,
+        idle_timeout: providers::stream_idle_timeout(settings.stream_idle_timeout_secs),
+        supports_streaming: provider.supports_streaming,
+        transcript: None,
+        capture: None,
+        auth_style: providers::AuthStyle::parse(&provider.auth_style).unwrap_or_default(),
+        tools: None,
+        max_tokens: provider.default_max_tokens,
+        temperature: None,
+        completion_mode: provider.completion_mode,
+        cancel: None,
+        custom_headers: provider.custom_headers,
+        extra_body: provider.extra_body,
+        key_id,
+        reconnect_attempts: settings.stream_reconnect_attempts.max(0) as u32,
+        system_prompt_mode: providers::SystemPromptMode::parse(&provider.system_prompt_mode)
+            .unwrap_or_default(),
+        offline: settings.offline_mode,
+        user: provider
+            .send_user_id
+            .then(|| anonymous_user_id(db, settings))
+            .flatten(),
+    }
+}
+
+/// The install's `anonymous_user_id`, generated and stored on first use.
+fn anonymous_user_id(db: &Database, settings: &db::AppSettings) -> Option<String> {
+    let id = settings.anonymous_user_id.trim();
+    if !id.is_empty() {
+        return Some(id.to_string());
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    match db.set_setting_raw("anonymous_user_id", &id) {
+        Ok(()) => Some(id),
+        Err(e) => {
+            tracing::warn!("[providers] failed to store anonymous user id: {}", e);
+            None
+        }
+    }
+}
+
+/// The local calendar day token usage is counted under, so daily caps reset
+/// at local midnight.
+pub fn usage_day(now: chrono::DateTime<chrono::Local>) -> String {
+    now.format("%Y-%m-%d").to_string()
+}
+
+/// Refuse a send once `provider` has used its `daily_token_cap` on `day`.
+pub fn check_token_budget(db: &Database, provider: &db::Provider, day: &str) -> Result<(), String> {
+    let Some(cap) = provider.daily_token_cap else {
+        return Ok(());
+    };
+    let used = db
+        .daily_tokens(&provider.id, day)
+        .map_err(|e| e.to_string())?;
+    if used >= cap {
+        return Err(format!(
+            "Daily token budget exceeded for {}: {} of {} tokens used today. It resets at midnight.",
+            provider.name, used, cap
+        ));
+    }
+    Ok(())
+}
+
+/// Count a finished response against its provider's usage on `day`. A
+/// failure is only logged, since the response itself succeeded.
+fn record_token_usage(db: &Database, provider_id: &str, day: &str, tokens: i64) {
+    if let Err(e) = db.add_daily_usage(provider_id, day, tokens) {
+        tracing::warn!("[usage] failed to record usage for {}: {}", provider_id, e);
+    }
+}
+
+/// Today's token usage for every provider that has used any.
+#[tauri::command]
+pub fn get_daily_usage(db: State<'_, Database>) -> Result<Vec<db::DailyUsage>, String> {
+    db.list_daily_usage(&usage_day(chrono::Local::now()))
+        .map_err(|e| e.to_string())
+}
+
+/// The last request `send_message` sent for a conversation, with credentials
+/// redacted. `execute_ai_command` requests are kept under `"ai_command"`.
+/// Held in memory only, so `None` after a restart.
+#[tauri::command]
+pub fn get_last_request_debug(
+    last_requests: State<'_, transcript::LastRequests>,
+    conversation_id: String,
+) -> Result<Option<String>, String> {
+    Ok(last_requests.last(&conversation_id))
+}
+
+/// Open the raw-traffic transcript for a conversation; failures only disable
+/// the transcript.
+fn open_transcript(
+    app: &tauri::AppHandle,
+    conversation_id: &str,
+) -> Option<std::sync::Arc<transcript::Transcript>> {
+    let log_dir = app.path().app_log_dir().ok()?;
+    match transcript::Transcript::open(&log_dir, conversation_id) {
+        Ok(t) => Some(std::sync::Arc::new(t)),
+        Err(e) => {
+            tracing::warn!("[transcript] {}", e);
+            None
+        }
+    }
+}
+
+/// Combine the global prefix/suffix settings with a conversation or command
+/// system prompt. Empty parts are skipped and the rest joined by a blank line;
+/// returns `None` when nothing is left.
+fn compose_system_prompt(
+    settings: &db::AppSettings,
+    system_prompt: Option<&str>,
+) -> Option<String> {
+    let parts: Vec<&str> = [
+        settings.global_system_prefix.as_str(),
+        system_prompt.unwrap_or_default(),
+        settings.global_system_suffix.as_str(),
+    ]
+    .into_iter()
+    .map(str::trim)
+    .filter(|part| !part.is_empty())
+    .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n\n"))
+    }
+}
+
+/// Whether `message` is a summary inserted by `summarize_conversation`.
+fn is_summary(message: &db::Message) -> bool {
+    message
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get("summary"))
+        .and_then(serde_json::Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// The part of a conversation still sent as context: everything from the
+/// latest summary message on (or the whole history if there is none).
+fn history_since_summary(messages: &[db::Message]) -> &[db::Message] {
+    let start = messages.iter().rposition(is_summary).unwrap_or(0);
+    &messages[start..]
+}
+
+/// Keep the latest `max_messages` of `history` (all of it when `0`). System
+/// and sticky messages are kept wherever they are and don't count toward the
+/// cap. Order is preserved.
+fn trim_history<'a>(history: &[&'a db::Message], max_messages: usize) -> Vec<&'a db::Message> {
+    let pinned = |m: &db::Message| m.role == "system" || m.sticky;
+    let mut budget = if max_messages == 0 {
+        usize::MAX
+    } else {
+        max_messages
+    };
+    let mut kept: Vec<&db::Message> = history
+        .iter()
+        .copied()
+        .rev()
+        .filter(|m| {
+            if pinned(m) {
+                true
+            } else if budget > 0 {
+                budget -= 1;
+                true
+            } else {
+                false
+            }
+        })
+        .collect();
+    kept.reverse();
+    kept
+}
+
+/// Provider messages for a conversation: the system prompt followed by the
+/// history since the latest summary, trimmed to `max_messages` (see
+/// `trim_history`). The summary itself is folded into the system prompt,
+/// since not every provider accepts a leading assistant turn.
+fn build_chat_messages(
+    system_prompt: Option<String>,
+    messages: &[db::Message],
+    max_messages: usize,
+) -> Vec<ChatMessage> {
+    let history = history_since_summary(messages);
+    let (summary, history) = match history.split_first() {
+        Some((first, rest)) if is_summary(first) => (Some(first.content.trim()), rest),
+        _ => (None, history),
+    };
+
+    let system_prompt = match (system_prompt, summary) {
+        (Some(prompt), Some(summary)) => Some(format!(
+            "{}\n\nSummary of the earlier conversation:\n{}",
+            prompt, summary
+        )),
+        (None, Some(summary)) => Some(format!("Summary of the earlier conversation:\n{}", summary)),
+        (prompt, None) => prompt,
+    };
+
+    let mut chat_messages: Vec<ChatMessage> = Vec::new();
+    if let Some(system_prompt) = system_prompt {
+        chat_messages.push(ChatMessage {
+            role: "system".to_string(),
+            content: system_prompt,
+        });
+    }
+    let history: Vec<&db::Message> = history.iter().filter(|m| m.include_in_context).collect();
+    for msg in trim_history(&history, max_messages) {
+        chat_messages.push(ChatMessage {
+            role: msg.role.clone(),
+            content: msg.content.clone(),
+        });
+    }
+    chat_messages
+}
+
+/// Maximum length (in characters) of a title derived from message text.
+const TITLE_MAX_CHARS: usize = 50;
+
+/// The `auto_title_mode` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoTitleMode {
+    Off,
+    Truncate,
+    Llm,
+}
+
+impl AutoTitleMode {
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode.trim().to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "truncate" => Ok(Self::Truncate),
+            "llm" => Ok(Self::Llm),
+            other => Err(format!(
+                "Unknown auto-title mode '{}'. Expected off, truncate or llm",
+                other
+            )),
+        }
+    }
+}
+
+/// The `prune_action` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruneAction {
+    Archive,
+    Delete,
+}
+
+impl PruneAction {
+    pub fn parse(action: &str) -> Result<Self, String> {
+        match action.trim().to_lowercase().as_str() {
+            "archive" => Ok(Self::Archive),
+            "delete" => Ok(Self::Delete),
+            other => Err(format!(
+                "Unknown prune action '{}'. Expected archive or delete",
+                other
+            )),
+        }
+    }
+}
+
+/// Enforce `max_conversations` now that `new_id` exists. Pruning never fails
+/// the command that created the conversation.
+fn prune_after_create(app: &tauri::AppHandle, db: &Database, new_id: &str) {
+    let pruned = db
+        .get_settings()
+        .map_err(|e| e.to_string())
+        .and_then(|settings| {
+            if settings.max_conversations <= 0 {
+                return Ok(None);
+            }
+            let delete = PruneAction::parse(&settings.prune_action)? == PruneAction::Delete;
+            db.prune_conversations(settings.max_conversations as usize, delete, new_id)
+                .map(|ids| Some((ids, delete)))
+                .map_err(|e| e.to_string())
+        });
+    match pruned {
+        Ok(Some((ids, delete))) if !ids.is_empty() => {
+            tracing::info!("[prune] pruned {} conversations", ids.len());
+            events::conversations_pruned(app, ids, delete);
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("[prune] failed: {}", e),
+    }
+}
+
+/// Truncate `text` to at most `max_chars` characters (never splitting a
+/// multi-byte character), appending "…" only if something was cut off.
+pub fn truncate_chars(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((byte_idx, _)) => format!("{}…", &text[..byte_idx]),
+        None => text.to_string(),
+    }
 }
 
 // ============================================
@@ -33,17 +386,49 @@ pub struct SendMessageRequest {
 
 #[tauri::command]
 pub fn create_conversation(
+    app: tauri::AppHandle,
     db: State<'_, Database>,
     req: CreateConversationRequest,
+) -> Result<db::Conversation, String> {
+    let conversation = new_conversation(&db, req)?;
+    events::conversation_created(&app, &conversation.id);
+    prune_after_create(&app, &db, &conversation.id);
+    Ok(conversation)
+}
+
+fn new_conversation(
+    db: &Database,
+    req: CreateConversationRequest,
 ) -> Result<db::Conversation, String> {
     let id = uuid::Uuid::new_v4().to_string();
     let title = req.title.as_deref().unwrap_or("New Chat");
+
+    // Anything not given explicitly comes from the default assistant, then
+    // from the app settings.
+    let assistant = db.get_default_assistant().map_err(|e| e.to_string())?;
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let from_assistant = |field: fn(&db::Assistant) -> Option<String>| {
+        assistant.as_ref().and_then(field).filter(|s| !s.is_empty())
+    };
+    let system_prompt = req
+        .system_prompt
+        .or_else(|| from_assistant(|a| Some(a.system_prompt.clone())))
+        .or_else(|| Some(settings.default_system_prompt.clone()).filter(|s| !s.is_empty()));
+    let provider_id = Some(req.provider_id)
+        .filter(|s| !s.is_empty())
+        .or_else(|| from_assistant(|a| a.provider_id.clone()))
+        .unwrap_or(settings.default_provider_id);
+    let model = Some(req.model)
+        .filter(|s| !s.is_empty())
+        .or_else(|| from_assistant(|a| a.model.clone()))
+        .unwrap_or(settings.default_model);
+
     db.create_conversation(
         &id,
         title,
-        &req.model,
-        &req.provider_id,
-        req.system_prompt.as_deref(),
+        &model,
+        &provider_id,
+        system_prompt.as_deref(),
         req.folder_id.as_deref(),
     )
     .map_err(|e| e.to_string())
@@ -54,34 +439,148 @@ pub fn list_conversations(db: State<'_, Database>) -> Result<Vec<db::Conversatio
     db.list_conversations().map_err(|e| e.to_string())
 }
 
+/// Conversations that would be affected by removing `model`, so the UI can
+/// warn first.
+#[tauri::command]
+pub fn conversations_using_model(
+    db: State<'_, Database>,
+    model: String,
+) -> Result<Vec<db::Conversation>, String> {
+    db.conversations_using_model(&model)
+        .map_err(|e| e.to_string())
+}
+
+/// Conversations that would be affected by deleting the provider.
+#[tauri::command]
+pub fn conversations_using_provider(
+    db: State<'_, Database>,
+    provider_id: String,
+) -> Result<Vec<db::Conversation>, String> {
+    db.conversations_using_provider(&provider_id)
+        .map_err(|e| e.to_string())
+}
+
+/// Conversations in one folder, or those outside any folder when
+/// `folder_id` is omitted.
+#[tauri::command]
+pub fn list_conversations_in_folder(
+    db: State<'_, Database>,
+    folder_id: Option<String>,
+) -> Result<Vec<db::Conversation>, String> {
+    match folder_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(folder_id) => db.list_conversations_in_folder(folder_id),
+        None => db.list_root_conversations(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+/// Conversation list for the sidebar, with message counts and a preview of the
+/// latest message.
+#[tauri::command]
+pub fn list_conversation_summaries(
+    db: State<'_, Database>,
+) -> Result<Vec<db::ConversationSummary>, String> {
+    db.list_conversation_summaries().map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn get_conversation(db: State<'_, Database>, id: String) -> Result<db::Conversation, String> {
     db.get_conversation(&id).map_err(|e| e.to_string())
 }
 
+/// The conversation row plus message count, token total and last activity,
+/// for detail headers that would otherwise need a second call.
+#[tauri::command]
+pub fn get_conversation_detail(
+    db: State<'_, Database>,
+    id: String,
+) -> Result<db::ConversationDetail, String> {
+    db.get_conversation_detail(&id).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn update_conversation_title(
+    app: tauri::AppHandle,
     db: State<'_, Database>,
     id: String,
     title: String,
 ) -> Result<(), String> {
     db.update_conversation_title(&id, &title)
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+    events::conversation_updated(&app, &id);
+    Ok(())
+}
+
+/// Set the temperature sent with this conversation's messages, or clear it
+/// with `None` to use the provider's default.
+#[tauri::command]
+pub fn update_conversation_temperature(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    id: String,
+    temperature: Option<f64>,
+) -> Result<(), String> {
+    if let Some(t) = temperature {
+        if !(0.0..=2.0).contains(&t) {
+            return Err(format!("Temperature must be between 0 and 2, got {}", t));
+        }
+    }
+    db.update_conversation_temperature(&id, temperature)
+        .map_err(|e| e.to_string())?;
+    events::conversation_updated(&app, &id);
+    Ok(())
+}
+
+/// Delete all messages of a conversation, keeping its title, folder, model
+/// and system prompt. Returns how many messages were deleted.
+#[tauri::command]
+pub fn clear_conversation(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    id: String,
+) -> Result<u64, String> {
+    db.get_conversation(&id)
+        .map_err(|_| format!("Conversation not found: {}", id))?;
+    let cleared = db.clear_messages(&id).map_err(|e| e.to_string())?;
+    events::conversation_updated(&app, &id);
+    Ok(cleared)
+}
+
+#[tauri::command]
+pub fn delete_conversation(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    id: String,
+) -> Result<(), String> {
+    db.delete_conversation(&id).map_err(|e| e.to_string())?;
+    events::conversation_deleted(&app, &id);
+    Ok(())
 }
 
 #[tauri::command]
-pub fn delete_conversation(db: State<'_, Database>, id: String) -> Result<(), String> {
-    db.delete_conversation(&id).map_err(|e| e.to_string())
+pub fn set_conversation_pinned(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    id: String,
+    pinned: bool,
+) -> Result<(), String> {
+    db.set_conversation_pinned(&id, pinned)
+        .map_err(|e| e.to_string())?;
+    events::conversation_updated(&app, &id);
+    Ok(())
 }
 
 #[tauri::command]
 pub fn archive_conversation(
+    app: tauri::AppHandle,
     db: State<'_, Database>,
     id: String,
     archived: bool,
 ) -> Result<(), String> {
     db.archive_conversation(&id, archived)
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+    events::conversation_updated(&app, &id);
+    Ok(())
 }
 
 #[tauri::command]
@@ -92,6 +591,28 @@ pub fn search_conversations(
     db.search_conversations(&query).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+pub fn search_conversations_scoped(
+    db: State<'_, Database>,
+    query: String,
+    scope: Option<db::SearchScope>,
+    folder_id: Option<String>,
+) -> Result<Vec<db::Conversation>, String> {
+    db.search_conversations_scoped(&query, scope.unwrap_or_default(), folder_id.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+/// "Find in chat": matching messages of one conversation, in order.
+#[tauri::command]
+pub fn search_messages_in_conversation(
+    db: State<'_, Database>,
+    conversation_id: String,
+    query: String,
+) -> Result<Vec<db::Message>, String> {
+    db.search_messages_in_conversation(&conversation_id, &query)
+        .map_err(|e| e.to_string())
+}
+
 // ============================================
 // Message Commands
 // ============================================
@@ -101,130 +622,768 @@ pub fn get_messages(
     db: State<'_, Database>,
     conversation_id: String,
 ) -> Result<Vec<db::Message>, String> {
-    db.get_messages(&conversation_id)
-        .map_err(|e| e.to_string())
+    db.get_messages(&conversation_id).map_err(|e| e.to_string())
 }
 
+/// Upper bound on a single page so the UI can't request everything at once.
+const MAX_MESSAGE_PAGE_SIZE: i64 = 200;
+
+/// Lazy-load history: the `limit` messages preceding `before_sort_order`
+/// (or the newest ones when omitted), oldest first.
 #[tauri::command]
-pub fn delete_message(db: State<'_, Database>, id: String) -> Result<(), String> {
-    db.delete_message(&id).map_err(|e| e.to_string())
+pub fn get_messages_page(
+    db: State<'_, Database>,
+    conversation_id: String,
+    before_sort_order: Option<i64>,
+    limit: i64,
+) -> Result<Vec<db::Message>, String> {
+    let limit = limit.clamp(1, MAX_MESSAGE_PAGE_SIZE);
+    db.get_messages_paginated(&conversation_id, before_sort_order, limit)
+        .map_err(|e| e.to_string())
 }
 
-// ============================================
-// Send Message with Streaming
-// ============================================
-
 #[tauri::command]
-pub async fn send_message(
+pub fn delete_message(
+    app: tauri::AppHandle,
     db: State<'_, Database>,
-    req: SendMessageRequest,
-    on_event: Channel<StreamEvent>,
+    id: String,
 ) -> Result<(), String> {
-    // Save user message to DB
-    let user_msg_id = uuid::Uuid::new_v4().to_string();
-    let sort_order = db
-        .get_message_count(&req.conversation_id)
-        .unwrap_or(0);
-
-    db.create_message(
-        &user_msg_id,
-        &req.conversation_id,
-        "user",
-        &req.content,
-        None,
-        None,
-        sort_order,
-    )
-    .map_err(|e| e.to_string())?;
+    // Look up the owning conversation first so the change event can name it.
+    let conversation_id = db.get_message(&id).ok().map(|m| m.conversation_id);
+    db.delete_message(&id).map_err(|e| e.to_string())?;
+    if let Some(conversation_id) = conversation_id {
+        events::message_deleted(&app, &conversation_id, &id);
+    }
+    Ok(())
+}
 
-    // Get all messages for context
-    let all_messages = db
-        .get_messages(&req.conversation_id)
+/// Start a new conversation from the history up to and including `message_id`.
+#[tauri::command]
+pub fn fork_conversation(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    message_id: String,
+    new_title: Option<String>,
+) -> Result<db::Conversation, String> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let conversation = db
+        .fork_conversation(&message_id, &id, new_title.as_deref())
         .map_err(|e| e.to_string())?;
+    events::conversation_created(&app, &id);
+    prune_after_create(&app, &db, &id);
+    Ok(conversation)
+}
 
-    // Build chat messages for provider
-    let mut chat_messages: Vec<ChatMessage> = Vec::new();
-
-    // Add system prompt if available
-    if let Some(system_prompt) = &req.system_prompt {
-        if !system_prompt.is_empty() {
-            chat_messages.push(ChatMessage {
-                role: "system".to_string(),
-                content: system_prompt.clone(),
-            });
-        }
+/// Repair a conversation whose message `sort_order` has gaps or duplicates.
+/// Returns how many messages were renumbered.
+#[tauri::command]
+pub fn normalize_conversation_order(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    conversation_id: String,
+) -> Result<usize, String> {
+    let changed = db
+        .normalize_message_order(&conversation_id)
+        .map_err(|e| e.to_string())?;
+    if changed > 0 {
+        tracing::info!(
+            "[messages] renumbered {} message(s) in {}",
+            changed,
+            conversation_id
+        );
+        events::conversation_updated(&app, &conversation_id);
     }
+    Ok(changed)
+}
 
-    // Add conversation history
-    for msg in &all_messages {
-        chat_messages.push(ChatMessage {
-            role: msg.role.clone(),
-            content: msg.content.clone(),
-        });
-    }
+/// Include or exclude a message from the context sent with later requests.
+/// Excluded messages still appear in the conversation.
+#[tauri::command]
+pub fn set_message_include_in_context(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    message_id: String,
+    include: bool,
+) -> Result<(), String> {
+    let message = db
+        .get_message(&message_id)
+        .map_err(|_| format!("Message not found: {}", message_id))?;
+    db.set_message_include_in_context(&message_id, include)
+        .map_err(|e| e.to_string())?;
+    events::message_updated(&app, &message.conversation_id, &message_id);
+    Ok(())
+}
 
-    // Get provider config from DB
-    let provider = db
-        .get_provider(&req.provider_id)
-        .map_err(|e| {
-            let msg = e.to_string();
-            if msg.contains("no rows") || msg.contains("Query returned no rows") {
-                "No AI provider configured. Please go to Settings → Providers to add one.".to_string()
-            } else {
-                format!("Failed to load provider: {}", msg)
-            }
-        })?;
+/// Pin a message so context trimming never drops it.
+#[tauri::command]
+pub fn set_message_sticky(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    message_id: String,
+    sticky: bool,
+) -> Result<(), String> {
+    let message = db
+        .get_message(&message_id)
+        .map_err(|_| format!("Message not found: {}", message_id))?;
+    db.set_message_sticky(&message_id, sticky)
+        .map_err(|e| e.to_string())?;
+    events::message_updated(&app, &message.conversation_id, &message_id);
+    Ok(())
+}
 
-    let config = ProviderConfig {
-        provider_type: provider.provider_type,
-        api_key: provider.api_key,
-        base_url: provider.base_url,
-        model: req.model.clone(),
+/// Relabel which model a conversation's messages came from, e.g. after
+/// switching it to a newer snapshot. `from` limits it to messages labeled
+/// with that model. Returns how many messages changed.
+#[tauri::command]
+pub fn relabel_messages_model(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    conversation_id: String,
+    from: Option<String>,
+    to: String,
+) -> Result<usize, String> {
+    let to = to.trim();
+    if to.is_empty() {
+        return Err("Model name is required".to_string());
+    }
+    db.get_conversation(&conversation_id)
+        .map_err(|_| format!("Conversation not found: {}", conversation_id))?;
+    let changed = db
+        .relabel_messages_model(&conversation_id, from.as_deref(), to)
+        .map_err(|e| e.to_string())?;
+    if changed > 0 {
+        tracing::info!(
+            "[messages] relabeled {} message(s) in {} as {}",
+            changed,
+            conversation_id,
+            to
+        );
+        events::conversation_updated(&app, &conversation_id);
+    }
+    Ok(changed)
+}
+
+const APPENDABLE_ROLES: &[&str] = &["user", "assistant", "system"];
+
+/// Append a message to the end of a conversation without generating a reply.
+/// Used by imports and tools that build conversations programmatically.
+#[tauri::command]
+pub fn append_message(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    conversation_id: String,
+    role: String,
+    content: String,
+    model: Option<String>,
+) -> Result<db::Message, String> {
+    let message = append_to_conversation(&db, &conversation_id, &role, &content, model.as_deref())?;
+    events::message_added(&app, &conversation_id, &message.id);
+    Ok(message)
+}
+
+fn append_to_conversation(
+    db: &Database,
+    conversation_id: &str,
+    role: &str,
+    content: &str,
+    model: Option<&str>,
+) -> Result<db::Message, String> {
+    if !APPENDABLE_ROLES.contains(&role) {
+        return Err(format!(
+            "Invalid role '{}'. Expected one of: {}",
+            role,
+            APPENDABLE_ROLES.join(", ")
+        ));
+    }
+    db.get_conversation(conversation_id)
+        .map_err(|_| format!("Conversation not found: {}", conversation_id))?;
+
+    let sort_order = db
+        .get_message_count(conversation_id)
+        .map_err(|e| e.to_string())?;
+    db.create_message(
+        &uuid::Uuid::new_v4().to_string(),
+        conversation_id,
+        role,
+        content,
+        model,
+        None,
+        sort_order,
+    )
+    .map_err(|e| e.to_string())
+}
+
+// ============================================
+// Send Message with Streaming
+// ============================================
+
+/// `client_message_id`s of `send_message` calls still running.
+static IN_FLIGHT_SENDS: std::sync::OnceLock<std::sync::Mutex<std::collections::HashSet<String>>> =
+    std::sync::OnceLock::new();
+
+fn in_flight_sends() -> std::sync::MutexGuard<'static, std::collections::HashSet<String>> {
+    IN_FLIGHT_SENDS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Marks a `client_message_id` as in flight for the lifetime of the guard.
+struct InFlightSend {
+    id: String,
+}
+
+impl InFlightSend {
+    /// `None` if a send with this id is already running.
+    fn register(id: &str) -> Option<Self> {
+        in_flight_sends()
+            .insert(id.to_string())
+            .then(|| Self { id: id.to_string() })
+    }
+}
+
+impl Drop for InFlightSend {
+    fn drop(&mut self) {
+        in_flight_sends().remove(&self.id);
+    }
+}
+
+/// Outcome of `begin_send`.
+enum SendStart {
+    /// A send with the same `client_message_id` already ran or is running.
+    Duplicate(SendMessageStatus),
+    /// The user message was saved at `sort_order`. `in_flight` holds the
+    /// `client_message_id` until the send finishes.
+    New {
+        user_msg_id: String,
+        sort_order: i64,
+        in_flight: Option<InFlightSend>,
+    },
+}
+
+/// Save the user message for `req`, unless its `client_message_id` was
+/// already sent or is being sent.
+fn begin_send(db: &Database, req: &SendMessageRequest) -> Result<SendStart, String> {
+    let client_id = req
+        .client_message_id
+        .clone()
+        .filter(|id| !id.trim().is_empty());
+    let duplicate = |in_progress: bool| {
+        tracing::info!(
+            "[send] ignoring duplicate send of {}",
+            client_id.as_deref().unwrap_or_default()
+        );
+        Ok(SendStart::Duplicate(SendMessageStatus {
+            user_message_id: client_id.clone().unwrap_or_default(),
+            duplicate: true,
+            in_progress,
+        }))
+    };
+    let in_flight = match client_id.as_deref() {
+        Some(id) => match InFlightSend::register(id) {
+            Some(guard) => Some(guard),
+            None => return duplicate(true),
+        },
+        None => None,
+    };
+    if let Some(id) = client_id.as_deref() {
+        if db.get_message(id).is_ok() {
+            return duplicate(false);
+        }
+    }
+
+    let user_msg_id = client_id
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let sort_order = db.get_message_count(&req.conversation_id).unwrap_or(0);
+    db.create_message(
+        &user_msg_id,
+        &req.conversation_id,
+        "user",
+        &req.content,
+        None,
+        None,
+        sort_order,
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(SendStart::New {
+        user_msg_id,
+        sort_order,
+        in_flight,
+    })
+}
+
+/// Load the conversation and its messages for `req`, and build the chat
+/// history to send: the system prompt wrapped in the global prefix/suffix,
+/// then the messages.
+fn send_context(
+    db: &Database,
+    req: &SendMessageRequest,
+    settings: &db::AppSettings,
+) -> Result<(db::Conversation, Vec<db::Message>, Vec<ChatMessage>), String> {
+    let all_messages = db
+        .get_messages(&req.conversation_id)
+        .map_err(|e| e.to_string())?;
+    let conversation = db
+        .get_conversation(&req.conversation_id)
+        .map_err(|e| e.to_string())?;
+
+    // The request's system prompt wins; otherwise use the one stored on the
+    // conversation so it isn't lost when the frontend doesn't resend it.
+    let system_prompt = req
+        .system_prompt
+        .as_deref()
+        .or(conversation.system_prompt.as_deref());
+
+    let chat_messages = build_chat_messages(
+        compose_system_prompt(settings, system_prompt),
+        &all_messages,
+        settings.max_context_messages.max(0) as usize,
+    );
+    Ok((conversation, all_messages, chat_messages))
+}
+
+#[tauri::command]
+pub async fn send_message(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    last_requests: State<'_, transcript::LastRequests>,
+    req: SendMessageRequest,
+    on_event: Channel<StreamEvent>,
+) -> Result<SendMessageStatus, String> {
+    let (user_msg_id, sort_order, _in_flight) = match begin_send(&db, &req)? {
+        SendStart::Duplicate(status) => return Ok(status),
+        SendStart::New {
+            user_msg_id,
+            sort_order,
+            in_flight,
+        } => (user_msg_id, sort_order, in_flight),
     };
+    events::message_added(&app, &req.conversation_id, &user_msg_id);
+
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let (conversation, all_messages, mut chat_messages) = send_context(&db, &req, &settings)?;
+    providers::ensure_conversation_turn(&chat_messages)?;
+
+    // Get provider config from DB
+    let provider = db.get_provider(&req.provider_id).map_err(|e| {
+        let msg = e.to_string();
+        if msg.contains("no rows") || msg.contains("Query returned no rows") {
+            "No AI provider configured. Please go to Settings → Providers to add one.".to_string()
+        } else {
+            format!("Failed to load provider: {}", msg)
+        }
+    })?;
+    check_token_budget(&db, &provider, &usage_day(chrono::Local::now()))?;
+
+    if let Some(prefill) = req.prefill.as_deref().filter(|p| !p.trim().is_empty()) {
+        if provider.provider_type != "anthropic" {
+            return Err("A reply prefill is only supported by Anthropic providers".to_string());
+        }
+        chat_messages.push(ChatMessage {
+            role: "assistant".to_string(),
+            content: prefill.to_string(),
+        });
+    }
+
+    let mut config = provider_config(&db, provider, req.model.clone(), &settings);
+    if settings.log_raw_requests {
+        config.transcript = open_transcript(&app, &req.conversation_id);
+    }
+    config.capture = Some(last_requests.start(&req.conversation_id));
+    config.tools = req.tools.clone();
+    config.temperature = conversation.temperature;
+
+    let (_, final_content) = stream_and_save_reply(
+        &app,
+        &db,
+        &config,
+        &req.provider_id,
+        &chat_messages,
+        &req.conversation_id,
+        sort_order + 1,
+        settings.response_token_warn_at,
+        &on_event,
+    )
+    .await?;
+
+    // A failed title is not a failed message.
+    let titled = auto_title(&app, &db, &settings, &req, &all_messages, &final_content).await;
+    if let Err(e) = titled {
+        tracing::warn!(
+            "[title] auto-title failed for {}: {}",
+            req.conversation_id,
+            e
+        );
+    }
+
+    Ok(SendMessageStatus {
+        user_message_id: user_msg_id,
+        duplicate: false,
+        in_progress: false,
+    })
+}
+
+/// Turn off `supports_streaming` for a provider that rejected a streamed
+/// request (see `StreamEvent::StreamingDisabled`), so later requests go
+/// straight to buffered mode. Emits `provider-updated`.
+fn disable_streaming(app: &tauri::AppHandle, db: &Database, provider_id: &str) {
+    if save_streaming_disabled(db, provider_id) {
+        events::provider_updated(app, provider_id);
+    }
+}
+
+/// The database half of `disable_streaming`; returns whether it was saved.
+fn save_streaming_disabled(db: &Database, provider_id: &str) -> bool {
+    match db.set_provider_streaming(provider_id, false) {
+        Ok(()) => {
+            tracing::info!("[stream] turned off streaming for provider {}", provider_id);
+            forget_capabilities(provider_id);
+            true
+        }
+        Err(e) => {
+            tracing::warn!(
+                "[stream] failed to turn off streaming for {}: {}",
+                provider_id,
+                e
+            );
+            false
+        }
+    }
+}
+
+/// How often the text of a streaming reply is written to the database.
+const STREAM_PERSIST_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
 
-    // Stream the response
+/// Stream a reply to `chat_messages`, forwarding every event to `on_event`,
+/// and save it as an assistant message at `sort_order`. The message row is
+/// created before the request and its content saved every
+/// `STREAM_PERSIST_INTERVAL`, so a crash mid-stream keeps what had arrived;
+/// a stream that fails, or reports an error before producing anything,
+/// removes it and returns the error. The response's tokens count towards
+/// `provider_id`'s daily usage, and its timing is saved with the message.
+/// Returns the new message's id and content.
+#[allow(clippy::too_many_arguments)]
+async fn stream_and_save_reply(
+    app: &tauri::AppHandle,
+    db: &Database,
+    config: &ProviderConfig,
+    provider_id: &str,
+    chat_messages: &[ChatMessage],
+    conversation_id: &str,
+    sort_order: i64,
+    token_warn_at: i64,
+    on_event: &Channel<StreamEvent>,
+) -> Result<(String, String), String> {
     let accumulated = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
     let accumulated_clone = accumulated.clone();
     let total_tokens = std::sync::Arc::new(std::sync::Mutex::new(0i64));
     let total_tokens_clone = total_tokens.clone();
+    let tool_calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let tool_calls_clone = tool_calls.clone();
+    let mut stream_error = None;
+    let mut token_meter = providers::ResponseTokenMeter::new(token_warn_at);
+    let mut timer = providers::StreamTimer::start(std::time::Instant::now());
 
-    providers::stream_chat(&config, &chat_messages, |event| {
+    let assistant_msg_id = uuid::Uuid::new_v4().to_string();
+    db.begin_streaming_message(
+        &assistant_msg_id,
+        conversation_id,
+        &config.model,
+        sort_order,
+    )
+    .map_err(|e| e.to_string())?;
+    events::message_added(app, conversation_id, &assistant_msg_id);
+    let mut last_persist = std::time::Instant::now();
+
+    let streamed = providers::stream_chat(config, chat_messages, |event| {
         match &event {
             StreamEvent::Delta { content } => {
-                accumulated_clone.lock().unwrap().push_str(content);
+                let mut accumulated = accumulated_clone.lock().unwrap();
+                accumulated.push_str(content);
+                if last_persist.elapsed() >= STREAM_PERSIST_INTERVAL {
+                    last_persist = std::time::Instant::now();
+                    if let Err(e) = db.set_message_content(&assistant_msg_id, &accumulated) {
+                        tracing::warn!("[stream] failed to save partial reply: {}", e);
+                    }
+                }
             }
-            StreamEvent::Done { total_tokens: tokens } => {
+            StreamEvent::Done {
+                total_tokens: tokens,
+            } => {
                 *total_tokens_clone.lock().unwrap() = *tokens;
             }
+            StreamEvent::ToolCall {
+                id,
+                name,
+                arguments,
+            } => {
+                tool_calls_clone.lock().unwrap().push(providers::ToolCall {
+                    id: id.clone(),
+                    name: name.clone(),
+                    arguments: arguments.clone(),
+                });
+            }
+            StreamEvent::StreamingDisabled { .. } => disable_streaming(app, db, provider_id),
+            StreamEvent::Error { message, .. } => stream_error = Some(message.clone()),
             _ => {}
         }
+        timer.observe(&event, std::time::Instant::now());
+        let warning = token_meter.observe(&event);
         let _ = on_event.send(event);
+        if let Some(warning) = warning {
+            let _ = on_event.send(warning);
+        }
     })
-    .await?;
+    .await;
+    let metrics = timer.finish(std::time::Instant::now(), *total_tokens.lock().unwrap());
+    // Providers report most failures as an `Error` event rather than an
+    // `Err`; with nothing received, that leaves no reply worth keeping.
+    let produced_nothing =
+        accumulated.lock().unwrap().is_empty() && tool_calls.lock().unwrap().is_empty();
+    let streamed = match (streamed, stream_error) {
+        (Ok(()), Some(message)) if produced_nothing => Err(message),
+        (streamed, _) => streamed,
+    };
+    if let Err(e) = streamed {
+        if let Err(delete_err) = db.delete_message(&assistant_msg_id) {
+            tracing::warn!("[stream] failed to remove unfinished reply: {}", delete_err);
+        }
+        events::message_deleted(app, conversation_id, &assistant_msg_id);
+        return Err(e);
+    }
 
-    // Save assistant message to DB
-    let assistant_msg_id = uuid::Uuid::new_v4().to_string();
     let final_content = accumulated.lock().unwrap().clone();
     let final_tokens = *total_tokens.lock().unwrap();
+    record_token_usage(
+        db,
+        provider_id,
+        &usage_day(chrono::Local::now()),
+        final_tokens,
+    );
 
-    db.create_message(
+    db.replace_message_content(
         &assistant_msg_id,
-        &req.conversation_id,
-        "assistant",
         &final_content,
-        Some(&req.model),
+        Some(&config.model),
         if final_tokens > 0 {
             Some(final_tokens)
         } else {
             None
         },
-        sort_order + 1,
     )
     .map_err(|e| e.to_string())?;
+    if let Err(e) = db.set_message_metrics(&assistant_msg_id, &metrics) {
+        tracing::warn!("[stream] failed to save stream metrics: {}", e);
+    }
+
+    let tool_calls = std::mem::take(&mut *tool_calls.lock().unwrap());
+    if !tool_calls.is_empty() {
+        db.set_message_metadata(
+            &assistant_msg_id,
+            &serde_json::json!({ "toolCalls": tool_calls }),
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    events::message_updated(app, conversation_id, &assistant_msg_id);
+
+    Ok((assistant_msg_id, final_content))
+}
+
+/// Title the conversation once it reaches `auto_title_after_messages`
+/// exchanges, unless the user has renamed it. `history` is the conversation
+/// up to and including the user message that `reply` answers.
+async fn auto_title(
+    app: &tauri::AppHandle,
+    db: &Database,
+    settings: &db::AppSettings,
+    req: &SendMessageRequest,
+    history: &[db::Message],
+    reply: &str,
+) -> Result<(), String> {
+    if apply_auto_title(db, settings, req, history, reply).await? {
+        events::conversation_updated(app, &req.conversation_id);
+    }
+    Ok(())
+}
+
+/// `auto_title` without the event. Returns whether the title changed.
+async fn apply_auto_title(
+    db: &Database,
+    settings: &db::AppSettings,
+    req: &SendMessageRequest,
+    history: &[db::Message],
+    reply: &str,
+) -> Result<bool, String> {
+    let mode = AutoTitleMode::parse(&settings.auto_title_mode)?;
+    let exchanges = history.iter().filter(|m| m.role == "user").count() as i64;
+    if mode == AutoTitleMode::Off || exchanges != settings.auto_title_after_messages.max(1) {
+        return Ok(false);
+    }
+    if db
+        .is_title_manual(&req.conversation_id)
+        .map_err(|e| e.to_string())?
+    {
+        return Ok(false);
+    }
+    let Some(first_user) = history.iter().find(|m| m.role == "user") else {
+        return Ok(false);
+    };
+
+    let title = match mode {
+        AutoTitleMode::Llm => {
+            llm_title(db, &req.provider_id, &req.model, &first_user.content, reply).await?
+        }
+        _ => truncate_chars(first_user.content.trim(), TITLE_MAX_CHARS),
+    };
+    if title.is_empty() {
+        return Ok(false);
+    }
+    db.set_auto_title(&req.conversation_id, &title)
+        .map_err(|e| e.to_string())
+}
+
+/// Attach a file to a message after checking its size against the
+/// `max_attachment_bytes` setting and, for images, that `model` on the given
+/// provider accepts image input. Size and MIME type come from the file.
+#[tauri::command]
+pub fn add_attachment(
+    db: State<'_, Database>,
+    message_id: String,
+    file_path: String,
+    mime_type: Option<String>,
+    provider_id: String,
+    model: String,
+) -> Result<db::Attachment, String> {
+    db.get_message(&message_id)
+        .map_err(|e| format!("Message not found: {}", e))?;
+    let provider = db
+        .get_provider(&provider_id)
+        .map_err(|e| format!("Provider not found: {}", e))?;
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+
+    let checked = attachments::check_file(
+        std::path::Path::new(&file_path),
+        mime_type.as_deref(),
+        settings.max_attachment_bytes,
+        providers::supports_vision(&provider.provider_type, &model),
+    )?;
+
+    let attachment = db::Attachment {
+        id: uuid::Uuid::new_v4().to_string(),
+        message_id,
+        file_name: checked.file_name,
+        file_path,
+        mime_type: checked.mime_type,
+        file_size: Some(checked.file_size),
+        created_at: chrono::Utc::now().timestamp_millis(),
+    };
+    db.create_attachment(&attachment)
+        .map_err(|e| e.to_string())?;
+    Ok(attachment)
+}
+
+/// Generate a new reply for one assistant message from the history before
+/// it, replacing its content in place. Later messages are left untouched.
+#[tauri::command]
+pub async fn regenerate_message(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    message_id: String,
+    on_event: Channel<StreamEvent>,
+) -> Result<(), String> {
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let (conversation, chat_messages) = regeneration_request(&db, &message_id, &settings)?;
+
+    let provider = db
+        .get_provider(&conversation.provider_id)
+        .map_err(|e| format!("Failed to load provider: {}", e))?;
+    check_token_budget(&db, &provider, &usage_day(chrono::Local::now()))?;
+    let mut config = provider_config(&db, provider, conversation.model.clone(), &settings);
+    if settings.log_raw_requests {
+        config.transcript = open_transcript(&app, &conversation.id);
+    }
+    config.temperature = conversation.temperature;
+
+    let mut content = String::new();
+    let mut total_tokens = 0i64;
+    let mut failed = false;
+    let mut timer = providers::StreamTimer::start(std::time::Instant::now());
+    providers::stream_chat(&config, &chat_messages, |event| {
+        timer.observe(&event, std::time::Instant::now());
+        match &event {
+            StreamEvent::Delta { content: delta } => content.push_str(delta),
+            StreamEvent::Done {
+                total_tokens: tokens,
+            } => total_tokens = *tokens,
+            StreamEvent::Error { .. } => failed = true,
+            StreamEvent::StreamingDisabled { .. } => {
+                disable_streaming(&app, &db, &conversation.provider_id)
+            }
+            _ => {}
+        }
+        let _ = on_event.send(event);
+    })
+    .await?;
+    let metrics = timer.finish(std::time::Instant::now(), total_tokens);
+    record_token_usage(
+        &db,
+        &conversation.provider_id,
+        &usage_day(chrono::Local::now()),
+        total_tokens,
+    );
+
+    // Keep the old reply if the new one never produced anything.
+    if failed && content.is_empty() {
+        return Ok(());
+    }
+    db.replace_message_content(
+        &message_id,
+        &content,
+        Some(&conversation.model),
+        (total_tokens > 0).then_some(total_tokens),
+    )
+    .map_err(|e| e.to_string())?;
+    if let Err(e) = db.set_message_metrics(&message_id, &metrics) {
+        tracing::warn!("[stream] failed to save stream metrics: {}", e);
+    }
+    events::message_updated(&app, &conversation.id, &message_id);
 
     Ok(())
 }
 
+/// The conversation of assistant message `message_id` and the history that
+/// led up to it, which is what a regeneration sends.
+fn regeneration_request(
+    db: &Database,
+    message_id: &str,
+    settings: &db::AppSettings,
+) -> Result<(db::Conversation, Vec<ChatMessage>), String> {
+    let target = db
+        .get_message(message_id)
+        .map_err(|e| format!("Message not found: {}", e))?;
+    if target.role != "assistant" {
+        return Err("Only assistant messages can be regenerated".to_string());
+    }
+    let conversation = db
+        .get_conversation(&target.conversation_id)
+        .map_err(|e| e.to_string())?;
+    let history: Vec<db::Message> = db
+        .get_messages(&target.conversation_id)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|m| m.sort_order < target.sort_order)
+        .collect();
+
+    let chat_messages = build_chat_messages(
+        compose_system_prompt(settings, conversation.system_prompt.as_deref()),
+        &history,
+        settings.max_context_messages.max(0) as usize,
+    );
+    providers::ensure_conversation_turn(&chat_messages)?;
+    Ok((conversation, chat_messages))
+}
+
 /// Ask the LLM to generate a short, descriptive conversation title
 /// based on the first user message and assistant reply.
 #[derive(Debug, Deserialize)]
@@ -239,26 +1398,49 @@ pub struct GenerateTitleRequest {
 
 #[tauri::command]
 pub async fn generate_conversation_title(
+    app: tauri::AppHandle,
     db: State<'_, Database>,
     req: GenerateTitleRequest,
 ) -> Result<String, String> {
-    let provider = db
-        .get_provider(&req.provider_id)
-        .map_err(|e| {
-            let msg = e.to_string();
-            if msg.contains("no rows") || msg.contains("Query returned no rows") {
-                "No AI provider configured. Please go to Settings → Providers to add one.".to_string()
-            } else {
-                format!("Failed to load provider: {}", msg)
-            }
-        })?;
+    let title = llm_title(
+        &db,
+        &req.provider_id,
+        &req.model,
+        &req.user_message,
+        &req.assistant_message,
+    )
+    .await?;
 
-    let config = ProviderConfig {
-        provider_type: provider.provider_type,
-        api_key: provider.api_key,
-        base_url: provider.base_url,
-        model: req.model,
-    };
+    // Only update if the user hasn't renamed the conversation.
+    if db
+        .set_auto_title(&req.conversation_id, &title)
+        .map_err(|e| e.to_string())?
+    {
+        events::conversation_updated(&app, &req.conversation_id);
+    }
+
+    Ok(title)
+}
+
+/// Ask `model` for a short title summarising one exchange.
+async fn llm_title(
+    db: &Database,
+    provider_id: &str,
+    model: &str,
+    user_message: &str,
+    assistant_message: &str,
+) -> Result<String, String> {
+    let provider = db.get_provider(provider_id).map_err(|e| {
+        let msg = e.to_string();
+        if msg.contains("no rows") || msg.contains("Query returned no rows") {
+            "No AI provider configured. Please go to Settings → Providers to add one.".to_string()
+        } else {
+            format!("Failed to load provider: {}", msg)
+        }
+    })?;
+
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let config = provider_config(db, provider, model.to_string(), &settings);
 
     let chat_messages = vec![
         ChatMessage {
@@ -269,9 +1451,9 @@ pub async fn generate_conversation_title(
             role: "user".to_string(),
             content: format!(
                 "User: {}\n\nAssistant: {}",
-                req.user_message,
+                user_message,
                 // Truncate long assistant replies to save tokens (char-safe)
-                &req.assistant_message.chars().take(300).collect::<String>()
+                &assistant_message.chars().take(300).collect::<String>()
             ),
         },
     ];
@@ -290,405 +1472,4404 @@ pub async fn generate_conversation_title(
     if title.is_empty() {
         return Err("LLM returned an empty title".to_string());
     }
-
-    // Only update if the current title is still the default to avoid
-    // overwriting user-renamed conversations.
-    let current = db
-        .get_conversation(&req.conversation_id)
-        .map_err(|e| e.to_string())?;
-    if current.title.is_empty() || current.title == "New Chat" {
-        db.update_conversation_title(&req.conversation_id, &title)
-            .map_err(|e| e.to_string())?;
-    }
-
     Ok(title)
 }
 
-// ============================================
-// Provider Commands
-// ============================================
+/// Titles `backfill_titles` generates at once for any one provider.
+const TITLE_BACKFILL_CONCURRENCY: usize = 2;
 
-#[tauri::command]
-pub fn list_providers(db: State<'_, Database>) -> Result<Vec<db::Provider>, String> {
-    db.list_providers().map_err(|e| e.to_string())
+/// Whether a conversation still has the title it was created with: the
+/// placeholder, or its first user message cut short the way the app does or
+/// once did. That is `TITLE_MAX_CHARS` characters plus `…` now; older chats
+/// opened from a selection got 47 bytes plus `...` once over 50 bytes, and
+/// chats started from the input box got its first 50 UTF-16 units.
+pub fn is_default_title(title: &str, first_user_message: &str) -> bool {
+    let title = title.trim();
+    let message = first_user_message.trim();
+    let legacy_selection = if first_user_message.len() > 50 {
+        first_user_message
+            .get(..47)
+            .map(|head| format!("{}...", head))
+    } else {
+        Some(first_user_message.to_string())
+    };
+    let legacy_input: Vec<u16> = message.encode_utf16().take(50).collect();
+    title.is_empty()
+        || title == "New Chat"
+        || title == truncate_chars(message, TITLE_MAX_CHARS)
+        || legacy_selection.is_some_and(|legacy| title == legacy.trim())
+        || title == String::from_utf16_lossy(&legacy_input).trim()
 }
 
-#[tauri::command]
-pub fn save_provider(db: State<'_, Database>, provider: db::Provider) -> Result<(), String> {
-    db.save_provider(&provider).map_err(|e| e.to_string())
-}
+/// A conversation to retitle, with its first user and assistant messages.
+type TitleCandidate = (db::Conversation, String, String);
+
+/// Generate LLM titles for every unarchived conversation that still has a
+/// default title and isn't empty. Providers are worked on in parallel, each
+/// with at most `TITLE_BACKFILL_CONCURRENCY` requests in flight. Returns the
+/// ids of the conversations retitled; failures are logged and skipped.
+pub async fn backfill_conversation_titles(db: &Database) -> Result<Vec<String>, String> {
+    use futures::StreamExt;
+
+    let mut by_provider: std::collections::HashMap<String, Vec<TitleCandidate>> =
+        std::collections::HashMap::new();
+    for conversation in db.list_conversations().map_err(|e| e.to_string())? {
+        if db
+            .is_title_manual(&conversation.id)
+            .map_err(|e| e.to_string())?
+        {
+            continue;
+        }
+        let messages = db
+            .get_messages(&conversation.id)
+            .map_err(|e| e.to_string())?;
+        let first = |role: &str| {
+            messages
+                .iter()
+                .find(|m| m.role == role)
+                .map(|m| m.content.clone())
+        };
+        let Some(user) = first("user") else {
+            continue;
+        };
+        if is_default_title(&conversation.title, &user) {
+            let assistant = first("assistant").unwrap_or_default();
+            by_provider
+                .entry(conversation.provider_id.clone())
+                .or_default()
+                .push((conversation, user, assistant));
+        }
+    }
+
+    let retitle = |(conversation, user, assistant): TitleCandidate| async move {
+        let title = llm_title(
+            db,
+            &conversation.provider_id,
+            &conversation.model,
+            &user,
+            &assistant,
+        )
+        .await;
+        let set = title.and_then(|title| {
+            db.set_auto_title(&conversation.id, &title)
+                .map_err(|e| e.to_string())
+        });
+        match set {
+            Ok(true) => Some(conversation.id),
+            Ok(false) => None,
+            Err(e) => {
+                tracing::warn!("[title] backfill failed for {}: {}", conversation.id, e);
+                None
+            }
+        }
+    };
+    let per_provider = by_provider.into_values().map(|candidates| {
+        futures::stream::iter(candidates)
+            .map(retitle)
+            .buffer_unordered(TITLE_BACKFILL_CONCURRENCY)
+            .collect::<Vec<_>>()
+    });
+    Ok(futures::future::join_all(per_provider)
+        .await
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect())
+}
+
+/// Title every conversation still named "New Chat" or after its truncated
+/// first message. Returns how many were retitled.
+#[tauri::command]
+pub async fn backfill_titles(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+) -> Result<usize, String> {
+    let retitled = backfill_conversation_titles(&db).await?;
+    for id in &retitled {
+        events::conversation_updated(&app, id);
+    }
+    Ok(retitled.len())
+}
+
+const SUMMARY_SYSTEM_PROMPT: &str = "Summarize the conversation below so it can replace the full transcript as context for continuing it. Keep every fact, decision, open question and piece of code or data the user may refer back to. Write in the conversation's language, as compact prose or bullet points. Output ONLY the summary.";
+
+/// Messages asking the model to summarize `messages`. Only the history since
+/// the latest summary is sent; that summary is included as its starting point.
+fn summary_request(messages: &[db::Message]) -> Vec<ChatMessage> {
+    let transcript = history_since_summary(messages)
+        .iter()
+        .filter(|msg| msg.include_in_context)
+        .map(|msg| {
+            let speaker = if is_summary(msg) {
+                "Earlier summary"
+            } else {
+                match msg.role.as_str() {
+                    "user" => "User",
+                    "assistant" => "Assistant",
+                    _ => "System",
+                }
+            };
+            format!("{}: {}", speaker, msg.content.trim())
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    vec![
+        ChatMessage {
+            role: "system".to_string(),
+            content: SUMMARY_SYSTEM_PROMPT.to_string(),
+        },
+        ChatMessage {
+            role: "user".to_string(),
+            content: transcript,
+        },
+    ]
+}
+
+/// Summarize a conversation with its own provider and model. With `insert`,
+/// the summary is saved as an assistant message tagged `{"summary": true}`;
+/// later `send_message` calls send it in place of all earlier turns, which
+/// stay visible in the transcript.
+#[tauri::command]
+pub async fn summarize_conversation(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    conversation_id: String,
+    insert: Option<bool>,
+) -> Result<String, String> {
+    let conversation = db
+        .get_conversation(&conversation_id)
+        .map_err(|e| format!("Conversation not found: {}", e))?;
+    let messages = db
+        .get_messages(&conversation_id)
+        .map_err(|e| e.to_string())?;
+    let chat_messages = summary_request(&messages);
+    providers::ensure_conversation_turn(&chat_messages)?;
+
+    let provider = db
+        .get_provider(&conversation.provider_id)
+        .map_err(|e| format!("Failed to load provider: {}", e))?;
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let config = provider_config(&db, provider, conversation.model.clone(), &settings);
+
+    let mut summary = String::new();
+    let mut error = None;
+    providers::stream_chat(&config, &chat_messages, |event| match event {
+        StreamEvent::Delta { content } => summary.push_str(&content),
+        StreamEvent::Error { message, .. } => error = Some(message),
+        _ => {}
+    })
+    .await?;
+    if let Some(message) = error {
+        return Err(message);
+    }
+
+    let summary = summary.trim().to_string();
+    if summary.is_empty() {
+        return Err("LLM returned an empty summary".to_string());
+    }
+
+    if insert.unwrap_or(false) {
+        let message_id = insert_summary(&db, &conversation, &summary)?;
+        events::message_added(&app, &conversation_id, &message_id);
+    }
+
+    Ok(summary)
+}
+
+/// Append `summary` to the conversation as a tagged assistant message and
+/// return its id.
+fn insert_summary(
+    db: &Database,
+    conversation: &db::Conversation,
+    summary: &str,
+) -> Result<String, String> {
+    let message_id = uuid::Uuid::new_v4().to_string();
+    let sort_order = db.get_message_count(&conversation.id).unwrap_or(0);
+    db.create_message(
+        &message_id,
+        &conversation.id,
+        "assistant",
+        summary,
+        Some(&conversation.model),
+        None,
+        sort_order,
+    )
+    .map_err(|e| e.to_string())?;
+    db.set_message_metadata(&message_id, &serde_json::json!({ "summary": true }))
+        .map_err(|e| e.to_string())?;
+    Ok(message_id)
+}
+
+// ============================================
+// Provider Commands
+// ============================================
+
+/// Config for the default provider with `model`, falling back to the
+/// provider's default model and then the global one. Used by the local API.
+pub fn default_provider_config(
+    db: &Database,
+    model: Option<String>,
+) -> Result<ProviderConfig, String> {
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let provider = db
+        .get_provider(&settings.default_provider_id)
+        .map_err(|e| format!("Failed to load the default provider: {}", e))?;
+    let model = model
+        .filter(|s| !s.is_empty())
+        .or_else(|| provider.default_model.clone().filter(|s| !s.is_empty()))
+        .unwrap_or_else(|| settings.default_model.clone());
+    Ok(provider_config(db, provider, model, &settings))
+}
+
+#[tauri::command]
+pub fn list_providers(db: State<'_, Database>) -> Result<Vec<db::Provider>, String> {
+    db.list_providers().map_err(|e| e.to_string())
+}
+
+/// Save a provider and return advisory warnings about its base URL (see
+/// `providers::base_url_warnings`). With `strict`, warnings refuse the save.
+#[tauri::command]
+pub fn save_provider(
+    db: State<'_, Database>,
+    provider: db::Provider,
+    strict: Option<bool>,
+) -> Result<Vec<String>, String> {
+    validate_provider(&provider)?;
+    let warnings =
+        providers::base_url_warnings(&provider.provider_type, provider.base_url.as_deref());
+    if strict.unwrap_or(false) && !warnings.is_empty() {
+        return Err(warnings.join("; "));
+    }
+    forget_capabilities(&provider.id);
+    db.save_provider(&provider).map_err(|e| e.to_string())?;
+    for warning in &warnings {
+        tracing::warn!("[providers] {}: {}", provider.id, warning);
+    }
+    Ok(warnings)
+}
+
+/// Checks every path that writes a provider makes first: saving from the
+/// editor, importing a config file and importing shared providers.
+fn validate_provider(provider: &db::Provider) -> Result<(), String> {
+    if !providers::PROVIDER_TYPES.contains(&provider.provider_type.as_str()) {
+        return Err(format!(
+            "Unknown provider type '{}'",
+            provider.provider_type
+        ));
+    }
+    providers::AuthStyle::parse(&provider.auth_style)?;
+    providers::SystemPromptMode::parse(&provider.system_prompt_mode)?;
+    if provider.default_max_tokens.is_some_and(|n| n <= 0) {
+        return Err("Default max tokens must be a positive number".to_string());
+    }
+    if provider.daily_token_cap.is_some_and(|n| n <= 0) {
+        return Err("Daily token cap must be a positive number".to_string());
+    }
+    providers::validate_custom_headers(&provider.custom_headers)?;
+    if provider.extra_body.as_ref().is_some_and(|v| !v.is_object()) {
+        return Err("Extra body must be a JSON object".to_string());
+    }
+    Ok(())
+}
+
+/// Fix a provider created with the wrong type without losing its history.
+/// Windows are told to drop any model list fetched under the old type.
+#[tauri::command]
+pub fn change_provider_type(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    id: String,
+    new_type: String,
+) -> Result<db::Provider, String> {
+    let new_type = new_type.trim();
+    if !providers::PROVIDER_TYPES.contains(&new_type) {
+        return Err(format!(
+            "Unknown provider type '{}'. Expected one of: {}",
+            new_type,
+            providers::PROVIDER_TYPES.join(", ")
+        ));
+    }
+    forget_capabilities(&id);
+    let provider = db
+        .update_provider_type(&id, new_type)
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => format!("Provider not found: {}", id),
+            e => e.to_string(),
+        })?;
+    tracing::info!("[providers] {} is now type {}", id, new_type);
+    events::provider_updated(&app, &id);
+    Ok(provider)
+}
 
 #[tauri::command]
 pub fn delete_provider(db: State<'_, Database>, id: String) -> Result<(), String> {
     db.delete_provider(&id).map_err(|e| e.to_string())
 }
 
+/// Delete a provider and all conversations (with their messages) that use it.
+#[tauri::command]
+pub fn delete_provider_with_data(
+    db: State<'_, Database>,
+    id: String,
+) -> Result<db::DeletionSummary, String> {
+    db.delete_provider_with_data(&id).map_err(|e| e.to_string())
+}
+
+/// Why a connection test failed (or `Ok`), so the UI can point the user at
+/// the right fix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConnectionTestReason {
+    Ok,
+    /// The key was rejected (401/403) or is missing.
+    AuthFailed,
+    /// The server could not be reached at all.
+    Unreachable,
+    /// The server rejected the model (400/404).
+    BadModel,
+    /// Any other failure; see `error`.
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionTestResult {
+    pub success: bool,
+    pub reason: ConnectionTestReason,
+    pub error: Option<String>,
+    /// Time until the first response byte (or the failure), in milliseconds.
+    pub latency_ms: u64,
+    pub model: String,
+    /// Streaming failed but a buffered request worked.
+    pub suggest_disable_streaming: bool,
+}
+
+/// Model used when the provider has no default of its own.
+const CONNECTION_TEST_FALLBACK_MODEL: &str = "gpt-4o-mini";
+
+/// Reason for a failed connection test. `kind` is known when the error came
+/// as a stream event; other errors are recognized from their message.
+fn classify_connection_error(message: &str, kind: &StreamErrorKind) -> ConnectionTestReason {
+    match kind {
+        StreamErrorKind::Connection | StreamErrorKind::Stalled => {
+            return ConnectionTestReason::Unreachable
+        }
+        StreamErrorKind::NotEventStream { .. } => return ConnectionTestReason::Other,
+        StreamErrorKind::Status { .. } | StreamErrorKind::Other => {}
+    }
+    match kind.status().or_else(|| providers::http_status_in(message)) {
+        Some(401 | 403) => return ConnectionTestReason::AuthFailed,
+        Some(400 | 404) => return ConnectionTestReason::BadModel,
+        Some(_) => return ConnectionTestReason::Other,
+        None => {}
+    }
+    let lower = message.to_lowercase();
+    if lower.contains("api key not configured") {
+        ConnectionTestReason::AuthFailed
+    } else if lower.contains("error sending request") || lower.contains("failed to connect") {
+        ConnectionTestReason::Unreachable
+    } else {
+        ConnectionTestReason::Other
+    }
+}
+
 #[tauri::command]
 pub async fn test_provider_connection(
     db: State<'_, Database>,
     id: String,
-) -> Result<serde_json::Value, String> {
-    let provider = db.get_provider(&id).map_err(|e| e.to_string())?;
+) -> Result<ConnectionTestResult, String> {
+    connection_test(&db, &id).await
+}
 
-    let config = ProviderConfig {
-        provider_type: provider.provider_type,
-        api_key: provider.api_key,
-        base_url: provider.base_url,
-        model: "gpt-4o-mini".to_string(), // Use a cheap model for testing
-    };
+async fn connection_test(db: &Database, id: &str) -> Result<ConnectionTestResult, String> {
+    let provider = db.get_provider(id).map_err(|e| e.to_string())?;
+
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let model = provider
+        .default_model
+        .clone()
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| CONNECTION_TEST_FALLBACK_MODEL.to_string());
+    let config = provider_config(db, provider, model.clone(), &settings);
 
     let test_messages = vec![ChatMessage {
         role: "user".to_string(),
         content: "Hello".to_string(),
     }];
 
+    let started = std::time::Instant::now();
+    let mut latency = None;
     let mut got_response = false;
-    providers::stream_chat(&config, &test_messages, |event| {
-        if matches!(event, StreamEvent::Delta { .. }) {
+    let mut streaming_rejected = false;
+    let mut stream_error = None;
+    let mut error_kind = StreamErrorKind::Other;
+    let result = providers::stream_chat(&config, &test_messages, |event| match event {
+        StreamEvent::Delta { .. } => {
+            latency.get_or_insert_with(|| started.elapsed());
             got_response = true;
         }
+        StreamEvent::Error { message, kind } => {
+            stream_error = Some(message);
+            error_kind = kind;
+        }
+        StreamEvent::StreamingDisabled { .. } => streaming_rejected = true,
+        _ => {}
     })
-    .await?;
+    .await;
+    if let Err(e) = result {
+        stream_error.get_or_insert(e);
+    }
+    let latency_ms = latency.unwrap_or_else(|| started.elapsed()).as_millis() as u64;
 
-    if got_response {
-        Ok(serde_json::json!({"success": true}))
-    } else {
-        Ok(serde_json::json!({"success": false, "error": "No response received"}))
+    let outcome = |reason, error: Option<String>, suggest_disable_streaming| ConnectionTestResult {
+        success: reason == ConnectionTestReason::Ok,
+        reason,
+        error,
+        latency_ms,
+        model: model.clone(),
+        suggest_disable_streaming,
+    };
+
+    if got_response && !streaming_rejected {
+        return Ok(outcome(ConnectionTestReason::Ok, None, false));
+    }
+
+    // If streaming failed, see whether a buffered request gets through; some
+    // OpenAI-compatible gateways reject `stream: true`. `stream_chat` has
+    // already retried buffered when the error said so.
+    let mut buffered_ok = got_response;
+    if !buffered_ok
+        && config.supports_streaming
+        && providers::is_openai_compatible(&config.provider_type)
+    {
+        let buffered = ProviderConfig {
+            supports_streaming: false,
+            ..config.clone()
+        };
+        let _ = providers::stream_chat(&buffered, &test_messages, |event| {
+            if matches!(event, StreamEvent::Delta { .. }) {
+                buffered_ok = true;
+            }
+        })
+        .await;
+    }
+    if buffered_ok {
+        return Ok(outcome(
+            ConnectionTestReason::Other,
+            Some("Streaming request failed, but a non-streaming request succeeded. Turn off streaming support for this provider.".to_string()),
+            true,
+        ));
     }
+
+    let error = stream_error.unwrap_or_else(|| "No response received".to_string());
+    Ok(outcome(
+        classify_connection_error(&error, &error_kind),
+        Some(error),
+        false,
+    ))
 }
 
+/// Models offered by a provider, ordered by `sort` (id by default).
+/// `favorites_first` is the older spelling of `sort: favoritesFirst`.
 #[tauri::command]
 pub async fn list_models(
     db: State<'_, Database>,
     provider_id: String,
+    dedupe: Option<bool>,
+    favorites_first: Option<bool>,
+    sort: Option<providers::ModelSort>,
 ) -> Result<Vec<ModelInfo>, String> {
-    eprintln!("[list_models] Called for provider_id={}", provider_id);
+    model_listing(&db, provider_id, dedupe, favorites_first, sort)
+        .await
+        .map(|listing| listing.models)
+}
 
-    // Write debug early
-    let _ = std::fs::write("/tmp/zitong_debug.txt", format!("list_models called: provider_id={}", provider_id));
+/// `list_models`, plus how many models the API returned before filtering and
+/// a warning when nothing is left, so the UI can explain an empty list.
+#[tauri::command]
+pub async fn list_models_with_metadata(
+    db: State<'_, Database>,
+    provider_id: String,
+    dedupe: Option<bool>,
+    favorites_first: Option<bool>,
+    sort: Option<providers::ModelSort>,
+) -> Result<providers::ModelListing, String> {
+    model_listing(&db, provider_id, dedupe, favorites_first, sort).await
+}
 
-    let provider = db
-        .get_provider(&provider_id)
-        .map_err(|e| {
-            let msg = format!("Provider not found: {}", e);
-            eprintln!("[list_models] {}", msg);
-            let _ = std::fs::write("/tmp/zitong_debug.txt", format!("ERR at get_provider: {}", msg));
-            msg
-        })?;
+async fn model_listing(
+    db: &Database,
+    provider_id: String,
+    dedupe: Option<bool>,
+    favorites_first: Option<bool>,
+    sort: Option<providers::ModelSort>,
+) -> Result<providers::ModelListing, String> {
+    tracing::debug!("[list_models] Called for provider_id={}", provider_id);
 
-    eprintln!("[list_models] Found provider type={}, has_key={}", provider.provider_type, provider.api_key.is_some());
-    let config = ProviderConfig {
-        provider_type: provider.provider_type,
-        api_key: provider.api_key,
-        base_url: provider.base_url,
-        model: String::new(),
-    };
+    let provider = db.get_provider(&provider_id).map_err(|e| {
+        let msg = format!("Provider not found: {}", e);
+        tracing::warn!("[list_models] {}", msg);
+        msg
+    })?;
+
+    tracing::debug!(
+        "[list_models] Found provider type={}, has_key={}",
+        provider.provider_type,
+        provider.api_key.is_some()
+    );
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let config = provider_config(db, provider, String::new(), &settings);
 
-    let result = providers::list_provider_models(&config).await;
+    let sort = sort.unwrap_or(if favorites_first.unwrap_or(false) {
+        providers::ModelSort::FavoritesFirst
+    } else {
+        providers::ModelSort::IdAsc
+    });
+    let favorites = db.list_favorite_models(&provider_id).unwrap_or_default();
+    let result = providers::list_provider_models_detailed(&config, dedupe.unwrap_or(false))
+        .await
+        .map(|mut listing| {
+            for model in &mut listing.models {
+                model.is_favorite = favorites.contains(&model.id);
+            }
+            providers::sort_models(&mut listing.models, sort);
+            listing
+        });
     match &result {
-        Ok(models) => {
-            let msg = format!("OK: {} models: {:?}", models.len(), models.iter().map(|m| &m.id).collect::<Vec<_>>());
-            eprintln!("[list_models] {}", msg);
-            let _ = std::fs::write("/tmp/zitong_debug.txt", msg);
-        }
-        Err(e) => {
-            eprintln!("[list_models] Error: {}", e);
-            let _ = std::fs::write("/tmp/zitong_debug.txt", format!("ERR: {}", e));
-        }
+        Ok(listing) => tracing::debug!(
+            "[list_models] OK: {} of {} models: {:?}",
+            listing.models.len(),
+            listing.fetched_count,
+            listing.models.iter().map(|m| &m.id).collect::<Vec<_>>()
+        ),
+        Err(e) => tracing::warn!("[list_models] Error: {}", e),
     }
     result
 }
 
-// ============================================
-// GitHub Copilot OAuth Commands
-// ============================================
+/// One provider's entry in `list_models_for_all`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderModels {
+    pub provider_id: String,
+    pub models: Vec<ModelInfo>,
+    /// Why the list couldn't be fetched; `models` is then empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
 
-#[tauri::command]
-pub async fn copilot_start_device_flow() -> Result<DeviceCodeResponse, String> {
-    providers::copilot_start_device_flow().await
+/// Model lists older than this are refreshed when the main window gains
+/// focus (with `auto_refresh_models_on_focus`).
+pub const MODEL_REFRESH_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// When the last refresh of every provider's model list started.
+static MODELS_REFRESHED_AT: std::sync::Mutex<Option<std::time::Instant>> =
+    std::sync::Mutex::new(None);
+
+/// Whether model lists last refreshed at `last` (`None`: never) are older
+/// than `max_age` at `now`.
+pub fn models_refresh_due(
+    last: Option<std::time::Instant>,
+    now: std::time::Instant,
+    max_age: std::time::Duration,
+) -> bool {
+    last.is_none_or(|last| now.saturating_duration_since(last) >= max_age)
 }
 
-#[tauri::command]
-pub async fn copilot_poll_auth(device_code: String) -> Result<String, String> {
-    providers::copilot_poll_auth(&device_code).await
+/// Record a refresh starting at `now` if one is due. Focus events arriving
+/// while it runs see a fresh timestamp, so at most one refresh starts.
+fn claim_models_refresh(now: std::time::Instant) -> bool {
+    let mut last = MODELS_REFRESHED_AT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if !models_refresh_due(*last, now, MODEL_REFRESH_MAX_AGE) {
+        return false;
+    }
+    *last = Some(now);
+    true
+}
+
+/// Fetch model lists for every enabled provider in parallel. Providers that
+/// offline mode can't reach are skipped; one that fails gets an `error`
+/// instead of failing the rest.
+async fn refresh_all_models(db: &Database) -> Result<Vec<ProviderModels>, String> {
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let configs: Vec<(String, ProviderConfig)> = db
+        .list_providers()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|p| p.enabled)
+        .map(|p| {
+            (
+                p.id.clone(),
+                provider_config(db, p, String::new(), &settings),
+            )
+        })
+        .filter(|(_, config)| !config.offline || providers::is_local_provider(config))
+        .collect();
+
+    let fetches = configs.iter().map(|(provider_id, config)| async move {
+        let favorites = db.list_favorite_models(provider_id).unwrap_or_default();
+        match providers::list_provider_models(config, false).await {
+            Ok(mut models) => {
+                for model in &mut models {
+                    model.is_favorite = favorites.contains(&model.id);
+                }
+                ProviderModels {
+                    provider_id: provider_id.clone(),
+                    models,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                tracing::warn!("[models] refresh failed for {}: {}", provider_id, e);
+                ProviderModels {
+                    provider_id: provider_id.clone(),
+                    models: Vec::new(),
+                    error: Some(e),
+                }
+            }
+        }
+    });
+    Ok(futures::future::join_all(fetches).await)
 }
 
+/// Model lists for every enabled provider (see `refresh_all_models`). Also
+/// broadcast as `models-updated` so other windows pick up the new lists.
 #[tauri::command]
-pub async fn copilot_exchange_token(github_token: String) -> Result<serde_json::Value, String> {
-    let (copilot_token, base_url) = providers::copilot_exchange_token(&github_token).await?;
-    Ok(serde_json::json!({
-        "token": copilot_token,
-        "baseUrl": base_url,
-    }))
+pub async fn list_models_for_all(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+) -> Result<Vec<ProviderModels>, String> {
+    *MODELS_REFRESHED_AT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = Some(std::time::Instant::now());
+    let refreshed = refresh_all_models(&db).await?;
+    events::models_updated(&app, &refreshed);
+    Ok(refreshed)
 }
 
-// ============================================
-// Settings Commands
-// ============================================
+/// Main window focus hook: with `auto_refresh_models_on_focus` on, refresh
+/// every model list in the background once they're older than
+/// `MODEL_REFRESH_MAX_AGE`, then emit `models-updated`.
+pub fn refresh_models_on_focus(app: &tauri::AppHandle) {
+    let enabled = app
+        .state::<Database>()
+        .get_settings()
+        .map(|s| s.auto_refresh_models_on_focus)
+        .unwrap_or(false);
+    if !enabled || !claim_models_refresh(std::time::Instant::now()) {
+        return;
+    }
+    tracing::debug!("[models] model lists are stale, refreshing on focus");
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        match refresh_all_models(&app.state::<Database>()).await {
+            Ok(refreshed) => events::models_updated(&app, &refreshed),
+            Err(e) => tracing::warn!("[models] focus refresh failed: {}", e),
+        }
+    });
+}
 
-#[tauri::command]
-pub fn get_settings(db: State<'_, Database>) -> Result<db::AppSettings, String> {
-    db.get_settings().map_err(|e| e.to_string())
+/// Whether the default provider and model in settings can actually be used.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DefaultsStatus {
+    pub ok: bool,
+    /// Actionable descriptions of what's wrong, for display to the user.
+    pub problems: Vec<String>,
+    /// False when the provider's model list couldn't be fetched, so the
+    /// default model wasn't checked.
+    pub model_checked: bool,
+}
+
+/// Check `default_provider_id` and the default model against the configured
+/// providers. The model is the provider's own default when it has one, else
+/// `default_model`, as when starting a chat. A provider whose model list
+/// can't be fetched (offline, no key) leaves the model unchecked rather than
+/// reporting it as invalid.
+pub async fn defaults_status(db: &Database) -> Result<DefaultsStatus, String> {
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let mut status = DefaultsStatus::default();
+    let provider_id = settings.default_provider_id.trim();
+
+    let provider = if provider_id.is_empty() {
+        status
+            .problems
+            .push("No default provider is set. Choose one in Settings → Providers.".to_string());
+        None
+    } else {
+        match db.get_provider(provider_id) {
+            Ok(provider) if !provider.enabled => {
+                status.problems.push(format!(
+                    "The default provider \"{}\" is disabled. Enable it in Settings → Providers or choose another default.",
+                    provider.name
+                ));
+                None
+            }
+            Ok(provider) => Some(provider),
+            Err(_) => {
+                status.problems.push(format!(
+                    "The default provider \"{}\" isn't configured. Add it in Settings → Providers or choose another default.",
+                    provider_id
+                ));
+                None
+            }
+        }
+    };
+
+    if let Some(provider) = provider {
+        let name = provider.name.clone();
+        let model = provider
+            .default_model
+            .clone()
+            .filter(|m| !m.trim().is_empty())
+            .unwrap_or_else(|| settings.default_model.clone());
+        let model = model.trim();
+        if model.is_empty() {
+            status.problems.push(format!(
+                "No default model is set for {}. Set one in Settings → Providers.",
+                name
+            ));
+        } else {
+            let config = provider_config(db, provider, String::new(), &settings);
+            match providers::list_provider_models(&config, false).await {
+                Ok(models) => {
+                    status.model_checked = true;
+                    if !models.iter().any(|m| m.id == model) {
+                        status.problems.push(format!(
+                            "The default model \"{}\" isn't offered by {}. Choose another default model in Settings → Providers.",
+                            model, name
+                        ));
+                    }
+                }
+                Err(e) => tracing::debug!("[defaults] couldn't list models for {}: {}", name, e),
+            }
+        }
+    }
+
+    status.ok = status.problems.is_empty();
+    Ok(status)
 }
 
 #[tauri::command]
-pub fn save_settings(db: State<'_, Database>, settings: db::AppSettings) -> Result<(), String> {
-    db.save_settings(&settings).map_err(|e| e.to_string())
+pub async fn validate_defaults(db: State<'_, Database>) -> Result<DefaultsStatus, String> {
+    let status = defaults_status(&db).await?;
+    for problem in &status.problems {
+        tracing::warn!("[defaults] {}", problem);
+    }
+    Ok(status)
 }
 
-// ============================================
-// Prompt Template Commands
-// ============================================
+/// `probe_capabilities` results by (provider id, model), dropped when the
+/// provider is saved.
+type CapabilityCache = std::collections::HashMap<(String, String), providers::Capabilities>;
+
+static CAPABILITIES: std::sync::OnceLock<std::sync::Mutex<CapabilityCache>> =
+    std::sync::OnceLock::new();
+
+fn capability_cache() -> std::sync::MutexGuard<'static, CapabilityCache> {
+    CAPABILITIES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
 
+fn forget_capabilities(provider_id: &str) {
+    capability_cache().retain(|(id, _), _| id != provider_id);
+}
+
+/// Probe what `model` on a provider supports. Results are cached until the
+/// provider is changed; `refresh` probes again regardless.
 #[tauri::command]
-pub fn list_prompt_templates(
+pub async fn probe_capabilities(
     db: State<'_, Database>,
-) -> Result<Vec<db::PromptTemplate>, String> {
-    db.list_prompt_templates().map_err(|e| e.to_string())
+    provider_id: String,
+    model: String,
+    refresh: Option<bool>,
+) -> Result<providers::Capabilities, String> {
+    let key = (provider_id.clone(), model.clone());
+    if !refresh.unwrap_or(false) {
+        if let Some(cached) = capability_cache().get(&key) {
+            return Ok(cached.clone());
+        }
+    }
+
+    let provider = db
+        .get_provider(&provider_id)
+        .map_err(|e| format!("Provider not found: {}", e))?;
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let config = provider_config(&db, provider, model, &settings);
+    let capabilities = providers::probe_capabilities(&config).await?;
+    capability_cache().insert(key, capabilities.clone());
+    Ok(capabilities)
 }
 
 #[tauri::command]
-pub fn save_prompt_template(
+pub fn add_favorite_model(
     db: State<'_, Database>,
-    template: db::PromptTemplate,
+    provider_id: String,
+    model_id: String,
 ) -> Result<(), String> {
-    db.save_prompt_template(&template)
+    db.add_favorite_model(&provider_id, &model_id)
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub fn delete_prompt_template(db: State<'_, Database>, id: String) -> Result<(), String> {
-    db.delete_prompt_template(&id).map_err(|e| e.to_string())
+pub fn remove_favorite_model(
+    db: State<'_, Database>,
+    provider_id: String,
+    model_id: String,
+) -> Result<(), String> {
+    db.remove_favorite_model(&provider_id, &model_id)
+        .map_err(|e| e.to_string())
 }
 
-// ============================================
-// Folder Commands
-// ============================================
-
 #[tauri::command]
-pub fn list_folders(db: State<'_, Database>) -> Result<Vec<db::Folder>, String> {
-    db.list_folders().map_err(|e| e.to_string())
+pub fn list_favorite_models(
+    db: State<'_, Database>,
+    provider_id: String,
+) -> Result<Vec<String>, String> {
+    db.list_favorite_models(&provider_id)
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub fn create_folder(db: State<'_, Database>, name: String) -> Result<db::Folder, String> {
-    let id = uuid::Uuid::new_v4().to_string();
-    db.create_folder(&id, &name).map_err(|e| e.to_string())
+pub fn add_provider_key(
+    db: State<'_, Database>,
+    provider_id: String,
+    key: String,
+) -> Result<db::ProviderKey, String> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Err("API key is empty".to_string());
+    }
+    db.get_provider(&provider_id).map_err(|e| e.to_string())?;
+    db.add_provider_key(&provider_id, key)
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub fn delete_folder(db: State<'_, Database>, id: String) -> Result<(), String> {
-    db.delete_folder(&id).map_err(|e| e.to_string())
+pub fn list_provider_keys(
+    db: State<'_, Database>,
+    provider_id: String,
+) -> Result<Vec<db::ProviderKey>, String> {
+    db.list_provider_keys(&provider_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn remove_provider_key(db: State<'_, Database>, id: String) -> Result<(), String> {
+    db.remove_provider_key(&id).map_err(|e| e.to_string())
 }
 
 // ============================================
-// AI Command Commands
+// Ollama Model Management Commands
 // ============================================
 
-#[tauri::command]
-pub fn list_ai_commands(db: State<'_, Database>) -> Result<Vec<db::AiCommand>, String> {
-    db.list_ai_commands().map_err(|e| e.to_string())
+type PullFlags = std::collections::HashMap<String, std::sync::Arc<std::sync::atomic::AtomicBool>>;
+
+/// Cancellation flags for in-flight pulls, keyed by `provider_id/model`.
+static OLLAMA_PULLS: std::sync::OnceLock<std::sync::Mutex<PullFlags>> = std::sync::OnceLock::new();
+
+fn ollama_pulls() -> std::sync::MutexGuard<'static, PullFlags> {
+    OLLAMA_PULLS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
 }
 
-/// Execute an AI command on the given selected text.
-/// Resolves provider/model from the command overrides or falls back to default settings.
-/// Returns the AI-generated result text.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ExecuteAiCommandRequest {
-    pub selected_text: String,
-    pub system_prompt: String,
-    pub provider_id: Option<String>,
-    pub model: Option<String>,
+/// Load an Ollama provider's config, rejecting other provider types.
+fn ollama_config(db: &Database, provider_id: &str) -> Result<ProviderConfig, String> {
+    let provider = db
+        .get_provider(provider_id)
+        .map_err(|e| format!("Provider not found: {}", e))?;
+    if provider.provider_type != "ollama" {
+        return Err(format!(
+            "Provider '{}' is not an Ollama provider",
+            provider.name
+        ));
+    }
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    Ok(provider_config(db, provider, String::new(), &settings))
 }
 
 #[tauri::command]
-pub async fn execute_ai_command(
+pub async fn ollama_pull_model(
     db: State<'_, Database>,
-    req: ExecuteAiCommandRequest,
-) -> Result<String, String> {
-    // Resolve provider & model — use command overrides or fall back to defaults
-    let settings = db.get_settings().map_err(|e| e.to_string())?;
-    let provider_id = req
-        .provider_id
-        .filter(|s| !s.is_empty())
-        .unwrap_or(settings.default_provider_id);
-    let provider = db
-        .get_provider(&provider_id)
-        .map_err(|e| {
-            let msg = e.to_string();
-            if msg.contains("no rows") || msg.contains("Query returned no rows") {
-                "No AI provider configured. Please go to Settings → Providers to add one.".to_string()
-            } else {
-                format!("Failed to load provider: {}", msg)
-            }
-        })?;
+    provider_id: String,
+    model: String,
+    on_progress: Channel<providers::PullProgress>,
+) -> Result<(), String> {
+    let model = model.trim().to_string();
+    if model.is_empty() {
+        return Err("Model name is required".to_string());
+    }
+    let config = ollama_config(&db, &provider_id)?;
 
-    // Fallback chain: command-level model → provider default_model → global default_model
-    let model = req
-        .model
-        .filter(|s| !s.is_empty())
-        .or_else(|| provider.default_model.clone().filter(|s| !s.is_empty()))
-        .unwrap_or(settings.default_model);
+    let key = format!("{}/{}", provider_id, model);
+    let cancel = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    {
+        let mut pulls = ollama_pulls();
+        if pulls.contains_key(&key) {
+            return Err(format!("'{}' is already being pulled", model));
+        }
+        pulls.insert(key.clone(), cancel.clone());
+    }
 
-    let config = ProviderConfig {
-        provider_type: provider.provider_type,
-        api_key: provider.api_key,
-        base_url: provider.base_url,
-        model: model.clone(),
-    };
+    tracing::info!("[ollama] pulling {} via provider {}", model, provider_id);
+    let result = providers::pull_ollama_model(&config, &model, &cancel, |progress| {
+        let _ = on_progress.send(progress);
+    })
+    .await;
+    ollama_pulls().remove(&key);
 
-    // Build messages: system prompt + user message containing the selected text
-    let mut chat_messages: Vec<ChatMessage> = Vec::new();
-    if !req.system_prompt.is_empty() {
-        chat_messages.push(ChatMessage {
-            role: "system".to_string(),
-            content: req.system_prompt,
-        });
+    if let Err(e) = &result {
+        tracing::warn!("[ollama] pull of {} ended: {}", model, e);
     }
-    chat_messages.push(ChatMessage {
-        role: "user".to_string(),
-        content: req.selected_text,
-    });
+    result
+}
 
-    // Stream the response and accumulate
-    let accumulated = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
-    let accumulated_clone = accumulated.clone();
+#[tauri::command]
+pub async fn ollama_delete_model(
+    db: State<'_, Database>,
+    provider_id: String,
+    model: String,
+) -> Result<(), String> {
+    let config = ollama_config(&db, &provider_id)?;
+    providers::delete_ollama_model(&config, model.trim()).await?;
+    tracing::info!(
+        "[ollama] deleted {} via provider {}",
+        model.trim(),
+        provider_id
+    );
+    Ok(())
+}
 
-    providers::stream_chat(&config, &chat_messages, |event| {
-        if let StreamEvent::Delta { content } = &event {
-            accumulated_clone.lock().unwrap().push_str(content);
+/// Abort an in-flight `ollama_pull_model`. Returns false if no such pull is running.
+#[tauri::command]
+pub fn ollama_cancel_pull(provider_id: String, model: String) -> bool {
+    let key = format!("{}/{}", provider_id, model.trim());
+    match ollama_pulls().get(&key) {
+        Some(cancel) => {
+            cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+            true
         }
-    })
-    .await?;
-
-    let result = accumulated.lock().unwrap().clone();
-    if result.is_empty() {
-        return Err("AI returned an empty response".to_string());
+        None => false,
     }
-    Ok(result)
 }
 
-/// Create a new conversation containing the user query and AI response,
-/// then emit "open-conversation" to the main window so it navigates there.
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct OpenInNewChatRequest {
-    pub user_text: String,
-    pub ai_response: String,
-    pub provider_id: String,
-    pub model: String,
+// ============================================
+// GitHub Copilot OAuth Commands
+// ============================================
+
+#[tauri::command]
+pub async fn copilot_start_device_flow() -> Result<DeviceCodeResponse, String> {
+    providers::copilot_start_device_flow().await
 }
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct OpenConversationEvent {
-    pub conversation_id: String,
+#[tauri::command]
+pub async fn copilot_poll_auth_state(
+    device_code: String,
+    interval: Option<u64>,
+) -> Result<providers::PollState, String> {
+    providers::copilot_poll_auth_state(&device_code, interval.unwrap_or(5)).await
 }
 
+/// Deprecated: use `copilot_poll_auth_state`, which reports the polling
+/// state without string matching.
 #[tauri::command]
-pub fn open_in_new_chat(
-    app: tauri::AppHandle,
+pub async fn copilot_poll_auth(device_code: String) -> Result<String, String> {
+    providers::copilot_poll_auth(&device_code).await
+}
+
+/// Whether a Copilot provider's stored sign-in still works, by trying a token
+/// exchange. `false` (no token, or GitHub rejected it) means the device flow
+/// should be run again; other failures, like being offline, are errors.
+#[tauri::command]
+pub async fn copilot_check_auth(
     db: State<'_, Database>,
-    req: OpenInNewChatRequest,
-) -> Result<String, String> {
-    // Create conversation
-    let convo_id = uuid::Uuid::new_v4().to_string();
-    let title = if req.user_text.len() > 50 {
-        format!("{}...", &req.user_text[..47])
-    } else {
-        req.user_text.clone()
+    provider_id: String,
+) -> Result<bool, String> {
+    let provider = db
+        .get_provider(&provider_id)
+        .map_err(|e| format!("Provider not found: {}", e))?;
+    if provider.provider_type != "github_copilot" {
+        return Err(format!(
+            "{} is not a GitHub Copilot provider",
+            provider.name
+        ));
+    }
+    let Some(github_token) = provider.api_key.filter(|k| !k.trim().is_empty()) else {
+        return Ok(false);
     };
+    match providers::copilot_exchange_token(&github_token).await {
+        Ok(_) => Ok(true),
+        Err(e) if e == providers::COPILOT_AUTH_EXPIRED => {
+            tracing::info!("[copilot] sign-in for {} has expired", provider_id);
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
 
-    db.create_conversation(&convo_id, &title, &req.model, &req.provider_id, None, None)
-        .map_err(|e| e.to_string())?;
-
-    // Save user message
-    let user_msg_id = uuid::Uuid::new_v4().to_string();
-    db.create_message(&user_msg_id, &convo_id, "user", &req.user_text, None, None, 0)
-        .map_err(|e| e.to_string())?;
+#[tauri::command]
+pub async fn copilot_exchange_token(github_token: String) -> Result<serde_json::Value, String> {
+    let (copilot_token, base_url) = providers::copilot_exchange_token(&github_token).await?;
+    Ok(serde_json::json!({
+        "token": copilot_token,
+        "baseUrl": base_url,
+    }))
+}
 
-    // Save assistant message
-    let assistant_msg_id = uuid::Uuid::new_v4().to_string();
-    db.create_message(
-        &assistant_msg_id,
-        &convo_id,
-        "assistant",
-        &req.ai_response,
-        Some(&req.model),
-        None,
-        1,
-    )
-    .map_err(|e| e.to_string())?;
+// ============================================
+// Settings Commands
+// ============================================
 
-    // Emit event so the main window can navigate to the new conversation
-    let _ = app.emit_to(
-        "main",
-        "open-conversation",
-        OpenConversationEvent {
-            conversation_id: convo_id.clone(),
-        },
-    );
+#[tauri::command]
+pub fn get_settings(db: State<'_, Database>) -> Result<db::AppSettings, String> {
+    db.get_settings().map_err(|e| e.to_string())
+}
 
-    // Hide the overlay panel first so it doesn't block the main window
-    #[cfg(target_os = "macos")]
+#[tauri::command]
+pub fn save_settings(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    settings: db::AppSettings,
+) -> Result<(), String> {
+    shortcuts::validate_optional_accelerator(Some(&settings.global_hotkey))?;
+    logging::parse_level(&settings.log_level)?;
+    AutoTitleMode::parse(&settings.auto_title_mode)?;
+    PruneAction::parse(&settings.prune_action)?;
+    local_api::parse_port(settings.local_api_port)?;
+    let journal_mode = db::parse_journal_mode(&settings.db_journal_mode)?;
+    let previous = db.get_settings().unwrap_or_default();
+    let previous_theme = previous.theme;
+    db.save_settings(&settings).map_err(|e| e.to_string())?;
+    if !settings
+        .db_journal_mode
+        .eq_ignore_ascii_case(&previous.db_journal_mode)
     {
-        let _ = crate::panel::hide_overlay_panel(&app);
+        db.set_journal_mode(journal_mode)
+            .map_err(|e| e.to_string())?;
     }
-    #[cfg(not(target_os = "macos"))]
-    {
-        if let Some(win) = app.get_webview_window("overlay") {
-            let _ = win.hide();
+    if settings.theme != previous_theme {
+        if let Err(e) = tray::apply_theme(&app, &settings.theme) {
+            tracing::warn!("[tray] failed to update icon: {}", e);
         }
     }
+    logging::set_level(&settings.log_level)?;
+    local_api::apply_settings(&app, &db, &settings)
+}
 
-    // Show the main window and activate the app so it comes to front
-    if let Some(win) = app.get_webview_window("main") {
-        let _ = win.show();
-        let _ = win.set_focus();
+/// Set the default provider and model in one write without saving the rest
+/// of `AppSettings`, so it can't clobber a concurrent settings save. Both
+/// empty clears them. The provider must exist and be enabled; with
+/// `verify_model`, the model must also be in its model list.
+#[tauri::command]
+pub async fn set_default_model(
+    db: State<'_, Database>,
+    provider_id: String,
+    model: String,
+    verify_model: Option<bool>,
+) -> Result<(), String> {
+    update_default_model(&db, &provider_id, &model, verify_model.unwrap_or(false)).await
+}
+
+/// The validation and write behind `set_default_model`.
+async fn update_default_model(
+    db: &Database,
+    provider_id: &str,
+    model: &str,
+    verify_model: bool,
+) -> Result<(), String> {
+    let provider_id = provider_id.trim();
+    let model = model.trim();
+    if provider_id.is_empty() != model.is_empty() {
+        return Err("Set both a default provider and model, or clear both".to_string());
+    }
+    if !provider_id.is_empty() {
+        let provider = db
+            .get_provider(provider_id)
+            .map_err(|_| format!("Provider not found: {}", provider_id))?;
+        if !provider.enabled {
+            return Err(format!("Provider \"{}\" is disabled", provider.name));
+        }
+        if verify_model {
+            let name = provider.name.clone();
+            let settings = db.get_settings().map_err(|e| e.to_string())?;
+            let config = provider_config(db, provider, String::new(), &settings);
+            let models = providers::list_provider_models(&config, false).await?;
+            if !models.iter().any(|m| m.id == model) {
+                return Err(format!(
+                    "\"{}\" does not offer the model \"{}\"",
+                    name, model
+                ));
+            }
+        }
     }
+    db.set_default_model(provider_id, model)
+        .map_err(|e| e.to_string())?;
+    tracing::info!("[settings] default model set to {}/{}", provider_id, model);
+    Ok(())
+}
 
-    // Activate the app (needed on macOS so the main window actually comes to front)
-    #[cfg(target_os = "macos")]
-    {
-        use objc2_app_kit::NSApplication;
-        use objc2::MainThreadMarker;
-        // IPC commands run on the main thread in Tauri
-        if let Some(mtm) = MainThreadMarker::new() {
-            let ns_app = NSApplication::sharedApplication(mtm);
-            #[allow(deprecated)]
-            ns_app.activateIgnoringOtherApps(true);
+/// Restore one setting to its default and return the resulting settings.
+#[tauri::command]
+pub fn reset_setting(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    key: String,
+) -> Result<db::AppSettings, String> {
+    if !db::AppSettings::is_known_key(&key) {
+        return Err(format!("Unknown setting: {}", key));
+    }
+    db.reset_setting(&key).map_err(|e| e.to_string())?;
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    match key.as_str() {
+        "log_level" => logging::set_level(&settings.log_level)?,
+        "db_journal_mode" => {
+            db.set_journal_mode(db::DEFAULT_JOURNAL_MODE)
+                .map_err(|e| e.to_string())?;
         }
+        "theme" => {
+            if let Err(e) = tray::apply_theme(&app, &settings.theme) {
+                tracing::warn!("[tray] failed to update icon: {}", e);
+            }
+        }
+        "local_api_enabled" | "local_api_port" | "local_api_token" => {
+            local_api::apply_settings(&app, &db, &settings)?;
+            // A reset token is regenerated while the server is running.
+            return db.get_settings().map_err(|e| e.to_string());
+        }
+        _ => {}
     }
+    Ok(settings)
+}
 
-    Ok(convo_id)
+/// Change the log level immediately and persist it as the `log_level` setting.
+#[tauri::command]
+pub fn set_log_level(db: State<'_, Database>, level: String) -> Result<(), String> {
+    let level = level.trim().to_lowercase();
+    logging::set_level(&level)?;
+    db.set_setting_raw("log_level", &level)
+        .map_err(|e| e.to_string())
 }
 
+/// Switch the SQLite journal mode now and persist it as `db_journal_mode`.
+/// Returns the mode SQLite reports after the switch.
 #[tauri::command]
-pub fn save_ai_command(db: State<'_, Database>, command: db::AiCommand) -> Result<(), String> {
-    db.save_ai_command(&command).map_err(|e| e.to_string())
+pub fn set_db_journal_mode(db: State<'_, Database>, mode: String) -> Result<String, String> {
+    let mode = db::parse_journal_mode(&mode)?;
+    let applied = db.set_journal_mode(mode).map_err(|e| e.to_string())?;
+    db.set_setting_raw("db_journal_mode", mode)
+        .map_err(|e| e.to_string())?;
+    tracing::info!("[db] journal mode is now {}", applied);
+    Ok(applied)
+}
+
+// ============================================
+// Diagnostics Commands
+// ============================================
+
+/// Snapshot of app state to attach to bug reports.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostics {
+    pub app_version: String,
+    pub os: String,
+    pub arch: String,
+    pub database: db::DbStats,
+    pub providers_configured: usize,
+    pub providers_enabled: usize,
+    /// Accessibility/automation status; `None` where the app does not need it.
+    pub permissions: Option<crate::clipboard::PermissionsStatus>,
+    pub log_level: String,
 }
 
 #[tauri::command]
-pub fn delete_ai_command(db: State<'_, Database>, id: String) -> Result<(), String> {
-    db.delete_ai_command(&id).map_err(|e| e.to_string())
+pub fn diagnostics(app: tauri::AppHandle, db: State<'_, Database>) -> Result<Diagnostics, String> {
+    collect_diagnostics(&db, app.package_info().version.to_string())
+}
+
+fn collect_diagnostics(db: &Database, app_version: String) -> Result<Diagnostics, String> {
+    let providers = db.list_providers().map_err(|e| e.to_string())?;
+
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    let permissions = Some(crate::clipboard::check_permissions());
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let permissions = None;
+
+    Ok(Diagnostics {
+        app_version,
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        database: db.stats().map_err(|e| e.to_string())?,
+        providers_configured: providers.len(),
+        providers_enabled: providers.iter().filter(|p| p.enabled).count(),
+        permissions,
+        log_level: logging::current_level(),
+    })
+}
+
+/// Count messages and attachments whose parent row no longer exists.
+#[tauri::command]
+pub fn find_orphans(db: State<'_, Database>) -> Result<db::OrphanReport, String> {
+    db.find_orphans().map_err(|e| e.to_string())
+}
+
+/// Delete orphaned messages and attachments; returns what was removed.
+#[tauri::command]
+pub fn delete_orphans(db: State<'_, Database>) -> Result<db::OrphanReport, String> {
+    let report = db.delete_orphans().map_err(|e| e.to_string())?;
+    tracing::info!(
+        "[db] removed {} orphaned message(s), {} attachment(s)",
+        report.messages,
+        report.attachments
+    );
+    Ok(report)
+}
+
+/// Normalize line endings, trailing whitespace and runs of blank lines in
+/// every message (see `db::normalize_message_text`). Returns how many
+/// messages changed.
+#[tauri::command]
+pub fn normalize_message_content(db: State<'_, Database>) -> Result<usize, String> {
+    let changed = db.normalize_content().map_err(|e| e.to_string())?;
+    tracing::info!("[db] normalized content of {} message(s)", changed);
+    Ok(changed)
 }
 
 // ============================================
-// Assistant Commands
+// Config Import/Export Commands
 // ============================================
 
+/// Export every conversation to `dest_path`, either as one JSON document
+/// (`format = "json"`) or a zip of Markdown files (`"markdown"`).
 #[tauri::command]
-pub fn list_assistants(db: State<'_, Database>) -> Result<Vec<db::Assistant>, String> {
-    db.list_assistants().map_err(|e| e.to_string())
+pub fn export_all(
+    db: State<'_, Database>,
+    format: String,
+    dest_path: String,
+) -> Result<export::ExportSummary, String> {
+    let format = export::ExportFormat::parse(&format)?;
+    let summary = export::export_all(&db, format, std::path::Path::new(&dest_path))?;
+    tracing::info!(
+        "[export] wrote {} conversation(s), {} message(s) to {}",
+        summary.conversations,
+        summary.messages,
+        dest_path
+    );
+    Ok(summary)
 }
 
+/// Render a conversation as a standalone HTML page with its Markdown
+/// formatted, for sharing.
 #[tauri::command]
-pub fn save_assistant(db: State<'_, Database>, assistant: db::Assistant) -> Result<(), String> {
-    db.save_assistant(&assistant).map_err(|e| e.to_string())
+pub fn export_conversation_html(db: State<'_, Database>, id: String) -> Result<String, String> {
+    let conversation = db
+        .get_conversation(&id)
+        .map_err(|e| format!("Conversation not found: {}", e))?;
+    let messages = db.get_messages(&id).map_err(|e| e.to_string())?;
+    Ok(export::conversation_html(&conversation, &messages))
 }
 
+/// Move a conversation out of the database into a JSON file at `dest_path`.
+/// It is only deleted once the file has been written.
 #[tauri::command]
-pub fn delete_assistant(db: State<'_, Database>, id: String) -> Result<(), String> {
-    db.delete_assistant(&id).map_err(|e| e.to_string())
+pub fn archive_to_file(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    conversation_id: String,
+    dest_path: String,
+) -> Result<(), String> {
+    let summary = export::archive_to_file(&db, &conversation_id, std::path::Path::new(&dest_path))?;
+    events::conversation_deleted(&app, &conversation_id);
+    tracing::info!(
+        "[export] archived conversation {} ({} message(s)) to {}",
+        conversation_id,
+        summary.messages,
+        dest_path
+    );
+    Ok(())
+}
+
+/// Restore a conversation from a file written by `archive_to_file`.
+#[tauri::command]
+pub fn import_from_file(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    src_path: String,
+) -> Result<db::Conversation, String> {
+    let conversation = export::import_from_file(&db, std::path::Path::new(&src_path))?;
+    events::conversation_created(&app, &conversation.id);
+    Ok(conversation)
+}
+
+/// Current `ConfigDocument` format version.
+const CONFIG_VERSION: u32 = 1;
+
+/// Shareable provider and settings baseline read by `import_config` and
+/// written by `export_config`. `settings` may hold any subset of fields.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigDocument {
+    #[serde(default)]
+    pub version: Option<u32>,
+    #[serde(default)]
+    pub providers: Vec<db::Provider>,
+    #[serde(default)]
+    pub settings: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub providers_imported: usize,
+    pub providers_skipped: usize,
+    pub settings_imported: usize,
+    pub settings_skipped: usize,
+    pub warnings: Vec<String>,
+}
+
+/// Export every provider plus all settings as a `ConfigDocument`. With
+/// `redact_keys`, API keys are left out.
+#[tauri::command]
+pub fn export_config(db: State<'_, Database>, redact_keys: bool) -> Result<String, String> {
+    let document = config_document(&db, redact_keys)?;
+    serde_json::to_string_pretty(&document).map_err(|e| e.to_string())
+}
+
+fn config_document(db: &Database, redact_keys: bool) -> Result<ConfigDocument, String> {
+    let mut providers = db.list_providers().map_err(|e| e.to_string())?;
+    if redact_keys {
+        for provider in &mut providers {
+            provider.api_key = None;
+        }
+    }
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let settings = match serde_json::to_value(settings).map_err(|e| e.to_string())? {
+        serde_json::Value::Object(map) => map,
+        _ => unreachable!("AppSettings serializes to an object"),
+    };
+    Ok(ConfigDocument {
+        version: Some(CONFIG_VERSION),
+        providers,
+        settings,
+    })
+}
+
+/// Import providers and settings from a `ConfigDocument`. Providers whose
+/// id already exists are replaced with `overwrite`, skipped otherwise.
+/// Everything is validated before anything is written.
+#[tauri::command]
+pub fn import_config(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    json: String,
+    overwrite: bool,
+) -> Result<ImportReport, String> {
+    let document: ConfigDocument =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid config file: {}", e))?;
+    let (mut report, previous) = write_config_document(&db, &document, overwrite)?;
+
+    if !document.settings.is_empty() {
+        let settings = db.get_settings().map_err(|e| e.to_string())?;
+        logging::set_level(&settings.log_level)?;
+        if settings.theme != previous.theme {
+            if let Err(e) = tray::apply_theme(&app, &settings.theme) {
+                tracing::warn!("[tray] failed to update icon: {}", e);
+            }
+        }
+        if let Err(e) = local_api::apply_settings(&app, &db, &settings) {
+            report.warnings.push(e);
+        }
+    }
+
+    tracing::info!(
+        "[config] imported {} provider(s), skipped {}, {} setting(s)",
+        report.providers_imported,
+        report.providers_skipped,
+        report.settings_imported
+    );
+    Ok(report)
+}
+
+/// Validate `document` and write its providers and settings. Returns the
+/// report and the settings as they were before the import.
+fn write_config_document(
+    db: &Database,
+    document: &ConfigDocument,
+    overwrite: bool,
+) -> Result<(ImportReport, db::AppSettings), String> {
+    if let Some(version) = document.version.filter(|v| *v > CONFIG_VERSION) {
+        return Err(format!(
+            "Config file version {} is newer than this app supports ({})",
+            version, CONFIG_VERSION
+        ));
+    }
+
+    let mut report = ImportReport::default();
+
+    for provider in &document.providers {
+        validate_provider(provider).map_err(|e| format!("Provider '{}': {}", provider.name, e))?;
+    }
+
+    let current = db.get_settings().map_err(|e| e.to_string())?;
+    let (settings, unknown) = current.merged_with(&document.settings)?;
+    shortcuts::validate_optional_accelerator(Some(&settings.global_hotkey))?;
+    logging::parse_level(&settings.log_level)?;
+    local_api::parse_port(settings.local_api_port)?;
+
+    let with_keys = document
+        .providers
+        .iter()
+        .filter(|p| p.api_key.as_deref().is_some_and(|k| !k.is_empty()))
+        .count();
+    if with_keys > 0 {
+        report.warnings.push(format!(
+            "{} provider(s) in this file include API keys in plaintext; keep the file private or delete it",
+            with_keys
+        ));
+    }
+
+    let existing: std::collections::HashSet<String> = db
+        .list_providers()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|p| p.id)
+        .collect();
+    for provider in &document.providers {
+        if existing.contains(&provider.id) && !overwrite {
+            report.providers_skipped += 1;
+            continue;
+        }
+        db.save_provider(provider).map_err(|e| e.to_string())?;
+        report.providers_imported += 1;
+    }
+
+    if !document.settings.is_empty() {
+        db.save_settings(&settings).map_err(|e| e.to_string())?;
+    }
+    report.settings_imported = document.settings.len() - unknown.len();
+    report.settings_skipped = unknown.len();
+    if !unknown.is_empty() {
+        report
+            .warnings
+            .push(format!("Ignored unknown settings: {}", unknown.join(", ")));
+    }
+    Ok((report, current))
+}
+
+/// A provider as shared by `export_providers_redacted`: no API key, and no
+/// custom header that holds a literal value rather than `${ENV}` references.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedProvider {
+    #[serde(flatten)]
+    pub provider: db::Provider,
+    /// Whether the exporting user had an API key set.
+    #[serde(default)]
+    pub has_key: bool,
+    /// Custom headers dropped because their values may be secrets.
+    #[serde(default)]
+    pub redacted_headers: Vec<String>,
+}
+
+/// Strip secrets from `providers` and render them as pretty JSON.
+pub fn redacted_providers_json(providers: Vec<db::Provider>) -> Result<String, String> {
+    let shared: Vec<SharedProvider> = providers
+        .into_iter()
+        .map(|mut provider| {
+            let has_key = provider.api_key.take().is_some_and(|k| !k.is_empty());
+            let redacted_headers: Vec<String> = provider
+                .custom_headers
+                .iter()
+                .filter(|(_, value)| !value.contains("${"))
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in &redacted_headers {
+                provider.custom_headers.remove(name);
+            }
+            SharedProvider {
+                provider,
+                has_key,
+                redacted_headers,
+            }
+        })
+        .collect();
+    serde_json::to_string_pretty(&shared).map_err(|e| e.to_string())
+}
+
+/// Every provider as JSON safe to paste into an issue or gist.
+#[tauri::command]
+pub fn export_providers_redacted(db: State<'_, Database>) -> Result<String, String> {
+    let providers = db.list_providers().map_err(|e| e.to_string())?;
+    redacted_providers_json(providers)
+}
+
+/// Import the output of `export_providers_redacted`. The UI asks for keys
+/// where `hasKey` is set and passes them in `api_keys`, keyed by provider id.
+/// Existing providers are replaced with `overwrite`, skipped otherwise.
+#[tauri::command]
+pub fn import_shared_providers(
+    db: State<'_, Database>,
+    json: String,
+    api_keys: std::collections::HashMap<String, String>,
+    overwrite: bool,
+) -> Result<ImportReport, String> {
+    import_shared_provider_list(&db, &json, &api_keys, overwrite)
+}
+
+/// The work behind `import_shared_providers`. A shared file is untrusted:
+/// custom headers that reference environment variables are dropped, since
+/// they would send the importing user's secrets to whatever `base_url` the
+/// file names, and each dropped header is listed in the warnings.
+fn import_shared_provider_list(
+    db: &Database,
+    json: &str,
+    api_keys: &std::collections::HashMap<String, String>,
+    overwrite: bool,
+) -> Result<ImportReport, String> {
+    let shared: Vec<SharedProvider> =
+        serde_json::from_str(json).map_err(|e| format!("Invalid provider list: {}", e))?;
+    for SharedProvider { provider, .. } in &shared {
+        validate_provider(provider).map_err(|e| format!("Provider '{}': {}", provider.name, e))?;
+    }
+
+    let existing: std::collections::HashSet<String> = db
+        .list_providers()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|p| p.id)
+        .collect();
+    let mut report = ImportReport::default();
+    for entry in shared {
+        let mut provider = entry.provider;
+        if existing.contains(&provider.id) && !overwrite {
+            report.providers_skipped += 1;
+            continue;
+        }
+        provider.api_key = api_keys
+            .get(&provider.id)
+            .map(|k| k.trim().to_string())
+            .filter(|k| !k.is_empty());
+        if entry.has_key && provider.api_key.is_none() {
+            report
+                .warnings
+                .push(format!("Provider '{}' needs an API key", provider.name));
+        }
+        if !entry.redacted_headers.is_empty() {
+            report.warnings.push(format!(
+                "Provider '{}' had headers removed before sharing: {}",
+                provider.name,
+                entry.redacted_headers.join(", ")
+            ));
+        }
+        let env_headers: Vec<String> = provider
+            .custom_headers
+            .iter()
+            .filter(|(_, value)| value.contains("${"))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in &env_headers {
+            provider.custom_headers.remove(name);
+        }
+        if !env_headers.is_empty() {
+            report.warnings.push(format!(
+                "Provider '{}' had headers that read environment variables removed; re-add them yourself if you trust its base URL: {}",
+                provider.name,
+                env_headers.join(", ")
+            ));
+        }
+        db.save_provider(&provider).map_err(|e| e.to_string())?;
+        report.providers_imported += 1;
+    }
+
+    tracing::info!(
+        "[config] imported {} shared provider(s), skipped {}",
+        report.providers_imported,
+        report.providers_skipped
+    );
+    Ok(report)
+}
+
+// ============================================
+// Prompt Template Commands
+// ============================================
+
+/// Parse the `{{name}}` placeholders in a template, returning the distinct
+/// names in order of first use. Unclosed, empty, nested or half-braced
+/// placeholders are reported with their character position. Single braces
+/// that balance (JSON examples, say) are left alone.
+pub fn template_variables(content: &str) -> Result<Vec<String>, String> {
+    let chars: Vec<char> = content.chars().collect();
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut names: Vec<String> = Vec::new();
+    // Positions of unmatched single `{`.
+    let mut open: Vec<usize> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let pair = |c: char| chars[i] == c && chars.get(i + 1) == Some(&c);
+        if pair('{') {
+            let start = i;
+            let mut j = i + 2;
+            while j < chars.len() && is_name(chars[j]) {
+                j += 1;
+            }
+            match (chars.get(j), chars.get(j + 1)) {
+                (Some('}'), Some('}')) if j > start + 2 => {
+                    let name: String = chars[start + 2..j].iter().collect();
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                    i = j + 2;
+                    continue;
+                }
+                (Some('}'), Some('}')) => {
+                    return Err(format!(
+                        "Empty placeholder '{{{{}}}}' at position {}",
+                        start
+                    ));
+                }
+                (Some('{'), _) => {
+                    return Err(format!("Nested placeholder at position {}", j));
+                }
+                (None, _) | (Some('}'), None) => {
+                    return Err(format!("Unclosed placeholder '{{{{' at position {}", start));
+                }
+                (Some('}'), Some(_)) => {
+                    return Err(format!(
+                        "Placeholder at position {} is closed with '}}' instead of '}}}}'",
+                        start
+                    ));
+                }
+                (Some(c), _) => {
+                    return Err(format!(
+                        "Invalid character '{}' in placeholder at position {}",
+                        c, start
+                    ));
+                }
+            }
+        }
+        if pair('}') {
+            match open.last() {
+                None => return Err(format!("Unmatched '}}}}' at position {}", i)),
+                // `{name}}`: a placeholder missing its second opening brace.
+                Some(&o) if o + 1 < i && chars[o + 1..i].iter().all(|&c| is_name(c)) => {
+                    return Err(format!(
+                        "Placeholder at position {} is opened with '{{' instead of '{{{{'",
+                        o
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+        match chars[i] {
+            '{' => open.push(i),
+            '}' => {
+                open.pop();
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(names)
+}
+
+/// Check a template before saving and return its variable names.
+#[tauri::command]
+pub fn validate_template(content: String) -> Result<Vec<String>, String> {
+    template_variables(&content)
+}
+
+/// Templates by name, or most used first with `most_used_first`.
+#[tauri::command]
+pub fn list_prompt_templates(
+    db: State<'_, Database>,
+    most_used_first: Option<bool>,
+) -> Result<Vec<db::PromptTemplate>, String> {
+    db.list_prompt_templates(most_used_first.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
+/// Substitute `values` into a template's `{{name}}` placeholders. Every
+/// placeholder needs a value.
+pub fn render_template(
+    content: &str,
+    values: &std::collections::HashMap<String, String>,
+) -> Result<String, String> {
+    template_variables(content)?;
+    let mut rendered = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        // `template_variables` has checked every `{{` closes after a name.
+        let end = after.find("}}").unwrap_or(after.len());
+        let name = &after[..end];
+        let value = values
+            .get(name)
+            .ok_or_else(|| format!("Missing value for template variable '{}'", name))?;
+        rendered.push_str(value);
+        rest = after.get(end + 2..).unwrap_or_default();
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Render a stored template with `values` and count the use.
+#[tauri::command]
+pub fn render_prompt_template(
+    db: State<'_, Database>,
+    id: String,
+    values: std::collections::HashMap<String, String>,
+) -> Result<String, String> {
+    let template = db.get_prompt_template(&id).map_err(|e| e.to_string())?;
+    let rendered = render_template(&template.content, &values)?;
+    db.record_prompt_template_use(&id)
+        .map_err(|e| e.to_string())?;
+    Ok(rendered)
+}
+
+#[tauri::command]
+pub fn save_prompt_template(
+    db: State<'_, Database>,
+    mut template: db::PromptTemplate,
+) -> Result<(), String> {
+    template.variables = template_variables(&template.content)?;
+    db.save_prompt_template(&template)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn delete_prompt_template(db: State<'_, Database>, id: String) -> Result<(), String> {
+    db.delete_prompt_template(&id).map_err(|e| e.to_string())
+}
+
+// ============================================
+// Folder Commands
+// ============================================
+
+#[tauri::command]
+pub fn list_folders(db: State<'_, Database>) -> Result<Vec<db::Folder>, String> {
+    db.list_folders().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_folders_with_counts(db: State<'_, Database>) -> Result<Vec<db::FolderSummary>, String> {
+    db.list_folders_with_counts().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn create_folder(db: State<'_, Database>, name: String) -> Result<db::Folder, String> {
+    let id = uuid::Uuid::new_v4().to_string();
+    db.create_folder(&id, &name).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn delete_folder(db: State<'_, Database>, id: String) -> Result<(), String> {
+    db.delete_folder(&id).map_err(|e| e.to_string())
+}
+
+// ============================================
+// AI Command Commands
+// ============================================
+
+/// AI commands in their configured order, or most used first with
+/// `most_used_first`.
+#[tauri::command]
+pub fn list_ai_commands(
+    db: State<'_, Database>,
+    most_used_first: Option<bool>,
+) -> Result<Vec<db::AiCommand>, String> {
+    db.list_ai_commands(most_used_first.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
+/// Execute an AI command on the given selected text.
+/// Resolves provider/model from the command overrides or falls back to default settings.
+/// Returns the AI-generated result text.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteAiCommandRequest {
+    pub selected_text: String,
+    pub system_prompt: String,
+    pub provider_id: Option<String>,
+    pub model: Option<String>,
+    /// The command's `behavior`. With the `live_replace` setting on,
+    /// `replace_selection`/`insert_after` output is pasted as it streams.
+    #[serde(default)]
+    pub behavior: Option<String>,
+    /// The stored command being run, if any; its `use_count` is bumped when
+    /// it produces a result.
+    #[serde(default)]
+    pub command_id: Option<String>,
+}
+
+/// Result of `execute_ai_command`. When the provider fails mid-stream the
+/// text received so far is returned with `incomplete` set and the error.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AiCommandOutput {
+    pub text: String,
+    pub incomplete: bool,
+    pub error: Option<String>,
+    /// The text was already pasted into the originating app (live replace).
+    pub applied: bool,
+}
+
+const TRANSIENT_STATUS_CODES: &[u16] = &[429, 500, 502, 503, 504];
+const AI_COMMAND_RETRY_DELAY_MS: u64 = 500;
+
+/// Whether a stream error is a connection-level failure worth retrying
+/// (transport errors, stalls, rate limits, 5xx) rather than a problem with
+/// the request itself.
+fn is_transient_stream_error(kind: &StreamErrorKind) -> bool {
+    match kind {
+        StreamErrorKind::Connection | StreamErrorKind::Stalled => true,
+        StreamErrorKind::Status { code, .. } => TRANSIENT_STATUS_CODES.contains(code),
+        StreamErrorKind::NotEventStream { .. } | StreamErrorKind::Other => false,
+    }
+}
+
+type OverlayStreams = std::collections::HashMap<String, providers::CancelToken>;
+
+/// Cancel tokens for in-flight `execute_ai_command` streams, keyed by a
+/// per-call id. Only the overlay runs AI commands; main-window chats are never
+/// registered here, so hiding the overlay can't stop them.
+static OVERLAY_STREAMS: std::sync::OnceLock<std::sync::Mutex<OverlayStreams>> =
+    std::sync::OnceLock::new();
+
+fn overlay_streams() -> std::sync::MutexGuard<'static, OverlayStreams> {
+    OVERLAY_STREAMS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Registers an overlay stream for the lifetime of the guard.
+struct OverlayStream {
+    id: String,
+}
+
+impl OverlayStream {
+    fn register(token: providers::CancelToken) -> Self {
+        let id = uuid::Uuid::new_v4().to_string();
+        overlay_streams().insert(id.clone(), token);
+        Self { id }
+    }
+}
+
+impl Drop for OverlayStream {
+    fn drop(&mut self) {
+        overlay_streams().remove(&self.id);
+    }
+}
+
+/// Cancel every in-flight overlay AI command. Called when the overlay is
+/// hidden so dismissed commands stop generating (and billing) tokens.
+/// Returns how many streams were cancelled.
+pub fn cancel_overlay_streams() -> usize {
+    let streams = overlay_streams();
+    for token in streams.values() {
+        token.cancel();
+    }
+    if !streams.is_empty() {
+        tracing::info!("[overlay] cancelled {} in-flight command(s)", streams.len());
+    }
+    streams.len()
+}
+
+/// Output collected from a single streaming attempt.
+#[derive(Debug, Default)]
+struct CommandAttempt {
+    text: String,
+    error: Option<String>,
+    /// Kind of the first `Error` event; `Other` for errors returned instead.
+    error_kind: StreamErrorKind,
+    /// `total_tokens` from the `Done` event, once the response finished.
+    done_tokens: Option<i64>,
+    /// The provider rejected streaming and the request was sent buffered.
+    streaming_rejected: bool,
+}
+
+impl CommandAttempt {
+    fn record(&mut self, event: StreamEvent) {
+        match event {
+            StreamEvent::Delta { content } => self.text.push_str(&content),
+            StreamEvent::Done { total_tokens } => self.done_tokens = Some(total_tokens),
+            // Later errors are usually fallout from the first.
+            StreamEvent::Error { message, kind } if self.error.is_none() => {
+                self.error = Some(message);
+                self.error_kind = kind;
+            }
+            StreamEvent::StreamingDisabled { .. } => self.streaming_rejected = true,
+            _ => {}
+        }
+    }
+
+    fn should_retry(&self) -> bool {
+        self.text.is_empty() && self.error.is_some() && is_transient_stream_error(&self.error_kind)
+    }
+
+    fn finish(self) -> Result<AiCommandOutput, String> {
+        match self.error {
+            Some(error) if self.text.is_empty() => Err(error),
+            Some(error) => Ok(AiCommandOutput {
+                text: self.text,
+                incomplete: true,
+                error: Some(error),
+                applied: false,
+            }),
+            None if self.text.is_empty() => Err("AI returned an empty response".to_string()),
+            None => Ok(AiCommandOutput {
+                text: self.text,
+                incomplete: false,
+                error: None,
+                applied: false,
+            }),
+        }
+    }
+}
+
+/// Fewest pending characters worth a live-replace paste.
+const LIVE_REPLACE_MIN_CHARS: usize = 24;
+/// Pause after each live-replace paste. The target app reads the clipboard
+/// asynchronously after the keystroke, so it must not change too soon.
+const LIVE_REPLACE_PASTE_INTERVAL_MS: u64 = 300;
+
+/// Coalesces streamed deltas into paste-sized batches for live replace.
+#[derive(Debug, Default)]
+struct LiveReplaceBatcher {
+    pending: String,
+}
+
+impl LiveReplaceBatcher {
+    fn push(&mut self, delta: &str) {
+        self.pending.push_str(delta);
+    }
+
+    /// The next batch once at least `LIVE_REPLACE_MIN_CHARS` are pending:
+    /// everything up to and including the last whitespace, so a word still
+    /// being streamed isn't split across pastes. Text without whitespace
+    /// (e.g. CJK) is taken whole.
+    fn take(&mut self) -> Option<String> {
+        if self.pending.chars().count() < LIVE_REPLACE_MIN_CHARS {
+            return None;
+        }
+        let end = self
+            .pending
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(self.pending.len());
+        let rest = self.pending.split_off(end);
+        Some(std::mem::replace(&mut self.pending, rest))
+    }
+
+    /// Whatever is left once the stream has ended.
+    fn finish(&mut self) -> Option<String> {
+        (!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending))
+    }
+}
+
+/// Paste deltas from `deltas` into the originating app as they arrive. The
+/// first batch hands focus back to it (collapsing the selection first for
+/// `insert_after`); later batches land at the caret. Returns whether
+/// anything was pasted; if the first paste fails the result is left for the
+/// overlay to show instead.
+async fn run_live_replace(
+    app: tauri::AppHandle,
+    mut deltas: tokio::sync::mpsc::UnboundedReceiver<String>,
+    at_selection_end: bool,
+) -> Result<bool, String> {
+    let mut batcher = LiveReplaceBatcher::default();
+    let mut pasted = false;
+    let mut open = true;
+    while open {
+        match deltas.recv().await {
+            Some(delta) => batcher.push(&delta),
+            None => open = false,
+        }
+        // Everything that arrived during the last paste goes out together.
+        while let Ok(delta) = deltas.try_recv() {
+            batcher.push(&delta);
+        }
+        let batch = if open {
+            batcher.take()
+        } else {
+            batcher.finish()
+        };
+        let Some(batch) = batch else { continue };
+        if pasted {
+            crate::clipboard::paste_into_focused(batch).await?;
+        } else if let Err(e) =
+            crate::clipboard::paste_into_origin(&app, batch, at_selection_end).await
+        {
+            tracing::warn!("[execute_ai_command] live replace unavailable: {}", e);
+            return Ok(false);
+        } else {
+            pasted = true;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(
+            LIVE_REPLACE_PASTE_INTERVAL_MS,
+        ))
+        .await;
+    }
+    Ok(pasted)
+}
+
+/// The database half of `execute_ai_command`: stream the command's output,
+/// retrying connection failures that happen before any output arrives, and
+/// count the use of `command_id` when it produces a result. Deltas are also
+/// sent to `live_tx` for live replace. Returns the output and whether
+/// streaming was turned off for the provider.
+async fn run_ai_command(
+    db: &Database,
+    config: &ProviderConfig,
+    provider_id: &str,
+    chat_messages: &[ChatMessage],
+    max_retries: u64,
+    live_tx: Option<&tokio::sync::mpsc::UnboundedSender<String>>,
+    command_id: Option<&str>,
+) -> (Result<AiCommandOutput, String>, bool) {
+    let mut streaming_disabled = false;
+    let mut retries = 0;
+    let output = loop {
+        let mut attempt = CommandAttempt::default();
+        let on_event = |event: StreamEvent| {
+            if let (Some(tx), StreamEvent::Delta { content }) = (live_tx, &event) {
+                let _ = tx.send(content.clone());
+            }
+            attempt.record(event);
+        };
+        if let Err(e) = providers::stream_chat(config, chat_messages, on_event).await {
+            attempt.error.get_or_insert(e);
+        }
+        if let Some(tokens) = attempt.done_tokens {
+            record_token_usage(db, provider_id, &usage_day(chrono::Local::now()), tokens);
+        }
+        if attempt.streaming_rejected {
+            streaming_disabled |= save_streaming_disabled(db, provider_id);
+        }
+
+        let cancelled = config.cancel.as_ref().is_some_and(|c| c.is_cancelled());
+        if retries < max_retries && attempt.should_retry() && !cancelled {
+            retries += 1;
+            tracing::warn!(
+                "[execute_ai_command] attempt {} failed, retrying: {}",
+                retries,
+                attempt.error.as_deref().unwrap_or_default()
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(
+                AI_COMMAND_RETRY_DELAY_MS * retries,
+            ))
+            .await;
+            continue;
+        }
+        break attempt.finish();
+    };
+
+    if let (Ok(_), Some(id)) = (&output, command_id) {
+        if let Err(e) = db.record_ai_command_use(id) {
+            tracing::warn!("[execute_ai_command] failed to record use of {}: {}", id, e);
+        }
+    }
+    (output, streaming_disabled)
+}
+
+#[tauri::command]
+pub async fn execute_ai_command(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    last_requests: State<'_, transcript::LastRequests>,
+    req: ExecuteAiCommandRequest,
+) -> Result<AiCommandOutput, String> {
+    // Resolve provider & model — use command overrides or fall back to defaults
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let provider_id = req
+        .provider_id
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| settings.default_provider_id.clone());
+    let provider = db.get_provider(&provider_id).map_err(|e| {
+        let msg = e.to_string();
+        if msg.contains("no rows") || msg.contains("Query returned no rows") {
+            "No AI provider configured. Please go to Settings → Providers to add one.".to_string()
+        } else {
+            format!("Failed to load provider: {}", msg)
+        }
+    })?;
+
+    // Fallback chain: command-level model → provider default_model → global default_model
+    let model = req
+        .model
+        .filter(|s| !s.is_empty())
+        .or_else(|| provider.default_model.clone().filter(|s| !s.is_empty()))
+        .unwrap_or_else(|| settings.default_model.clone());
+    check_token_budget(&db, &provider, &usage_day(chrono::Local::now()))?;
+
+    let mut config = provider_config(&db, provider, model, &settings);
+    config.capture = Some(last_requests.start(transcript::AI_COMMAND_KEY));
+    let cancel = providers::CancelToken::default();
+    config.cancel = Some(cancel.clone());
+
+    let live_target = req
+        .behavior
+        .as_deref()
+        .and_then(|behavior| CommandBehavior::parse(behavior).ok())
+        .filter(|_| settings.live_replace)
+        .and_then(CommandBehavior::paste_at_selection_end);
+    // Live replace hides the overlay on its first paste, which would cancel
+    // a registered stream; it keeps running in the target app instead.
+    let _registration = live_target
+        .is_none()
+        .then(|| OverlayStream::register(cancel.clone()));
+    let (live_tx, live_paste) = match live_target {
+        Some(at_selection_end) => {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let paste =
+                tauri::async_runtime::spawn(run_live_replace(app.clone(), rx, at_selection_end));
+            (Some(tx), Some(paste))
+        }
+        None => (None, None),
+    };
+
+    // Build messages: system prompt + user message containing the selected text
+    let mut chat_messages: Vec<ChatMessage> = Vec::new();
+    if let Some(system_prompt) = compose_system_prompt(&settings, Some(&req.system_prompt)) {
+        chat_messages.push(ChatMessage {
+            role: "system".to_string(),
+            content: system_prompt,
+        });
+    }
+    chat_messages.push(ChatMessage {
+        role: "user".to_string(),
+        content: req.selected_text,
+    });
+    providers::ensure_conversation_turn(&chat_messages)?;
+
+    let max_retries = settings.ai_command_max_retries.max(0) as u64;
+    let (output, streaming_disabled) = run_ai_command(
+        &db,
+        &config,
+        &provider_id,
+        &chat_messages,
+        max_retries,
+        live_tx.as_ref(),
+        req.command_id.as_deref(),
+    )
+    .await;
+    if streaming_disabled {
+        events::provider_updated(&app, &provider_id);
+    }
+
+    // Closing the channel lets the paste task flush what it still holds.
+    drop(live_tx);
+    let Some(live_paste) = live_paste else {
+        return output;
+    };
+    let pasted = live_paste.await.map_err(|e| e.to_string())?;
+    let mut output = output?;
+    match pasted {
+        Ok(applied) => output.applied = applied,
+        Err(e) => {
+            tracing::warn!("[execute_ai_command] live replace failed: {}", e);
+            // Part of the text is already in the target app.
+            output.applied = true;
+            output.incomplete = true;
+            output
+                .error
+                .get_or_insert(format!("Live replace failed: {}", e));
+        }
+    }
+    Ok(output)
+}
+
+/// What to do with an AI command's output (`AiCommand.behavior`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandBehavior {
+    ReplaceSelection,
+    InsertAfter,
+    AnswerInNew,
+}
+
+impl CommandBehavior {
+    pub fn parse(behavior: &str) -> Result<Self, String> {
+        match behavior {
+            "replace_selection" => Ok(Self::ReplaceSelection),
+            "insert_after" => Ok(Self::InsertAfter),
+            "answer_in_new" => Ok(Self::AnswerInNew),
+            other => Err(format!("Unknown command behavior: {}", other)),
+        }
+    }
+
+    /// For the behaviors that paste into the previously focused app, whether
+    /// the text goes after the selection rather than over it.
+    pub fn paste_at_selection_end(self) -> Option<bool> {
+        match self {
+            Self::ReplaceSelection => Some(false),
+            Self::InsertAfter => Some(true),
+            Self::AnswerInNew => None,
+        }
+    }
+}
+
+/// Deliver an AI command result according to its behavior: paste over the
+/// selection in the previously focused app, paste after it, or open the
+/// exchange in a new chat. `user_text`, `provider_id` and `model` are only
+/// used for `answer_in_new`; provider and model default to the settings.
+#[tauri::command]
+pub async fn apply_command_result(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    behavior: String,
+    result: String,
+    user_text: Option<String>,
+    provider_id: Option<String>,
+    model: Option<String>,
+) -> Result<(), String> {
+    let behavior = CommandBehavior::parse(&behavior)?;
+    match behavior.paste_at_selection_end() {
+        Some(at_selection_end) => {
+            crate::clipboard::paste_into_origin(&app, result, at_selection_end).await
+        }
+        None => {
+            let settings = db.get_settings().map_err(|e| e.to_string())?;
+            let req = OpenInNewChatRequest {
+                user_text: user_text.unwrap_or_default(),
+                ai_response: result,
+                provider_id: provider_id
+                    .filter(|s| !s.is_empty())
+                    .unwrap_or(settings.default_provider_id),
+                model: model
+                    .filter(|s| !s.is_empty())
+                    .unwrap_or(settings.default_model),
+            };
+            // open_in_new_chat activates the app, which only works on the main thread.
+            let handle = app.clone();
+            app.run_on_main_thread(move || {
+                if let Err(e) = open_in_new_chat(handle.clone(), handle.state::<Database>(), req) {
+                    tracing::warn!("[apply_command_result] open in new chat failed: {}", e);
+                }
+            })
+            .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Create a new conversation containing the user query and AI response,
+/// then emit "open-conversation" to the main window so it navigates there.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenInNewChatRequest {
+    pub user_text: String,
+    pub ai_response: String,
+    pub provider_id: String,
+    pub model: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenConversationEvent {
+    pub conversation_id: String,
+}
+
+/// Create a conversation titled after `user_text` holding it as the first
+/// user message. Returns the conversation id.
+pub fn start_new_chat(
+    db: &Database,
+    user_text: &str,
+    provider_id: &str,
+    model: &str,
+    system_prompt: Option<&str>,
+) -> Result<String, String> {
+    let convo_id = uuid::Uuid::new_v4().to_string();
+    let title = truncate_chars(user_text.trim(), TITLE_MAX_CHARS);
+
+    db.create_conversation(&convo_id, &title, model, provider_id, system_prompt, None)
+        .map_err(|e| e.to_string())?;
+
+    let user_msg_id = uuid::Uuid::new_v4().to_string();
+    db.create_message(&user_msg_id, &convo_id, "user", user_text, None, None, 0)
+        .map_err(|e| e.to_string())?;
+    Ok(convo_id)
+}
+
+/// Whether the main window stays hidden at launch: when launched with
+/// `--hidden` (as login items are), or when `start_as_background` is on.
+pub fn should_start_hidden(launched_hidden: bool, settings: &db::AppSettings) -> bool {
+    launched_hidden || settings.start_as_background
+}
+
+/// Hide the overlay and bring the main window to the front.
+pub fn reveal_main_window(app: &tauri::AppHandle) {
+    // Hide the overlay panel first so it doesn't block the main window
+    #[cfg(target_os = "macos")]
+    {
+        let _ = crate::panel::hide_overlay_panel(app);
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        if let Some(win) = app.get_webview_window("overlay") {
+            let _ = win.hide();
+        }
+    }
+
+    // Show the main window and activate the app so it comes to front
+    if let Some(win) = app.get_webview_window("main") {
+        let _ = win.show();
+        let _ = win.set_focus();
+    }
+
+    // Activate the app (needed on macOS so the main window actually comes to front)
+    #[cfg(target_os = "macos")]
+    {
+        use objc2::MainThreadMarker;
+        use objc2_app_kit::NSApplication;
+        // Callers run this on the main thread
+        if let Some(mtm) = MainThreadMarker::new() {
+            let ns_app = NSApplication::sharedApplication(mtm);
+            #[allow(deprecated)]
+            ns_app.activateIgnoringOtherApps(true);
+        }
+    }
+}
+
+pub fn emit_open_conversation(app: &tauri::AppHandle, conversation_id: &str) {
+    let _ = app.emit_to(
+        "main",
+        "open-conversation",
+        OpenConversationEvent {
+            conversation_id: conversation_id.to_string(),
+        },
+    );
+}
+
+#[tauri::command]
+pub fn open_in_new_chat(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    req: OpenInNewChatRequest,
+) -> Result<String, String> {
+    let convo_id = start_new_chat(&db, &req.user_text, &req.provider_id, &req.model, None)?;
+    prune_after_create(&app, &db, &convo_id);
+
+    // Save assistant message
+    let assistant_msg_id = uuid::Uuid::new_v4().to_string();
+    db.create_message(
+        &assistant_msg_id,
+        &convo_id,
+        "assistant",
+        &req.ai_response,
+        Some(&req.model),
+        None,
+        1,
+    )
+    .map_err(|e| e.to_string())?;
+    events::conversation_created(&app, &convo_id);
+
+    // Emit event so the main window can navigate to the new conversation
+    emit_open_conversation(&app, &convo_id);
+    // IPC commands run on the main thread in Tauri
+    reveal_main_window(&app);
+
+    Ok(convo_id)
+}
+
+/// Like `OpenInNewChatRequest`, but the reply is generated by the backend.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenInNewChatStreamingRequest {
+    pub user_text: String,
+    pub provider_id: String,
+    pub model: String,
+    pub system_prompt: Option<String>,
+}
+
+/// Open a new conversation for `user_text` straight away, then stream the
+/// assistant reply into it over `on_event`, saving it once done. Returns the
+/// conversation id.
+#[tauri::command]
+pub async fn open_in_new_chat_streaming(
+    app: tauri::AppHandle,
+    db: State<'_, Database>,
+    req: OpenInNewChatStreamingRequest,
+    on_event: Channel<StreamEvent>,
+) -> Result<String, String> {
+    let settings = db.get_settings().map_err(|e| e.to_string())?;
+    let (convo_id, chat_messages, reply_sort_order) = start_streaming_chat(&db, &req, &settings)?;
+    events::conversation_created(&app, &convo_id);
+    prune_after_create(&app, &db, &convo_id);
+    emit_open_conversation(&app, &convo_id);
+    // Async commands run off the main thread; activation must not.
+    let handle = app.clone();
+    app.run_on_main_thread(move || reveal_main_window(&handle))
+        .map_err(|e| e.to_string())?;
+
+    let provider = db
+        .get_provider(&req.provider_id)
+        .map_err(|e| format!("Failed to load provider: {}", e))?;
+    check_token_budget(&db, &provider, &usage_day(chrono::Local::now()))?;
+    let mut config = provider_config(&db, provider, req.model, &settings);
+    if settings.log_raw_requests {
+        config.transcript = open_transcript(&app, &convo_id);
+    }
+
+    stream_and_save_reply(
+        &app,
+        &db,
+        &config,
+        &req.provider_id,
+        &chat_messages,
+        &convo_id,
+        reply_sort_order,
+        settings.response_token_warn_at,
+        &on_event,
+    )
+    .await?;
+    Ok(convo_id)
+}
+
+/// Create the conversation and user message for `open_in_new_chat_streaming`.
+/// Returns the conversation id, the messages to send and the reply's
+/// `sort_order`.
+fn start_streaming_chat(
+    db: &Database,
+    req: &OpenInNewChatStreamingRequest,
+    settings: &db::AppSettings,
+) -> Result<(String, Vec<ChatMessage>, i64), String> {
+    let convo_id = start_new_chat(
+        db,
+        &req.user_text,
+        &req.provider_id,
+        &req.model,
+        req.system_prompt.as_deref(),
+    )?;
+    let history = db.get_messages(&convo_id).map_err(|e| e.to_string())?;
+    let chat_messages = build_chat_messages(
+        compose_system_prompt(settings, req.system_prompt.as_deref()),
+        &history,
+        settings.max_context_messages.max(0) as usize,
+    );
+    Ok((convo_id, chat_messages, history.len() as i64))
+}
+
+#[tauri::command]
+pub fn save_ai_command(db: State<'_, Database>, command: db::AiCommand) -> Result<(), String> {
+    shortcuts::validate_optional_accelerator(command.keyboard_shortcut.as_deref())?;
+    db.save_ai_command(&command).map_err(|e| e.to_string())
+}
+
+/// Validate an accelerator string for live feedback in shortcut forms.
+#[tauri::command]
+pub fn validate_accelerator(accel: String) -> Result<(), String> {
+    shortcuts::validate_accelerator(&accel)
+}
+
+#[tauri::command]
+pub fn delete_ai_command(db: State<'_, Database>, id: String) -> Result<(), String> {
+    db.delete_ai_command(&id).map_err(|e| e.to_string())
+}
+
+/// Bring back the built-in AI commands. Existing ones are kept unless
+/// `overwrite` resets them. Returns how many were restored.
+#[tauri::command]
+pub fn restore_default_ai_commands(
+    db: State<'_, Database>,
+    overwrite: bool,
+) -> Result<usize, String> {
+    db.restore_default_ai_commands(overwrite)
+        .map_err(|e| e.to_string())
+}
+
+// ============================================
+// Assistant Commands
+// ============================================
+
+#[tauri::command]
+pub fn list_assistants(db: State<'_, Database>) -> Result<Vec<db::Assistant>, String> {
+    db.list_assistants().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn save_assistant(db: State<'_, Database>, assistant: db::Assistant) -> Result<(), String> {
+    db.save_assistant(&assistant).map_err(|e| e.to_string())
+}
+
+/// Copy an assistant as a starting point for a variant; returns the copy.
+#[tauri::command]
+pub fn duplicate_assistant(db: State<'_, Database>, id: String) -> Result<db::Assistant, String> {
+    db.duplicate_assistant(&id).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => format!("Assistant not found: {}", id),
+        e => e.to_string(),
+    })
+}
+
+#[tauri::command]
+pub fn delete_assistant(db: State<'_, Database>, id: String) -> Result<(), String> {
+    db.delete_assistant(&id).map_err(|e| e.to_string())
+}
+
+/// Bring back the built-in assistants. Existing ones are kept unless
+/// `overwrite` resets them. Returns how many were restored.
+#[tauri::command]
+pub fn restore_default_assistants(
+    db: State<'_, Database>,
+    overwrite: bool,
+) -> Result<usize, String> {
+    db.restore_default_assistants(overwrite)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::TempDb;
+    use crate::providers::tests::{http_response, mock_server};
+    use std::sync::{Arc, Mutex};
+
+    fn status(code: u16) -> StreamErrorKind {
+        StreamErrorKind::Status {
+            code,
+            body: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_streaming_falls_back_and_is_turned_off() {
+        let streamed = Arc::new(Mutex::new(0));
+        let streamed_count = streamed.clone();
+        let url = mock_server(move |request| {
+            if request.contains(r#""stream":true"#) {
+                *streamed_count.lock().unwrap() += 1;
+                http_response(
+                    "400 Bad Request",
+                    "application/json",
+                    r#"{"error":{"message":"stream is not supported"}}"#,
+                )
+            } else {
+                http_response(
+                    "200 OK",
+                    "application/json",
+                    r#"{"choices":[{"message":{"role":"assistant","content":"Hi"}}]}"#,
+                )
+            }
+        })
+        .await;
+        let db = TempDb::new("streaming-fallback");
+        let provider: db::Provider = serde_json::from_value(serde_json::json!({
+            "id": "mock",
+            "type": "openai_compatible",
+            "name": "Mock",
+            "apiKey": "sk-test",
+            "baseUrl": url,
+            "enabled": true,
+        }))
+        .unwrap();
+        db.save_provider(&provider).unwrap();
+        let settings = db.get_settings().unwrap();
+        let messages = [ChatMessage {
+            role: "user".into(),
+            content: "Hello".into(),
+        }];
+
+        let config = provider_config(&db, db.get_provider("mock").unwrap(), "m".into(), &settings);
+        assert!(config.supports_streaming);
+        let mut events = Vec::new();
+        providers::stream_chat(&config, &messages, |event| {
+            if let StreamEvent::StreamingDisabled { .. } = event {
+                save_streaming_disabled(&db, "mock");
+            }
+            events.push(event);
+        })
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            &events[1],
+            StreamEvent::StreamingDisabled { reason } if reason.contains("stream is not supported")
+        ));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, StreamEvent::Delta { content } if content == "Hi")));
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, StreamEvent::Error { .. })));
+        assert!(!db.get_provider("mock").unwrap().supports_streaming);
+        assert_eq!(*streamed.lock().unwrap(), 1);
+
+        // Later requests go straight to buffered mode.
+        let config = provider_config(&db, db.get_provider("mock").unwrap(), "m".into(), &settings);
+        let mut events = Vec::new();
+        providers::stream_chat(&config, &messages, |event| events.push(event))
+            .await
+            .unwrap();
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, StreamEvent::StreamingDisabled { .. })));
+        assert_eq!(*streamed.lock().unwrap(), 1);
+    }
+
+    /// Log output captured from the current thread.
+    #[derive(Clone, Default)]
+    struct CapturedLog(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_model_listing_logs_no_api_key() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        // A port nothing listens on.
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+        let db = TempDb::new("log-redaction");
+        let provider: db::Provider = serde_json::from_value(serde_json::json!({
+            "id": "gemini",
+            "type": "gemini",
+            "name": "Gemini",
+            "apiKey": "AIzaSecretKey",
+            "baseUrl": url,
+            "enabled": true,
+        }))
+        .unwrap();
+        db.save_provider(&provider).unwrap();
+
+        let log = CapturedLog::default();
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(logging::redacting(move || writer.clone()))
+                .with_ansi(false),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+        // Gemini sends the key as a query parameter, and the connection
+        // error quotes the request URL.
+        assert!(model_listing(&db, "gemini".into(), None, None, None)
+            .await
+            .is_err());
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        assert!(log.contains("[list_models] Error"), "{}", log);
+        assert!(log.contains("key=[REDACTED]"), "{}", log);
+        assert!(!log.contains("AIzaSecretKey"), "{}", log);
+    }
+
+    #[test]
+    fn transient_stream_errors() {
+        for kind in [
+            StreamErrorKind::Connection,
+            StreamErrorKind::Stalled,
+            status(429),
+            status(500),
+            status(503),
+        ] {
+            assert!(is_transient_stream_error(&kind), "{:?}", kind);
+        }
+        for kind in [
+            status(400),
+            status(401),
+            status(404),
+            StreamErrorKind::NotEventStream {
+                content_type: "application/json".into(),
+            },
+            StreamErrorKind::Other,
+        ] {
+            assert!(!is_transient_stream_error(&kind), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn connection_errors_are_classified_by_kind() {
+        use ConnectionTestReason::*;
+        let cases = [
+            (
+                "Stream error: Invalid status code: 401",
+                status(401),
+                AuthFailed,
+            ),
+            (
+                "Stream error: Invalid status code: 404",
+                status(404),
+                BadModel,
+            ),
+            (
+                "Stream error: Transport error",
+                StreamErrorKind::Connection,
+                Unreachable,
+            ),
+            ("stream stalled", StreamErrorKind::Stalled, Unreachable),
+            ("API key not configured", StreamErrorKind::Other, AuthFailed),
+            (
+                "Gemini blocked the prompt: SAFETY",
+                StreamErrorKind::Other,
+                Other,
+            ),
+        ];
+        for (message, kind, reason) in cases {
+            assert_eq!(
+                classify_connection_error(message, &kind),
+                reason,
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn command_attempt_retries_only_transient_errors_before_output() {
+        let mut attempt = CommandAttempt::default();
+        attempt.record(StreamEvent::Error {
+            message: "Stream error: Transport error: connection reset".into(),
+            kind: StreamErrorKind::Connection,
+        });
+        assert!(attempt.should_retry());
+
+        // The message alone doesn't make an error transient.
+        let mut attempt = CommandAttempt::default();
+        attempt.record(StreamEvent::error("Stream error: Transport error"));
+        assert!(!attempt.should_retry());
+
+        // Nor is anything retried once output has arrived.
+        let mut attempt = CommandAttempt::default();
+        attempt.record(StreamEvent::Delta {
+            content: "Hello".into(),
+        });
+        attempt.record(StreamEvent::Error {
+            message: "stream stalled".into(),
+            kind: StreamErrorKind::Stalled,
+        });
+        assert!(!attempt.should_retry());
+    }
+
+    #[test]
+    fn command_attempt_empty_response_is_an_error() {
+        let mut attempt = CommandAttempt::default();
+        attempt.record(StreamEvent::Done { total_tokens: 3 });
+        assert_eq!(
+            attempt.finish().unwrap_err(),
+            "AI returned an empty response"
+        );
+    }
+
+    #[test]
+    fn command_attempt_keeps_partial_output_on_error() {
+        let mut attempt = CommandAttempt::default();
+        for event in [
+            StreamEvent::Delta {
+                content: "Partial ".into(),
+            },
+            StreamEvent::Delta {
+                content: "text".into(),
+            },
+            StreamEvent::Error {
+                message: "Stream error: Transport error".into(),
+                kind: StreamErrorKind::Connection,
+            },
+        ] {
+            attempt.record(event);
+        }
+        let output = attempt.finish().unwrap();
+        assert_eq!(output.text, "Partial text");
+        assert!(output.incomplete);
+        assert_eq!(
+            output.error.as_deref(),
+            Some("Stream error: Transport error")
+        );
+    }
+
+    #[tokio::test]
+    async fn daily_cap_blocks_sends_until_the_day_rolls_over() {
+        let mut body = crate::providers::tests::openai_sse(&["Hi"]);
+        body.push_str(
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":4,\"total_tokens\":7}}\n\ndata: [DONE]\n\n",
+        );
+        let url = mock_server(move |_| http_response("200 OK", "text/event-stream", &body)).await;
+        let db = TempDb::new("daily-cap");
+        let provider: db::Provider = serde_json::from_value(serde_json::json!({
+            "id": "mock",
+            "type": "openai_compatible",
+            "name": "Mock",
+            "apiKey": "sk-test",
+            "baseUrl": url,
+            "enabled": true,
+            "dailyTokenCap": 10,
+        }))
+        .unwrap();
+        db.save_provider(&provider).unwrap();
+        let settings = db.get_settings().unwrap();
+        let config = provider_config(&db, provider.clone(), "m".into(), &settings);
+        let messages = [ChatMessage {
+            role: "user".into(),
+            content: "Hello".into(),
+        }];
+        // The same gate and accounting the commands run, on a given day.
+        let send = |day: &'static str| {
+            let (db, provider, config, messages) = (&db, &provider, &config, &messages);
+            async move {
+                check_token_budget(db, provider, day)?;
+                let mut attempt = CommandAttempt::default();
+                providers::stream_chat(config, messages, |event| attempt.record(event)).await?;
+                if let Some(tokens) = attempt.done_tokens {
+                    record_token_usage(db, &provider.id, day, tokens);
+                }
+                Ok::<_, String>(())
+            }
+        };
+
+        send("2026-03-01").await.unwrap();
+        send("2026-03-01").await.unwrap();
+        assert_eq!(db.daily_tokens("mock", "2026-03-01").unwrap(), 14);
+        let err = send("2026-03-01").await.unwrap_err();
+        assert!(err.contains("14 of 10"), "{err}");
+        assert_eq!(db.daily_tokens("mock", "2026-03-01").unwrap(), 14);
+
+        send("2026-03-02").await.unwrap();
+        assert_eq!(db.daily_tokens("mock", "2026-03-02").unwrap(), 7);
+    }
+
+    #[test]
+    fn usage_day_follows_the_local_date() {
+        use chrono::TimeZone;
+        let before = chrono::Local
+            .with_ymd_and_hms(2026, 3, 1, 23, 59, 59)
+            .unwrap();
+        assert_eq!(usage_day(before), "2026-03-01");
+        assert_eq!(
+            usage_day(before + chrono::Duration::seconds(1)),
+            "2026-03-02"
+        );
+    }
+
+    #[test]
+    fn system_prompt_prefix_and_suffix_combinations() {
+        let with = |prefix: &str, suffix: &str| db::AppSettings {
+            global_system_prefix: prefix.into(),
+            global_system_suffix: suffix.into(),
+            ..Default::default()
+        };
+        let cases: &[(&str, Option<&str>, &str, Option<&str>)] = &[
+            ("", None, "", None),
+            ("", Some("  "), "", None),
+            ("", Some("Prompt"), "", Some("Prompt")),
+            ("Pre", None, "", Some("Pre")),
+            ("", None, "Suf", Some("Suf")),
+            ("Pre", Some("Prompt"), "", Some("Pre\n\nPrompt")),
+            ("", Some("Prompt"), "Suf", Some("Prompt\n\nSuf")),
+            ("Pre", None, "Suf", Some("Pre\n\nSuf")),
+            (
+                " Pre\n",
+                Some("\nPrompt "),
+                "Suf ",
+                Some("Pre\n\nPrompt\n\nSuf"),
+            ),
+        ];
+        for &(prefix, prompt, suffix, expected) in cases {
+            assert_eq!(
+                compose_system_prompt(&with(prefix, suffix), prompt).as_deref(),
+                expected,
+                "prefix {prefix:?}, prompt {prompt:?}, suffix {suffix:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn favorite_models_sort_first_and_survive_a_refresh() {
+        let refreshed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let refreshed_flag = refreshed.clone();
+        let url = mock_server(move |_| {
+            let ids: &[&str] = if refreshed_flag.load(std::sync::atomic::Ordering::SeqCst) {
+                &["gpt-4o", "gpt-4o-mini", "gpt-5", "o3"]
+            } else {
+                &["gpt-4o", "gpt-4o-mini", "o3"]
+            };
+            let data: Vec<_> = ids
+                .iter()
+                .map(|id| serde_json::json!({ "id": id }))
+                .collect();
+            http_response(
+                "200 OK",
+                "application/json",
+                &serde_json::json!({ "data": data }).to_string(),
+            )
+        })
+        .await;
+        let db = TempDb::new("favorite-models");
+        let mut provider = crate::db::tests::test_provider("mock");
+        provider.base_url = Some(url);
+        db.save_provider(&provider).unwrap();
+        db.add_favorite_model("mock", "o3").unwrap();
+        db.add_favorite_model("mock", "gpt-4o-mini").unwrap();
+
+        let listed = |favorites_first| {
+            let db = &db;
+            async move {
+                model_listing(db, "mock".into(), None, Some(favorites_first), None)
+                    .await
+                    .unwrap()
+                    .models
+                    .into_iter()
+                    .map(|m| (m.id, m.is_favorite))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let expected = |ids: &[(&str, bool)]| {
+            ids.iter()
+                .map(|&(id, favorite)| (id.to_string(), favorite))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            listed(true).await,
+            expected(&[("gpt-4o-mini", true), ("o3", true), ("gpt-4o", false)])
+        );
+
+        refreshed.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(
+            listed(true).await,
+            expected(&[
+                ("gpt-4o-mini", true),
+                ("o3", true),
+                ("gpt-4o", false),
+                ("gpt-5", false),
+            ])
+        );
+        assert_eq!(
+            listed(false).await,
+            expected(&[
+                ("gpt-4o", false),
+                ("gpt-4o-mini", true),
+                ("gpt-5", false),
+                ("o3", true),
+            ])
+        );
+        assert_eq!(
+            db.list_favorite_models("mock").unwrap(),
+            vec!["o3", "gpt-4o-mini"]
+        );
+    }
+
+    #[test]
+    fn titles_truncate_on_character_boundaries() {
+        let emoji = format!("{}😀😀", "a".repeat(TITLE_MAX_CHARS - 1));
+        let title = truncate_chars(&emoji, TITLE_MAX_CHARS);
+        assert_eq!(title, format!("{}😀…", "a".repeat(TITLE_MAX_CHARS - 1)));
+
+        let cjk = "漢字かな交じり文".repeat(10);
+        let title = truncate_chars(&cjk, TITLE_MAX_CHARS);
+        assert_eq!(title.chars().count(), TITLE_MAX_CHARS + 1);
+        assert!(cjk.starts_with(title.trim_end_matches('…')));
+
+        let exact = "字".repeat(TITLE_MAX_CHARS);
+        assert_eq!(truncate_chars(&exact, TITLE_MAX_CHARS), exact);
+        assert_eq!(truncate_chars("短い", TITLE_MAX_CHARS), "短い");
+        assert_eq!(truncate_chars("", TITLE_MAX_CHARS), "");
+        assert_eq!(truncate_chars("👨‍👩‍👧", 1), "👨…");
+    }
+
+    #[test]
+    fn appended_messages_keep_their_order_and_roles() {
+        let db = TempDb::new("append-message");
+        db.create_conversation("c1", "t", "m", "p", None, None)
+            .unwrap();
+        let turns = [
+            ("system", "Be brief"),
+            ("user", "Hi"),
+            ("assistant", "Hello"),
+            ("user", "Bye"),
+        ];
+        for (role, content) in turns {
+            let model = (role == "assistant").then_some("gpt-4o");
+            append_to_conversation(&db, "c1", role, content, model).unwrap();
+        }
+
+        let messages: Vec<_> = db
+            .get_messages("c1")
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.role, m.content, m.sort_order, m.model))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                ("system".into(), "Be brief".into(), 0, None),
+                ("user".into(), "Hi".into(), 1, None),
+                ("assistant".into(), "Hello".into(), 2, Some("gpt-4o".into())),
+                ("user".into(), "Bye".into(), 3, None),
+            ]
+        );
+
+        let err = append_to_conversation(&db, "c1", "tool", "x", None).unwrap_err();
+        assert!(err.contains("Invalid role 'tool'"), "{err}");
+        let err = append_to_conversation(&db, "missing", "user", "x", None).unwrap_err();
+        assert!(err.contains("Conversation not found"), "{err}");
+        assert_eq!(db.get_messages("c1").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn command_behaviors_dispatch_to_the_right_action() {
+        let cases = [
+            (
+                "replace_selection",
+                CommandBehavior::ReplaceSelection,
+                Some(false),
+            ),
+            ("insert_after", CommandBehavior::InsertAfter, Some(true)),
+            ("answer_in_new", CommandBehavior::AnswerInNew, None),
+        ];
+        for (name, behavior, paste_at_end) in cases {
+            assert_eq!(CommandBehavior::parse(name), Ok(behavior));
+            assert_eq!(behavior.paste_at_selection_end(), paste_at_end, "{name}");
+        }
+        let err = CommandBehavior::parse("Replace_Selection").unwrap_err();
+        assert_eq!(err, "Unknown command behavior: Replace_Selection");
+        assert!(CommandBehavior::parse("").is_err());
+    }
+
+    #[test]
+    fn new_conversations_inherit_the_default_assistant() {
+        let db = TempDb::new("default-assistant");
+        let mut assistant = db.get_default_assistant().unwrap().unwrap();
+        assistant.system_prompt = "You are a pirate.".into();
+        assistant.provider_id = Some("assistant-provider".into());
+        assistant.model = Some("assistant-model".into());
+        db.save_assistant(&assistant).unwrap();
+        let request = |system_prompt: Option<&str>, model: &str| CreateConversationRequest {
+            title: None,
+            model: model.into(),
+            provider_id: String::new(),
+            system_prompt: system_prompt.map(Into::into),
+            folder_id: None,
+        };
+
+        let inherited = new_conversation(&db, request(None, "")).unwrap();
+        assert_eq!(inherited.title, "New Chat");
+        assert_eq!(
+            inherited.system_prompt.as_deref(),
+            Some("You are a pirate.")
+        );
+        assert_eq!(inherited.provider_id, "assistant-provider");
+        assert_eq!(inherited.model, "assistant-model");
+
+        let explicit = new_conversation(&db, request(Some("Be terse."), "gpt-4o")).unwrap();
+        assert_eq!(explicit.system_prompt.as_deref(), Some("Be terse."));
+        assert_eq!(explicit.provider_id, "assistant-provider");
+        assert_eq!(explicit.model, "gpt-4o");
+
+        assistant.is_default = false;
+        db.save_assistant(&assistant).unwrap();
+        let settings = db.get_settings().unwrap();
+        let plain = new_conversation(&db, request(None, "")).unwrap();
+        assert_eq!(
+            plain.system_prompt.as_deref(),
+            Some(settings.default_system_prompt.as_str())
+        );
+        assert_eq!(plain.provider_id, settings.default_provider_id);
+        assert_eq!(plain.model, settings.default_model);
+    }
+
+    #[test]
+    fn new_conversations_fall_back_to_the_default_system_prompt() {
+        let db = TempDb::new("default-system-prompt");
+        let mut assistant = db.get_default_assistant().unwrap().unwrap();
+        assistant.system_prompt = String::new();
+        db.save_assistant(&assistant).unwrap();
+        let mut settings = db.get_settings().unwrap();
+        settings.default_system_prompt = "Answer in French.".into();
+        db.save_settings(&settings).unwrap();
+        let request = || CreateConversationRequest {
+            title: None,
+            model: String::new(),
+            provider_id: String::new(),
+            system_prompt: None,
+            folder_id: None,
+        };
+
+        let conversation = new_conversation(&db, request()).unwrap();
+        assert_eq!(
+            conversation.system_prompt.as_deref(),
+            Some("Answer in French.")
+        );
+
+        settings.default_system_prompt = String::new();
+        db.save_settings(&settings).unwrap();
+        let conversation = new_conversation(&db, request()).unwrap();
+        assert_eq!(conversation.system_prompt, None);
+    }
+
+    #[test]
+    fn changing_provider_type_changes_the_endpoint() {
+        let db = TempDb::new("provider-type");
+        let mut provider = crate::db::tests::test_provider("p1");
+        provider.provider_type = "openai".into();
+        provider.base_url = None;
+        db.save_provider(&provider).unwrap();
+        db.create_conversation("c1", "t", "gpt-4o", "p1", None, None)
+            .unwrap();
+        let settings = db.get_settings().unwrap();
+        let endpoint = |provider: db::Provider| {
+            provider_config(&db, provider, "m".into(), &settings).get_endpoint()
+        };
+        assert_eq!(
+            endpoint(db.get_provider("p1").unwrap()),
+            "https://api.openai.com/v1/chat/completions"
+        );
+
+        let updated = db.update_provider_type("p1", "anthropic").unwrap();
+        assert_eq!(updated.provider_type, "anthropic");
+        assert_eq!(updated.api_key.as_deref(), Some("sk-test"));
+        assert_eq!(
+            endpoint(db.get_provider("p1").unwrap()),
+            "https://api.anthropic.com/v1/messages"
+        );
+        assert_eq!(db.get_conversation("c1").unwrap().provider_id, "p1");
+        assert!(matches!(
+            db.update_provider_type("missing", "openai"),
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        ));
+    }
+
+    #[test]
+    fn summary_prompt_covers_history_since_the_last_summary() {
+        let db = TempDb::new("summary-prompt");
+        let conversation = db
+            .create_conversation("c1", "t", "gpt-4o", "p", None, None)
+            .unwrap();
+        for (i, (role, content)) in [
+            ("user", "What is Rust?"),
+            ("assistant", "A systems language."),
+            ("user", "off the record"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            db.create_message(&format!("m{i}"), "c1", role, content, None, None, i as i64)
+                .unwrap();
+        }
+        db.set_message_include_in_context("m2", false).unwrap();
+
+        let request = summary_request(&db.get_messages("c1").unwrap());
+        assert_eq!(request.len(), 2);
+        assert_eq!(request[0].role, "system");
+        assert_eq!(request[0].content, SUMMARY_SYSTEM_PROMPT);
+        assert_eq!(
+            request[1].content,
+            "User: What is Rust?\n\nAssistant: A systems language."
+        );
+
+        insert_summary(&db, &conversation, "Rust was explained.").unwrap();
+        db.create_message("m4", "c1", "user", "And Go?", None, None, 4)
+            .unwrap();
+        let messages = db.get_messages("c1").unwrap();
+        let summary = &messages[3];
+        assert_eq!(summary.role, "assistant");
+        assert_eq!(summary.model.as_deref(), Some("gpt-4o"));
+        assert!(is_summary(summary));
+
+        let request = summary_request(&messages);
+        assert_eq!(
+            request[1].content,
+            "Earlier summary: Rust was explained.\n\nUser: And Go?"
+        );
+        // Later sends carry the summary instead of the turns it replaced.
+        let chat = build_chat_messages(None, &messages, 0);
+        let chat: Vec<_> = chat
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_str()))
+            .collect();
+        assert_eq!(
+            chat,
+            [
+                (
+                    "system",
+                    "Summary of the earlier conversation:\nRust was explained."
+                ),
+                ("user", "And Go?"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_test_reason_for_each_response() {
+        let url = mock_server(|request| {
+            let model = request
+                .split(r#""model":""#)
+                .nth(1)
+                .and_then(|rest| rest.split('"').next())
+                .unwrap_or_default();
+            match model {
+                "ok" => http_response(
+                    "200 OK",
+                    "text/event-stream",
+                    &crate::providers::tests::openai_sse(&["Hi"]),
+                ),
+                "unauthorized" => http_response(
+                    "401 Unauthorized",
+                    "application/json",
+                    r#"{"error":{"message":"Incorrect API key provided"}}"#,
+                ),
+                "forbidden" => http_response("403 Forbidden", "application/json", "{}"),
+                "missing" => http_response(
+                    "404 Not Found",
+                    "application/json",
+                    r#"{"error":{"message":"The model does not exist"}}"#,
+                ),
+                _ => http_response("500 Internal Server Error", "text/plain", "boom"),
+            }
+        })
+        .await;
+        let db = TempDb::new("connection-test");
+        let save = |id: &str, base_url: &str| {
+            let mut provider = crate::db::tests::test_provider(id);
+            provider.base_url = Some(base_url.to_string());
+            provider.default_model = Some(id.to_string());
+            db.save_provider(&provider).unwrap();
+        };
+        for id in ["ok", "unauthorized", "forbidden", "missing", "broken"] {
+            save(id, &url);
+        }
+        save("offline", "http://127.0.0.1:9/v1");
+
+        use ConnectionTestReason::*;
+        for (id, reason) in [
+            ("ok", Ok),
+            ("unauthorized", AuthFailed),
+            ("forbidden", AuthFailed),
+            ("missing", BadModel),
+            ("broken", Other),
+            ("offline", Unreachable),
+        ] {
+            let result = connection_test(&db, id).await.unwrap();
+            assert_eq!(result.reason, reason, "{id}: {:?}", result.error);
+            assert_eq!(result.success, reason == Ok, "{id}");
+            assert_eq!(result.error.is_none(), reason == Ok, "{id}");
+            assert_eq!(result.model, id);
+            assert!(!result.suggest_disable_streaming, "{id}");
+        }
+    }
+
+    #[test]
+    fn regenerating_replaces_only_the_target_reply() {
+        let db = TempDb::new("regenerate");
+        db.create_conversation("c", "t", "m", "p", Some("Be brief"), None)
+            .unwrap();
+        for (id, role, content, order) in [
+            ("1", "user", "q1", 0),
+            ("2", "assistant", "a1", 1),
+            ("3", "user", "q2", 2),
+            ("4", "assistant", "a2", 3),
+        ] {
+            db.create_message(id, "c", role, content, None, None, order)
+                .unwrap();
+        }
+        db.set_message_metadata("2", &serde_json::json!({ "toolCalls": [] }))
+            .unwrap();
+        let settings = db.get_settings().unwrap();
+
+        let (conversation, request) = regeneration_request(&db, "2", &settings).unwrap();
+        assert_eq!(conversation.id, "c");
+        let request: Vec<_> = request
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_str()))
+            .collect();
+        assert_eq!(request, [("system", "Be brief"), ("user", "q1")]);
+
+        db.replace_message_content("2", "a1 again", Some("m2"), Some(5))
+            .unwrap();
+        let messages: Vec<_> = db
+            .get_messages("c")
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.id, m.content, m.sort_order))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                ("1".to_string(), "q1".to_string(), 0),
+                ("2".to_string(), "a1 again".to_string(), 1),
+                ("3".to_string(), "q2".to_string(), 2),
+                ("4".to_string(), "a2".to_string(), 3),
+            ]
+        );
+        let replaced = db.get_message("2").unwrap();
+        assert_eq!(replaced.model.as_deref(), Some("m2"));
+        assert_eq!(replaced.token_count, Some(5));
+        assert!(replaced.metadata.is_none());
+
+        let err = regeneration_request(&db, "3", &settings).unwrap_err();
+        assert_eq!(err, "Only assistant messages can be regenerated");
+        assert!(regeneration_request(&db, "missing", &settings).is_err());
+    }
+
+    #[test]
+    fn config_export_round_trips_into_a_fresh_database() {
+        let source = TempDb::new("config-source");
+        let mut provider = crate::db::tests::test_provider("p1");
+        provider.auth_style = "x_api_key_header".into();
+        provider.default_max_tokens = Some(2048);
+        source.save_provider(&provider).unwrap();
+        source
+            .save_provider(&crate::db::tests::test_provider("p2"))
+            .unwrap();
+        let mut settings = source.get_settings().unwrap();
+        settings.theme = "dark".into();
+        settings.font_size = 18;
+        settings.default_provider_id = "p1".into();
+        source.save_settings(&settings).unwrap();
+
+        let json = serde_json::to_string(&config_document(&source, false).unwrap()).unwrap();
+        let document: ConfigDocument = serde_json::from_str(&json).unwrap();
+        let target = TempDb::new("config-target");
+        let (report, _) = write_config_document(&target, &document, false).unwrap();
+
+        assert_eq!(report.providers_imported, 2);
+        assert_eq!(report.settings_skipped, 0);
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        let as_json = |db: &Database| {
+            (
+                serde_json::to_value(db.list_providers().unwrap()).unwrap(),
+                serde_json::to_value(db.get_settings().unwrap()).unwrap(),
+            )
+        };
+        assert_eq!(as_json(&target), as_json(&source));
+
+        // Existing providers are kept unless overwriting.
+        let redacted = config_document(&source, true).unwrap();
+        assert!(redacted.providers.iter().all(|p| p.api_key.is_none()));
+        let (report, _) = write_config_document(&target, &redacted, false).unwrap();
+        assert_eq!(
+            (report.providers_imported, report.providers_skipped),
+            (0, 2)
+        );
+        assert_eq!(
+            target.get_provider("p1").unwrap().api_key.as_deref(),
+            Some("sk-test")
+        );
+
+        let mut unknown = ConfigDocument {
+            version: Some(CONFIG_VERSION),
+            providers: Vec::new(),
+            settings: serde_json::Map::new(),
+        };
+        unknown.settings.insert("fontSize".into(), 20.into());
+        unknown.settings.insert("bogus".into(), true.into());
+        let (report, previous) = write_config_document(&target, &unknown, false).unwrap();
+        assert_eq!((report.settings_imported, report.settings_skipped), (1, 1));
+        assert_eq!(previous.font_size, 18);
+        assert_eq!(target.get_settings().unwrap().font_size, 20);
+
+        unknown.version = Some(CONFIG_VERSION + 1);
+        assert!(write_config_document(&target, &unknown, false).is_err());
+    }
+
+    #[test]
+    fn config_import_validates_providers_like_the_editor() {
+        let db = TempDb::new("config-invalid");
+        let mut bad_headers = crate::db::tests::test_provider("bad-headers");
+        bad_headers
+            .custom_headers
+            .insert("X-Token".into(), "${TOKEN".into());
+        let mut bad_body = crate::db::tests::test_provider("bad-body");
+        bad_body.extra_body = Some(serde_json::json!([1, 2]));
+        let mut bad_cap = crate::db::tests::test_provider("bad-cap");
+        bad_cap.daily_token_cap = Some(0);
+
+        for provider in [bad_headers, bad_body, bad_cap] {
+            let id = provider.id.clone();
+            let document = ConfigDocument {
+                version: Some(CONFIG_VERSION),
+                providers: vec![crate::db::tests::test_provider("good"), provider],
+                settings: serde_json::Map::new(),
+            };
+            let err = write_config_document(&db, &document, false).unwrap_err();
+            assert!(err.starts_with(&format!("Provider '{}': ", id)), "{}", err);
+        }
+        assert!(db.list_providers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn diagnostics_populate_on_a_fresh_database() {
+        let db = TempDb::new("diagnostics");
+
+        let report = collect_diagnostics(&db, "1.2.3".into()).unwrap();
+        assert_eq!(report.app_version, "1.2.3");
+        assert_eq!(report.providers_configured, report.providers_enabled);
+        assert_eq!(report.database.table_counts.get("messages"), Some(&0));
+        assert_eq!(report.database.table_counts.get("conversations"), Some(&0));
+        assert!(!report.database.journal_mode.is_empty());
+        assert!(!report.log_level.is_empty());
+    }
+
+    #[test]
+    fn sends_fall_back_to_the_stored_system_prompt() {
+        let db = TempDb::new("stored-system-prompt");
+        db.create_conversation("c1", "Chat", "m", "p", Some("Answer in French."), None)
+            .unwrap();
+        db.create_message("u1", "c1", "user", "Hi", None, None, 0)
+            .unwrap();
+        let settings = db::AppSettings::default();
+        let mut req = SendMessageRequest {
+            conversation_id: "c1".into(),
+            content: "Hi".into(),
+            model: "m".into(),
+            provider_id: "p".into(),
+            system_prompt: None,
+            tools: None,
+            client_message_id: None,
+            prefill: None,
+        };
+
+        let (_, history, messages) = send_context(&db, &req, &settings).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, "system");
+        assert_eq!(messages[0].content, "Answer in French.");
+        assert_eq!(messages[1].content, "Hi");
+
+        req.system_prompt = Some("Answer in German.".into());
+        let (_, _, messages) = send_context(&db, &req, &settings).unwrap();
+        assert_eq!(messages[0].content, "Answer in German.");
+    }
+
+    #[tokio::test]
+    async fn excluded_messages_are_left_out_of_the_request() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = mock_server(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            http_response(
+                "200 OK",
+                "text/event-stream",
+                &crate::providers::tests::openai_sse(&["Ok"]),
+            )
+        })
+        .await;
+        let db = TempDb::new("include-in-context");
+        let mut provider = crate::db::tests::test_provider("mock");
+        provider.base_url = Some(url);
+        db.save_provider(&provider).unwrap();
+        db.create_conversation("c1", "Chat", "m", "mock", None, None)
+            .unwrap();
+        for (i, (role, content)) in [
+            ("user", "Keep this question"),
+            ("assistant", "NOISY TOOL DUMP"),
+            ("user", "And this one"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            db.create_message(
+                &format!("m{}", i),
+                "c1",
+                role,
+                content,
+                None,
+                None,
+                i as i64,
+            )
+            .unwrap();
+        }
+        db.set_message_include_in_context("m1", false).unwrap();
+        let settings = db.get_settings().unwrap();
+        let req = SendMessageRequest {
+            conversation_id: "c1".into(),
+            content: "And this one".into(),
+            model: "m".into(),
+            provider_id: "mock".into(),
+            system_prompt: None,
+            tools: None,
+            client_message_id: None,
+            prefill: None,
+        };
+
+        let (_, history, chat_messages) = send_context(&db, &req, &settings).unwrap();
+        assert_eq!(history.len(), 3);
+        let config = provider_config(&db, db.get_provider("mock").unwrap(), "m".into(), &settings);
+        providers::stream_chat(&config, &chat_messages, |_| {})
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(
+            requests[0].contains("Keep this question"),
+            "{}",
+            requests[0]
+        );
+        assert!(requests[0].contains("And this one"), "{}", requests[0]);
+        assert!(!requests[0].contains("NOISY TOOL DUMP"), "{}", requests[0]);
+    }
+
+    #[test]
+    fn trimming_keeps_the_system_prompt_and_sticky_messages() {
+        let db = TempDb::new("sticky-trim");
+        db.create_conversation("c1", "Chat", "m", "p", Some("Be brief."), None)
+            .unwrap();
+        for (i, (role, content)) in [
+            ("user", "First question"),
+            ("assistant", "Remember: the code word is kiwi"),
+            ("user", "Second question"),
+            ("assistant", "Second answer"),
+            ("user", "Latest question"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            db.create_message(
+                &format!("m{}", i),
+                "c1",
+                role,
+                content,
+                None,
+                None,
+                i as i64,
+            )
+            .unwrap();
+        }
+        db.set_message_sticky("m1", true).unwrap();
+        let conversation = db.get_conversation("c1").unwrap();
+
+        let messages = build_chat_messages(
+            conversation.system_prompt,
+            &db.get_messages("c1").unwrap(),
+            2,
+        );
+        let sent: Vec<(&str, &str)> = messages
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_str()))
+            .collect();
+        assert_eq!(
+            sent,
+            [
+                ("system", "Be brief."),
+                ("assistant", "Remember: the code word is kiwi"),
+                ("assistant", "Second answer"),
+                ("user", "Latest question"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelling_overlay_streams_stops_the_stream_loop() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Sends one delta, then holds the connection open without finishing.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 8192];
+            let _ = socket.read(&mut buf).await;
+            let first = r#"data: {"choices":[{"delta":{"content":"Hel"}}]}"#;
+            let _ = socket
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n{}\n\n",
+                        first
+                    )
+                    .as_bytes(),
+                )
+                .await;
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+        });
+        let mut config = crate::providers::tests::mock_config(format!("http://{}/v1", addr));
+        let cancel = providers::CancelToken::default();
+        config.cancel = Some(cancel.clone());
+        let registration = OverlayStream::register(cancel);
+
+        let (first_delta, received) = tokio::sync::oneshot::channel();
+        let mut first_delta = Some(first_delta);
+        let stream = tokio::spawn(async move {
+            let mut events = Vec::new();
+            providers::stream_chat(
+                &config,
+                &[ChatMessage {
+                    role: "user".into(),
+                    content: "Hi".into(),
+                }],
+                |event| {
+                    if matches!(event, StreamEvent::Delta { .. }) {
+                        if let Some(tx) = first_delta.take() {
+                            let _ = tx.send(());
+                        }
+                    }
+                    events.push(event);
+                },
+            )
+            .await
+            .map(|_| events)
+        });
+
+        received.await.unwrap();
+        assert!(cancel_overlay_streams() >= 1);
+        let events = tokio::time::timeout(std::time::Duration::from_secs(5), stream)
+            .await
+            .expect("stream loop stops once cancelled")
+            .unwrap()
+            .unwrap();
+        assert!(events.iter().any(
+            |e| matches!(e, StreamEvent::Error { message, .. } if message == "Stream cancelled")
+        ));
+        assert!(!events.iter().any(|e| matches!(e, StreamEvent::Done { .. })));
+
+        let id = registration.id.clone();
+        drop(registration);
+        assert!(!overlay_streams().contains_key(&id));
+    }
+
+    #[tokio::test]
+    async fn auto_title_never_overwrites_a_user_title() {
+        let db = TempDb::new("auto-title-manual");
+        let settings = db::AppSettings {
+            auto_title_mode: "truncate".into(),
+            ..Default::default()
+        };
+        let req = |conversation_id: &str| SendMessageRequest {
+            conversation_id: conversation_id.into(),
+            content: "Plan a trip to Kyoto".into(),
+            model: "m".into(),
+            provider_id: "p".into(),
+            system_prompt: None,
+            tools: None,
+            client_message_id: None,
+            prefill: None,
+        };
+        for id in ["renamed", "fresh"] {
+            db.create_conversation(id, "New Chat", "m", "p", None, None)
+                .unwrap();
+            db.create_message(
+                &format!("{}-u", id),
+                id,
+                "user",
+                "Plan a trip to Kyoto",
+                None,
+                None,
+                0,
+            )
+            .unwrap();
+        }
+        db.update_conversation_title("renamed", "Japan 2025")
+            .unwrap();
+
+        let history = db.get_messages("renamed").unwrap();
+        assert!(
+            !apply_auto_title(&db, &settings, &req("renamed"), &history, "Sure!")
+                .await
+                .unwrap()
+        );
+        assert_eq!(db.get_conversation("renamed").unwrap().title, "Japan 2025");
+
+        let history = db.get_messages("fresh").unwrap();
+        assert!(
+            apply_auto_title(&db, &settings, &req("fresh"), &history, "Sure!")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            db.get_conversation("fresh").unwrap().title,
+            "Plan a trip to Kyoto"
+        );
+        assert!(!db.is_title_manual("fresh").unwrap());
+    }
+
+    #[test]
+    fn template_braces_must_balance() {
+        assert_eq!(
+            template_variables("Hi {{name}}, from {{team}} and {{name}}"),
+            Ok(vec!["name".to_string(), "team".to_string()])
+        );
+        assert_eq!(
+            template_variables(r#"Reply as JSON: {"ok": true}"#),
+            Ok(vec![])
+        );
+
+        assert_eq!(
+            template_variables("Hi {{name} there"),
+            Err("Placeholder at position 3 is closed with '}' instead of '}}'".to_string())
+        );
+        assert_eq!(
+            template_variables("Hi {{name}"),
+            Err("Unclosed placeholder '{{' at position 3".to_string())
+        );
+        assert_eq!(
+            template_variables("Hi {name}} there"),
+            Err("Placeholder at position 3 is opened with '{' instead of '{{'".to_string())
+        );
+        assert_eq!(
+            template_variables("done }}"),
+            Err("Unmatched '}}' at position 5".to_string())
+        );
+        assert_eq!(
+            template_variables("{{a{{b}}}}"),
+            Err("Nested placeholder at position 3".to_string())
+        );
+        assert_eq!(
+            template_variables("{{{{b}}}}"),
+            Err("Nested placeholder at position 2".to_string())
+        );
+    }
+
+    #[test]
+    fn streaming_new_chat_saves_the_question_before_the_reply() {
+        let db = TempDb::new("new-chat-streaming");
+        let req = OpenInNewChatStreamingRequest {
+            user_text: "Explain monads".into(),
+            provider_id: "p".into(),
+            model: "m".into(),
+            system_prompt: Some("Be brief.".into()),
+        };
+
+        let (id, chat_messages, reply_sort_order) =
+            start_streaming_chat(&db, &req, &db::AppSettings::default()).unwrap();
+        let conversation = db.get_conversation(&id).unwrap();
+        assert_eq!(conversation.title, "Explain monads");
+        assert_eq!(conversation.system_prompt.as_deref(), Some("Be brief."));
+        let saved = db.get_messages(&id).unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!((saved[0].role.as_str(), saved[0].sort_order), ("user", 0));
+        let sent: Vec<_> = chat_messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(sent, ["Be brief.", "Explain monads"]);
+
+        // The reply streams in after the question.
+        assert_eq!(reply_sort_order, 1);
+        db.begin_streaming_message("reply", &id, "m", reply_sort_order)
+            .unwrap();
+        let roles: Vec<_> = db
+            .get_messages(&id)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.role)
+            .collect();
+        assert_eq!(roles, ["user", "assistant"]);
+    }
+
+    #[test]
+    fn shared_providers_hold_no_secrets() {
+        let mut keyed = crate::db::tests::test_provider("keyed");
+        keyed.api_key = Some("sk-live-SECRET".into());
+        keyed
+            .custom_headers
+            .insert("X-Token".into(), "tok-SECRET".into());
+        keyed
+            .custom_headers
+            .insert("X-Gateway".into(), "${GATEWAY_TOKEN}".into());
+        let mut keyless = crate::db::tests::test_provider("keyless");
+        keyless.api_key = None;
+
+        let json = redacted_providers_json(vec![keyed, keyless]).unwrap();
+        assert!(!json.contains("SECRET"), "{json}");
+        let shared: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(shared[0]["apiKey"], serde_json::Value::Null);
+        assert_eq!(shared[0]["hasKey"], true);
+        assert_eq!(shared[0]["redactedHeaders"], serde_json::json!(["X-Token"]));
+        assert_eq!(shared[0]["customHeaders"]["X-Gateway"], "${GATEWAY_TOKEN}");
+        assert_eq!(shared[1]["hasKey"], false);
+    }
+
+    #[test]
+    fn shared_providers_cannot_read_environment_variables() {
+        let db = TempDb::new("import-shared");
+        let mut provider = crate::db::tests::test_provider("shared");
+        provider.base_url = Some("https://attacker.example/v1".into());
+        provider
+            .custom_headers
+            .insert("X-Foo".into(), "${HOME}".into());
+        provider
+            .custom_headers
+            .insert("X-Client".into(), "zitong".into());
+        let json = serde_json::to_string(&[SharedProvider {
+            provider,
+            has_key: false,
+            redacted_headers: Vec::new(),
+        }])
+        .unwrap();
+
+        let report = import_shared_provider_list(&db, &json, &Default::default(), false).unwrap();
+
+        assert_eq!(report.providers_imported, 1);
+        let saved = db.get_provider("shared").unwrap();
+        assert_eq!(saved.custom_headers.get("X-Foo"), None);
+        assert_eq!(saved.custom_headers["X-Client"], "zitong");
+        assert_eq!(report.warnings.len(), 1);
+        assert!(
+            report.warnings[0].ends_with(": X-Foo"),
+            "{:?}",
+            report.warnings
+        );
+    }
+
+    #[tokio::test]
+    async fn keys_rotate_and_a_rate_limited_key_cools_down() {
+        let url = mock_server(|request| {
+            if request.contains("Bearer key-b") {
+                http_response("429 Too Many Requests", "application/json", "{}")
+            } else {
+                http_response("200 OK", "application/json", r#"{"data":[{"id":"m"}]}"#)
+            }
+        })
+        .await;
+        let db = TempDb::new("key-rotation");
+        let mut provider = crate::db::tests::test_provider("rotating");
+        provider.base_url = Some(url);
+        db.save_provider(&provider).unwrap();
+        let settings = db.get_settings().unwrap();
+        let next_key = || {
+            provider_config(
+                &db,
+                db.get_provider("rotating").unwrap(),
+                "m".into(),
+                &settings,
+            )
+            .api_key
+            .unwrap()
+        };
+
+        // Without rotated keys the provider's own key is used.
+        assert_eq!(next_key(), "sk-test");
+        for key in ["key-a", "key-b", "key-c"] {
+            db.add_provider_key("rotating", key).unwrap();
+        }
+        let order: Vec<String> = (0..4).map(|_| next_key()).collect();
+        assert_eq!(order, ["key-a", "key-b", "key-c", "key-a"]);
+
+        let config = provider_config(
+            &db,
+            db.get_provider("rotating").unwrap(),
+            "m".into(),
+            &settings,
+        );
+        assert_eq!(config.api_key.as_deref(), Some("key-b"));
+        let err = providers::list_provider_models(&config, false)
+            .await
+            .unwrap_err();
+        assert_eq!(providers::http_status_in(&err), Some(429));
+        assert!(providers::cooling_down_keys().contains(config.key_id.as_ref().unwrap()));
+
+        let order: Vec<String> = (0..3).map(|_| next_key()).collect();
+        assert_eq!(order, ["key-c", "key-a", "key-c"]);
+    }
+
+    #[tokio::test]
+    async fn defaults_report_a_missing_provider() {
+        let db = TempDb::new("defaults-missing");
+        db.set_setting_raw("default_provider_id", "gone").unwrap();
+
+        let status = defaults_status(&db).await.unwrap();
+        assert!(!status.ok);
+        assert!(!status.model_checked);
+        assert_eq!(
+            status.problems,
+            ["The default provider \"gone\" isn't configured. Add it in Settings → Providers or choose another default."]
+        );
+
+        let url = mock_server(|_| {
+            http_response(
+                "200 OK",
+                "application/json",
+                r#"{"data":[{"id":"gpt-4o"}]}"#,
+            )
+        })
+        .await;
+        let mut provider = crate::db::tests::test_provider("gone");
+        provider.base_url = Some(url);
+        db.save_provider(&provider).unwrap();
+        db.set_setting_raw("default_model", "gpt-4o").unwrap();
+        let status = defaults_status(&db).await.unwrap();
+        assert!(status.ok, "{:?}", status.problems);
+        assert!(status.model_checked);
+    }
+
+    #[test]
+    fn hidden_flag_or_background_setting_starts_hidden() {
+        let background = db::AppSettings {
+            start_as_background: true,
+            ..Default::default()
+        };
+        let foreground = db::AppSettings {
+            start_as_background: false,
+            ..Default::default()
+        };
+        assert!(should_start_hidden(true, &background));
+        assert!(should_start_hidden(true, &foreground));
+        assert!(should_start_hidden(false, &background));
+        assert!(!should_start_hidden(false, &foreground));
+    }
+
+    #[tokio::test]
+    async fn backfill_retitles_only_default_titled_conversations() {
+        let url = mock_server(|_| {
+            let stream =
+                crate::providers::tests::openai_sse(&["Kyoto ", "trip plan"]) + "data: [DONE]\n\n";
+            http_response("200 OK", "text/event-stream", &stream)
+        })
+        .await;
+        let db = TempDb::new("backfill-titles");
+        let mut provider = crate::db::tests::test_provider("mock");
+        provider.base_url = Some(url);
+        db.save_provider(&provider).unwrap();
+        let question = "Plan a three day trip to Kyoto for me, with temples and food";
+        for (id, title) in [
+            ("placeholder", "New Chat".to_string()),
+            ("truncated", truncate_chars(question, TITLE_MAX_CHARS)),
+            ("custom", "Japan notes".to_string()),
+            ("renamed", "New Chat".to_string()),
+            ("empty", "New Chat".to_string()),
+        ] {
+            db.create_conversation(id, &title, "m", "mock", None, None)
+                .unwrap();
+            if id != "empty" {
+                db.create_message(&format!("{}-u", id), id, "user", question, None, None, 0)
+                    .unwrap();
+                db.create_message(
+                    &format!("{}-a", id),
+                    id,
+                    "assistant",
+                    "Sure!",
+                    None,
+                    None,
+                    1,
+                )
+                .unwrap();
+            }
+        }
+        db.update_conversation_title("renamed", "New Chat").unwrap();
+
+        let mut retitled = backfill_conversation_titles(&db).await.unwrap();
+        retitled.sort();
+        assert_eq!(retitled, ["placeholder", "truncated"]);
+        let title = |id: &str| db.get_conversation(id).unwrap().title;
+        assert_eq!(title("placeholder"), "Kyoto trip plan");
+        assert_eq!(title("truncated"), "Kyoto trip plan");
+        assert_eq!(title("custom"), "Japan notes");
+        assert_eq!(title("renamed"), "New Chat");
+        assert_eq!(title("empty"), "New Chat");
+    }
+
+    #[tokio::test]
+    async fn conversation_temperature_reaches_the_request_body() {
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let seen = bodies.clone();
+        let url = mock_server(move |request| {
+            let body = request.split_once("\r\n\r\n").unwrap().1;
+            seen.lock()
+                .unwrap()
+                .push(serde_json::from_str::<serde_json::Value>(body).unwrap());
+            http_response(
+                "200 OK",
+                "text/event-stream",
+                &crate::providers::tests::openai_sse(&["Ok"]),
+            )
+        })
+        .await;
+        let db = TempDb::new("conversation-temperature");
+        let mut provider = crate::db::tests::test_provider("mock");
+        provider.base_url = Some(url);
+        db.save_provider(&provider).unwrap();
+        db.create_conversation("c1", "Chat", "m", "mock", None, None)
+            .unwrap();
+        db.create_message("u1", "c1", "user", "Hi", None, None, 0)
+            .unwrap();
+        let settings = db.get_settings().unwrap();
+        let req = SendMessageRequest {
+            conversation_id: "c1".into(),
+            content: "Hi".into(),
+            model: "m".into(),
+            provider_id: "mock".into(),
+            system_prompt: None,
+            tools: None,
+            client_message_id: None,
+            prefill: None,
+        };
+        let send = || async {
+            let (conversation, _, chat_messages) = send_context(&db, &req, &settings).unwrap();
+            let mut config =
+                provider_config(&db, db.get_provider("mock").unwrap(), "m".into(), &settings);
+            config.temperature = conversation.temperature;
+            providers::stream_chat(&config, &chat_messages, |_| {})
+                .await
+                .unwrap();
+        };
+
+        send().await;
+        db.update_conversation_temperature("c1", Some(0.2)).unwrap();
+        send().await;
+
+        let bodies = bodies.lock().unwrap();
+        assert!(bodies[0].get("temperature").is_none(), "{}", bodies[0]);
+        assert_eq!(bodies[1]["temperature"], 0.2);
+    }
+
+    #[test]
+    fn repeated_client_message_ids_are_sent_once() {
+        let db = TempDb::new("duplicate-send");
+        db.create_conversation("c1", "Chat", "m", "p", None, None)
+            .unwrap();
+        let client_id = format!("client-{}", uuid::Uuid::new_v4());
+        let req = SendMessageRequest {
+            conversation_id: "c1".into(),
+            content: "Hi".into(),
+            model: "m".into(),
+            provider_id: "p".into(),
+            system_prompt: None,
+            tools: None,
+            client_message_id: Some(client_id.clone()),
+            prefill: None,
+        };
+        let status = |start: SendStart| match start {
+            SendStart::Duplicate(status) => Some((status.user_message_id, status.in_progress)),
+            SendStart::New { .. } => None,
+        };
+
+        let first = begin_send(&db, &req).unwrap();
+        let SendStart::New { user_msg_id, .. } = &first else {
+            panic!("the first send should go through");
+        };
+        assert_eq!(user_msg_id, &client_id);
+        // The first send is still streaming.
+        assert_eq!(
+            status(begin_send(&db, &req).unwrap()),
+            Some((client_id.clone(), true))
+        );
+        drop(first);
+        // It has finished, and its message is saved.
+        assert_eq!(
+            status(begin_send(&db, &req).unwrap()),
+            Some((client_id.clone(), false))
+        );
+        assert_eq!(db.get_messages("c1").unwrap().len(), 1);
+
+        let anonymous = SendMessageRequest {
+            client_message_id: None,
+            ..req
+        };
+        assert!(status(begin_send(&db, &anonymous).unwrap()).is_none());
+        assert!(status(begin_send(&db, &anonymous).unwrap()).is_none());
+        assert_eq!(db.get_messages("c1").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn live_replace_batches_end_on_a_word_boundary() {
+        let mut batcher = LiveReplaceBatcher::default();
+        batcher.push("The quick brown ");
+        assert_eq!(batcher.take(), None);
+
+        batcher.push("fox jumps ov");
+        assert_eq!(
+            batcher.take().as_deref(),
+            Some("The quick brown fox jumps ")
+        );
+        assert_eq!(batcher.take(), None);
+
+        // Text without whitespace goes out whole once it is long enough.
+        batcher.push("很长的一段没有空格的中文文本需要一次性粘贴出去好");
+        assert_eq!(
+            batcher.take().as_deref(),
+            Some("ov很长的一段没有空格的中文文本需要一次性粘贴出去好")
+        );
+
+        batcher.push("end");
+        assert_eq!(batcher.take(), None);
+        assert_eq!(batcher.finish().as_deref(), Some("end"));
+        assert_eq!(batcher.finish(), None);
+    }
+
+    #[tokio::test]
+    async fn executing_a_command_bumps_its_use_count() {
+        let db = TempDb::new("command-use");
+        let url = mock_server(|request| {
+            if request.contains("fail") {
+                http_response("400 Bad Request", "application/json", "{}")
+            } else {
+                let body = providers::tests::openai_sse(&["Done."]) + "data: [DONE]\n\n";
+                http_response("200 OK", "text/event-stream", &body)
+            }
+        })
+        .await;
+        let config = providers::tests::mock_config(format!("{}/v1", url));
+        let messages = |text: &str| {
+            vec![ChatMessage {
+                role: "user".into(),
+                content: text.into(),
+            }]
+        };
+        let explain = || {
+            db.list_ai_commands(false)
+                .unwrap()
+                .into_iter()
+                .find(|c| c.id == "explain")
+                .unwrap()
+        };
+        assert_eq!(explain().use_count, 0);
+
+        let (output, _) =
+            run_ai_command(&db, &config, "p", &messages("hi"), 0, None, Some("explain")).await;
+        assert_eq!(output.unwrap().text, "Done.");
+        assert_eq!(explain().use_count, 1);
+        assert!(explain().last_used_at.is_some());
+
+        let (output, _) = run_ai_command(
+            &db,
+            &config,
+            "p",
+            &messages("fail"),
+            0,
+            None,
+            Some("explain"),
+        )
+        .await;
+        assert!(output.is_err());
+        assert_eq!(explain().use_count, 1);
+
+        let most_used = db.list_ai_commands(true).unwrap();
+        assert_eq!(most_used[0].id, "explain");
+    }
+
+    #[tokio::test]
+    async fn default_model_updates_are_validated_and_narrow() {
+        let db = TempDb::new("default-model");
+        let url = mock_server(|_| {
+            http_response(
+                "200 OK",
+                "application/json",
+                r#"{"data":[{"id":"gpt-4o"}]}"#,
+            )
+        })
+        .await;
+        let mut provider = crate::db::tests::test_provider("p1");
+        provider.base_url = Some(format!("{}/v1", url));
+        db.save_provider(&provider).unwrap();
+        let mut disabled = crate::db::tests::test_provider("p2");
+        disabled.enabled = false;
+        db.save_provider(&disabled).unwrap();
+        db.set_setting_raw("offline_mode", "true").unwrap();
+
+        for (provider_id, model) in [
+            ("p1", ""),
+            ("", "gpt-4o"),
+            ("missing", "gpt-4o"),
+            ("p2", "gpt-4o"),
+        ] {
+            assert!(
+                update_default_model(&db, provider_id, model, false)
+                    .await
+                    .is_err(),
+                "{}/{}",
+                provider_id,
+                model
+            );
+        }
+        let error = update_default_model(&db, "p1", "gpt-5", true)
+            .await
+            .unwrap_err();
+        assert!(error.contains("does not offer"), "{}", error);
+
+        update_default_model(&db, " p1 ", "gpt-4o", true)
+            .await
+            .unwrap();
+        let settings = db.get_settings().unwrap();
+        assert_eq!(settings.default_provider_id, "p1");
+        assert_eq!(settings.default_model, "gpt-4o");
+        assert!(settings.offline_mode);
+
+        update_default_model(&db, "", "", false).await.unwrap();
+        let settings = db.get_settings().unwrap();
+        assert_eq!(settings.default_provider_id, "");
+        assert_eq!(settings.default_model, "");
+        assert!(settings.offline_mode);
+    }
+
+    #[tokio::test]
+    async fn user_field_is_sent_only_when_enabled() {
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let seen = bodies.clone();
+        let url = mock_server(move |request| {
+            let body = request.split_once("\r\n\r\n").unwrap().1;
+            seen.lock()
+                .unwrap()
+                .push(serde_json::from_str::<serde_json::Value>(body).unwrap());
+            let body = crate::providers::tests::openai_sse(&["Ok"]) + "data: [DONE]\n\n";
+            http_response("200 OK", "text/event-stream", &body)
+        })
+        .await;
+        let db = TempDb::new("user-field");
+        let mut provider = crate::db::tests::test_provider("mock");
+        provider.base_url = Some(format!("{}/v1", url));
+        db.save_provider(&provider).unwrap();
+        let send = |provider: db::Provider| {
+            let settings = db.get_settings().unwrap();
+            let config = provider_config(&db, provider, "m".into(), &settings);
+            async move {
+                let messages = [ChatMessage {
+                    role: "user".into(),
+                    content: "Hi".into(),
+                }];
+                providers::stream_chat(&config, &messages, |_| {})
+                    .await
+                    .unwrap();
+            }
+        };
+
+        send(provider.clone()).await;
+        assert_eq!(db.get_settings().unwrap().anonymous_user_id, "");
+
+        provider.send_user_id = true;
+        send(provider.clone()).await;
+        let user_id = db.get_settings().unwrap().anonymous_user_id;
+        assert!(!user_id.is_empty());
+        send(provider).await;
+
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 3);
+        assert!(bodies[0].get("user").is_none());
+        assert_eq!(bodies[1]["user"], user_id.as_str());
+        assert_eq!(bodies[2]["user"], user_id.as_str());
+    }
+
+    #[tokio::test]
+    async fn listings_filtered_to_nothing_explain_why() {
+        let url = mock_server(|request| {
+            let body = if request.contains("empty-key") {
+                r#"{"data":[]}"#
+            } else {
+                r#"{"data":[{"id":"text-embedding-3-small"},{"id":"tts-1"},{"id":"whisper-1"}]}"#
+            };
+            http_response("200 OK", "application/json", body)
+        })
+        .await;
+        let db = TempDb::new("empty-listing");
+        let mut provider = crate::db::tests::test_provider("openai");
+        provider.provider_type = "openai".into();
+        provider.base_url = Some(format!("{}/v1", url));
+        db.save_provider(&provider).unwrap();
+        let mut empty = provider.clone();
+        empty.id = "empty".into();
+        empty.api_key = Some("empty-key".into());
+        db.save_provider(&empty).unwrap();
+
+        let listing = model_listing(&db, "openai".into(), None, None, None)
+            .await
+            .unwrap();
+        assert!(listing.models.is_empty());
+        assert_eq!(listing.fetched_count, 3);
+        let warning = listing.warning.unwrap();
+        assert!(warning.contains("returned 3 models"), "{}", warning);
+
+        let listing = model_listing(&db, "empty".into(), None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(listing.fetched_count, 0);
+        assert!(listing.warning.unwrap().contains("no models"));
+    }
+
+    #[test]
+    fn model_refresh_is_due_once_the_cache_is_older_than_the_threshold() {
+        let max_age = std::time::Duration::from_secs(600);
+        let now = std::time::Instant::now() + max_age * 2;
+        let ago = |secs| Some(now - std::time::Duration::from_secs(secs));
+
+        assert!(models_refresh_due(None, now, max_age));
+        assert!(!models_refresh_due(ago(0), now, max_age));
+        assert!(!models_refresh_due(ago(599), now, max_age));
+        assert!(models_refresh_due(ago(600), now, max_age));
+        assert!(models_refresh_due(ago(3600), now, max_age));
+        // A refresh recorded after `now` (a racing focus event) is fresh.
+        assert!(!models_refresh_due(Some(now + max_age), now, max_age));
+    }
+
+    #[tokio::test]
+    async fn model_refresh_skips_disabled_and_unreachable_providers() {
+        let url = mock_server(|_| {
+            http_response(
+                "200 OK",
+                "application/json",
+                r#"{"data":[{"id":"local-model"}]}"#,
+            )
+        })
+        .await;
+        let db = TempDb::new("refresh-models");
+        let mut local = crate::db::tests::test_provider("local");
+        local.base_url = Some(format!("{}/v1", url));
+        db.save_provider(&local).unwrap();
+        let mut disabled = local.clone();
+        disabled.id = "disabled".into();
+        disabled.enabled = false;
+        db.save_provider(&disabled).unwrap();
+        let mut remote = crate::db::tests::test_provider("remote");
+        remote.base_url = Some("https://api.example.com/v1".into());
+        db.save_provider(&remote).unwrap();
+        db.set_setting_raw("offline_mode", "true").unwrap();
+
+        let refreshed = refresh_all_models(&db).await.unwrap();
+
+        assert_eq!(refreshed.len(), 1);
+        assert_eq!(refreshed[0].provider_id, "local");
+        assert_eq!(refreshed[0].models[0].id, "local-model");
+        assert_eq!(refreshed[0].error, None);
+    }
+
+    #[tokio::test]
+    async fn backfill_retitles_legacy_titles_from_before_the_upgrade() {
+        let url = mock_server(|_| {
+            let stream =
+                crate::providers::tests::openai_sse(&["Kyoto ", "trip plan"]) + "data: [DONE]\n\n";
+            http_response("200 OK", "text/event-stream", &stream)
+        })
+        .await;
+        let question = "Plan a three day trip to Kyoto for me, with temples and food";
+        let seed = format!(
+            "{}
+            INSERT INTO conversations (id, title, model, provider_id, created_at, updated_at) VALUES
+                ('selection', '{}...', 'm', 'mock', 0, 0),
+                ('input', '{}', 'm', 'mock', 0, 0),
+                ('current', '{}', 'm', 'mock', 0, 0),
+                ('custom', 'Japan notes', 'm', 'mock', 0, 0);
+            INSERT INTO messages (id, conversation_id, role, content, created_at, sort_order) VALUES
+                ('selection-u', 'selection', 'user', '{q}', 0, 0),
+                ('selection-a', 'selection', 'assistant', 'Sure!', 0, 1),
+                ('input-u', 'input', 'user', '{q}', 0, 0),
+                ('input-a', 'input', 'assistant', 'Sure!', 0, 1),
+                ('current-u', 'current', 'user', '{q}', 0, 0),
+                ('current-a', 'current', 'assistant', 'Sure!', 0, 1),
+                ('custom-u', 'custom', 'user', '{q}', 0, 0),
+                ('custom-a', 'custom', 'assistant', 'Sure!', 0, 1);",
+            crate::db::tests::PRE_TITLE_MANUAL_SCHEMA,
+            &question[..47],
+            &question[..50],
+            truncate_chars(question, TITLE_MAX_CHARS),
+            q = question
+        );
+        let db = TempDb::seeded("backfill-legacy-titles", &seed);
+        let mut provider = crate::db::tests::test_provider("mock");
+        provider.base_url = Some(url);
+        db.save_provider(&provider).unwrap();
+
+        for (id, manual) in [
+            ("selection", false),
+            ("input", false),
+            ("current", false),
+            ("custom", true),
+        ] {
+            assert_eq!(db.is_title_manual(id).unwrap(), manual, "{}", id);
+        }
+        let mut retitled = backfill_conversation_titles(&db).await.unwrap();
+        retitled.sort();
+        assert_eq!(retitled, ["current", "input", "selection"]);
+        let title = |id: &str| db.get_conversation(id).unwrap().title;
+        assert_eq!(title("selection"), "Kyoto trip plan");
+        assert_eq!(title("input"), "Kyoto trip plan");
+        assert_eq!(title("current"), "Kyoto trip plan");
+        assert_eq!(title("custom"), "Japan notes");
+    }
 }
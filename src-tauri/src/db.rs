@@ -1,12 +1,24 @@
-use rusqlite::{Connection, Result, params};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Connection, OptionalExtension, Result, TransactionBehavior};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
 
 // ============================================
 // Database Models
 // ============================================
 
+/// Which fields `search_conversations_scoped` matches against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SearchScope {
+    TitleOnly,
+    ContentOnly,
+    #[default]
+    All,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Conversation {
@@ -19,6 +31,13 @@ pub struct Conversation {
     pub updated_at: i64,
     pub is_archived: bool,
     pub folder_id: Option<String>,
+    /// Pinned conversations list first and are never auto-pruned.
+    #[serde(default)]
+    pub is_pinned: bool,
+    /// Sampling temperature for this conversation's requests; `None` leaves
+    /// it to the provider.
+    #[serde(default)]
+    pub temperature: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,11 +52,29 @@ pub struct Message {
     pub created_at: i64,
     pub parent_id: Option<String>,
     pub sort_order: i64,
+    /// Structured extras stored alongside the text, such as tool calls.
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    /// When false the message stays in the transcript but is left out of the
+    /// context sent to the model.
+    #[serde(default = "default_true")]
+    pub include_in_context: bool,
+    /// Always sent as context, even when `max_context_messages` trims older
+    /// history.
+    #[serde(default)]
+    pub sticky: bool,
+    /// Streamed replies only: time to first token, total duration and output
+    /// speed (see `providers::StreamMetrics`).
+    #[serde(default)]
+    pub ttft_ms: Option<i64>,
+    #[serde(default)]
+    pub duration_ms: Option<i64>,
+    #[serde(default)]
+    pub tokens_per_second: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-#[allow(dead_code)]
 pub struct Attachment {
     pub id: String,
     pub message_id: String,
@@ -59,6 +96,121 @@ pub struct Provider {
     pub base_url: Option<String>,
     pub default_model: Option<String>,
     pub enabled: bool,
+    /// Whether the endpoint accepts `stream: true`. When false, chats use a
+    /// single buffered request instead (OpenAI-compatible providers only).
+    #[serde(default = "default_true")]
+    pub supports_streaming: bool,
+    /// How the API key is sent: `bearer_header`, `x_api_key_header`,
+    /// `query_param` or `query_param:<name>`. OpenAI-compatible providers only.
+    #[serde(default = "default_auth_style")]
+    pub auth_style: String,
+    /// Output token limit sent with chat requests. `None` uses the API's
+    /// default, or a per-model default on Anthropic.
+    #[serde(default)]
+    pub default_max_tokens: Option<i64>,
+    /// Talk to the legacy prompt-based `/completions` endpoint instead of
+    /// `/chat/completions`. OpenAI-compatible providers only.
+    #[serde(default)]
+    pub completion_mode: bool,
+    /// Extra headers sent with every request. Values may contain `${NAME}`
+    /// references, resolved from the environment at request time.
+    #[serde(default)]
+    pub custom_headers: std::collections::BTreeMap<String, String>,
+    /// JSON object merged into chat request bodies for parameters the app
+    /// doesn't model. Fields the app sets win on conflict.
+    #[serde(default)]
+    pub extra_body: Option<serde_json::Value>,
+    /// Where system prompts go: `system_role`, `prepend_user` (folded into
+    /// the first user message) or `developer_role`.
+    #[serde(default = "default_system_prompt_mode")]
+    pub system_prompt_mode: String,
+    /// Tokens the provider may use per local day before sends are refused;
+    /// `None` means no cap.
+    #[serde(default)]
+    pub daily_token_cap: Option<i64>,
+    /// Send the install's `anonymous_user_id` as the `user` field of
+    /// OpenAI-style requests, for providers that use it for abuse monitoring.
+    #[serde(default)]
+    pub send_user_id: bool,
+}
+
+/// One provider's token usage on one local day (`YYYY-MM-DD`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyUsage {
+    pub provider_id: String,
+    pub day: String,
+    pub tokens: i64,
+    /// Responses that finished, whether or not they reported usage.
+    pub requests: i64,
+}
+
+/// One of several API keys a provider rotates through. Used instead of
+/// `Provider::api_key` whenever the provider has any enabled keys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderKey {
+    pub id: String,
+    pub provider_id: String,
+    pub key: String,
+    pub enabled: bool,
+    pub last_used: Option<i64>,
+    pub created_at: i64,
+}
+
+fn default_auth_style() -> String {
+    crate::providers::DEFAULT_AUTH_STYLE.to_string()
+}
+
+fn default_system_prompt_mode() -> String {
+    crate::providers::DEFAULT_SYSTEM_PROMPT_MODE.to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Counts of rows removed by `Database::delete_provider_with_data`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletionSummary {
+    pub conversations_deleted: i64,
+    pub messages_deleted: i64,
+}
+
+/// Storage statistics reported by `Database::stats`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbStats {
+    pub path: Option<String>,
+    /// Main database file size (page count × page size).
+    pub size_bytes: i64,
+    /// Size of the `-wal` file, if one exists.
+    pub wal_size_bytes: Option<u64>,
+    pub journal_mode: String,
+    pub table_counts: std::collections::BTreeMap<String, i64>,
+    pub wal_checkpoint: WalCheckpoint,
+}
+
+/// Rows whose parent no longer exists, found by `Database::find_orphans` or
+/// removed by `Database::delete_orphans`.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanReport {
+    /// Messages whose conversation is gone.
+    pub messages: i64,
+    /// Attachments whose message is gone.
+    pub attachments: i64,
+}
+
+/// Result of a `PRAGMA wal_checkpoint`. The frame counts are -1 when the
+/// database is not in WAL mode.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WalCheckpoint {
+    pub busy: bool,
+    pub log_frames: i64,
+    pub checkpointed_frames: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,6 +223,11 @@ pub struct PromptTemplate {
     pub variables: Vec<String>,
     pub created_at: i64,
     pub updated_at: i64,
+    /// Times the template was rendered; maintained by the app, not by saves.
+    #[serde(default)]
+    pub use_count: i64,
+    #[serde(default)]
+    pub last_used_at: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -83,8 +240,82 @@ pub struct Folder {
     pub created_at: i64,
 }
 
+/// A folder together with the number of (non-archived) conversations it holds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderSummary {
+    #[serde(flatten)]
+    pub folder: Folder,
+    pub conversation_count: i64,
+}
+
+/// A conversation enriched with sidebar data: message count and a short
+/// preview of the latest message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationSummary {
+    #[serde(flatten)]
+    pub conversation: Conversation,
+    pub message_count: i64,
+    pub last_message_preview: Option<String>,
+}
+
+/// A conversation plus the aggregates a detail header shows.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct ConversationDetail {
+    #[serde(flatten)]
+    pub conversation: Conversation,
+    pub message_count: i64,
+    /// Sum of recorded `token_count`s; messages without one count as zero.
+    pub total_tokens: i64,
+    /// `created_at` of the newest message, `None` for an empty conversation.
+    pub last_message_at: Option<i64>,
+}
+
+/// Maximum number of characters kept in `ConversationSummary::last_message_preview`.
+const PREVIEW_MAX_CHARS: usize = 80;
+
+/// Collapse whitespace and cut `content` to `PREVIEW_MAX_CHARS` characters,
+/// appending "…" when anything was dropped.
+fn make_preview(content: &str) -> String {
+    let collapsed = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() > PREVIEW_MAX_CHARS {
+        let mut preview: String = collapsed.chars().take(PREVIEW_MAX_CHARS).collect();
+        preview.push('…');
+        preview
+    } else {
+        collapsed
+    }
+}
+
+/// Tidy message text as imported transcripts tend to need: `\r\n` becomes
+/// `\n`, trailing whitespace is stripped from each line, and three or more
+/// blank lines in a row collapse to two. Already-normalized text is
+/// returned unchanged.
+pub fn normalize_message_text(content: &str) -> String {
+    let mut normalized = String::with_capacity(content.len());
+    let mut blank_run = 0;
+    for (i, line) in content.split('\n').enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_run += 1;
+            if blank_run > 2 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        if i > 0 {
+            normalized.push('\n');
+        }
+        normalized.push_str(line);
+    }
+    normalized
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct AppSettings {
     pub theme: String,
     pub default_model: String,
@@ -100,6 +331,75 @@ pub struct AppSettings {
     pub code_theme: String,
     pub compact_mode: bool,
     pub launch_at_login: bool,
+    /// Stay in the tray instead of opening the main window at launch.
+    /// Launches with `--hidden` (login items) stay in the tray regardless.
+    pub start_as_background: bool,
+    /// Seconds without any stream event before a response is aborted as
+    /// stalled. `0` disables the check.
+    pub stream_idle_timeout_secs: i64,
+    /// Instruction prepended to every system prompt (chat and AI commands).
+    pub global_system_prefix: String,
+    /// Instruction appended to every system prompt (chat and AI commands).
+    pub global_system_suffix: String,
+    /// One of `off`, `error`, `warn`, `info`, `debug`, `trace`.
+    pub log_level: String,
+    /// After copying an overlay result, paste it into the originating app
+    /// and restore the previous clipboard contents.
+    pub auto_paste: bool,
+    /// Paste `replace_selection`/`insert_after` AI command output into the
+    /// originating app as it streams instead of showing it in the overlay.
+    pub live_replace: bool,
+    /// Extra attempts for an AI command that fails to connect before any
+    /// output arrives.
+    pub ai_command_max_retries: i64,
+    /// Times a chat stream whose connection drops before it finishes is
+    /// resumed by asking the model to continue its partial reply. `0`
+    /// reports the drop as an error.
+    pub stream_reconnect_attempts: i64,
+    /// Refuse network calls to providers that aren't on this machine
+    /// (see `providers::is_local_provider`).
+    pub offline_mode: bool,
+    /// Write raw provider requests/responses for each chat message to a
+    /// per-conversation transcript in the log dir (credentials redacted).
+    pub log_raw_requests: bool,
+    /// Largest file that may be attached to a message. `0` disables the limit.
+    pub max_attachment_bytes: i64,
+    /// Warn once when a single response passes this many tokens. `0`
+    /// disables the warning.
+    pub response_token_warn_at: i64,
+    /// SQLite journal mode applied when the database is opened: `WAL`,
+    /// `DELETE` or `TRUNCATE`. WAL is unsafe on network drives and synced
+    /// folders.
+    pub db_journal_mode: String,
+    /// Send at most this many of the latest history messages. System and
+    /// sticky messages are always sent on top. `0` sends the whole history.
+    pub max_context_messages: i64,
+    /// How conversations are titled automatically: `off`, `truncate` (the
+    /// first user message) or `llm` (a generated title).
+    pub auto_title_mode: String,
+    /// Number of completed exchanges after which the auto title is set.
+    pub auto_title_after_messages: i64,
+    /// Cap on unarchived conversations; past it the least recently updated
+    /// unpinned ones are pruned when a conversation is created. `0` means
+    /// unlimited.
+    pub max_conversations: i64,
+    /// What pruning does to a conversation: `archive` or `delete`.
+    pub prune_action: String,
+    /// Serve an OpenAI-compatible `/v1/chat/completions` on localhost that
+    /// answers with the default provider.
+    pub local_api_enabled: bool,
+    /// Port the local API listens on (127.0.0.1 only).
+    pub local_api_port: i64,
+    /// Bearer token local API clients must send. Generated when the server
+    /// is first started without one.
+    pub local_api_token: String,
+    /// Random id for this install, sent as the OpenAI `user` field by
+    /// providers with `send_user_id`. Generated when first needed.
+    pub anonymous_user_id: String,
+    /// Refresh every provider's model list in the background when the main
+    /// window gains focus and the lists are older than
+    /// `commands::MODEL_REFRESH_MAX_AGE`.
+    pub auto_refresh_models_on_focus: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,6 +434,12 @@ pub struct AiCommand {
     pub keyboard_shortcut: Option<String>,
     pub enabled: bool,
     pub sort_order: i64,
+    /// Times the command produced a result; maintained by the app, not by
+    /// saves.
+    #[serde(default)]
+    pub use_count: i64,
+    #[serde(default)]
+    pub last_used_at: Option<i64>,
 }
 
 impl Default for AppSettings {
@@ -153,7 +459,130 @@ impl Default for AppSettings {
             code_theme: "oneDark".to_string(),
             compact_mode: false,
             launch_at_login: false,
+            start_as_background: true,
+            stream_idle_timeout_secs: crate::providers::DEFAULT_STREAM_IDLE_TIMEOUT_SECS as i64,
+            global_system_prefix: String::new(),
+            global_system_suffix: String::new(),
+            log_level: crate::logging::DEFAULT_LOG_LEVEL.to_string(),
+            auto_paste: false,
+            live_replace: false,
+            ai_command_max_retries: 2,
+            stream_reconnect_attempts: 0,
+            offline_mode: false,
+            log_raw_requests: false,
+            max_attachment_bytes: crate::attachments::DEFAULT_MAX_ATTACHMENT_BYTES,
+            response_token_warn_at: 0,
+            db_journal_mode: DEFAULT_JOURNAL_MODE.to_string(),
+            max_context_messages: 0,
+            auto_title_mode: "llm".to_string(),
+            auto_title_after_messages: 1,
+            max_conversations: 0,
+            prune_action: "archive".to_string(),
+            local_api_enabled: false,
+            local_api_port: crate::local_api::DEFAULT_PORT,
+            local_api_token: String::new(),
+            anonymous_user_id: String::new(),
+            auto_refresh_models_on_focus: false,
+        }
+    }
+}
+
+impl AppSettings {
+    /// Settings-table rows for every field, keyed by their stored name.
+    pub fn to_pairs(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("theme", self.theme.clone()),
+            ("default_model", self.default_model.clone()),
+            ("default_provider_id", self.default_provider_id.clone()),
+            ("default_system_prompt", self.default_system_prompt.clone()),
+            ("global_hotkey", self.global_hotkey.clone()),
+            ("send_on_enter", self.send_on_enter.to_string()),
+            ("stream_responses", self.stream_responses.to_string()),
+            ("font_size", self.font_size.to_string()),
+            ("accent_color", self.accent_color.clone()),
+            ("font_family", self.font_family.clone()),
+            ("chat_bubble_style", self.chat_bubble_style.clone()),
+            ("code_theme", self.code_theme.clone()),
+            ("compact_mode", self.compact_mode.to_string()),
+            ("launch_at_login", self.launch_at_login.to_string()),
+            ("start_as_background", self.start_as_background.to_string()),
+            (
+                "stream_idle_timeout_secs",
+                self.stream_idle_timeout_secs.to_string(),
+            ),
+            ("global_system_prefix", self.global_system_prefix.clone()),
+            ("global_system_suffix", self.global_system_suffix.clone()),
+            ("log_level", self.log_level.clone()),
+            ("auto_paste", self.auto_paste.to_string()),
+            ("live_replace", self.live_replace.to_string()),
+            (
+                "ai_command_max_retries",
+                self.ai_command_max_retries.to_string(),
+            ),
+            (
+                "stream_reconnect_attempts",
+                self.stream_reconnect_attempts.to_string(),
+            ),
+            ("offline_mode", self.offline_mode.to_string()),
+            ("log_raw_requests", self.log_raw_requests.to_string()),
+            (
+                "max_attachment_bytes",
+                self.max_attachment_bytes.to_string(),
+            ),
+            (
+                "response_token_warn_at",
+                self.response_token_warn_at.to_string(),
+            ),
+            ("db_journal_mode", self.db_journal_mode.clone()),
+            (
+                "max_context_messages",
+                self.max_context_messages.to_string(),
+            ),
+            ("auto_title_mode", self.auto_title_mode.clone()),
+            (
+                "auto_title_after_messages",
+                self.auto_title_after_messages.to_string(),
+            ),
+            ("max_conversations", self.max_conversations.to_string()),
+            ("prune_action", self.prune_action.clone()),
+            ("local_api_enabled", self.local_api_enabled.to_string()),
+            ("local_api_port", self.local_api_port.to_string()),
+            ("local_api_token", self.local_api_token.clone()),
+            ("anonymous_user_id", self.anonymous_user_id.clone()),
+            (
+                "auto_refresh_models_on_focus",
+                self.auto_refresh_models_on_focus.to_string(),
+            ),
+        ]
+    }
+
+    /// Overlay the fields present in `patch` (camelCase keys, as serialized)
+    /// onto these settings. Returns the merged settings and the patch keys
+    /// that matched no setting.
+    pub fn merged_with(
+        &self,
+        patch: &serde_json::Map<String, serde_json::Value>,
+    ) -> std::result::Result<(AppSettings, Vec<String>), String> {
+        let mut merged = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        let fields = merged
+            .as_object_mut()
+            .expect("AppSettings serializes to an object");
+        let mut unknown = Vec::new();
+        for (key, value) in patch {
+            if fields.contains_key(key) {
+                fields.insert(key.clone(), value.clone());
+            } else {
+                unknown.push(key.clone());
+            }
         }
+        let merged =
+            serde_json::from_value(merged).map_err(|e| format!("Invalid settings: {}", e))?;
+        Ok((merged, unknown))
+    }
+
+    /// Whether `key` names a field stored by `to_pairs`.
+    pub fn is_known_key(key: &str) -> bool {
+        Self::default().to_pairs().iter().any(|(k, _)| *k == key)
     }
 }
 
@@ -161,22 +590,495 @@ impl Default for AppSettings {
 // Database Manager
 // ============================================
 
+/// Column list matching `message_from_row`.
+const MESSAGE_COLUMNS: &str =
+    "id, conversation_id, role, content, model, token_count, created_at, parent_id, sort_order, metadata, include_in_context, sticky, ttft_ms, duration_ms, tokens_per_second";
+
+fn message_from_row(row: &rusqlite::Row) -> Result<Message> {
+    Ok(Message {
+        id: row.get(0)?,
+        conversation_id: row.get(1)?,
+        role: row.get(2)?,
+        content: row.get(3)?,
+        model: row.get(4)?,
+        token_count: row.get(5)?,
+        created_at: row.get(6)?,
+        parent_id: row.get(7)?,
+        sort_order: row.get(8)?,
+        metadata: row
+            .get::<_, Option<String>>(9)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        include_in_context: row.get::<_, i64>(10)? != 0,
+        sticky: row.get::<_, i64>(11)? != 0,
+        ttft_ms: row.get(12)?,
+        duration_ms: row.get(13)?,
+        tokens_per_second: row.get(14)?,
+    })
+}
+
+/// Column list matching `conversation_from_row`.
+const CONVERSATION_COLUMNS: &str =
+    "id, title, model, provider_id, system_prompt, created_at, updated_at, is_archived, folder_id, is_pinned, temperature";
+
+fn conversation_from_row(row: &rusqlite::Row) -> Result<Conversation> {
+    Ok(Conversation {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        model: row.get(2)?,
+        provider_id: row.get(3)?,
+        system_prompt: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+        is_archived: row.get::<_, i64>(7)? != 0,
+        folder_id: row.get(8)?,
+        is_pinned: row.get::<_, i64>(9)? != 0,
+        temperature: row.get(10)?,
+    })
+}
+
+/// Column list matching `prompt_template_from_row`.
+const PROMPT_TEMPLATE_COLUMNS: &str =
+    "id, name, content, category, variables, created_at, updated_at, use_count, last_used_at";
+
+fn prompt_template_from_row(row: &rusqlite::Row) -> Result<PromptTemplate> {
+    let variables_json: String = row.get(4)?;
+    Ok(PromptTemplate {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        content: row.get(2)?,
+        category: row.get(3)?,
+        variables: serde_json::from_str(&variables_json).unwrap_or_default(),
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+        use_count: row.get(7)?,
+        last_used_at: row.get(8)?,
+    })
+}
+
+/// Column list matching `assistant_from_row`.
+const ASSISTANT_COLUMNS: &str =
+    "id, name, icon, description, system_prompt, provider_id, model, temperature, max_tokens, is_default, sort_order, created_at, updated_at";
+
+fn assistant_from_row(row: &rusqlite::Row) -> Result<Assistant> {
+    Ok(Assistant {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        icon: row.get(2)?,
+        description: row.get(3)?,
+        system_prompt: row.get(4)?,
+        provider_id: row.get(5)?,
+        model: row.get(6)?,
+        temperature: row.get(7)?,
+        max_tokens: row.get(8)?,
+        is_default: row.get::<_, i64>(9)? != 0,
+        sort_order: row.get(10)?,
+        created_at: row.get(11)?,
+        updated_at: row.get(12)?,
+    })
+}
+
+/// Column list matching `provider_from_row`.
+const PROVIDER_COLUMNS: &str =
+    "id, provider_type, name, api_key, base_url, default_model, enabled, supports_streaming, auth_style, default_max_tokens, completion_mode, custom_headers, extra_body, system_prompt_mode, daily_token_cap, send_user_id";
+
+fn provider_from_row(row: &rusqlite::Row) -> Result<Provider> {
+    Ok(Provider {
+        id: row.get(0)?,
+        provider_type: row.get(1)?,
+        name: row.get(2)?,
+        api_key: row.get(3)?,
+        base_url: row.get(4)?,
+        default_model: row.get(5)?,
+        enabled: row.get::<_, i64>(6)? != 0,
+        supports_streaming: row.get::<_, i64>(7)? != 0,
+        auth_style: row.get(8)?,
+        default_max_tokens: row.get(9)?,
+        completion_mode: row.get::<_, i64>(10)? != 0,
+        custom_headers: row
+            .get::<_, Option<String>>(11)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+        extra_body: row
+            .get::<_, Option<String>>(12)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+        system_prompt_mode: row.get(13)?,
+        daily_token_cap: row.get(14)?,
+        send_user_id: row.get::<_, i64>(15)? != 0,
+    })
+}
+
+fn provider_key_from_row(row: &rusqlite::Row) -> Result<ProviderKey> {
+    Ok(ProviderKey {
+        id: row.get(0)?,
+        provider_id: row.get(1)?,
+        key: row.get(2)?,
+        enabled: row.get::<_, i64>(3)? != 0,
+        last_used: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+type PooledConnection = r2d2::PooledConnection<SqliteConnectionManager>;
+
+/// Connections kept by the pool. Reads run concurrently in WAL mode; writes
+/// still take SQLite's single writer lock in turn.
+const POOL_SIZE: u32 = 4;
+/// How long a statement waits on another connection's lock before failing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct Database {
-    conn: Mutex<Connection>,
+    path: PathBuf,
+    /// Rebuilt when the journal mode changes, since SQLite can only leave WAL
+    /// once no other connection has the database open.
+    pool: RwLock<Pool>,
+}
+
+/// Per-connection setup run by the pool for every new connection.
+#[derive(Debug)]
+struct ConnectionSetup {
+    journal_mode: &'static str,
+}
+
+impl r2d2::CustomizeConnection<Connection, rusqlite::Error> for ConnectionSetup {
+    fn on_acquire(&self, conn: &mut Connection) -> Result<()> {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch("PRAGMA foreign_keys=ON;")?;
+        // A failed switch (e.g. another process holds the file) leaves the
+        // current mode in place; `set_journal_mode` reports what was applied.
+        if let Err(e) = apply_journal_mode(conn, self.journal_mode) {
+            tracing::warn!(
+                "[db] could not set journal mode {}: {}",
+                self.journal_mode,
+                e
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Surface a pool failure through the `rusqlite::Error` every `Database`
+/// method already returns.
+fn pool_error(e: r2d2::Error) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+        Some(format!("Connection pool: {}", e)),
+    )
+}
+
+fn build_pool(db_path: &Path, journal_mode: &'static str) -> Result<Pool> {
+    r2d2::Pool::builder()
+        .max_size(POOL_SIZE)
+        // Open connections on demand so a journal mode switch isn't racing
+        // a batch of connections the pool opens up front.
+        .min_idle(Some(0))
+        .connection_customizer(Box::new(ConnectionSetup { journal_mode }))
+        .build(SqliteConnectionManager::file(db_path))
+        .map_err(pool_error)
+}
+
+/// The `db_journal_mode` stored in an existing database, or the default for a
+/// new one. Read before the pool is built so every connection starts in it.
+fn stored_journal_mode(db_path: &Path) -> Result<&'static str> {
+    let conn = Connection::open(db_path)?;
+    let stored: Option<String> = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = 'db_journal_mode'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()
+        // No settings table yet on a fresh database.
+        .unwrap_or(None);
+    Ok(match stored.as_deref().map(parse_journal_mode) {
+        Some(Ok(mode)) => mode,
+        Some(Err(e)) => {
+            tracing::warn!("[db] {}; using {}", e, DEFAULT_JOURNAL_MODE);
+            DEFAULT_JOURNAL_MODE
+        }
+        None => DEFAULT_JOURNAL_MODE,
+    })
+}
+
+/// Built-in AI commands: (id, label, icon, behavior, system_prompt).
+const SEED_AI_COMMANDS: &[(&str, &str, &str, &str, &str)] = &[
+    ("improve_writing", "Improve writing", "✏️", "replace_selection", "Improve the writing quality of the following text. Fix grammar, enhance clarity, and improve flow while preserving the original meaning. Output ONLY the improved text — no explanations, no commentary, no bullet points describing changes."),
+    ("expand_writing", "Expand my writing", "⚡", "insert_after", "Expand and elaborate on the following text with more detail, examples, and depth. Output ONLY the expanded text — no explanations or meta-commentary."),
+    ("fix_spelling", "Fix spelling and grammar", "⚡", "replace_selection", "Fix all spelling and grammar errors in the following text. Only correct errors, do not change the style or meaning. Output ONLY the corrected text — no explanations, no bullet points describing changes."),
+    ("simplify", "Simplify my writing", "⚡", "insert_after", "Simplify the following text. Use shorter sentences, simpler words, and clearer structure. Output ONLY the simplified text — no explanations or meta-commentary."),
+    ("explain", "Explain this", "❓", "answer_in_new", "Explain the following text or concept in clear, simple terms."),
+    ("key_takeaways", "List key takeaways", "⚡", "answer_in_new", "List the key takeaways and main points from the following text."),
+    ("summarize", "Summarize", "⚡", "answer_in_new", "Provide a concise summary of the following text."),
+    ("summarize_long", "Summarize (long)", "⚡", "answer_in_new", "Provide a detailed, comprehensive summary of the following text."),
+    ("rewrite_friendly", "Rewrite in friendly tone", "⚡", "insert_after", "Rewrite the following text in a warm, friendly, and approachable tone. Output ONLY the rewritten text — no explanations or meta-commentary."),
+    ("rewrite_professional", "Rewrite in professional tone", "⚡", "insert_after", "Rewrite the following text in a professional, formal tone suitable for business communication. Output ONLY the rewritten text — no explanations or meta-commentary."),
+    ("rewrite_persuasive", "Rewrite in persuasive tone", "⚡", "insert_after", "Rewrite the following text in a persuasive, compelling tone. Output ONLY the rewritten text — no explanations or meta-commentary."),
+    ("rewrite_instructional", "Rewrite in instructional tone", "⚡", "insert_after", "Rewrite the following text as clear, step-by-step instructions. Output ONLY the rewritten text — no explanations or meta-commentary."),
+    ("translate_english", "Translate to English", "🇺🇸", "replace_selection", "Translate the following text into English. Output ONLY the translated text — no explanations, no original text, no commentary."),
+    ("translate_chinese_simplified", "Translate to Simplified Chinese", "🇨🇳", "replace_selection", "Translate the following text into Simplified Chinese (简体中文). Output ONLY the translated text — no explanations, no original text, no commentary."),
+    ("translate_chinese_traditional", "Translate to Traditional Chinese", "🇹🇼", "replace_selection", "Translate the following text into Traditional Chinese (繁體中文). Output ONLY the translated text — no explanations, no original text, no commentary."),
+    ("translate_japanese", "Translate to Japanese", "🇯🇵", "replace_selection", "Translate the following text into Japanese (日本語). Output ONLY the translated text — no explanations, no original text, no commentary."),
+    ("translate_korean", "Translate to Korean", "🇰🇷", "replace_selection", "Translate the following text into Korean (한국어). Output ONLY the translated text — no explanations, no original text, no commentary."),
+    ("translate_spanish", "Translate to Spanish", "🇪🇸", "replace_selection", "Translate the following text into Spanish (Español). Output ONLY the translated text — no explanations, no original text, no commentary."),
+    ("translate_french", "Translate to French", "🇫🇷", "replace_selection", "Translate the following text into French (Français). Output ONLY the translated text — no explanations, no original text, no commentary."),
+    ("translate_german", "Translate to German", "🇩🇪", "replace_selection", "Translate the following text into German (Deutsch). Output ONLY the translated text — no explanations, no original text, no commentary."),
+    ("translate_portuguese", "Translate to Portuguese", "🇧🇷", "replace_selection", "Translate the following text into Portuguese (Português). Output ONLY the translated text — no explanations, no original text, no commentary."),
+];
+
+/// Built-in assistants: (id, name, icon, description, system_prompt). The
+/// first is the default on a fresh install.
+const SEED_ASSISTANTS: &[(&str, &str, &str, &str, &str)] = &[
+    ("general_assistant", "General Assistant", "🤖", "A helpful, general-purpose AI assistant.", "You are a helpful AI assistant. Answer questions clearly and concisely."),
+    ("creative_writer", "Creative Writer", "✍️", "Specializes in creative writing and storytelling.", "You are a creative writing assistant. Help with stories, poems, scripts and creative content. Be imaginative and expressive."),
+    ("code_helper", "Code Helper", "💻", "Expert programmer and code reviewer.", "You are an expert programmer. Help write, debug, and review code. Explain technical concepts clearly. Always provide working code examples."),
+    ("research_analyst", "Research Analyst", "🔍", "Thorough researcher and fact-checker.", "You are a research analyst. Provide well-researched, factual answers with attention to detail. Cite sources when possible and present balanced perspectives."),
+    ("writing_editor", "Writing Editor", "📝", "Professional editor for polishing text.", "You are a professional editor. Help improve writing quality, grammar, clarity, and style. Provide specific suggestions and explain your edits."),
+];
+
+pub const DEFAULT_JOURNAL_MODE: &str = "WAL";
+/// Journal modes accepted by the `db_journal_mode` setting.
+pub const JOURNAL_MODES: &[&str] = &["WAL", "DELETE", "TRUNCATE"];
+
+/// Validate a `db_journal_mode` value, case-insensitively.
+pub fn parse_journal_mode(mode: &str) -> std::result::Result<&'static str, String> {
+    let mode = mode.trim();
+    JOURNAL_MODES
+        .iter()
+        .find(|m| m.eq_ignore_ascii_case(mode))
+        .copied()
+        .ok_or_else(|| {
+            format!(
+                "Invalid journal mode '{}'. Expected one of: {}",
+                mode,
+                JOURNAL_MODES.join(", ")
+            )
+        })
+}
+
+/// Switch `conn` to `mode` and return the mode SQLite reports afterwards.
+/// Leaving WAL checkpoints first so no committed pages are left in the log.
+fn apply_journal_mode(conn: &Connection, mode: &str) -> Result<String> {
+    let current: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
+    if current.eq_ignore_ascii_case("wal") && !mode.eq_ignore_ascii_case("wal") {
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    }
+    conn.query_row(&format!("PRAGMA journal_mode={}", mode), [], |row| {
+        row.get(0)
+    })
+}
+
+/// Renumber a conversation's messages to `0..N`; see
+/// `Database::normalize_message_order`.
+fn renumber_messages(conn: &Connection, conversation_id: &str) -> Result<usize> {
+    let rows: Vec<(String, i64)> = {
+        let mut stmt = conn.prepare(
+            "SELECT id, sort_order FROM messages WHERE conversation_id = ?1
+             ORDER BY created_at ASC, sort_order ASC, rowid ASC",
+        )?;
+        let rows = stmt.query_map(params![conversation_id], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        rows.collect::<Result<_>>()?
+    };
+
+    let mut changed = 0;
+    for (index, (id, sort_order)) in rows.iter().enumerate() {
+        let index = index as i64;
+        if *sort_order != index {
+            conn.execute(
+                "UPDATE messages SET sort_order = ?1 WHERE id = ?2",
+                params![index, id],
+            )?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
 }
 
 impl Database {
+    /// Check out a pooled connection. A panic while one is in use returns it
+    /// to the pool with any `Transaction` rolled back by its drop, and a panic
+    /// under the pool lock is recovered below, so nothing is left poisoned
+    /// for later calls.
+    fn conn(&self) -> Result<PooledConnection> {
+        let pool = self
+            .pool
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        pool.get().map_err(pool_error)
+    }
+
     pub fn new(db_path: &Path) -> Result<Self> {
-        let conn = Connection::open(db_path)?;
+        let pool = build_pool(db_path, stored_journal_mode(db_path)?)?;
         let db = Self {
-            conn: Mutex::new(conn),
+            path: db_path.to_path_buf(),
+            pool: RwLock::new(pool),
         };
         db.run_migrations()?;
         Ok(db)
     }
 
+    /// File size, row counts and WAL state, for diagnostics.
+    pub fn stats(&self) -> Result<DbStats> {
+        let conn = self.conn()?;
+        let path = conn.path().filter(|p| !p.is_empty()).map(|p| p.to_string());
+        let page_count: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        let journal_mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
+        let wal_checkpoint = conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |row| {
+            Ok(WalCheckpoint {
+                busy: row.get::<_, i64>(0)? != 0,
+                log_frames: row.get(1)?,
+                checkpointed_frames: row.get(2)?,
+            })
+        })?;
+
+        let tables: Vec<String> = conn
+            .prepare(
+                "SELECT name FROM sqlite_master
+                 WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+                 ORDER BY name",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_>>()?;
+        let mut table_counts = std::collections::BTreeMap::new();
+        for table in tables {
+            let count: i64 = conn.query_row(
+                &format!("SELECT COUNT(*) FROM \"{}\"", table.replace('"', "\"\"")),
+                [],
+                |row| row.get(0),
+            )?;
+            table_counts.insert(table, count);
+        }
+
+        let wal_size_bytes = path
+            .as_ref()
+            .and_then(|p| std::fs::metadata(format!("{}-wal", p)).ok())
+            .map(|m| m.len());
+
+        Ok(DbStats {
+            path,
+            size_bytes: page_count * page_size,
+            wal_size_bytes,
+            journal_mode,
+            table_counts,
+            wal_checkpoint,
+        })
+    }
+
+    /// Copy every WAL frame into the main database file and truncate the WAL.
+    /// A no-op outside WAL mode.
+    pub fn checkpoint(&self) -> Result<WalCheckpoint> {
+        let conn = self.conn()?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+            Ok(WalCheckpoint {
+                busy: row.get::<_, i64>(0)? != 0,
+                log_frames: row.get(1)?,
+                checkpointed_frames: row.get(2)?,
+            })
+        })
+    }
+
+    /// Checkpoint, then close the pool's idle connections, for app exit.
+    /// Later calls still work; they open fresh connections.
+    pub fn close(&self) -> Result<WalCheckpoint> {
+        let checkpoint = self.checkpoint()?;
+        let journal_mode = stored_journal_mode(&self.path)?;
+        let mut pool = self
+            .pool
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Dropping the old pool closes its connections; the new one opens none
+        // until asked.
+        *pool = build_pool(&self.path, journal_mode)?;
+        Ok(checkpoint)
+    }
+
+    /// Switch the open database to `mode` (one of `JOURNAL_MODES`) and return
+    /// the resulting mode. Does not persist the `db_journal_mode` setting.
+    pub fn set_journal_mode(&self, mode: &'static str) -> Result<String> {
+        let mut pool = self
+            .pool
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Replacing the pool closes its idle connections; the new pool's first
+        // connection applies `mode`, waiting out any still checked out.
+        *pool = build_pool(&self.path, mode)?;
+        let conn = pool.get().map_err(pool_error)?;
+        conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))
+    }
+
+    /// Count rows left behind by failed cascades or manual edits. Foreign key
+    /// enforcement is per-connection, so older rows may predate it.
+    pub fn find_orphans(&self) -> Result<OrphanReport> {
+        let conn = self.conn()?;
+        let messages = conn.query_row(
+            "SELECT COUNT(*) FROM messages
+             WHERE conversation_id NOT IN (SELECT id FROM conversations)",
+            [],
+            |row| row.get(0),
+        )?;
+        let attachments = conn.query_row(
+            "SELECT COUNT(*) FROM attachments
+             WHERE message_id NOT IN (SELECT id FROM messages)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(OrphanReport {
+            messages,
+            attachments,
+        })
+    }
+
+    /// Delete the rows `find_orphans` reports, plus attachments of the
+    /// orphaned messages. Attachments go first so the count includes those the
+    /// message cascade would otherwise remove silently.
+    pub fn delete_orphans(&self) -> Result<OrphanReport> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let attachments = tx.execute(
+            "DELETE FROM attachments WHERE message_id NOT IN
+                (SELECT m.id FROM messages m JOIN conversations c ON c.id = m.conversation_id)",
+            [],
+        )?;
+        let messages = tx.execute(
+            "DELETE FROM messages WHERE conversation_id NOT IN (SELECT id FROM conversations)",
+            [],
+        )?;
+        tx.commit()?;
+        Ok(OrphanReport {
+            messages: messages as i64,
+            attachments: attachments as i64,
+        })
+    }
+
+    /// Apply `normalize_message_text` to every message in one transaction.
+    /// Returns how many messages changed; running it again changes none.
+    pub fn normalize_content(&self) -> Result<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let messages: Vec<(String, String)> = tx
+            .prepare("SELECT id, content FROM messages")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_>>()?;
+        let mut changed = 0;
+        for (id, content) in messages {
+            let normalized = normalize_message_text(&content);
+            if normalized != content {
+                tx.execute(
+                    "UPDATE messages SET content = ?1 WHERE id = ?2",
+                    params![normalized, id],
+                )?;
+                changed += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
     fn run_migrations(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
 
         conn.execute_batch(
             "
@@ -277,6 +1179,33 @@ impl Database {
                 sort_order      INTEGER NOT NULL DEFAULT 0
             );
 
+            CREATE TABLE IF NOT EXISTS favorite_models (
+                provider_id     TEXT NOT NULL,
+                model_id        TEXT NOT NULL,
+                created_at      INTEGER NOT NULL,
+                PRIMARY KEY (provider_id, model_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS provider_keys (
+                id              TEXT PRIMARY KEY,
+                provider_id     TEXT NOT NULL,
+                key             TEXT NOT NULL,
+                enabled         INTEGER NOT NULL DEFAULT 1,
+                last_used       INTEGER,
+                created_at      INTEGER NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_provider_keys_provider_id
+                ON provider_keys(provider_id);
+
+            CREATE TABLE IF NOT EXISTS usage_daily (
+                provider_id     TEXT NOT NULL,
+                day             TEXT NOT NULL,
+                tokens          INTEGER NOT NULL DEFAULT 0,
+                requests        INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (provider_id, day)
+            );
+
             CREATE INDEX IF NOT EXISTS idx_messages_conversation_id
                 ON messages(conversation_id, sort_order);
 
@@ -285,9 +1214,6 @@ impl Database {
 
             CREATE INDEX IF NOT EXISTS idx_attachments_message_id
                 ON attachments(message_id);
-
-            PRAGMA journal_mode=WAL;
-            PRAGMA foreign_keys=ON;
         ",
         )?;
 
@@ -300,6 +1226,169 @@ impl Database {
             conn.execute_batch("ALTER TABLE providers ADD COLUMN default_model TEXT;")?;
         }
 
+        let has_message_metadata: bool = conn
+            .prepare("SELECT metadata FROM messages LIMIT 0")
+            .is_ok();
+        if !has_message_metadata {
+            conn.execute_batch("ALTER TABLE messages ADD COLUMN metadata TEXT;")?;
+        }
+
+        let has_include_in_context: bool = conn
+            .prepare("SELECT include_in_context FROM messages LIMIT 0")
+            .is_ok();
+        if !has_include_in_context {
+            conn.execute_batch(
+                "ALTER TABLE messages ADD COLUMN include_in_context INTEGER NOT NULL DEFAULT 1;",
+            )?;
+        }
+
+        let has_sticky: bool = conn.prepare("SELECT sticky FROM messages LIMIT 0").is_ok();
+        if !has_sticky {
+            conn.execute_batch(
+                "ALTER TABLE messages ADD COLUMN sticky INTEGER NOT NULL DEFAULT 0;",
+            )?;
+        }
+
+        let has_stream_metrics: bool = conn.prepare("SELECT ttft_ms FROM messages LIMIT 0").is_ok();
+        if !has_stream_metrics {
+            conn.execute_batch(
+                "ALTER TABLE messages ADD COLUMN ttft_ms INTEGER;
+                 ALTER TABLE messages ADD COLUMN duration_ms INTEGER;
+                 ALTER TABLE messages ADD COLUMN tokens_per_second REAL;",
+            )?;
+        }
+
+        let has_title_manual: bool = conn
+            .prepare("SELECT title_manual FROM conversations LIMIT 0")
+            .is_ok();
+        if !has_title_manual {
+            conn.execute_batch(
+                "ALTER TABLE conversations ADD COLUMN title_manual INTEGER NOT NULL DEFAULT 0;",
+            )?;
+            // A title that isn't one the app would have given the chat
+            // itself must be a user rename.
+            let titled: Vec<(String, String, Option<String>)> = conn
+                .prepare(
+                    "SELECT c.id, c.title,
+                            (SELECT m.content FROM messages m
+                             WHERE m.conversation_id = c.id AND m.role = 'user'
+                             ORDER BY m.sort_order ASC LIMIT 1)
+                     FROM conversations c",
+                )?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<_>>()?;
+            for (id, title, first_user_message) in titled {
+                let first_user_message = first_user_message.unwrap_or_default();
+                if !crate::commands::is_default_title(&title, &first_user_message) {
+                    conn.execute(
+                        "UPDATE conversations SET title_manual = 1 WHERE id = ?1",
+                        params![id],
+                    )?;
+                }
+            }
+        }
+
+        let has_is_pinned: bool = conn
+            .prepare("SELECT is_pinned FROM conversations LIMIT 0")
+            .is_ok();
+        if !has_is_pinned {
+            conn.execute_batch(
+                "ALTER TABLE conversations ADD COLUMN is_pinned INTEGER NOT NULL DEFAULT 0;",
+            )?;
+        }
+
+        let has_temperature: bool = conn
+            .prepare("SELECT temperature FROM conversations LIMIT 0")
+            .is_ok();
+        if !has_temperature {
+            conn.execute_batch("ALTER TABLE conversations ADD COLUMN temperature REAL;")?;
+        }
+
+        let has_supports_streaming: bool = conn
+            .prepare("SELECT supports_streaming FROM providers LIMIT 0")
+            .is_ok();
+        if !has_supports_streaming {
+            conn.execute_batch(
+                "ALTER TABLE providers ADD COLUMN supports_streaming INTEGER NOT NULL DEFAULT 1;",
+            )?;
+        }
+
+        let has_auth_style: bool = conn
+            .prepare("SELECT auth_style FROM providers LIMIT 0")
+            .is_ok();
+        if !has_auth_style {
+            conn.execute_batch(
+                "ALTER TABLE providers ADD COLUMN auth_style TEXT NOT NULL DEFAULT 'bearer_header';",
+            )?;
+        }
+
+        let has_default_max_tokens: bool = conn
+            .prepare("SELECT default_max_tokens FROM providers LIMIT 0")
+            .is_ok();
+        if !has_default_max_tokens {
+            conn.execute_batch("ALTER TABLE providers ADD COLUMN default_max_tokens INTEGER;")?;
+        }
+
+        let has_completion_mode: bool = conn
+            .prepare("SELECT completion_mode FROM providers LIMIT 0")
+            .is_ok();
+        if !has_completion_mode {
+            conn.execute_batch(
+                "ALTER TABLE providers ADD COLUMN completion_mode INTEGER NOT NULL DEFAULT 0;",
+            )?;
+        }
+
+        let has_custom_headers: bool = conn
+            .prepare("SELECT custom_headers FROM providers LIMIT 0")
+            .is_ok();
+        if !has_custom_headers {
+            conn.execute_batch("ALTER TABLE providers ADD COLUMN custom_headers TEXT;")?;
+        }
+
+        let has_extra_body: bool = conn
+            .prepare("SELECT extra_body FROM providers LIMIT 0")
+            .is_ok();
+        if !has_extra_body {
+            conn.execute_batch("ALTER TABLE providers ADD COLUMN extra_body TEXT;")?;
+        }
+
+        let has_system_prompt_mode: bool = conn
+            .prepare("SELECT system_prompt_mode FROM providers LIMIT 0")
+            .is_ok();
+        if !has_system_prompt_mode {
+            conn.execute_batch(
+                "ALTER TABLE providers ADD COLUMN system_prompt_mode TEXT NOT NULL DEFAULT 'system_role';",
+            )?;
+        }
+
+        let has_daily_token_cap: bool = conn
+            .prepare("SELECT daily_token_cap FROM providers LIMIT 0")
+            .is_ok();
+        if !has_daily_token_cap {
+            conn.execute_batch("ALTER TABLE providers ADD COLUMN daily_token_cap INTEGER;")?;
+        }
+
+        let has_send_user_id: bool = conn
+            .prepare("SELECT send_user_id FROM providers LIMIT 0")
+            .is_ok();
+        if !has_send_user_id {
+            conn.execute_batch(
+                "ALTER TABLE providers ADD COLUMN send_user_id INTEGER NOT NULL DEFAULT 0;",
+            )?;
+        }
+
+        for table in ["prompt_templates", "ai_commands"] {
+            let has_use_count: bool = conn
+                .prepare(&format!("SELECT use_count FROM {} LIMIT 0", table))
+                .is_ok();
+            if !has_use_count {
+                conn.execute_batch(&format!(
+                    "ALTER TABLE {table} ADD COLUMN use_count INTEGER NOT NULL DEFAULT 0;
+                     ALTER TABLE {table} ADD COLUMN last_used_at INTEGER;"
+                ))?;
+            }
+        }
+
         // Drop the lock before calling seed methods that also acquire it
         drop(conn);
 
@@ -319,7 +1408,7 @@ impl Database {
     /// One-time migration: append "Output ONLY …" to default AI command prompts
     /// so the LLM stops returning verbose explanations alongside the rewritten text.
     fn migrate_ai_command_prompts(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
 
         // Only patch prompts that still match the original seed text (user hasn't customised them)
         let updates: Vec<(&str, &str, &str)> = vec![
@@ -377,101 +1466,143 @@ impl Database {
 
     /// Add translate commands for existing users who already have ai_commands.
     fn seed_translate_commands(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-
-        let translate_cmds = vec![
-            ("translate_english", "Translate to English", "🇺🇸", "Translate the following text into English. Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_chinese_simplified", "Translate to Simplified Chinese", "🇨🇳", "Translate the following text into Simplified Chinese (简体中文). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_chinese_traditional", "Translate to Traditional Chinese", "🇹🇼", "Translate the following text into Traditional Chinese (繁體中文). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_japanese", "Translate to Japanese", "🇯🇵", "Translate the following text into Japanese (日本語). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_korean", "Translate to Korean", "🇰🇷", "Translate the following text into Korean (한국어). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_spanish", "Translate to Spanish", "🇪🇸", "Translate the following text into Spanish (Español). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_french", "Translate to French", "🇫🇷", "Translate the following text into French (Français). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_german", "Translate to German", "🇩🇪", "Translate the following text into German (Deutsch). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_portuguese", "Translate to Portuguese", "🇧🇷", "Translate the following text into Portuguese (Português). Output ONLY the translated text — no explanations, no original text, no commentary."),
-        ];
-
-        // Get max sort_order so we append after existing commands
-        let max_order: i64 = conn
-            .query_row("SELECT COALESCE(MAX(sort_order), -1) FROM ai_commands", [], |row| row.get(0))
-            .unwrap_or(-1);
+        let translate = SEED_AI_COMMANDS
+            .iter()
+            .filter(|c| c.0.starts_with("translate_"));
+        self.put_seed_ai_commands(translate, false)?;
+        Ok(())
+    }
 
-        for (i, (id, label, icon, prompt)) in translate_cmds.iter().enumerate() {
-            conn.execute(
-                "INSERT OR IGNORE INTO ai_commands (id, label, icon, behavior, system_prompt, sort_order) VALUES (?1, ?2, ?3, 'replace_selection', ?4, ?5)",
-                params![id, label, icon, prompt, max_order + 1 + i as i64],
-            )?;
+    fn seed_ai_commands(&self) -> Result<()> {
+        let conn = self.conn()?;
+        let count: i64 =
+            conn.query_row("SELECT COUNT(*) FROM ai_commands", [], |row| row.get(0))?;
+        if count > 0 {
+            return Ok(());
         }
+        drop(conn);
 
+        self.put_seed_ai_commands(SEED_AI_COMMANDS.iter(), false)?;
         Ok(())
     }
 
-    fn seed_ai_commands(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        let count: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM ai_commands", [], |row| row.get(0)
-        )?;
-        if count > 0 { return Ok(()); }
-
-        let seeds = vec![
-            ("improve_writing", "Improve writing", "✏️", "replace_selection", "Improve the writing quality of the following text. Fix grammar, enhance clarity, and improve flow while preserving the original meaning. Output ONLY the improved text — no explanations, no commentary, no bullet points describing changes."),
-            ("expand_writing", "Expand my writing", "⚡", "insert_after", "Expand and elaborate on the following text with more detail, examples, and depth. Output ONLY the expanded text — no explanations or meta-commentary."),
-            ("fix_spelling", "Fix spelling and grammar", "⚡", "replace_selection", "Fix all spelling and grammar errors in the following text. Only correct errors, do not change the style or meaning. Output ONLY the corrected text — no explanations, no bullet points describing changes."),
-            ("simplify", "Simplify my writing", "⚡", "insert_after", "Simplify the following text. Use shorter sentences, simpler words, and clearer structure. Output ONLY the simplified text — no explanations or meta-commentary."),
-            ("explain", "Explain this", "❓", "answer_in_new", "Explain the following text or concept in clear, simple terms."),
-            ("key_takeaways", "List key takeaways", "⚡", "answer_in_new", "List the key takeaways and main points from the following text."),
-            ("summarize", "Summarize", "⚡", "answer_in_new", "Provide a concise summary of the following text."),
-            ("summarize_long", "Summarize (long)", "⚡", "answer_in_new", "Provide a detailed, comprehensive summary of the following text."),
-            ("rewrite_friendly", "Rewrite in friendly tone", "⚡", "insert_after", "Rewrite the following text in a warm, friendly, and approachable tone. Output ONLY the rewritten text — no explanations or meta-commentary."),
-            ("rewrite_professional", "Rewrite in professional tone", "⚡", "insert_after", "Rewrite the following text in a professional, formal tone suitable for business communication. Output ONLY the rewritten text — no explanations or meta-commentary."),
-            ("rewrite_persuasive", "Rewrite in persuasive tone", "⚡", "insert_after", "Rewrite the following text in a persuasive, compelling tone. Output ONLY the rewritten text — no explanations or meta-commentary."),
-            ("rewrite_instructional", "Rewrite in instructional tone", "⚡", "insert_after", "Rewrite the following text as clear, step-by-step instructions. Output ONLY the rewritten text — no explanations or meta-commentary."),
-            ("translate_english", "Translate to English", "🇺🇸", "replace_selection", "Translate the following text into English. Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_chinese_simplified", "Translate to Simplified Chinese", "🇨🇳", "replace_selection", "Translate the following text into Simplified Chinese (简体中文). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_chinese_traditional", "Translate to Traditional Chinese", "🇹🇼", "replace_selection", "Translate the following text into Traditional Chinese (繁體中文). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_japanese", "Translate to Japanese", "🇯🇵", "replace_selection", "Translate the following text into Japanese (日本語). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_korean", "Translate to Korean", "🇰🇷", "replace_selection", "Translate the following text into Korean (한국어). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_spanish", "Translate to Spanish", "🇪🇸", "replace_selection", "Translate the following text into Spanish (Español). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_french", "Translate to French", "🇫🇷", "replace_selection", "Translate the following text into French (Français). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_german", "Translate to German", "🇩🇪", "replace_selection", "Translate the following text into German (Deutsch). Output ONLY the translated text — no explanations, no original text, no commentary."),
-            ("translate_portuguese", "Translate to Portuguese", "🇧🇷", "replace_selection", "Translate the following text into Portuguese (Português). Output ONLY the translated text — no explanations, no original text, no commentary."),
-        ];
-
-        for (i, (id, label, icon, behavior, prompt)) in seeds.iter().enumerate() {
-            conn.execute(
-                "INSERT INTO ai_commands (id, label, icon, behavior, system_prompt, sort_order) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-                params![id, label, icon, behavior, prompt, i as i64],
-            )?;
+    fn seed_assistants(&self) -> Result<()> {
+        let conn = self.conn()?;
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM assistants", [], |row| row.get(0))?;
+        if count > 0 {
+            return Ok(());
         }
+        drop(conn);
 
+        self.restore_default_assistants(false)?;
         Ok(())
     }
 
-    fn seed_assistants(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        let count: i64 = conn.query_row(
-            "SELECT COUNT(*) FROM assistants", [], |row| row.get(0)
+    /// Re-insert the built-in AI commands. Missing ones are appended after
+    /// the existing commands; ones that still exist are left alone, or with
+    /// `overwrite` reset to their built-in text, enabled, with no provider or
+    /// model override (position and shortcut are kept). Returns how many
+    /// rows were written.
+    pub fn restore_default_ai_commands(&self, overwrite: bool) -> Result<usize> {
+        self.put_seed_ai_commands(SEED_AI_COMMANDS.iter(), overwrite)
+    }
+
+    fn put_seed_ai_commands<'a>(
+        &self,
+        seeds: impl Iterator<Item = &'a (&'a str, &'a str, &'a str, &'a str, &'a str)>,
+        overwrite: bool,
+    ) -> Result<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut next_order: i64 = tx.query_row(
+            "SELECT COALESCE(MAX(sort_order), -1) + 1 FROM ai_commands",
+            [],
+            |row| row.get(0),
         )?;
-        if count > 0 { return Ok(()); }
+        let mut written = 0;
+        for (id, label, icon, behavior, prompt) in seeds {
+            let exists = tx
+                .query_row(
+                    "SELECT 1 FROM ai_commands WHERE id = ?1",
+                    params![id],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+            if !exists {
+                tx.execute(
+                    "INSERT INTO ai_commands (id, label, icon, behavior, system_prompt, sort_order) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![id, label, icon, behavior, prompt, next_order],
+                )?;
+                next_order += 1;
+            } else if overwrite {
+                tx.execute(
+                    "UPDATE ai_commands SET label = ?1, icon = ?2, behavior = ?3, system_prompt = ?4,
+                        provider_id = NULL, model = NULL, output_language = 'default', enabled = 1
+                     WHERE id = ?5",
+                    params![label, icon, behavior, prompt, id],
+                )?;
+            } else {
+                continue;
+            }
+            written += 1;
+        }
+        tx.commit()?;
+        Ok(written)
+    }
 
+    /// Re-insert the built-in assistants. Missing ones are appended after the
+    /// existing assistants, the first of them becoming the default if none
+    /// is set; ones that still exist are left alone, or with `overwrite`
+    /// reset to their built-in text with no model or sampling overrides
+    /// (position and default flag are kept). Returns how many rows were
+    /// written.
+    pub fn restore_default_assistants(&self, overwrite: bool) -> Result<usize> {
+        let mut conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
-        let seeds = [
-            ("general_assistant", "General Assistant", "🤖", "A helpful, general-purpose AI assistant.", "You are a helpful AI assistant. Answer questions clearly and concisely."),
-            ("creative_writer", "Creative Writer", "✍️", "Specializes in creative writing and storytelling.", "You are a creative writing assistant. Help with stories, poems, scripts and creative content. Be imaginative and expressive."),
-            ("code_helper", "Code Helper", "💻", "Expert programmer and code reviewer.", "You are an expert programmer. Help write, debug, and review code. Explain technical concepts clearly. Always provide working code examples."),
-            ("research_analyst", "Research Analyst", "🔍", "Thorough researcher and fact-checker.", "You are a research analyst. Provide well-researched, factual answers with attention to detail. Cite sources when possible and present balanced perspectives."),
-            ("writing_editor", "Writing Editor", "📝", "Professional editor for polishing text.", "You are a professional editor. Help improve writing quality, grammar, clarity, and style. Provide specific suggestions and explain your edits."),
-        ];
-
-        for (i, (id, name, icon, desc, prompt)) in seeds.iter().enumerate() {
-            conn.execute(
-                "INSERT INTO assistants (id, name, icon, description, system_prompt, is_default, sort_order, created_at, updated_at)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-                params![id, name, icon, desc, prompt, if i == 0 { 1i64 } else { 0i64 }, i as i64, now, now],
-            )?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut next_order: i64 = tx.query_row(
+            "SELECT COALESCE(MAX(sort_order), -1) + 1 FROM assistants",
+            [],
+            |row| row.get(0),
+        )?;
+        let mut has_default: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM assistants WHERE is_default = 1)",
+            [],
+            |row| row.get(0),
+        )?;
+        let mut written = 0;
+        for (id, name, icon, desc, prompt) in SEED_ASSISTANTS {
+            let exists = tx
+                .query_row(
+                    "SELECT 1 FROM assistants WHERE id = ?1",
+                    params![id],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+            if !exists {
+                tx.execute(
+                    "INSERT INTO assistants (id, name, icon, description, system_prompt, is_default, sort_order, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![id, name, icon, desc, prompt, !has_default as i64, next_order, now, now],
+                )?;
+                has_default = true;
+                next_order += 1;
+            } else if overwrite {
+                tx.execute(
+                    "UPDATE assistants SET name = ?1, icon = ?2, description = ?3, system_prompt = ?4,
+                        provider_id = NULL, model = NULL, temperature = NULL, max_tokens = NULL, updated_at = ?5
+                     WHERE id = ?6",
+                    params![name, icon, desc, prompt, now, id],
+                )?;
+            } else {
+                continue;
+            }
+            written += 1;
         }
-
-        Ok(())
+        tx.commit()?;
+        Ok(written)
     }
 
     // ============================================
@@ -487,7 +1618,7 @@ impl Database {
         system_prompt: Option<&str>,
         folder_id: Option<&str>,
     ) -> Result<Conversation> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
 
         conn.execute(
@@ -506,43 +1637,118 @@ impl Database {
             updated_at: now,
             is_archived: false,
             folder_id: folder_id.map(|s| s.to_string()),
+            is_pinned: false,
+            temperature: None,
         })
     }
 
     pub fn list_conversations(&self) -> Result<Vec<Conversation>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, title, model, provider_id, system_prompt, created_at, updated_at, is_archived, folder_id
-             FROM conversations
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM conversations
              WHERE is_archived = 0
+             ORDER BY is_pinned DESC, updated_at DESC",
+            CONVERSATION_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map([], conversation_from_row)?;
+
+        rows.collect()
+    }
+
+    /// Every conversation, archived included, oldest first. Used by exports.
+    pub fn list_all_conversations(&self) -> Result<Vec<Conversation>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM conversations ORDER BY created_at ASC",
+            CONVERSATION_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map([], conversation_from_row)?;
+
+        rows.collect()
+    }
+
+    /// Every conversation, archived included, that is set to `model` or has a
+    /// message generated by it, most recent first.
+    pub fn conversations_using_model(&self, model: &str) -> Result<Vec<Conversation>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM conversations
+             WHERE model = ?1
+                OR EXISTS (SELECT 1 FROM messages m
+                           WHERE m.conversation_id = conversations.id AND m.model = ?1)
              ORDER BY updated_at DESC",
-        )?;
+            CONVERSATION_COLUMNS
+        ))?;
 
-        let rows = stmt.query_map([], |row| {
-            Ok(Conversation {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                model: row.get(2)?,
-                provider_id: row.get(3)?,
-                system_prompt: row.get(4)?,
-                created_at: row.get(5)?,
-                updated_at: row.get(6)?,
-                is_archived: row.get::<_, i64>(7)? != 0,
-                folder_id: row.get(8)?,
-            })
-        })?;
+        let rows = stmt.query_map(params![model], conversation_from_row)?;
 
         rows.collect()
     }
 
-    pub fn get_conversation(&self, id: &str) -> Result<Conversation> {
-        let conn = self.conn.lock().unwrap();
-        conn.query_row(
-            "SELECT id, title, model, provider_id, system_prompt, created_at, updated_at, is_archived, folder_id
-             FROM conversations WHERE id = ?1",
-            params![id],
-            |row| {
-                Ok(Conversation {
+    /// Every conversation, archived included, that is set to `provider_id`,
+    /// most recent first.
+    pub fn conversations_using_provider(&self, provider_id: &str) -> Result<Vec<Conversation>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM conversations WHERE provider_id = ?1 ORDER BY updated_at DESC",
+            CONVERSATION_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map(params![provider_id], conversation_from_row)?;
+
+        rows.collect()
+    }
+
+    /// Unarchived conversations filed in `folder_id`, pinned first, then most
+    /// recent first.
+    pub fn list_conversations_in_folder(&self, folder_id: &str) -> Result<Vec<Conversation>> {
+        self.list_conversations_by_folder(Some(folder_id))
+    }
+
+    /// Unarchived conversations that are not in any folder, pinned first, then
+    /// most recent first.
+    pub fn list_root_conversations(&self) -> Result<Vec<Conversation>> {
+        self.list_conversations_by_folder(None)
+    }
+
+    fn list_conversations_by_folder(&self, folder_id: Option<&str>) -> Result<Vec<Conversation>> {
+        let conn = self.conn()?;
+        // `IS` compares NULL as a value, so `None` selects the root.
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM conversations
+             WHERE is_archived = 0 AND folder_id IS ?1
+             ORDER BY is_pinned DESC, updated_at DESC",
+            CONVERSATION_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map(params![folder_id], conversation_from_row)?;
+
+        rows.collect()
+    }
+
+    /// Like `list_conversations`, but each row also carries its message count
+    /// and a preview of the most recent message.
+    pub fn list_conversation_summaries(&self) -> Result<Vec<ConversationSummary>> {
+        let conn = self.conn()?;
+        // Only pull a bounded prefix of the latest message; whitespace collapsing
+        // can shorten it, so fetch a little more than the preview length.
+        let mut stmt = conn.prepare(
+            "SELECT c.id, c.title, c.model, c.provider_id, c.system_prompt, c.created_at, c.updated_at, c.is_archived, c.folder_id, c.is_pinned, c.temperature,
+                    (SELECT COUNT(*) FROM messages m WHERE m.conversation_id = c.id),
+                    (SELECT substr(m.content, 1, ?1) FROM messages m
+                     WHERE m.conversation_id = c.id
+                     ORDER BY m.sort_order DESC LIMIT 1)
+             FROM conversations c
+             WHERE c.is_archived = 0
+             ORDER BY c.is_pinned DESC, c.updated_at DESC",
+        )?;
+
+        let rows = stmt.query_map(params![(PREVIEW_MAX_CHARS * 4) as i64], |row| {
+            let last_content: Option<String> = row.get(12)?;
+            Ok(ConversationSummary {
+                conversation: Conversation {
                     id: row.get(0)?,
                     title: row.get(1)?,
                     model: row.get(2)?,
@@ -552,29 +1758,221 @@ impl Database {
                     updated_at: row.get(6)?,
                     is_archived: row.get::<_, i64>(7)? != 0,
                     folder_id: row.get(8)?,
-                })
-            },
+                    is_pinned: row.get::<_, i64>(9)? != 0,
+                    temperature: row.get(10)?,
+                },
+                message_count: row.get(11)?,
+                last_message_preview: last_content.as_deref().map(make_preview),
+            })
+        })?;
+
+        rows.collect()
+    }
+
+    pub fn get_conversation(&self, id: &str) -> Result<Conversation> {
+        let conn = self.conn()?;
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM conversations WHERE id = ?1",
+                CONVERSATION_COLUMNS
+            ),
+            params![id],
+            conversation_from_row,
         )
     }
 
+    /// `get_conversation` plus message count, token total and last activity.
+    pub fn get_conversation_detail(&self, id: &str) -> Result<ConversationDetail> {
+        let conversation = self.get_conversation(id)?;
+        let conn = self.conn()?;
+        let (message_count, total_tokens, last_message_at) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(token_count), 0), MAX(created_at)
+             FROM messages WHERE conversation_id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        Ok(ConversationDetail {
+            conversation,
+            message_count,
+            total_tokens,
+            last_message_at,
+        })
+    }
+
+    /// Rename a conversation on the user's behalf. The title is marked manual
+    /// so auto-titling never replaces it.
     pub fn update_conversation_title(&self, id: &str, title: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
         conn.execute(
-            "UPDATE conversations SET title = ?1, updated_at = ?2 WHERE id = ?3",
+            "UPDATE conversations SET title = ?1, title_manual = 1, updated_at = ?2 WHERE id = ?3",
             params![title, now, id],
         )?;
         Ok(())
     }
 
+    /// Set or clear (`None`) the conversation's temperature override.
+    pub fn update_conversation_temperature(
+        &self,
+        id: &str,
+        temperature: Option<f64>,
+    ) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "UPDATE conversations SET temperature = ?1 WHERE id = ?2",
+            params![temperature, id],
+        )?;
+        Ok(())
+    }
+
+    /// Set a generated title unless the user has renamed the conversation.
+    /// Returns whether the title changed. `updated_at` is left alone so
+    /// titling old conversations doesn't reorder the list.
+    pub fn set_auto_title(&self, id: &str, title: &str) -> Result<bool> {
+        let conn = self.conn()?;
+        let changed = conn.execute(
+            "UPDATE conversations SET title = ?1 WHERE id = ?2 AND title_manual = 0",
+            params![title, id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    pub fn is_title_manual(&self, id: &str) -> Result<bool> {
+        let conn = self.conn()?;
+        conn.query_row(
+            "SELECT title_manual FROM conversations WHERE id = ?1",
+            params![id],
+            |row| Ok(row.get::<_, i64>(0)? != 0),
+        )
+    }
+
     pub fn delete_conversation(&self, id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         conn.execute("DELETE FROM conversations WHERE id = ?1", params![id])?;
         Ok(())
     }
 
+    /// Delete every message of a conversation with its attachment rows,
+    /// keeping the conversation itself. Returns how many messages were
+    /// deleted.
+    pub fn clear_messages(&self, conversation_id: &str) -> Result<u64> {
+        let mut conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "DELETE FROM attachments WHERE message_id IN
+                (SELECT id FROM messages WHERE conversation_id = ?1)",
+            params![conversation_id],
+        )?;
+        let deleted = tx.execute(
+            "DELETE FROM messages WHERE conversation_id = ?1",
+            params![conversation_id],
+        )?;
+        tx.execute(
+            "UPDATE conversations SET updated_at = ?1 WHERE id = ?2",
+            params![now, conversation_id],
+        )?;
+        tx.commit()?;
+        Ok(deleted as u64)
+    }
+
+    /// Delete a conversation with its messages and attachment rows in one
+    /// transaction, without relying on cascades. Returns whether it existed.
+    pub fn delete_conversation_tree(&self, id: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "DELETE FROM attachments WHERE message_id IN
+                (SELECT id FROM messages WHERE conversation_id = ?1)",
+            params![id],
+        )?;
+        tx.execute(
+            "DELETE FROM messages WHERE conversation_id = ?1",
+            params![id],
+        )?;
+        let deleted = tx.execute("DELETE FROM conversations WHERE id = ?1", params![id])?;
+        tx.commit()?;
+        Ok(deleted > 0)
+    }
+
+    /// Insert a previously exported conversation as-is, keeping ids and
+    /// timestamps, with its messages renumbered as `normalize_message_order`
+    /// would. Fails without writing anything if any id already exists.
+    pub fn restore_conversation(
+        &self,
+        conversation: &Conversation,
+        title_manual: bool,
+        messages: &[Message],
+        attachments: &[Attachment],
+    ) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            &format!(
+                "INSERT INTO conversations ({}, title_manual) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                CONVERSATION_COLUMNS
+            ),
+            params![
+                conversation.id,
+                conversation.title,
+                conversation.model,
+                conversation.provider_id,
+                conversation.system_prompt,
+                conversation.created_at,
+                conversation.updated_at,
+                conversation.is_archived as i64,
+                conversation.folder_id,
+                conversation.is_pinned as i64,
+                conversation.temperature,
+                title_manual as i64,
+            ],
+        )?;
+        for message in messages {
+            tx.execute(
+                &format!(
+                    "INSERT INTO messages ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                    MESSAGE_COLUMNS
+                ),
+                params![
+                    message.id,
+                    conversation.id,
+                    message.role,
+                    message.content,
+                    message.model,
+                    message.token_count,
+                    message.created_at,
+                    message.parent_id,
+                    message.sort_order,
+                    message.metadata.as_ref().map(|m| m.to_string()),
+                    message.include_in_context as i64,
+                    message.sticky as i64,
+                    message.ttft_ms,
+                    message.duration_ms,
+                    message.tokens_per_second,
+                ],
+            )?;
+        }
+        for attachment in attachments {
+            tx.execute(
+                "INSERT INTO attachments (id, message_id, file_name, file_path, mime_type, file_size, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    attachment.id,
+                    attachment.message_id,
+                    attachment.file_name,
+                    attachment.file_path,
+                    attachment.mime_type,
+                    attachment.file_size,
+                    attachment.created_at,
+                ],
+            )?;
+        }
+        renumber_messages(&tx, &conversation.id)?;
+        tx.commit()
+    }
+
     pub fn archive_conversation(&self, id: &str, archived: bool) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
         conn.execute(
             "UPDATE conversations SET is_archived = ?1, updated_at = ?2 WHERE id = ?3",
@@ -583,30 +1981,114 @@ impl Database {
         Ok(())
     }
 
+    pub fn set_conversation_pinned(&self, id: &str, pinned: bool) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "UPDATE conversations SET is_pinned = ?1 WHERE id = ?2",
+            params![pinned as i64, id],
+        )?;
+        Ok(())
+    }
+
+    /// Archive (or, with `delete`, delete) the least recently updated
+    /// unarchived conversations until at most `max` remain. Pinned
+    /// conversations and `keep_id` are never pruned, so more than `max` can
+    /// remain. Returns the ids pruned.
+    pub fn prune_conversations(
+        &self,
+        max: usize,
+        delete: bool,
+        keep_id: &str,
+    ) -> Result<Vec<String>> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let count: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM conversations WHERE is_archived = 0",
+            [],
+            |row| row.get(0),
+        )?;
+        let excess = count - max as i64;
+        if excess <= 0 {
+            return Ok(Vec::new());
+        }
+
+        let ids: Vec<String> = {
+            let mut stmt = tx.prepare(
+                "SELECT id FROM conversations
+                 WHERE is_archived = 0 AND is_pinned = 0 AND id != ?1
+                 ORDER BY updated_at ASC, created_at ASC
+                 LIMIT ?2",
+            )?;
+            let rows = stmt.query_map(params![keep_id, excess], |row| row.get(0))?;
+            rows.collect::<Result<_>>()?
+        };
+        for id in &ids {
+            if delete {
+                tx.execute(
+                    "DELETE FROM attachments WHERE message_id IN
+                        (SELECT id FROM messages WHERE conversation_id = ?1)",
+                    params![id],
+                )?;
+                tx.execute(
+                    "DELETE FROM messages WHERE conversation_id = ?1",
+                    params![id],
+                )?;
+                tx.execute("DELETE FROM conversations WHERE id = ?1", params![id])?;
+            } else {
+                tx.execute(
+                    "UPDATE conversations SET is_archived = 1 WHERE id = ?1",
+                    params![id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(ids)
+    }
+
     pub fn search_conversations(&self, query: &str) -> Result<Vec<Conversation>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let pattern = format!("%{}%", query);
         let mut stmt = conn.prepare(
-            "SELECT DISTINCT c.id, c.title, c.model, c.provider_id, c.system_prompt, c.created_at, c.updated_at, c.is_archived, c.folder_id
+            "SELECT DISTINCT c.id, c.title, c.model, c.provider_id, c.system_prompt, c.created_at, c.updated_at, c.is_archived, c.folder_id, c.is_pinned, c.temperature
              FROM conversations c
              LEFT JOIN messages m ON m.conversation_id = c.id
              WHERE c.title LIKE ?1 OR m.content LIKE ?1
              ORDER BY c.updated_at DESC",
         )?;
 
-        let rows = stmt.query_map(params![pattern], |row| {
-            Ok(Conversation {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                model: row.get(2)?,
-                provider_id: row.get(3)?,
-                system_prompt: row.get(4)?,
-                created_at: row.get(5)?,
-                updated_at: row.get(6)?,
-                is_archived: row.get::<_, i64>(7)? != 0,
-                folder_id: row.get(8)?,
-            })
-        })?;
+        let rows = stmt.query_map(params![pattern], conversation_from_row)?;
+
+        rows.collect()
+    }
+
+    /// Like `search_conversations`, but restricted to titles and/or message
+    /// content, and optionally to a single folder.
+    pub fn search_conversations_scoped(
+        &self,
+        query: &str,
+        scope: SearchScope,
+        folder_id: Option<&str>,
+    ) -> Result<Vec<Conversation>> {
+        let conn = self.conn()?;
+        let pattern = format!("%{}%", query);
+        let title_match = "c.title LIKE ?1";
+        let content_match =
+            "EXISTS (SELECT 1 FROM messages m WHERE m.conversation_id = c.id AND m.content LIKE ?1)";
+        let scope_clause = match scope {
+            SearchScope::TitleOnly => title_match.to_string(),
+            SearchScope::ContentOnly => content_match.to_string(),
+            SearchScope::All => format!("({} OR {})", title_match, content_match),
+        };
+        let sql = format!(
+            "SELECT c.id, c.title, c.model, c.provider_id, c.system_prompt, c.created_at, c.updated_at, c.is_archived, c.folder_id, c.is_pinned, c.temperature
+             FROM conversations c
+             WHERE {} AND (?2 IS NULL OR c.folder_id = ?2)
+             ORDER BY c.updated_at DESC",
+            scope_clause
+        );
+        let mut stmt = conn.prepare(&sql)?;
+
+        let rows = stmt.query_map(params![pattern, folder_id], conversation_from_row)?;
 
         rows.collect()
     }
@@ -626,7 +2108,7 @@ impl Database {
         token_count: Option<i64>,
         sort_order: i64,
     ) -> Result<Message> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
 
         conn.execute(
@@ -651,124 +2133,700 @@ impl Database {
             created_at: now,
             parent_id: None,
             sort_order,
+            metadata: None,
+            include_in_context: true,
+            sticky: false,
+            ttft_ms: None,
+            duration_ms: None,
+            tokens_per_second: None,
         })
     }
 
-    pub fn get_messages(&self, conversation_id: &str) -> Result<Vec<Message>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, conversation_id, role, content, model, token_count, created_at, parent_id, sort_order
-             FROM messages
-             WHERE conversation_id = ?1
-             ORDER BY sort_order ASC",
-        )?;
+    /// Renumber a conversation's messages to a contiguous `0..N` sequence,
+    /// ordered by `created_at` then the existing `sort_order`. Repairs gaps and
+    /// duplicates left behind by bugs or imports. Returns the number of rows
+    /// whose `sort_order` changed.
+    pub fn normalize_message_order(&self, conversation_id: &str) -> Result<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let changed = renumber_messages(&tx, conversation_id)?;
+        tx.commit()?;
+        Ok(changed)
+    }
 
-        let rows = stmt.query_map(params![conversation_id], |row| {
-            Ok(Message {
-                id: row.get(0)?,
-                conversation_id: row.get(1)?,
-                role: row.get(2)?,
-                content: row.get(3)?,
-                model: row.get(4)?,
-                token_count: row.get(5)?,
-                created_at: row.get(6)?,
-                parent_id: row.get(7)?,
-                sort_order: row.get(8)?,
-            })
-        })?;
+    /// Include or exclude a message from the context sent to the model.
+    pub fn set_message_include_in_context(&self, id: &str, include: bool) -> Result<()> {
+        let conn = self.conn()?;
+        let updated = conn.execute(
+            "UPDATE messages SET include_in_context = ?1 WHERE id = ?2",
+            params![include as i64, id],
+        )?;
+        if updated == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        Ok(())
+    }
 
-        rows.collect()
+    /// Mark a message as always kept when context is trimmed.
+    pub fn set_message_sticky(&self, id: &str, sticky: bool) -> Result<()> {
+        let conn = self.conn()?;
+        let updated = conn.execute(
+            "UPDATE messages SET sticky = ?1 WHERE id = ?2",
+            params![sticky as i64, id],
+        )?;
+        if updated == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        Ok(())
     }
 
-    pub fn get_message_count(&self, conversation_id: &str) -> Result<i64> {
-        let conn = self.conn.lock().unwrap();
-        conn.query_row(
-            "SELECT COUNT(*) FROM messages WHERE conversation_id = ?1",
-            params![conversation_id],
-            |row| row.get(0),
+    /// Set the model label of a conversation's messages to `to`: those
+    /// labeled `from`, or every labeled message when `from` is `None`.
+    /// Content is left alone. Returns how many messages changed.
+    pub fn relabel_messages_model(
+        &self,
+        conversation_id: &str,
+        from: Option<&str>,
+        to: &str,
+    ) -> Result<usize> {
+        let conn = self.conn()?;
+        conn.execute(
+            "UPDATE messages SET model = ?1
+             WHERE conversation_id = ?2 AND model IS NOT NULL AND model != ?1
+               AND (?3 IS NULL OR model = ?3)",
+            params![to, conversation_id, from],
         )
     }
 
-    pub fn delete_message(&self, id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM messages WHERE id = ?1", params![id])?;
+    /// Insert an empty assistant message for a reply that is about to
+    /// stream. It carries `{"streaming": true}` metadata until
+    /// `replace_message_content` finalizes it, so a row left behind by a
+    /// crash can be told apart from a finished reply.
+    pub fn begin_streaming_message(
+        &self,
+        id: &str,
+        conversation_id: &str,
+        model: &str,
+        sort_order: i64,
+    ) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let now = chrono::Utc::now().timestamp_millis();
+        tx.execute(
+            "INSERT INTO messages (id, conversation_id, role, content, model, created_at, sort_order, metadata)
+             VALUES (?1, ?2, 'assistant', '', ?3, ?4, ?5, ?6)",
+            params![
+                id,
+                conversation_id,
+                model,
+                now,
+                sort_order,
+                serde_json::json!({ "streaming": true }).to_string()
+            ],
+        )?;
+        tx.execute(
+            "UPDATE conversations SET updated_at = ?1 WHERE id = ?2",
+            params![now, conversation_id],
+        )?;
+        tx.commit()?;
         Ok(())
     }
 
-    // ============================================
-    // Provider CRUD
-    // ============================================
+    /// Save the text streamed so far into a message started with
+    /// `begin_streaming_message`.
+    pub fn set_message_content(&self, id: &str, content: &str) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "UPDATE messages SET content = ?1 WHERE id = ?2",
+            params![content, id],
+        )?;
+        Ok(())
+    }
 
-    pub fn save_provider(&self, provider: &Provider) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    /// Replace a message's metadata (JSON, e.g. `{"toolCalls": [...]}`).
+    pub fn set_message_metadata(&self, id: &str, metadata: &serde_json::Value) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "UPDATE messages SET metadata = ?1 WHERE id = ?2",
+            params![metadata.to_string(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Record how quickly a streamed reply arrived.
+    pub fn set_message_metrics(
+        &self,
+        id: &str,
+        metrics: &crate::providers::StreamMetrics,
+    ) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "UPDATE messages SET ttft_ms = ?1, duration_ms = ?2, tokens_per_second = ?3 WHERE id = ?4",
+            params![metrics.ttft_ms, metrics.duration_ms, metrics.tokens_per_second, id],
+        )?;
+        Ok(())
+    }
+
+    /// Overwrite a message's reply in place, keeping its id and position.
+    /// Metadata and stream metrics from the previous reply (e.g. tool calls)
+    /// are cleared.
+    pub fn replace_message_content(
+        &self,
+        id: &str,
+        content: &str,
+        model: Option<&str>,
+        token_count: Option<i64>,
+    ) -> Result<()> {
+        let conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let updated = conn.execute(
+            "UPDATE messages SET content = ?1, model = ?2, token_count = ?3, metadata = NULL,
+                 ttft_ms = NULL, duration_ms = NULL, tokens_per_second = NULL
+             WHERE id = ?4",
+            params![content, model, token_count, id],
+        )?;
+        if updated == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        conn.execute(
+            "UPDATE conversations SET updated_at = ?1
+             WHERE id = (SELECT conversation_id FROM messages WHERE id = ?2)",
+            params![now, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn create_attachment(&self, attachment: &Attachment) -> Result<()> {
+        let conn = self.conn()?;
         conn.execute(
-            "INSERT OR REPLACE INTO providers (id, provider_type, name, api_key, base_url, default_model, enabled)
+            "INSERT INTO attachments (id, message_id, file_name, file_path, mime_type, file_size, created_at)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
             params![
-                provider.id,
-                provider.provider_type,
-                provider.name,
-                provider.api_key,
-                provider.base_url,
-                provider.default_model,
-                provider.enabled as i64,
+                attachment.id,
+                attachment.message_id,
+                attachment.file_name,
+                attachment.file_path,
+                attachment.mime_type,
+                attachment.file_size,
+                attachment.created_at,
             ],
         )?;
         Ok(())
     }
 
-    pub fn list_providers(&self) -> Result<Vec<Provider>> {
-        let conn = self.conn.lock().unwrap();
+    /// Attachment rows of every message in a conversation.
+    pub fn list_conversation_attachments(&self, conversation_id: &str) -> Result<Vec<Attachment>> {
+        let conn = self.conn()?;
         let mut stmt = conn.prepare(
-            "SELECT id, provider_type, name, api_key, base_url, default_model, enabled FROM providers ORDER BY name",
+            "SELECT a.id, a.message_id, a.file_name, a.file_path, a.mime_type, a.file_size, a.created_at
+             FROM attachments a
+             JOIN messages m ON m.id = a.message_id
+             WHERE m.conversation_id = ?1
+             ORDER BY m.sort_order, a.created_at",
         )?;
-
-        let rows = stmt.query_map([], |row| {
-            Ok(Provider {
+        let rows = stmt.query_map(params![conversation_id], |row| {
+            Ok(Attachment {
                 id: row.get(0)?,
-                provider_type: row.get(1)?,
-                name: row.get(2)?,
-                api_key: row.get(3)?,
-                base_url: row.get(4)?,
-                default_model: row.get(5)?,
-                enabled: row.get::<_, i64>(6)? != 0,
+                message_id: row.get(1)?,
+                file_name: row.get(2)?,
+                file_path: row.get(3)?,
+                mime_type: row.get(4)?,
+                file_size: row.get(5)?,
+                created_at: row.get(6)?,
             })
         })?;
+        rows.collect()
+    }
+
+    pub fn get_messages(&self, conversation_id: &str) -> Result<Vec<Message>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM messages WHERE conversation_id = ?1 ORDER BY sort_order ASC",
+            MESSAGE_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map(params![conversation_id], message_from_row)?;
 
         rows.collect()
     }
 
-    pub fn get_provider(&self, id: &str) -> Result<Provider> {
-        let conn = self.conn.lock().unwrap();
-        conn.query_row(
-            "SELECT id, provider_type, name, api_key, base_url, default_model, enabled FROM providers WHERE id = ?1",
-            params![id],
-            |row| {
-                Ok(Provider {
-                    id: row.get(0)?,
-                    provider_type: row.get(1)?,
-                    name: row.get(2)?,
-                    api_key: row.get(3)?,
-                    base_url: row.get(4)?,
-                    default_model: row.get(5)?,
-                    enabled: row.get::<_, i64>(6)? != 0,
+    /// Messages in one conversation whose content contains `query`
+    /// (case-insensitive for ASCII), in conversation order. An empty query
+    /// matches nothing.
+    pub fn search_messages_in_conversation(
+        &self,
+        conversation_id: &str,
+        query: &str,
+    ) -> Result<Vec<Message>> {
+        if query.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let conn = self.conn()?;
+        let pattern = format!("%{}%", query);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM messages
+             WHERE conversation_id = ?1 AND content LIKE ?2
+             ORDER BY sort_order ASC",
+            MESSAGE_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map(params![conversation_id, pattern], message_from_row)?;
+
+        rows.collect()
+    }
+
+    /// One page of a conversation's history, walking backwards from the newest
+    /// message. Returns up to `limit` messages with `sort_order` below
+    /// `before_sort_order` (or the latest ones when `None`), in ascending order
+    /// so the page can be prepended as-is. Pass the first message's
+    /// `sort_order` as the next cursor; an empty page means the start was reached.
+    pub fn get_messages_paginated(
+        &self,
+        conversation_id: &str,
+        before_sort_order: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Message>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM (
+                 SELECT * FROM messages
+                 WHERE conversation_id = ?1 AND (?2 IS NULL OR sort_order < ?2)
+                 ORDER BY sort_order DESC
+                 LIMIT ?3
+             )
+             ORDER BY sort_order ASC",
+            MESSAGE_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map(
+            params![conversation_id, before_sort_order, limit],
+            message_from_row,
+        )?;
+
+        rows.collect()
+    }
+
+    pub fn get_message(&self, id: &str) -> Result<Message> {
+        let conn = self.conn()?;
+        conn.query_row(
+            &format!("SELECT {} FROM messages WHERE id = ?1", MESSAGE_COLUMNS),
+            params![id],
+            message_from_row,
+        )
+    }
+
+    pub fn get_message_count(&self, conversation_id: &str) -> Result<i64> {
+        let conn = self.conn()?;
+        conn.query_row(
+            "SELECT COUNT(*) FROM messages WHERE conversation_id = ?1",
+            params![conversation_id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn delete_message(&self, id: &str) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM messages WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Branch a new conversation `new_id` off the conversation containing
+    /// `message_id`, copying its settings and every message up to and
+    /// including that one (with fresh IDs). Defaults the title to
+    /// "<source title> (fork)".
+    pub fn fork_conversation(
+        &self,
+        message_id: &str,
+        new_id: &str,
+        title: Option<&str>,
+    ) -> Result<Conversation> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let now = chrono::Utc::now().timestamp_millis();
+
+        let (source_id, cutoff): (String, i64) = tx.query_row(
+            "SELECT conversation_id, sort_order FROM messages WHERE id = ?1",
+            params![message_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let source = tx.query_row(
+            "SELECT title, model, provider_id, system_prompt, folder_id, temperature FROM conversations WHERE id = ?1",
+            params![source_id],
+            |row| {
+                Ok(Conversation {
+                    id: new_id.to_string(),
+                    title: row.get(0)?,
+                    model: row.get(1)?,
+                    provider_id: row.get(2)?,
+                    system_prompt: row.get(3)?,
+                    created_at: now,
+                    updated_at: now,
+                    is_archived: false,
+                    folder_id: row.get(4)?,
+                    is_pinned: false,
+                    temperature: row.get(5)?,
                 })
             },
+        )?;
+        let fork = Conversation {
+            title: title
+                .map(|t| t.to_string())
+                .unwrap_or_else(|| format!("{} (fork)", source.title)),
+            ..source
+        };
+
+        tx.execute(
+            "INSERT INTO conversations (id, title, model, provider_id, system_prompt, created_at, updated_at, folder_id, temperature)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                fork.id,
+                fork.title,
+                fork.model,
+                fork.provider_id,
+                fork.system_prompt,
+                now,
+                now,
+                fork.folder_id,
+                fork.temperature,
+            ],
+        )?;
+
+        let message_ids: Vec<String> = {
+            let mut stmt = tx.prepare(
+                "SELECT id FROM messages WHERE conversation_id = ?1 AND sort_order <= ?2 ORDER BY sort_order",
+            )?;
+            let rows = stmt.query_map(params![source_id, cutoff], |row| row.get(0))?;
+            rows.collect::<Result<_>>()?
+        };
+        for old_id in message_ids {
+            tx.execute(
+                "INSERT INTO messages (id, conversation_id, role, content, model, token_count, created_at, sort_order, metadata, include_in_context, sticky, ttft_ms, duration_ms, tokens_per_second)
+                 SELECT ?1, ?2, role, content, model, token_count, created_at, sort_order, metadata, include_in_context, sticky, ttft_ms, duration_ms, tokens_per_second
+                 FROM messages WHERE id = ?3",
+                params![uuid::Uuid::new_v4().to_string(), fork.id, old_id],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(fork)
+    }
+
+    // ============================================
+    // Provider CRUD
+    // ============================================
+
+    pub fn save_provider(&self, provider: &Provider) -> Result<()> {
+        let custom_headers = (!provider.custom_headers.is_empty())
+            .then(|| serde_json::to_string(&provider.custom_headers).unwrap_or_default());
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO providers (id, provider_type, name, api_key, base_url, default_model, enabled, supports_streaming, auth_style, default_max_tokens, completion_mode, custom_headers, extra_body, system_prompt_mode, daily_token_cap, send_user_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+            params![
+                provider.id,
+                provider.provider_type,
+                provider.name,
+                provider.api_key,
+                provider.base_url,
+                provider.default_model,
+                provider.enabled as i64,
+                provider.supports_streaming as i64,
+                provider.auth_style,
+                provider.default_max_tokens,
+                provider.completion_mode as i64,
+                custom_headers,
+                provider.extra_body.as_ref().map(|v| v.to_string()),
+                provider.system_prompt_mode,
+                provider.daily_token_cap,
+                provider.send_user_id as i64,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // ============================================
+    // Daily token usage
+    // ============================================
+
+    /// Add one finished response's `tokens` to a provider's usage for `day`.
+    pub fn add_daily_usage(&self, provider_id: &str, day: &str, tokens: i64) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT INTO usage_daily (provider_id, day, tokens, requests) VALUES (?1, ?2, ?3, 1)
+             ON CONFLICT(provider_id, day) DO UPDATE SET
+                 tokens = tokens + excluded.tokens,
+                 requests = requests + 1",
+            params![provider_id, day, tokens.max(0)],
+        )?;
+        Ok(())
+    }
+
+    /// Tokens a provider has used on `day`.
+    pub fn daily_tokens(&self, provider_id: &str, day: &str) -> Result<i64> {
+        let conn = self.conn()?;
+        conn.query_row(
+            "SELECT tokens FROM usage_daily WHERE provider_id = ?1 AND day = ?2",
+            params![provider_id, day],
+            |row| row.get(0),
+        )
+        .optional()
+        .map(|tokens| tokens.unwrap_or(0))
+    }
+
+    /// Every provider's usage on `day`, heaviest first.
+    pub fn list_daily_usage(&self, day: &str) -> Result<Vec<DailyUsage>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT provider_id, day, tokens, requests FROM usage_daily
+             WHERE day = ?1 ORDER BY tokens DESC, provider_id",
+        )?;
+        let rows = stmt.query_map(params![day], |row| {
+            Ok(DailyUsage {
+                provider_id: row.get(0)?,
+                day: row.get(1)?,
+                tokens: row.get(2)?,
+                requests: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn list_providers(&self) -> Result<Vec<Provider>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM providers ORDER BY name",
+            PROVIDER_COLUMNS
+        ))?;
+
+        let rows = stmt.query_map([], provider_from_row)?;
+
+        rows.collect()
+    }
+
+    pub fn get_provider(&self, id: &str) -> Result<Provider> {
+        let conn = self.conn()?;
+        conn.query_row(
+            &format!("SELECT {} FROM providers WHERE id = ?1", PROVIDER_COLUMNS),
+            params![id],
+            provider_from_row,
+        )
+    }
+
+    /// Turn a provider's `supports_streaming` flag on or off.
+    pub fn set_provider_streaming(&self, id: &str, supports_streaming: bool) -> Result<()> {
+        let conn = self.conn()?;
+        let updated = conn.execute(
+            "UPDATE providers SET supports_streaming = ?1 WHERE id = ?2",
+            params![supports_streaming as i64, id],
+        )?;
+        if updated == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        Ok(())
+    }
+
+    /// Change a provider's type in place, keeping its conversations.
+    pub fn update_provider_type(&self, id: &str, provider_type: &str) -> Result<Provider> {
+        let conn = self.conn()?;
+        let updated = conn.execute(
+            "UPDATE providers SET provider_type = ?1 WHERE id = ?2",
+            params![provider_type, id],
+        )?;
+        if updated == 0 {
+            return Err(rusqlite::Error::QueryReturnedNoRows);
+        }
+        conn.query_row(
+            &format!("SELECT {} FROM providers WHERE id = ?1", PROVIDER_COLUMNS),
+            params![id],
+            provider_from_row,
         )
     }
 
+    /// Delete a provider and the rows keyed to it, in one transaction.
     pub fn delete_provider(&self, id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute("DELETE FROM providers WHERE id = ?1", params![id])?;
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "DELETE FROM favorite_models WHERE provider_id = ?1",
+            params![id],
+        )?;
+        tx.execute(
+            "DELETE FROM provider_keys WHERE provider_id = ?1",
+            params![id],
+        )?;
+        tx.execute(
+            "DELETE FROM usage_daily WHERE provider_id = ?1",
+            params![id],
+        )?;
+        tx.execute("DELETE FROM providers WHERE id = ?1", params![id])?;
+        tx.commit()
+    }
+
+    /// Delete a provider together with every conversation created with it
+    /// (and their messages/attachments), all in one transaction.
+    pub fn delete_provider_with_data(&self, id: &str) -> Result<DeletionSummary> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        // Delete messages explicitly rather than relying on ON DELETE CASCADE,
+        // so we can report how many went away.
+        let messages_deleted = tx.execute(
+            "DELETE FROM messages WHERE conversation_id IN
+                (SELECT id FROM conversations WHERE provider_id = ?1)",
+            params![id],
+        )?;
+        let conversations_deleted = tx.execute(
+            "DELETE FROM conversations WHERE provider_id = ?1",
+            params![id],
+        )?;
+        tx.execute(
+            "DELETE FROM favorite_models WHERE provider_id = ?1",
+            params![id],
+        )?;
+        tx.execute(
+            "DELETE FROM provider_keys WHERE provider_id = ?1",
+            params![id],
+        )?;
+        tx.execute(
+            "DELETE FROM usage_daily WHERE provider_id = ?1",
+            params![id],
+        )?;
+        tx.execute("DELETE FROM providers WHERE id = ?1", params![id])?;
+
+        tx.commit()?;
+        Ok(DeletionSummary {
+            conversations_deleted: conversations_deleted as i64,
+            messages_deleted: messages_deleted as i64,
+        })
+    }
+
+    // ============================================
+    // Provider Keys
+    // ============================================
+
+    pub fn add_provider_key(&self, provider_id: &str, key: &str) -> Result<ProviderKey> {
+        let conn = self.conn()?;
+        let provider_key = ProviderKey {
+            id: uuid::Uuid::new_v4().to_string(),
+            provider_id: provider_id.to_string(),
+            key: key.to_string(),
+            enabled: true,
+            last_used: None,
+            created_at: chrono::Utc::now().timestamp_millis(),
+        };
+        conn.execute(
+            "INSERT INTO provider_keys (id, provider_id, key, enabled, last_used, created_at)
+             VALUES (?1, ?2, ?3, 1, NULL, ?4)",
+            params![
+                provider_key.id,
+                provider_key.provider_id,
+                provider_key.key,
+                provider_key.created_at
+            ],
+        )?;
+        Ok(provider_key)
+    }
+
+    pub fn list_provider_keys(&self, provider_id: &str) -> Result<Vec<ProviderKey>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, provider_id, key, enabled, last_used, created_at FROM provider_keys
+             WHERE provider_id = ?1 ORDER BY created_at, rowid",
+        )?;
+        let rows = stmt.query_map(params![provider_id], provider_key_from_row)?;
+        rows.collect()
+    }
+
+    pub fn remove_provider_key(&self, id: &str) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM provider_keys WHERE id = ?1", params![id])?;
         Ok(())
     }
 
+    /// Take the least recently used enabled key for a provider and mark it
+    /// used. Keys in `skip` are passed over unless every key is in it, in
+    /// which case the least recently used one is returned anyway. `None`
+    /// when the provider has no enabled keys.
+    pub fn next_provider_key(
+        &self,
+        provider_id: &str,
+        skip: &[String],
+    ) -> Result<Option<ProviderKey>> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let keys: Vec<ProviderKey> = {
+            let mut stmt = tx.prepare(
+                "SELECT id, provider_id, key, enabled, last_used, created_at FROM provider_keys
+                 WHERE provider_id = ?1 AND enabled = 1
+                 ORDER BY last_used IS NOT NULL, last_used, created_at, rowid",
+            )?;
+            let rows = stmt.query_map(params![provider_id], provider_key_from_row)?;
+            rows.collect::<Result<_>>()?
+        };
+        let Some(mut key) = keys
+            .iter()
+            .find(|k| !skip.contains(&k.id))
+            .or(keys.first())
+            .cloned()
+        else {
+            return Ok(None);
+        };
+
+        // Strictly increasing, so keys picked within the same millisecond
+        // still rotate in order.
+        let latest = keys.iter().filter_map(|k| k.last_used).max();
+        let now = chrono::Utc::now()
+            .timestamp_millis()
+            .max(latest.map_or(0, |t| t + 1));
+        tx.execute(
+            "UPDATE provider_keys SET last_used = ?1 WHERE id = ?2",
+            params![now, key.id],
+        )?;
+        tx.commit()?;
+        key.last_used = Some(now);
+        Ok(Some(key))
+    }
+
+    // ============================================
+    // Favorite Models
+    // ============================================
+
+    pub fn add_favorite_model(&self, provider_id: &str, model_id: &str) -> Result<()> {
+        let conn = self.conn()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        conn.execute(
+            "INSERT OR IGNORE INTO favorite_models (provider_id, model_id, created_at) VALUES (?1, ?2, ?3)",
+            params![provider_id, model_id, now],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_favorite_model(&self, provider_id: &str, model_id: &str) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            "DELETE FROM favorite_models WHERE provider_id = ?1 AND model_id = ?2",
+            params![provider_id, model_id],
+        )?;
+        Ok(())
+    }
+
+    /// Starred model ids for a provider, in the order they were starred.
+    pub fn list_favorite_models(&self, provider_id: &str) -> Result<Vec<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT model_id FROM favorite_models WHERE provider_id = ?1 ORDER BY created_at, rowid",
+        )?;
+        let rows = stmt.query_map(params![provider_id], |row| row.get(0))?;
+        rows.collect()
+    }
+
     // ============================================
     // Settings
     // ============================================
 
     pub fn get_settings(&self) -> Result<AppSettings> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let mut stmt = conn.prepare("SELECT key, value FROM settings")?;
         let rows: Vec<(String, String)> = stmt
             .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
@@ -792,6 +2850,57 @@ impl Database {
                 "code_theme" => settings.code_theme = value,
                 "compact_mode" => settings.compact_mode = value == "true",
                 "launch_at_login" => settings.launch_at_login = value == "true",
+                "start_as_background" => settings.start_as_background = value == "true",
+                "stream_idle_timeout_secs" => {
+                    settings.stream_idle_timeout_secs =
+                        value.parse().unwrap_or(settings.stream_idle_timeout_secs)
+                }
+                "global_system_prefix" => settings.global_system_prefix = value,
+                "global_system_suffix" => settings.global_system_suffix = value,
+                "log_level" => settings.log_level = value,
+                "auto_paste" => settings.auto_paste = value == "true",
+                "live_replace" => settings.live_replace = value == "true",
+                "log_raw_requests" => settings.log_raw_requests = value == "true",
+                "offline_mode" => settings.offline_mode = value == "true",
+                "ai_command_max_retries" => {
+                    settings.ai_command_max_retries =
+                        value.parse().unwrap_or(settings.ai_command_max_retries)
+                }
+                "stream_reconnect_attempts" => {
+                    settings.stream_reconnect_attempts =
+                        value.parse().unwrap_or(settings.stream_reconnect_attempts)
+                }
+                "max_attachment_bytes" => {
+                    settings.max_attachment_bytes =
+                        value.parse().unwrap_or(settings.max_attachment_bytes)
+                }
+                "db_journal_mode" => settings.db_journal_mode = value,
+                "max_context_messages" => {
+                    settings.max_context_messages =
+                        value.parse().unwrap_or(settings.max_context_messages)
+                }
+                "auto_title_mode" => settings.auto_title_mode = value,
+                "auto_title_after_messages" => {
+                    settings.auto_title_after_messages =
+                        value.parse().unwrap_or(settings.auto_title_after_messages)
+                }
+                "max_conversations" => {
+                    settings.max_conversations = value.parse().unwrap_or(settings.max_conversations)
+                }
+                "prune_action" => settings.prune_action = value,
+                "local_api_enabled" => settings.local_api_enabled = value == "true",
+                "local_api_port" => {
+                    settings.local_api_port = value.parse().unwrap_or(settings.local_api_port)
+                }
+                "local_api_token" => settings.local_api_token = value,
+                "anonymous_user_id" => settings.anonymous_user_id = value,
+                "auto_refresh_models_on_focus" => {
+                    settings.auto_refresh_models_on_focus = value == "true"
+                }
+                "response_token_warn_at" => {
+                    settings.response_token_warn_at =
+                        value.parse().unwrap_or(settings.response_token_warn_at)
+                }
                 _ => {}
             }
         }
@@ -800,25 +2909,8 @@ impl Database {
     }
 
     pub fn save_settings(&self, settings: &AppSettings) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        let pairs = vec![
-            ("theme", settings.theme.clone()),
-            ("default_model", settings.default_model.clone()),
-            ("default_provider_id", settings.default_provider_id.clone()),
-            ("default_system_prompt", settings.default_system_prompt.clone()),
-            ("global_hotkey", settings.global_hotkey.clone()),
-            ("send_on_enter", settings.send_on_enter.to_string()),
-            ("stream_responses", settings.stream_responses.to_string()),
-            ("font_size", settings.font_size.to_string()),
-            ("accent_color", settings.accent_color.clone()),
-            ("font_family", settings.font_family.clone()),
-            ("chat_bubble_style", settings.chat_bubble_style.clone()),
-            ("code_theme", settings.code_theme.clone()),
-            ("compact_mode", settings.compact_mode.to_string()),
-            ("launch_at_login", settings.launch_at_login.to_string()),
-        ];
-
-        for (key, value) in pairs {
+        let conn = self.conn()?;
+        for (key, value) in settings.to_pairs() {
             conn.execute(
                 "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
                 params![key, value],
@@ -828,10 +2920,17 @@ impl Database {
         Ok(())
     }
 
+    /// Remove a stored setting so `get_settings` falls back to its default.
+    pub fn reset_setting(&self, key: &str) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute("DELETE FROM settings WHERE key = ?1", params![key])?;
+        Ok(())
+    }
+
     // Raw key/value access for ad-hoc settings that don't belong on AppSettings
     // (e.g. updater rate-limit timestamps).
     pub fn get_setting_raw(&self, key: &str) -> Result<Option<String>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let mut stmt = conn.prepare("SELECT value FROM settings WHERE key = ?1")?;
         let mut rows = stmt.query(params![key])?;
         if let Some(row) = rows.next()? {
@@ -841,8 +2940,25 @@ impl Database {
         }
     }
 
+    /// Write `default_provider_id` and `default_model` together, touching no
+    /// other setting.
+    pub fn set_default_model(&self, provider_id: &str, model: &str) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        for (key, value) in [
+            ("default_provider_id", provider_id),
+            ("default_model", model),
+        ] {
+            tx.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )?;
+        }
+        tx.commit()
+    }
+
     pub fn set_setting_raw(&self, key: &str, value: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         conn.execute(
             "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
             params![key, value],
@@ -860,7 +2976,7 @@ impl Database {
         if x <= -30000 || y <= -30000 || width < 100 || height < 100 {
             return Ok(());
         }
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let pairs = [
             ("window_x", x.to_string()),
             ("window_y", y.to_string()),
@@ -877,7 +2993,7 @@ impl Database {
     }
 
     pub fn get_window_state(&self) -> Option<(i32, i32, u32, u32)> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn().ok()?;
         let mut stmt = conn
             .prepare("SELECT key, value FROM settings WHERE key IN ('window_x', 'window_y', 'window_width', 'window_height')")
             .ok()?;
@@ -913,39 +3029,48 @@ impl Database {
     // Prompt Templates
     // ============================================
 
-    pub fn list_prompt_templates(&self) -> Result<Vec<PromptTemplate>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, name, content, category, variables, created_at, updated_at
-             FROM prompt_templates ORDER BY name",
-        )?;
-
-        let rows = stmt.query_map([], |row| {
-            let variables_json: String = row.get(4)?;
-            let variables: Vec<String> =
-                serde_json::from_str(&variables_json).unwrap_or_default();
+    /// All templates by name, or most used first with `most_used_first`.
+    pub fn list_prompt_templates(&self, most_used_first: bool) -> Result<Vec<PromptTemplate>> {
+        let conn = self.conn()?;
+        let order = if most_used_first {
+            "use_count DESC, last_used_at DESC, name"
+        } else {
+            "name"
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM prompt_templates ORDER BY {}",
+            PROMPT_TEMPLATE_COLUMNS, order
+        ))?;
 
-            Ok(PromptTemplate {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                content: row.get(2)?,
-                category: row.get(3)?,
-                variables,
-                created_at: row.get(5)?,
-                updated_at: row.get(6)?,
-            })
-        })?;
+        let rows = stmt.query_map([], prompt_template_from_row)?;
 
         rows.collect()
     }
 
+    pub fn get_prompt_template(&self, id: &str) -> Result<PromptTemplate> {
+        let conn = self.conn()?;
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM prompt_templates WHERE id = ?1",
+                PROMPT_TEMPLATE_COLUMNS
+            ),
+            params![id],
+            prompt_template_from_row,
+        )
+    }
+
     pub fn save_prompt_template(&self, template: &PromptTemplate) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let variables_json = serde_json::to_string(&template.variables).unwrap_or_default();
 
+        // Upsert rather than replace so usage counters survive edits.
         conn.execute(
-            "INSERT OR REPLACE INTO prompt_templates (id, name, content, category, variables, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            "INSERT INTO prompt_templates (id, name, content, category, variables, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name, content = excluded.content, category = excluded.category,
+                variables = excluded.variables, created_at = excluded.created_at,
+                updated_at = excluded.updated_at",
             params![
                 template.id,
                 template.name,
@@ -959,8 +3084,30 @@ impl Database {
         Ok(())
     }
 
+    /// Count one use of a template or AI command. `table` is
+    /// `prompt_templates` or `ai_commands`.
+    fn record_use(&self, table: &str, id: &str) -> Result<()> {
+        let conn = self.conn()?;
+        conn.execute(
+            &format!(
+                "UPDATE {} SET use_count = use_count + 1, last_used_at = ?1 WHERE id = ?2",
+                table
+            ),
+            params![chrono::Utc::now().timestamp_millis(), id],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_prompt_template_use(&self, id: &str) -> Result<()> {
+        self.record_use("prompt_templates", id)
+    }
+
+    pub fn record_ai_command_use(&self, id: &str) -> Result<()> {
+        self.record_use("ai_commands", id)
+    }
+
     pub fn delete_prompt_template(&self, id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         conn.execute("DELETE FROM prompt_templates WHERE id = ?1", params![id])?;
         Ok(())
     }
@@ -970,7 +3117,7 @@ impl Database {
     // ============================================
 
     pub fn list_folders(&self) -> Result<Vec<Folder>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let mut stmt = conn.prepare(
             "SELECT id, name, parent_id, sort_order, created_at FROM folders ORDER BY sort_order",
         )?;
@@ -988,8 +3135,35 @@ impl Database {
         rows.collect()
     }
 
+    /// All folders with the number of non-archived conversations in each.
+    pub fn list_folders_with_counts(&self) -> Result<Vec<FolderSummary>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT f.id, f.name, f.parent_id, f.sort_order, f.created_at, COUNT(c.id)
+             FROM folders f
+             LEFT JOIN conversations c ON c.folder_id = f.id AND c.is_archived = 0
+             GROUP BY f.id
+             ORDER BY f.sort_order",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(FolderSummary {
+                folder: Folder {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    parent_id: row.get(2)?,
+                    sort_order: row.get(3)?,
+                    created_at: row.get(4)?,
+                },
+                conversation_count: row.get(5)?,
+            })
+        })?;
+
+        rows.collect()
+    }
+
     pub fn create_folder(&self, id: &str, name: &str) -> Result<Folder> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
 
         conn.execute(
@@ -1007,7 +3181,7 @@ impl Database {
     }
 
     pub fn delete_folder(&self, id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         conn.execute("DELETE FROM folders WHERE id = ?1", params![id])?;
         Ok(())
     }
@@ -1016,12 +3190,20 @@ impl Database {
     // AI Commands
     // ============================================
 
-    pub fn list_ai_commands(&self) -> Result<Vec<AiCommand>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, label, icon, behavior, system_prompt, provider_id, model, output_language, keyboard_shortcut, enabled, sort_order
-             FROM ai_commands ORDER BY sort_order",
-        )?;
+    /// All AI commands in their configured order, or most used first with
+    /// `most_used_first`.
+    pub fn list_ai_commands(&self, most_used_first: bool) -> Result<Vec<AiCommand>> {
+        let conn = self.conn()?;
+        let order = if most_used_first {
+            "use_count DESC, last_used_at DESC, sort_order"
+        } else {
+            "sort_order"
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, label, icon, behavior, system_prompt, provider_id, model, output_language, keyboard_shortcut, enabled, sort_order, use_count, last_used_at
+             FROM ai_commands ORDER BY {}",
+            order
+        ))?;
 
         let rows = stmt.query_map([], |row| {
             Ok(AiCommand {
@@ -1036,6 +3218,8 @@ impl Database {
                 keyboard_shortcut: row.get(8)?,
                 enabled: row.get::<_, i64>(9)? != 0,
                 sort_order: row.get(10)?,
+                use_count: row.get(11)?,
+                last_used_at: row.get(12)?,
             })
         })?;
 
@@ -1043,10 +3227,17 @@ impl Database {
     }
 
     pub fn save_ai_command(&self, cmd: &AiCommand) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
+        // Upsert rather than replace so usage counters survive edits.
         conn.execute(
-            "INSERT OR REPLACE INTO ai_commands (id, label, icon, behavior, system_prompt, provider_id, model, output_language, keyboard_shortcut, enabled, sort_order)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            "INSERT INTO ai_commands (id, label, icon, behavior, system_prompt, provider_id, model, output_language, keyboard_shortcut, enabled, sort_order)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+             ON CONFLICT(id) DO UPDATE SET
+                label = excluded.label, icon = excluded.icon, behavior = excluded.behavior,
+                system_prompt = excluded.system_prompt, provider_id = excluded.provider_id,
+                model = excluded.model, output_language = excluded.output_language,
+                keyboard_shortcut = excluded.keyboard_shortcut, enabled = excluded.enabled,
+                sort_order = excluded.sort_order",
             params![
                 cmd.id,
                 cmd.label,
@@ -1065,7 +3256,7 @@ impl Database {
     }
 
     pub fn delete_ai_command(&self, id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         conn.execute("DELETE FROM ai_commands WHERE id = ?1", params![id])?;
         Ok(())
     }
@@ -1075,35 +3266,81 @@ impl Database {
     // ============================================
 
     pub fn list_assistants(&self) -> Result<Vec<Assistant>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, name, icon, description, system_prompt, provider_id, model, temperature, max_tokens, is_default, sort_order, created_at, updated_at
-             FROM assistants ORDER BY sort_order",
-        )?;
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM assistants ORDER BY sort_order",
+            ASSISTANT_COLUMNS
+        ))?;
 
-        let rows = stmt.query_map([], |row| {
-            Ok(Assistant {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                icon: row.get(2)?,
-                description: row.get(3)?,
-                system_prompt: row.get(4)?,
-                provider_id: row.get(5)?,
-                model: row.get(6)?,
-                temperature: row.get(7)?,
-                max_tokens: row.get(8)?,
-                is_default: row.get::<_, i64>(9)? != 0,
-                sort_order: row.get(10)?,
-                created_at: row.get(11)?,
-                updated_at: row.get(12)?,
-            })
-        })?;
+        let rows = stmt.query_map([], assistant_from_row)?;
 
         rows.collect()
     }
 
+    /// The assistant marked `is_default`, if any.
+    pub fn get_default_assistant(&self) -> Result<Option<Assistant>> {
+        let conn = self.conn()?;
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM assistants WHERE is_default = 1 ORDER BY sort_order LIMIT 1",
+                ASSISTANT_COLUMNS
+            ),
+            [],
+            assistant_from_row,
+        )
+        .optional()
+    }
+
+    /// Copy an assistant under a new id, named "<name> (copy)" and placed
+    /// right after the original. The copy is never the default.
+    pub fn duplicate_assistant(&self, id: &str) -> Result<Assistant> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let now = chrono::Utc::now().timestamp_millis();
+
+        let source = tx.query_row(
+            &format!("SELECT {} FROM assistants WHERE id = ?1", ASSISTANT_COLUMNS),
+            params![id],
+            assistant_from_row,
+        )?;
+        let copy = Assistant {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: format!("{} (copy)", source.name),
+            is_default: false,
+            sort_order: source.sort_order + 1,
+            created_at: now,
+            updated_at: now,
+            ..source
+        };
+
+        tx.execute(
+            "UPDATE assistants SET sort_order = sort_order + 1 WHERE sort_order >= ?1",
+            params![copy.sort_order],
+        )?;
+        tx.execute(
+            "INSERT INTO assistants (id, name, icon, description, system_prompt, provider_id, model, temperature, max_tokens, is_default, sort_order, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, 0, ?10, ?11, ?12)",
+            params![
+                copy.id,
+                copy.name,
+                copy.icon,
+                copy.description,
+                copy.system_prompt,
+                copy.provider_id,
+                copy.model,
+                copy.temperature,
+                copy.max_tokens,
+                copy.sort_order,
+                copy.created_at,
+                copy.updated_at,
+            ],
+        )?;
+        tx.commit()?;
+        Ok(copy)
+    }
+
     pub fn save_assistant(&self, a: &Assistant) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         let now = chrono::Utc::now().timestamp_millis();
 
         // If setting as default, clear other defaults first
@@ -1134,8 +3371,1064 @@ impl Database {
     }
 
     pub fn delete_assistant(&self, id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.conn()?;
         conn.execute("DELETE FROM assistants WHERE id = ?1", params![id])?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A database in a fresh temp file, deleted (with its WAL files) on drop.
+    pub(crate) struct TempDb {
+        db: Database,
+        path: PathBuf,
+    }
+
+    impl TempDb {
+        pub(crate) fn new(name: &str) -> Self {
+            Self::seeded(name, "")
+        }
+
+        /// Like `new`, but `sql` runs on the empty file first, so migrations
+        /// can be tested against an older schema.
+        pub(crate) fn seeded(name: &str, sql: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "zitong-test-{}-{}.db",
+                name,
+                std::process::id()
+            ));
+            remove_db_files(&path);
+            if !sql.is_empty() {
+                let conn = Connection::open(&path).expect("create seed database");
+                conn.execute_batch(sql).expect("seed database");
+            }
+            let db = Database::new(&path).expect("open test database");
+            Self { db, path }
+        }
+    }
+
+    impl std::ops::Deref for TempDb {
+        type Target = Database;
+
+        fn deref(&self) -> &Database {
+            &self.db
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            remove_db_files(&self.path);
+        }
+    }
+
+    /// An enabled OpenAI-compatible provider with the given id.
+    pub(crate) fn test_provider(id: &str) -> Provider {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "type": "openai_compatible",
+            "name": id,
+            "apiKey": "sk-test",
+            "baseUrl": "http://127.0.0.1:9/v1",
+            "enabled": true,
+        }))
+        .expect("valid provider")
+    }
+
+    fn remove_db_files(path: &Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn queries_survive_a_poisoned_pool_lock() {
+        let db = TempDb::new("poison");
+        db.create_conversation("c1", "Before", "m", "p", None, None)
+            .unwrap();
+
+        // Panic while holding the pool's write lock, as a panicking
+        // `set_journal_mode` or `close` would.
+        std::thread::scope(|s| {
+            let panicked = s
+                .spawn(|| {
+                    let _guard = db.pool.write().unwrap();
+                    panic!("poison the pool lock");
+                })
+                .join();
+            assert!(panicked.is_err());
+        });
+        assert!(db.pool.is_poisoned());
+
+        // Panic while a checked-out connection is mid-transaction.
+        std::thread::scope(|s| {
+            let panicked = s
+                .spawn(|| {
+                    let mut conn = db.conn().unwrap();
+                    let tx = conn
+                        .transaction_with_behavior(TransactionBehavior::Immediate)
+                        .unwrap();
+                    tx.execute("DELETE FROM conversations", []).unwrap();
+                    panic!("drop the connection mid-transaction");
+                })
+                .join();
+            assert!(panicked.is_err());
+        });
+
+        db.create_conversation("c2", "After", "m", "p", None, None)
+            .unwrap();
+        let titles: Vec<String> = db
+            .list_conversations()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.title)
+            .collect();
+        assert_eq!(titles.len(), 2);
+        assert!(titles.contains(&"Before".to_string()));
+        assert_eq!(db.set_journal_mode("WAL").unwrap(), "wal");
+    }
+
+    #[test]
+    fn reads_run_alongside_a_writer() {
+        let db = TempDb::new("concurrent");
+        db.create_conversation("c1", "Chat", "m", "p", None, None)
+            .unwrap();
+
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| {
+                for i in 0..200 {
+                    db.create_message(&format!("m{}", i), "c1", "user", "hi", None, None, i)
+                        .unwrap();
+                }
+            });
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut seen = 0;
+                        for _ in 0..100 {
+                            assert_eq!(db.list_conversations().unwrap().len(), 1);
+                            let count = db.get_messages("c1").unwrap().len();
+                            // Each read sees a committed prefix of the writes.
+                            assert!(count >= seen);
+                            seen = count;
+                        }
+                    })
+                })
+                .collect();
+            writer.join().unwrap();
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+
+        assert_eq!(db.get_messages("c1").unwrap().len(), 200);
+        assert_eq!(db.stats().unwrap().journal_mode, "wal");
+    }
+
+    #[test]
+    fn relabel_only_touches_matching_messages_in_one_conversation() {
+        let db = TempDb::new("relabel");
+        db.create_conversation("c1", "t", "gpt-4o", "p", None, None)
+            .unwrap();
+        db.create_conversation("c2", "t", "gpt-4o", "p", None, None)
+            .unwrap();
+        db.create_message("u", "c1", "user", "q", None, None, 0)
+            .unwrap();
+        db.create_message("a1", "c1", "assistant", "r1", Some("gpt-4o"), Some(3), 1)
+            .unwrap();
+        db.create_message("a2", "c1", "assistant", "r2", Some("gpt-4o-mini"), None, 2)
+            .unwrap();
+        db.create_message("a3", "c1", "assistant", "r3", Some("gpt-4o"), None, 3)
+            .unwrap();
+        db.create_message("x", "c2", "assistant", "other", Some("gpt-4o"), None, 0)
+            .unwrap();
+
+        let relabeled = db
+            .relabel_messages_model("c1", Some("gpt-4o"), "gpt-4o-2024-11-20")
+            .unwrap();
+        assert_eq!(relabeled, 2);
+        let messages: Vec<_> = db
+            .get_messages("c1")
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.model, m.content, m.token_count))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (None, "q".to_string(), None),
+                (
+                    Some("gpt-4o-2024-11-20".to_string()),
+                    "r1".to_string(),
+                    Some(3)
+                ),
+                (Some("gpt-4o-mini".to_string()), "r2".to_string(), None),
+                (
+                    Some("gpt-4o-2024-11-20".to_string()),
+                    "r3".to_string(),
+                    None
+                ),
+            ]
+        );
+        let other = db.get_messages("c2").unwrap();
+        assert_eq!(other[0].model.as_deref(), Some("gpt-4o"));
+        assert_eq!(other[0].content, "other");
+
+        let again = db
+            .relabel_messages_model("c1", Some("gpt-4o"), "gpt-4o-2024-11-20")
+            .unwrap();
+        assert_eq!(again, 0);
+    }
+
+    #[test]
+    fn folder_counts_skip_archived_conversations() {
+        let db = TempDb::new("folder-counts");
+        db.create_folder("f1", "F1").unwrap();
+        db.create_folder("f2", "F2").unwrap();
+        db.create_conversation("c1", "t", "m", "p", None, Some("f1"))
+            .unwrap();
+        db.create_conversation("c2", "t", "m", "p", None, Some("f1"))
+            .unwrap();
+        db.create_conversation("c3", "t", "m", "p", None, Some("f1"))
+            .unwrap();
+        db.create_conversation("c4", "t", "m", "p", None, None)
+            .unwrap();
+        db.archive_conversation("c3", true).unwrap();
+
+        let counts: Vec<_> = db
+            .list_folders_with_counts()
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.folder.id, f.conversation_count))
+            .collect();
+        assert_eq!(counts, vec![("f1".to_string(), 2), ("f2".to_string(), 0)]);
+    }
+
+    #[test]
+    fn summaries_carry_counts_and_a_truncated_preview() {
+        let db = TempDb::new("summaries");
+        db.create_conversation("c1", "t", "m", "p", None, None)
+            .unwrap();
+        db.create_conversation("c2", "t", "m", "p", None, None)
+            .unwrap();
+        let long = "日本語".repeat(40);
+        db.create_message("m1", "c1", "user", "hello\n  world", None, None, 0)
+            .unwrap();
+        db.create_message("m2", "c1", "assistant", &long, None, None, 1)
+            .unwrap();
+        db.create_message("m3", "c2", "user", "  short\n\treply ", None, None, 0)
+            .unwrap();
+
+        let summaries = db.list_conversation_summaries().unwrap();
+        let summary = |id: &str| {
+            summaries
+                .iter()
+                .find(|s| s.conversation.id == id)
+                .unwrap()
+                .clone()
+        };
+        let first = summary("c1");
+        assert_eq!(first.message_count, 2);
+        let preview = first.last_message_preview.unwrap();
+        assert_eq!(preview.chars().count(), PREVIEW_MAX_CHARS + 1);
+        assert!(preview.ends_with('…'));
+        assert!(long.starts_with(preview.trim_end_matches('…')));
+        let second = summary("c2");
+        assert_eq!(second.message_count, 1);
+        assert_eq!(second.last_message_preview.as_deref(), Some("short reply"));
+    }
+
+    #[test]
+    fn empty_conversation_has_no_preview() {
+        let db = TempDb::new("summary-empty");
+        db.create_conversation("c1", "t", "m", "p", None, None)
+            .unwrap();
+        let summaries = db.list_conversation_summaries().unwrap();
+        assert_eq!(summaries[0].message_count, 0);
+        assert_eq!(summaries[0].last_message_preview, None);
+    }
+
+    #[test]
+    fn deleting_a_provider_purges_only_its_conversations() {
+        let db = TempDb::new("purge-provider");
+        db.save_provider(&test_provider("p1")).unwrap();
+        db.save_provider(&test_provider("p2")).unwrap();
+        db.create_conversation("c1", "t", "m", "p1", None, None)
+            .unwrap();
+        db.create_conversation("c2", "t", "m", "p1", None, None)
+            .unwrap();
+        db.create_conversation("c3", "t", "m", "p2", None, None)
+            .unwrap();
+        for (id, conversation) in [("m1", "c1"), ("m2", "c1"), ("m3", "c2"), ("m4", "c3")] {
+            db.create_message(id, conversation, "user", "hi", None, None, 0)
+                .unwrap();
+        }
+
+        let summary = db.delete_provider_with_data("p1").unwrap();
+        assert_eq!(summary.conversations_deleted, 2);
+        assert_eq!(summary.messages_deleted, 3);
+        assert!(db.get_provider("p1").is_err());
+        assert!(db.get_conversation("c1").is_err());
+        assert!(db.get_conversation("c2").is_err());
+
+        assert!(db.get_provider("p2").is_ok());
+        assert!(db.get_conversation("c3").is_ok());
+        assert_eq!(db.get_messages("c3").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn messages_paginate_backwards_in_pages_of_ten() {
+        let db = TempDb::new("paginate");
+        db.create_conversation("c1", "t", "m", "p", None, None)
+            .unwrap();
+        db.create_conversation("c2", "t", "m", "p", None, None)
+            .unwrap();
+        for i in 0..25 {
+            db.create_message(&format!("m{i}"), "c1", "user", "x", None, None, i)
+                .unwrap();
+        }
+        db.create_message("other", "c2", "user", "x", None, None, 3)
+            .unwrap();
+
+        let mut cursor = None;
+        let mut pages = Vec::new();
+        loop {
+            let page = db.get_messages_paginated("c1", cursor, 10).unwrap();
+            let Some(first) = page.first() else {
+                break;
+            };
+            cursor = Some(first.sort_order);
+            pages.push(page.iter().map(|m| m.sort_order).collect::<Vec<_>>());
+        }
+        assert_eq!(
+            pages,
+            vec![
+                (15..25).collect::<Vec<_>>(),
+                (5..15).collect(),
+                (0..5).collect(),
+            ]
+        );
+    }
+
+    #[test]
+    fn search_scopes_and_folder_restriction() {
+        let db = TempDb::new("search-scope");
+        db.create_folder("f1", "F1").unwrap();
+        db.create_conversation("a", "Rust tips", "m", "p", None, Some("f1"))
+            .unwrap();
+        db.create_conversation("b", "Cooking", "m", "p", None, None)
+            .unwrap();
+        db.create_conversation("c", "Rust and more rust", "m", "p", None, None)
+            .unwrap();
+        db.create_conversation("d", "Gardening", "m", "p", None, Some("f1"))
+            .unwrap();
+        db.create_message("m1", "b", "user", "how about rust on pans", None, None, 0)
+            .unwrap();
+        db.create_message("m2", "c", "user", "rust again", None, None, 0)
+            .unwrap();
+        db.create_message("m3", "d", "user", "tomatoes", None, None, 0)
+            .unwrap();
+
+        let ids = |scope, folder: Option<&str>| {
+            let mut ids: Vec<_> = db
+                .search_conversations_scoped("rust", scope, folder)
+                .unwrap()
+                .into_iter()
+                .map(|c| c.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(SearchScope::TitleOnly, None), ["a", "c"]);
+        assert_eq!(ids(SearchScope::ContentOnly, None), ["b", "c"]);
+        assert_eq!(ids(SearchScope::All, None), ["a", "b", "c"]);
+        assert_eq!(ids(SearchScope::All, Some("f1")), ["a"]);
+        assert_eq!(
+            ids(SearchScope::ContentOnly, Some("f1")),
+            Vec::<String>::new()
+        );
+        assert_eq!(ids(SearchScope::All, Some("missing")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn existing_providers_default_to_streaming() {
+        let db = TempDb::seeded(
+            "streaming-migration",
+            "CREATE TABLE providers (
+                id              TEXT PRIMARY KEY,
+                provider_type   TEXT NOT NULL,
+                name            TEXT NOT NULL,
+                api_key         TEXT,
+                base_url        TEXT,
+                default_model   TEXT,
+                enabled         INTEGER NOT NULL DEFAULT 1
+            );
+            INSERT INTO providers (id, provider_type, name) VALUES ('old', 'openai', 'Old');",
+        );
+        assert!(db.get_provider("old").unwrap().supports_streaming);
+
+        let mut provider = test_provider("new");
+        provider.supports_streaming = false;
+        db.save_provider(&provider).unwrap();
+        assert!(!db.get_provider("new").unwrap().supports_streaming);
+    }
+
+    #[test]
+    fn fork_from_the_middle_message() {
+        let db = TempDb::new("fork");
+        db.create_conversation("c", "Src", "m", "p", Some("sys"), None)
+            .unwrap();
+        for i in 0..5 {
+            db.create_message(
+                &format!("m{i}"),
+                "c",
+                "user",
+                &format!("x{i}"),
+                None,
+                None,
+                i,
+            )
+            .unwrap();
+        }
+
+        let fork = db.fork_conversation("m2", "f", None).unwrap();
+        assert_eq!(fork.title, "Src (fork)");
+        assert_eq!(fork.system_prompt.as_deref(), Some("sys"));
+        let contents: Vec<_> = db
+            .get_messages("f")
+            .unwrap()
+            .into_iter()
+            .map(|m| m.content)
+            .collect();
+        assert_eq!(contents, ["x0", "x1", "x2"]);
+        assert_eq!(db.get_messages("c").unwrap().len(), 5);
+
+        let named = db.fork_conversation("m0", "g", Some("Other")).unwrap();
+        assert_eq!(named.title, "Other");
+        assert_eq!(db.get_messages("g").unwrap().len(), 1);
+        assert!(db.fork_conversation("missing", "h", None).is_err());
+    }
+
+    #[test]
+    fn resetting_font_size_restores_the_default() {
+        let db = TempDb::new("reset-setting");
+        let mut settings = db.get_settings().unwrap();
+        settings.font_size = 20;
+        settings.theme = "dark".into();
+        db.save_settings(&settings).unwrap();
+
+        db.reset_setting("font_size").unwrap();
+        let settings = db.get_settings().unwrap();
+        assert_eq!(settings.font_size, 14);
+        assert_eq!(settings.font_size, AppSettings::default().font_size);
+        assert_eq!(settings.theme, "dark");
+
+        assert!(AppSettings::is_known_key("font_size"));
+        assert!(!AppSettings::is_known_key("no_such_setting"));
+    }
+
+    #[test]
+    fn conversations_listed_per_folder_and_root() {
+        let db = TempDb::new("folder-listing");
+        db.create_folder("f1", "A").unwrap();
+        db.create_folder("f2", "B").unwrap();
+        for (id, folder) in [
+            ("a1", Some("f1")),
+            ("a2", Some("f1")),
+            ("b", Some("f2")),
+            ("r", None),
+        ] {
+            db.create_conversation(id, id, "m", "p", None, folder)
+                .unwrap();
+        }
+        db.archive_conversation("a2", true).unwrap();
+
+        let ids = |conversations: Vec<Conversation>| {
+            let mut ids: Vec<_> = conversations.into_iter().map(|c| c.id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(db.list_conversations_in_folder("f1").unwrap()), ["a1"]);
+        assert_eq!(ids(db.list_conversations_in_folder("f2").unwrap()), ["b"]);
+        assert_eq!(ids(db.list_root_conversations().unwrap()), ["r"]);
+        assert!(db
+            .list_conversations_in_folder("missing")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn duplicate_sort_orders_are_renumbered_chronologically() {
+        let db = TempDb::new("normalize-order");
+        db.create_conversation("c1", "Chat", "m", "p", None, None)
+            .unwrap();
+        for (id, created_at, sort_order) in
+            [("a", 100, 0), ("b", 200, 3), ("c", 300, 3), ("d", 300, 7)]
+        {
+            db.create_message(id, "c1", "user", id, None, None, sort_order)
+                .unwrap();
+            db.conn()
+                .unwrap()
+                .execute(
+                    "UPDATE messages SET created_at = ?1 WHERE id = ?2",
+                    params![created_at, id],
+                )
+                .unwrap();
+        }
+
+        assert_eq!(db.normalize_message_order("c1").unwrap(), 3);
+        let order: Vec<(String, i64)> = db
+            .get_messages("c1")
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.id, m.sort_order))
+            .collect();
+        let expected = [("a", 0), ("b", 1), ("c", 2), ("d", 3)];
+        assert_eq!(order, expected.map(|(id, n)| (id.to_string(), n)));
+        assert_eq!(db.normalize_message_order("c1").unwrap(), 0);
+    }
+
+    #[test]
+    fn orphans_are_found_and_deleted() {
+        let db = TempDb::new("orphans");
+        db.create_conversation("c1", "Chat", "m", "p", None, None)
+            .unwrap();
+        db.create_message("kept", "c1", "user", "hi", None, None, 0)
+            .unwrap();
+        {
+            // Old rows could be written with enforcement off.
+            let conn = db.conn().unwrap();
+            conn.execute_batch(
+                "PRAGMA foreign_keys=OFF;
+                 INSERT INTO messages (id, conversation_id, role, content, created_at, sort_order)
+                     VALUES ('lost', 'gone', 'user', 'hi', 0, 0);
+                 INSERT INTO attachments (id, message_id, file_name, file_path, created_at) VALUES
+                     ('a1', 'kept', 'a.txt', '/a.txt', 0),
+                     ('a2', 'lost', 'b.txt', '/b.txt', 0),
+                     ('a3', 'missing', 'c.txt', '/c.txt', 0);
+                 PRAGMA foreign_keys=ON;",
+            )
+            .unwrap();
+        }
+
+        let found = db.find_orphans().unwrap();
+        assert_eq!((found.messages, found.attachments), (1, 1));
+
+        let deleted = db.delete_orphans().unwrap();
+        assert_eq!((deleted.messages, deleted.attachments), (1, 2));
+        let after = db.find_orphans().unwrap();
+        assert_eq!((after.messages, after.attachments), (0, 0));
+        assert_eq!(db.get_messages("c1").unwrap().len(), 1);
+        let attachments: i64 = db
+            .conn()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM attachments", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(attachments, 1);
+    }
+
+    #[test]
+    fn stored_journal_mode_applies_at_open() {
+        for mode in JOURNAL_MODES {
+            let db = TempDb::seeded(
+                &format!("journal-{}", mode),
+                &format!(
+                    "CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+                     INSERT INTO settings VALUES ('db_journal_mode', '{}');",
+                    mode.to_lowercase()
+                ),
+            );
+            let applied: String = db
+                .conn()
+                .unwrap()
+                .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(applied, mode.to_lowercase());
+        }
+    }
+
+    #[test]
+    fn journal_mode_switches_on_every_connection() {
+        let db = TempDb::new("journal-switch");
+        db.create_conversation("c1", "Chat", "m", "p", None, None)
+            .unwrap();
+        for mode in ["DELETE", "TRUNCATE", "WAL"] {
+            assert_eq!(db.set_journal_mode(mode).unwrap(), mode.to_lowercase());
+            // Hold one connection so the query below opens another.
+            let held = db.conn().unwrap();
+            let applied: String = db
+                .conn()
+                .unwrap()
+                .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+                .unwrap();
+            drop(held);
+            assert_eq!(applied, mode.to_lowercase());
+        }
+        assert_eq!(db.list_conversations().unwrap().len(), 1);
+        assert_eq!(parse_journal_mode(" truncate "), Ok("TRUNCATE"));
+        assert!(parse_journal_mode("MEMORY").is_err());
+    }
+
+    #[test]
+    fn conversation_detail_aggregates_its_messages() {
+        let db = TempDb::new("conversation-detail");
+        db.create_conversation("c1", "Chat", "m", "p", None, None)
+            .unwrap();
+        db.create_conversation("c2", "Other", "m", "p", None, None)
+            .unwrap();
+        for (id, conversation, tokens, created_at) in [
+            ("m1", "c1", Some(10), 1_000),
+            ("m2", "c1", None, 3_000),
+            ("m3", "c1", Some(32), 2_000),
+            ("m4", "c2", Some(500), 9_000),
+        ] {
+            db.create_message(id, conversation, "user", "hi", None, tokens, 0)
+                .unwrap();
+            db.conn()
+                .unwrap()
+                .execute(
+                    "UPDATE messages SET created_at = ?1 WHERE id = ?2",
+                    params![created_at, id],
+                )
+                .unwrap();
+        }
+        db.create_conversation("empty", "Empty", "m", "p", None, None)
+            .unwrap();
+
+        let detail = db.get_conversation_detail("c1").unwrap();
+        assert_eq!(detail.conversation.title, "Chat");
+        assert_eq!(detail.message_count, 3);
+        assert_eq!(detail.total_tokens, 42);
+        assert_eq!(detail.last_message_at, Some(3_000));
+
+        let empty = db.get_conversation_detail("empty").unwrap();
+        assert_eq!(
+            (
+                empty.message_count,
+                empty.total_tokens,
+                empty.last_message_at
+            ),
+            (0, 0, None)
+        );
+        assert!(db.get_conversation_detail("missing").is_err());
+    }
+
+    #[test]
+    fn checkpoint_empties_the_wal() {
+        let db = TempDb::new("checkpoint");
+        assert_eq!(db.stats().unwrap().journal_mode, "wal");
+        for i in 0..20 {
+            db.create_conversation(&format!("c{}", i), "Chat", "m", "p", None, None)
+                .unwrap();
+        }
+        let wal = PathBuf::from(format!("{}-wal", db.path.display()));
+        assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+
+        let checkpoint = db.checkpoint().unwrap();
+        assert!(!checkpoint.busy);
+        assert_eq!(checkpoint.log_frames, checkpoint.checkpointed_frames);
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+
+        db.close().unwrap();
+        assert_eq!(db.list_conversations().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn find_in_chat_returns_matches_from_one_conversation_in_order() {
+        let db = TempDb::new("find-in-chat");
+        db.create_conversation("c1", "Chat", "m", "p", None, None)
+            .unwrap();
+        db.create_conversation("c2", "Other", "m", "p", None, None)
+            .unwrap();
+        for (id, conversation, content, sort_order) in [
+            ("m1", "c1", "Tell me about Rust lifetimes", 0),
+            ("m2", "c1", "Lifetimes describe how long references live", 1),
+            ("m3", "c1", "And traits?", 2),
+            ("m4", "c1", "More on LIFETIMES please", 3),
+            ("m5", "c2", "lifetimes elsewhere", 0),
+        ] {
+            db.create_message(id, conversation, "user", content, None, None, sort_order)
+                .unwrap();
+        }
+
+        let hits: Vec<String> = db
+            .search_messages_in_conversation("c1", "lifetimes")
+            .unwrap()
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(hits, ["m1", "m2", "m4"]);
+        assert!(db
+            .search_messages_in_conversation("c1", "python")
+            .unwrap()
+            .is_empty());
+        assert!(db
+            .search_messages_in_conversation("c1", "  ")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn partially_streamed_replies_survive_a_restart() {
+        let db = TempDb::new("partial-stream");
+        db.create_conversation("c1", "Chat", "m", "p", None, None)
+            .unwrap();
+        db.create_message("u1", "c1", "user", "Write a poem", None, None, 0)
+            .unwrap();
+        db.begin_streaming_message("a1", "c1", "m", 1).unwrap();
+        db.set_message_content("a1", "Roses are red,").unwrap();
+
+        // A second handle on the file sees what a restarted app would.
+        let reopened = Database::new(&db.path).unwrap();
+        let messages = reopened.get_messages("c1").unwrap();
+        assert_eq!(messages.len(), 2);
+        let partial = &messages[1];
+        assert_eq!(partial.content, "Roses are red,");
+        assert_eq!(partial.model.as_deref(), Some("m"));
+        assert_eq!(
+            partial.metadata,
+            Some(serde_json::json!({ "streaming": true }))
+        );
+
+        db.replace_message_content("a1", "Roses are red, violets are blue", Some("m"), Some(9))
+            .unwrap();
+        let done = reopened.get_message("a1").unwrap();
+        assert_eq!(done.content, "Roses are red, violets are blue");
+        assert_eq!(done.token_count, Some(9));
+        assert_eq!(done.metadata, None);
+    }
+
+    #[test]
+    fn pruning_beyond_the_cap_keeps_pinned_conversations() {
+        let db = TempDb::new("prune-cap");
+        let create = |i: i64, delete: bool| {
+            let id = format!("c{}", i);
+            db.create_conversation(&id, "Chat", "m", "p", None, None)
+                .unwrap();
+            db.conn()
+                .unwrap()
+                .execute(
+                    "UPDATE conversations SET updated_at = ?1 WHERE id = ?2",
+                    params![i * 1000, id],
+                )
+                .unwrap();
+            db.prune_conversations(3, delete, &id).unwrap()
+        };
+
+        assert!(create(0, false).is_empty());
+        db.set_conversation_pinned("c0", true).unwrap();
+        assert!(create(1, false).is_empty());
+        assert!(create(2, false).is_empty());
+        assert_eq!(create(3, false), ["c1"]);
+        assert_eq!(create(4, false), ["c2"]);
+        assert_eq!(create(5, false), ["c3"]);
+
+        let archived = |id: &str| db.get_conversation(id).unwrap().is_archived;
+        assert!(!archived("c0"));
+        assert!(archived("c1") && archived("c2") && archived("c3"));
+        assert!(!archived("c4") && !archived("c5"));
+
+        assert_eq!(create(6, true), ["c4"]);
+        assert!(db.get_conversation("c4").is_err());
+        assert!(!archived("c0"));
+    }
+
+    #[test]
+    fn existing_conversations_gain_an_empty_temperature() {
+        let db = TempDb::seeded(
+            "temperature-migration",
+            "CREATE TABLE conversations (
+                id              TEXT PRIMARY KEY,
+                title           TEXT NOT NULL DEFAULT 'New Chat',
+                model           TEXT NOT NULL,
+                provider_id     TEXT NOT NULL,
+                system_prompt   TEXT,
+                created_at      INTEGER NOT NULL,
+                updated_at      INTEGER NOT NULL,
+                is_archived     INTEGER NOT NULL DEFAULT 0,
+                folder_id       TEXT
+            );
+            INSERT INTO conversations (id, title, model, provider_id, created_at, updated_at)
+                VALUES ('old', 'Old chat', 'm', 'p', 0, 0);",
+        );
+        assert_eq!(db.get_conversation("old").unwrap().temperature, None);
+
+        db.update_conversation_temperature("old", Some(0.3))
+            .unwrap();
+        assert_eq!(db.get_conversation("old").unwrap().temperature, Some(0.3));
+        db.update_conversation_temperature("old", None).unwrap();
+        assert_eq!(db.get_conversation("old").unwrap().temperature, None);
+    }
+
+    #[test]
+    fn clearing_messages_keeps_the_conversation() {
+        let db = TempDb::new("clear-messages");
+        db.create_folder("f1", "Work").unwrap();
+        db.create_conversation(
+            "c1",
+            "Planning",
+            "gpt-4o",
+            "p",
+            Some("Be brief."),
+            Some("f1"),
+        )
+        .unwrap();
+        db.create_conversation("c2", "Other", "m", "p", None, None)
+            .unwrap();
+        for (id, conversation) in [("m1", "c1"), ("m2", "c1"), ("m3", "c2")] {
+            db.create_message(id, conversation, "user", "hi", None, None, 0)
+                .unwrap();
+        }
+        db.create_attachment(&Attachment {
+            id: "a1".into(),
+            message_id: "m1".into(),
+            file_name: "a.txt".into(),
+            file_path: "/a.txt".into(),
+            mime_type: None,
+            file_size: None,
+            created_at: 0,
+        })
+        .unwrap();
+        db.conn()
+            .unwrap()
+            .execute(
+                "UPDATE conversations SET updated_at = 0 WHERE id = 'c1'",
+                [],
+            )
+            .unwrap();
+
+        assert_eq!(db.clear_messages("c1").unwrap(), 2);
+        assert!(db.get_messages("c1").unwrap().is_empty());
+        assert!(db.list_conversation_attachments("c1").unwrap().is_empty());
+        let conversation = db.get_conversation("c1").unwrap();
+        assert_eq!(conversation.title, "Planning");
+        assert_eq!(conversation.model, "gpt-4o");
+        assert_eq!(conversation.system_prompt.as_deref(), Some("Be brief."));
+        assert_eq!(conversation.folder_id.as_deref(), Some("f1"));
+        assert!(conversation.updated_at > 0);
+        assert_eq!(db.get_messages("c2").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn restoring_seeds_skips_existing_rows_unless_overwriting() {
+        let db = TempDb::new("restore-seeds");
+        db.delete_assistant("code_helper").unwrap();
+        db.delete_ai_command("summarize").unwrap();
+        let conn = db.conn().unwrap();
+        conn.execute(
+            "UPDATE assistants SET name = 'Mine', model = 'gpt-4o' WHERE id = 'general_assistant'",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE ai_commands SET label = 'Mine' WHERE id = 'explain'",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+        let assistant = |id: &str| {
+            db.list_assistants()
+                .unwrap()
+                .into_iter()
+                .find(|a| a.id == id)
+        };
+        let command = |id: &str| {
+            db.list_ai_commands(false)
+                .unwrap()
+                .into_iter()
+                .find(|c| c.id == id)
+        };
+
+        assert_eq!(db.restore_default_assistants(false).unwrap(), 1);
+        assert_eq!(db.restore_default_ai_commands(false).unwrap(), 1);
+        assert!(assistant("code_helper").is_some());
+        assert!(command("summarize").is_some());
+        assert_eq!(assistant("general_assistant").unwrap().name, "Mine");
+        assert_eq!(command("explain").unwrap().label, "Mine");
+
+        assert_eq!(
+            db.restore_default_assistants(true).unwrap(),
+            SEED_ASSISTANTS.len()
+        );
+        assert_eq!(
+            db.restore_default_ai_commands(true).unwrap(),
+            SEED_AI_COMMANDS.len()
+        );
+        let general = assistant("general_assistant").unwrap();
+        assert_eq!(general.name, "General Assistant");
+        assert_eq!(general.model, None);
+        assert!(general.is_default);
+        assert_eq!(command("explain").unwrap().label, "Explain this");
+        assert_eq!(db.list_assistants().unwrap().len(), SEED_ASSISTANTS.len());
+    }
+
+    #[test]
+    fn conversations_are_found_by_model_and_provider() {
+        let db = TempDb::new("using-model");
+        db.create_conversation("c1", "Set to it", "gpt-4o", "openai", None, None)
+            .unwrap();
+        db.create_conversation("c2", "Replied by it", "claude", "anthropic", None, None)
+            .unwrap();
+        db.create_conversation("c3", "Archived", "gpt-4o", "openai", None, None)
+            .unwrap();
+        db.create_conversation("c4", "Unrelated", "claude", "anthropic", None, None)
+            .unwrap();
+        db.create_message("m1", "c2", "assistant", "hi", Some("gpt-4o"), None, 0)
+            .unwrap();
+        db.create_message("m2", "c4", "assistant", "hi", Some("claude"), None, 0)
+            .unwrap();
+        db.archive_conversation("c3", true).unwrap();
+        let ids = |conversations: Vec<Conversation>| {
+            let mut ids: Vec<String> = conversations.into_iter().map(|c| c.id).collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(
+            ids(db.conversations_using_model("gpt-4o").unwrap()),
+            ["c1", "c2", "c3"]
+        );
+        assert_eq!(
+            ids(db.conversations_using_model("claude").unwrap()),
+            ["c2", "c4"]
+        );
+        assert!(db.conversations_using_model("gpt-4").unwrap().is_empty());
+        assert_eq!(
+            ids(db.conversations_using_provider("openai").unwrap()),
+            ["c1", "c3"]
+        );
+        assert!(db
+            .conversations_using_provider("ollama")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn duplicated_assistants_are_independent_and_not_default() {
+        let db = TempDb::new("duplicate-assistant");
+        let original = db.get_default_assistant().unwrap().unwrap();
+
+        let copy = db.duplicate_assistant(&original.id).unwrap();
+        assert_ne!(copy.id, original.id);
+        assert_eq!(copy.name, format!("{} (copy)", original.name));
+        assert_eq!(copy.system_prompt, original.system_prompt);
+        assert!(!copy.is_default);
+
+        let mut edited = copy.clone();
+        edited.system_prompt = "Only answer in haiku.".into();
+        db.save_assistant(&edited).unwrap();
+        let assistants = db.list_assistants().unwrap();
+        let ids: Vec<&str> = assistants.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids[..2], [original.id.as_str(), copy.id.as_str()]);
+        assert_eq!(assistants[0].system_prompt, original.system_prompt);
+        assert_eq!(assistants.iter().filter(|a| a.is_default).count(), 1);
+        assert_eq!(db.get_default_assistant().unwrap().unwrap().id, original.id);
+
+        db.delete_assistant(&copy.id).unwrap();
+        assert_eq!(db.list_assistants().unwrap()[0].id, original.id);
+        assert!(matches!(
+            db.duplicate_assistant("missing"),
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        ));
+    }
+
+    #[test]
+    fn stream_metrics_are_saved_with_the_message() {
+        let db = TempDb::new("stream-metrics");
+        db.create_conversation("c1", "Chat", "m", "p", None, None)
+            .unwrap();
+        db.create_message("m1", "c1", "assistant", "Hi", Some("m"), None, 0)
+            .unwrap();
+        let metrics = crate::providers::StreamMetrics {
+            ttft_ms: Some(400),
+            duration_ms: 2400,
+            tokens_per_second: Some(50.0),
+        };
+
+        db.set_message_metrics("m1", &metrics).unwrap();
+
+        let message = &db.get_messages("c1").unwrap()[0];
+        assert_eq!(message.ttft_ms, Some(400));
+        assert_eq!(message.duration_ms, Some(2400));
+        assert_eq!(message.tokens_per_second, Some(50.0));
+    }
+
+    #[test]
+    fn messy_message_content_is_normalized_once() {
+        let db = TempDb::new("normalize-content");
+        db.create_conversation("c1", "Imported", "m", "p", None, None)
+            .unwrap();
+        let messy = "Line one  \r\nLine two\t\r\n\r\n\r\n\r\n\r\n  indented stays\n\n\nend  ";
+        db.create_message("m1", "c1", "user", messy, None, None, 0)
+            .unwrap();
+        db.create_message("m2", "c1", "assistant", "Already\n\nclean", None, None, 1)
+            .unwrap();
+
+        assert_eq!(db.normalize_content().unwrap(), 1);
+        let messages = db.get_messages("c1").unwrap();
+        assert_eq!(
+            messages[0].content,
+            "Line one\nLine two\n\n\n  indented stays\n\n\nend"
+        );
+        assert_eq!(messages[1].content, "Already\n\nclean");
+        assert_eq!(db.normalize_content().unwrap(), 0);
+    }
+
+    /// The `conversations` and `messages` tables as they were before
+    /// `title_manual` existed.
+    pub(crate) const PRE_TITLE_MANUAL_SCHEMA: &str = "CREATE TABLE conversations (
+            id              TEXT PRIMARY KEY,
+            title           TEXT NOT NULL DEFAULT 'New Chat',
+            model           TEXT NOT NULL,
+            provider_id     TEXT NOT NULL,
+            system_prompt   TEXT,
+            created_at      INTEGER NOT NULL,
+            updated_at      INTEGER NOT NULL,
+            is_archived     INTEGER NOT NULL DEFAULT 0,
+            folder_id       TEXT
+        );
+        CREATE TABLE messages (
+            id              TEXT PRIMARY KEY,
+            conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+            role            TEXT NOT NULL,
+            content         TEXT NOT NULL,
+            model           TEXT,
+            token_count     INTEGER,
+            created_at      INTEGER NOT NULL,
+            parent_id       TEXT,
+            sort_order      INTEGER NOT NULL
+        );";
+
+    #[test]
+    fn only_renamed_titles_are_marked_manual_on_upgrade() {
+        let question = "Plan a three day trip to Kyoto for me, with temples and food";
+        let truncated = crate::commands::truncate_chars(question, 50);
+        let seed = format!(
+            "{}
+            INSERT INTO conversations (id, title, model, provider_id, created_at, updated_at) VALUES
+                ('placeholder', 'New Chat', 'm', 'p', 0, 0),
+                ('truncated', '{}', 'm', 'p', 0, 0),
+                ('short', 'Hi there', 'm', 'p', 0, 0),
+                ('renamed', 'Japan notes', 'm', 'p', 0, 0),
+                ('empty', 'Untouched', 'm', 'p', 0, 0);
+            INSERT INTO messages (id, conversation_id, role, content, created_at, sort_order) VALUES
+                ('t1', 'truncated', 'user', '{}', 0, 0),
+                ('s1', 'short', 'user', 'Hi there', 0, 0),
+                ('r1', 'renamed', 'user', '{}', 0, 0);",
+            PRE_TITLE_MANUAL_SCHEMA, truncated, question, question
+        );
+        let db = TempDb::seeded("title-manual-migration", &seed);
+
+        for (id, manual) in [
+            ("placeholder", false),
+            ("truncated", false),
+            ("short", false),
+            ("renamed", true),
+            ("empty", true),
+        ] {
+            assert_eq!(db.is_title_manual(id).unwrap(), manual, "{}", id);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Change notifications broadcast to every window after a database mutation,
+//! so the main window, overlay and settings stay in sync without polling.
+//!
+//! Emits are best-effort: a window that isn't open (or a failed emit) never
+//! fails the command that triggered it.
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+pub const CONVERSATION_CREATED: &str = "conversation-created";
+pub const CONVERSATION_UPDATED: &str = "conversation-updated";
+pub const CONVERSATION_DELETED: &str = "conversation-deleted";
+pub const MESSAGE_ADDED: &str = "message-added";
+pub const MESSAGE_DELETED: &str = "message-deleted";
+pub const MESSAGE_UPDATED: &str = "message-updated";
+/// Conversations were archived or deleted to stay under `max_conversations`.
+pub const CONVERSATIONS_PRUNED: &str = "conversations-pruned";
+/// A provider's connection details changed; cached model lists for it are stale.
+pub const PROVIDER_UPDATED: &str = "provider-updated";
+/// Model lists were refreshed for every enabled provider.
+pub const MODELS_UPDATED: &str = "models-updated";
+
+/// Payload for all DB change events. `message_id` is only set for message events.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbChangeEvent {
+    pub conversation_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+}
+
+fn emit(app: &AppHandle, event: &str, conversation_id: &str, message_id: Option<&str>) {
+    let payload = DbChangeEvent {
+        conversation_id: conversation_id.to_string(),
+        message_id: message_id.map(|s| s.to_string()),
+    };
+    if let Err(e) = app.emit(event, payload) {
+        tracing::warn!("[events] failed to emit {}: {}", event, e);
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderChangeEvent {
+    pub provider_id: String,
+}
+
+pub fn conversation_created(app: &AppHandle, conversation_id: &str) {
+    emit(app, CONVERSATION_CREATED, conversation_id, None);
+}
+
+pub fn conversation_updated(app: &AppHandle, conversation_id: &str) {
+    emit(app, CONVERSATION_UPDATED, conversation_id, None);
+}
+
+pub fn conversation_deleted(app: &AppHandle, conversation_id: &str) {
+    emit(app, CONVERSATION_DELETED, conversation_id, None);
+}
+
+pub fn message_added(app: &AppHandle, conversation_id: &str, message_id: &str) {
+    emit(app, MESSAGE_ADDED, conversation_id, Some(message_id));
+}
+
+pub fn message_deleted(app: &AppHandle, conversation_id: &str, message_id: &str) {
+    emit(app, MESSAGE_DELETED, conversation_id, Some(message_id));
+}
+
+pub fn message_updated(app: &AppHandle, conversation_id: &str, message_id: &str) {
+    emit(app, MESSAGE_UPDATED, conversation_id, Some(message_id));
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneEvent {
+    pub conversation_ids: Vec<String>,
+    /// `archived` or `deleted`.
+    pub action: &'static str,
+}
+
+pub fn conversations_pruned(app: &AppHandle, conversation_ids: Vec<String>, deleted: bool) {
+    let payload = PruneEvent {
+        conversation_ids,
+        action: if deleted { "deleted" } else { "archived" },
+    };
+    if let Err(e) = app.emit(CONVERSATIONS_PRUNED, payload) {
+        tracing::warn!("[events] failed to emit {}: {}", CONVERSATIONS_PRUNED, e);
+    }
+}
+
+pub fn provider_updated(app: &AppHandle, provider_id: &str) {
+    let payload = ProviderChangeEvent {
+        provider_id: provider_id.to_string(),
+    };
+    if let Err(e) = app.emit(PROVIDER_UPDATED, payload) {
+        tracing::warn!("[events] failed to emit {}: {}", PROVIDER_UPDATED, e);
+    }
+}
+
+pub fn models_updated(app: &AppHandle, refreshed: &[crate::commands::ProviderModels]) {
+    if let Err(e) = app.emit(MODELS_UPDATED, refreshed) {
+        tracing::warn!("[events] failed to emit {}: {}", MODELS_UPDATED, e);
+    }
+}
@@ -0,0 +1,662 @@
+//! Whole-library export for `export_all`, and single-conversation archives.
+//!
+//! Two library formats are supported: one JSON document holding every
+//! folder, conversation and message, or a zip with one Markdown file per
+//! conversation laid out in the conversation's folder path. Both are
+//! written one conversation at a time so the library never has to fit in
+//! memory.
+//!
+//! `archive_to_file` moves one conversation out of the database into a JSON
+//! file that `import_from_file` restores. `conversation_html` renders one
+//! conversation as a standalone page for sharing.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Attachment, Conversation, Database, Folder, Message};
+
+/// Current JSON export format version.
+const EXPORT_VERSION: u32 = 1;
+
+/// Current `ConversationArchive` format version.
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    MarkdownZip,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format.trim().to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "markdown" | "md" | "zip" => Ok(Self::MarkdownZip),
+            other => Err(format!(
+                "Unknown export format '{}'. Expected 'json' or 'markdown'",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportSummary {
+    pub conversations: usize,
+    pub messages: usize,
+}
+
+/// One conversation as written to the JSON export.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConversationExport<'a> {
+    #[serde(flatten)]
+    conversation: &'a Conversation,
+    /// Folder names from the root down; empty for unfiled conversations.
+    folder_path: Vec<String>,
+    messages: &'a [Message],
+}
+
+/// Folder id → names from the root folder down to that folder.
+fn folder_paths(folders: &[Folder]) -> HashMap<String, Vec<String>> {
+    let by_id: HashMap<&str, &Folder> = folders.iter().map(|f| (f.id.as_str(), f)).collect();
+    folders
+        .iter()
+        .map(|folder| {
+            let mut path = Vec::new();
+            let mut current = Some(folder);
+            // Bounded walk so a parent cycle can't loop forever.
+            while let Some(f) = current.filter(|_| path.len() <= folders.len()) {
+                path.push(f.name.clone());
+                current = f.parent_id.as_deref().and_then(|id| by_id.get(id).copied());
+            }
+            path.reverse();
+            (folder.id.clone(), path)
+        })
+        .collect()
+}
+
+/// Make a title or folder name safe to use as a zip path component.
+fn sanitize_component(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_matches('.').trim();
+    if cleaned.is_empty() {
+        "Untitled".to_string()
+    } else {
+        cleaned.chars().take(80).collect()
+    }
+}
+
+fn role_heading(role: &str) -> &str {
+    match role {
+        "user" => "User",
+        "assistant" => "Assistant",
+        "system" => "System",
+        other => other,
+    }
+}
+
+/// Render one conversation as Markdown.
+pub fn conversation_markdown(conversation: &Conversation, messages: &[Message]) -> String {
+    let mut out = format!("# {}\n\n", conversation.title);
+    out.push_str(&format!("- Model: {}\n", conversation.model));
+    if let Some(created) = chrono::DateTime::from_timestamp_millis(conversation.created_at) {
+        out.push_str(&format!("- Created: {}\n", created.to_rfc3339()));
+    }
+    let system_prompt = conversation.system_prompt.as_deref().map(str::trim);
+    if let Some(system_prompt) = system_prompt.filter(|s| !s.is_empty()) {
+        out.push_str(&format!("\n## System\n\n{}\n", system_prompt));
+    }
+    for message in messages {
+        out.push_str(&format!(
+            "\n## {}\n\n{}\n",
+            role_heading(&message.role),
+            message.content.trim_end()
+        ));
+    }
+    out
+}
+
+/// Styles for `conversation_html`; the page loads nothing external.
+const HTML_STYLE: &str = "
+body { font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif; max-width: 46rem; margin: 2rem auto; padding: 0 1rem; color: #1f2328; line-height: 1.55; }
+header { border-bottom: 1px solid #d0d7de; margin-bottom: 1.5rem; }
+header p { color: #656d76; font-size: 0.85rem; }
+.message { border-radius: 10px; padding: 0.75rem 1rem; margin: 1rem 0; }
+.message > .role { font-size: 0.75rem; font-weight: 600; text-transform: uppercase; letter-spacing: 0.04em; color: #656d76; }
+.user { background: #ddf4ff; }
+.assistant { background: #f6f8fa; }
+.system { background: #fff8c5; }
+pre { background: #1f2328; color: #f6f8fa; padding: 0.75rem; border-radius: 6px; overflow-x: auto; }
+code { font-family: ui-monospace, SFMono-Regular, Menlo, monospace; font-size: 0.85em; }
+:not(pre) > code { background: rgba(175, 184, 193, 0.3); padding: 0.1em 0.3em; border-radius: 4px; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d0d7de; padding: 0.3rem 0.6rem; }
+img { max-width: 100%; }
+";
+
+/// Escape text for use in HTML content or a quoted attribute.
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Whether a link or image URL could run script when followed.
+fn is_script_url(url: &str) -> bool {
+    let scheme: String = url
+        .trim()
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .take_while(|&c| c != ':')
+        .collect::<String>()
+        .to_ascii_lowercase();
+    url.contains(':') && matches!(scheme.as_str(), "javascript" | "vbscript" | "data")
+}
+
+/// Render message Markdown to HTML. Raw HTML in the message is shown as
+/// text, and script URLs in links and images are dropped, so message content
+/// can't inject markup into the page.
+pub fn markdown_to_html(markdown: &str) -> String {
+    use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        // pulldown-cmark has no option to turn raw HTML off, so it is
+        // disabled here: HTML blocks and inline tags become text, which
+        // `push_html` escapes.
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if is_script_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: CowStr::Borrowed("#"),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if is_script_url(&dest_url) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: CowStr::Borrowed(""),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut html = String::with_capacity(markdown.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut html, events);
+    html
+}
+
+/// Render one conversation as a self-contained HTML page: the system prompt
+/// and each message, Markdown rendered, styled by role.
+pub fn conversation_html(conversation: &Conversation, messages: &[Message]) -> String {
+    let title = escape_html(&conversation.title);
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta http-equiv=\"Content-Security-Policy\" content=\"default-src 'none'; style-src 'unsafe-inline'; img-src https: data:\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n<header>\n<h1>{title}</h1>\n<p>Model: {}",
+        escape_html(&conversation.model)
+    );
+    if let Some(created) = chrono::DateTime::from_timestamp_millis(conversation.created_at) {
+        out.push_str(&format!(" · Created: {}", created.to_rfc3339()));
+    }
+    out.push_str("</p>\n</header>\n<main>\n");
+
+    let system_prompt = conversation.system_prompt.as_deref().map(str::trim);
+    let system = system_prompt
+        .filter(|s| !s.is_empty())
+        .map(|s| ("system", s));
+    let turns = messages
+        .iter()
+        .map(|m| (m.role.as_str(), m.content.trim_end()));
+    for (role, content) in system.into_iter().chain(turns) {
+        let class = match role {
+            "user" | "assistant" | "system" => role,
+            _ => "other",
+        };
+        out.push_str(&format!(
+            "<section class=\"message {}\">\n<div class=\"role\">{}</div>\n{}</section>\n",
+            class,
+            escape_html(role_heading(role)),
+            markdown_to_html(content)
+        ));
+    }
+    out.push_str("</main>\n</body>\n</html>\n");
+    out
+}
+
+/// Write every conversation (archived included) to `dest` in `format`.
+pub fn export_all(
+    db: &Database,
+    format: ExportFormat,
+    dest: &Path,
+) -> Result<ExportSummary, String> {
+    let folders = db.list_folders().map_err(|e| e.to_string())?;
+    let conversations = db.list_all_conversations().map_err(|e| e.to_string())?;
+    let paths = folder_paths(&folders);
+    let folder_path = |conversation: &Conversation| {
+        conversation
+            .folder_id
+            .as_ref()
+            .and_then(|id| paths.get(id).cloned())
+            .unwrap_or_default()
+    };
+
+    let file =
+        File::create(dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+    let mut summary = ExportSummary::default();
+
+    match format {
+        ExportFormat::Json => {
+            let mut out = BufWriter::new(file);
+            // Written by hand so conversations can be appended one at a time:
+            // `{"version":…,"exportedAt":…,"folders":[…],"conversations":[…]}`.
+            write!(
+                out,
+                "{{\"version\":{},\"exportedAt\":{},\"folders\":",
+                EXPORT_VERSION,
+                chrono::Utc::now().timestamp_millis()
+            )
+            .map_err(|e| e.to_string())?;
+            serde_json::to_writer(&mut out, &folders).map_err(|e| e.to_string())?;
+            out.write_all(b",\"conversations\":[")
+                .map_err(|e| e.to_string())?;
+            for (index, conversation) in conversations.iter().enumerate() {
+                let messages = db
+                    .get_messages(&conversation.id)
+                    .map_err(|e| e.to_string())?;
+                if index > 0 {
+                    out.write_all(b",").map_err(|e| e.to_string())?;
+                }
+                serde_json::to_writer(
+                    &mut out,
+                    &ConversationExport {
+                        conversation,
+                        folder_path: folder_path(conversation),
+                        messages: &messages,
+                    },
+                )
+                .map_err(|e| e.to_string())?;
+                summary.conversations += 1;
+                summary.messages += messages.len();
+            }
+            out.write_all(b"]}").map_err(|e| e.to_string())?;
+            out.flush().map_err(|e| e.to_string())?;
+        }
+        ExportFormat::MarkdownZip => {
+            let mut zip = zip::ZipWriter::new(BufWriter::new(file));
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated);
+            for conversation in &conversations {
+                let messages = db
+                    .get_messages(&conversation.id)
+                    .map_err(|e| e.to_string())?;
+                let mut entry: Vec<String> = folder_path(conversation)
+                    .iter()
+                    .map(|name| sanitize_component(name))
+                    .collect();
+                // The id suffix keeps same-titled conversations apart.
+                let short_id: String = conversation.id.chars().take(8).collect();
+                entry.push(format!(
+                    "{} ({}).md",
+                    sanitize_component(&conversation.title),
+                    short_id
+                ));
+
+                zip.start_file(entry.join("/"), options)
+                    .map_err(|e| e.to_string())?;
+                zip.write_all(conversation_markdown(conversation, &messages).as_bytes())
+                    .map_err(|e| e.to_string())?;
+                summary.conversations += 1;
+                summary.messages += messages.len();
+            }
+            zip.finish()
+                .map_err(|e| e.to_string())?
+                .flush()
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// One conversation written by `archive_to_file` and read back by
+/// `import_from_file`. Attachment files stay where they are; only their rows
+/// are archived.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationArchive {
+    pub version: u32,
+    pub conversation: Conversation,
+    /// Whether the user renamed the conversation; absent from older archives.
+    #[serde(default)]
+    pub title_manual: bool,
+    pub messages: Vec<Message>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+/// Write a conversation to `dest` as JSON, then delete it from the database.
+/// Nothing is deleted unless the file was fully written and synced.
+pub fn archive_to_file(
+    db: &Database,
+    conversation_id: &str,
+    dest: &Path,
+) -> Result<ExportSummary, String> {
+    let archive = ConversationArchive {
+        version: ARCHIVE_VERSION,
+        conversation: db
+            .get_conversation(conversation_id)
+            .map_err(|e| e.to_string())?,
+        title_manual: db
+            .is_title_manual(conversation_id)
+            .map_err(|e| e.to_string())?,
+        messages: db
+            .get_messages(conversation_id)
+            .map_err(|e| e.to_string())?,
+        attachments: db
+            .list_conversation_attachments(conversation_id)
+            .map_err(|e| e.to_string())?,
+    };
+
+    let file =
+        File::create(dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+    let mut out = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut out, &archive).map_err(|e| e.to_string())?;
+    out.into_inner()
+        .map_err(|e| e.to_string())?
+        .sync_all()
+        .map_err(|e| format!("Failed to write {}: {}", dest.display(), e))?;
+
+    db.delete_conversation_tree(conversation_id)
+        .map_err(|e| e.to_string())?;
+    Ok(ExportSummary {
+        conversations: 1,
+        messages: archive.messages.len(),
+    })
+}
+
+/// Restore a conversation written by `archive_to_file`. A folder that no
+/// longer exists is dropped, leaving the conversation unfiled, and message
+/// order is repaired by `Database::restore_conversation`.
+pub fn import_from_file(db: &Database, src: &Path) -> Result<Conversation, String> {
+    let file = File::open(src).map_err(|e| format!("Failed to open {}: {}", src.display(), e))?;
+    let mut archive: ConversationArchive = serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| format!("Invalid archive file: {}", e))?;
+    if archive.version > ARCHIVE_VERSION {
+        return Err(format!(
+            "Archive version {} is newer than this app supports ({})",
+            archive.version, ARCHIVE_VERSION
+        ));
+    }
+    if db.get_conversation(&archive.conversation.id).is_ok() {
+        return Err("This conversation is already in the library".to_string());
+    }
+
+    if let Some(folder_id) = archive.conversation.folder_id.clone() {
+        let folders = db.list_folders().map_err(|e| e.to_string())?;
+        if !folders.iter().any(|f| f.id == folder_id) {
+            archive.conversation.folder_id = None;
+        }
+    }
+    db.restore_conversation(
+        &archive.conversation,
+        archive.title_manual,
+        &archive.messages,
+        &archive.attachments,
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(archive.conversation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::TempDb;
+
+    #[test]
+    fn code_fences_and_links_render_as_html() {
+        let html = markdown_to_html(
+            "See [docs](https://example.com/a?b=1&c=2).\n\n```rust\nlet x = 1 < 2;\n```\n",
+        );
+        assert!(
+            html.contains(r#"<a href="https://example.com/a?b=1&amp;c=2">docs</a>"#),
+            "{html}"
+        );
+        assert!(
+            html.contains(r#"<pre><code class="language-rust">let x = 1 &lt; 2;"#),
+            "{html}"
+        );
+    }
+
+    #[test]
+    fn raw_html_and_script_urls_are_neutralized() {
+        let html = markdown_to_html(
+            "<script>alert(1)</script>\n\nhi <img src=x onerror=alert(1)> \
+             [x](javascript:alert(1)) [y]( JaVaScRiPt:alert(2)) ![i](data:text/html,hi)",
+        );
+        assert!(!html.contains("<script"), "{html}");
+        assert!(
+            html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"),
+            "{html}"
+        );
+        assert!(!html.contains("<img src=x"), "{html}");
+        assert!(
+            html.contains("&lt;img src=x onerror=alert(1)&gt;"),
+            "{html}"
+        );
+        assert!(!html.to_lowercase().contains("javascript:"), "{html}");
+        assert!(!html.contains("data:text"), "{html}");
+    }
+
+    #[test]
+    fn conversation_page_escapes_every_field() {
+        let db = TempDb::new("export-html");
+        db.create_conversation(
+            "c1",
+            "<b>Title</b> & co",
+            "m\"1",
+            "p",
+            Some("Be **brief**"),
+            None,
+        )
+        .unwrap();
+        db.create_message(
+            "u",
+            "c1",
+            "user",
+            "Hello </section><script>x()</script>",
+            None,
+            None,
+            0,
+        )
+        .unwrap();
+        db.create_message(
+            "a",
+            "c1",
+            "assistant",
+            "Use `code` and\n\n```\nlet a = \"<b>\";\n```",
+            None,
+            None,
+            1,
+        )
+        .unwrap();
+
+        let conversation = db.get_conversation("c1").unwrap();
+        let messages = db.get_messages("c1").unwrap();
+        let page = conversation_html(&conversation, &messages);
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<title>&lt;b&gt;Title&lt;/b&gt; &amp; co</title>"));
+        assert!(page.contains("Model: m&quot;1"));
+        assert!(page.contains(r#"<section class="message system">"#));
+        assert!(page.contains("<strong>brief</strong>"));
+        assert!(page.contains(r#"<section class="message user">"#));
+        assert!(page.contains(r#"<section class="message assistant">"#));
+        assert!(page.contains("<code>code</code>"));
+        assert!(page.contains("<pre><code>let a = \"&lt;b&gt;\";"));
+        assert_eq!(page.matches("</section>").count(), 3);
+        assert!(!page.contains("<script"));
+    }
+
+    #[test]
+    fn everything_exports_to_one_json_document() {
+        let db = TempDb::new("export-all");
+        db.create_folder("f1", "Work").unwrap();
+        db.create_conversation("c1", "Plans", "m", "p", None, Some("f1"))
+            .unwrap();
+        db.create_conversation("c2", "Loose", "m", "p", None, None)
+            .unwrap();
+        db.create_message("m1", "c1", "user", "Hi", None, None, 0)
+            .unwrap();
+        db.create_message("m2", "c1", "assistant", "Hello", None, None, 1)
+            .unwrap();
+        db.create_message("m3", "c2", "user", "Yo", None, None, 0)
+            .unwrap();
+        let dest = std::env::temp_dir().join(format!("zitong-export-{}.json", std::process::id()));
+
+        let summary = export_all(&db, ExportFormat::Json, &dest).unwrap();
+        let document: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&dest).unwrap()).unwrap();
+        let _ = std::fs::remove_file(&dest);
+
+        assert_eq!((summary.conversations, summary.messages), (2, 3));
+        assert_eq!(document["version"], EXPORT_VERSION);
+        assert_eq!(document["folders"][0]["name"], "Work");
+        let conversations = document["conversations"].as_array().unwrap();
+        let plans = conversations.iter().find(|c| c["id"] == "c1").unwrap();
+        assert_eq!(plans["folderPath"], serde_json::json!(["Work"]));
+        let contents: Vec<_> = plans["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["content"].as_str().unwrap())
+            .collect();
+        assert_eq!(contents, ["Hi", "Hello"]);
+        let loose = conversations.iter().find(|c| c["id"] == "c2").unwrap();
+        assert_eq!(loose["folderPath"], serde_json::json!([]));
+        assert_eq!(loose["messages"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn archiving_deletes_only_after_the_file_is_written() {
+        let db = TempDb::new("archive-to-file");
+        db.create_conversation("c1", "New Chat", "m", "p", None, None)
+            .unwrap();
+        db.update_conversation_title("c1", "Keep me").unwrap();
+        db.create_message("m1", "c1", "user", "Hi", None, None, 0)
+            .unwrap();
+        db.create_message("m2", "c1", "assistant", "Hello", None, None, 1)
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("zitong-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // The directory doesn't exist, so the write fails.
+        let missing = dir.join("missing/c1.json");
+        assert!(archive_to_file(&db, "c1", &missing).is_err());
+        assert_eq!(db.get_conversation("c1").unwrap().title, "Keep me");
+        assert_eq!(db.get_messages("c1").unwrap().len(), 2);
+
+        std::fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("c1.json");
+        let summary = archive_to_file(&db, "c1", &dest).unwrap();
+        assert_eq!((summary.conversations, summary.messages), (1, 2));
+        assert!(db.get_conversation("c1").is_err());
+        assert!(db.get_messages("c1").unwrap().is_empty());
+
+        let restored = import_from_file(&db, &dest).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(restored.title, "Keep me");
+        assert!(db.is_title_manual("c1").unwrap());
+        let contents: Vec<_> = db
+            .get_messages("c1")
+            .unwrap()
+            .into_iter()
+            .map(|m| m.content)
+            .collect();
+        assert_eq!(contents, ["Hi", "Hello"]);
+    }
+
+    #[test]
+    fn imported_messages_are_renumbered() {
+        let source = TempDb::new("import-order-source");
+        source
+            .create_conversation("c1", "Out of order", "m", "p", None, None)
+            .unwrap();
+        for (id, content) in [("m1", "First"), ("m2", "Second"), ("m3", "Third")] {
+            source
+                .create_message(id, "c1", "user", content, None, None, 0)
+                .unwrap();
+        }
+        let mut messages = source.get_messages("c1").unwrap();
+        messages.sort_by(|a, b| a.id.cmp(&b.id));
+        for (message, (created_at, sort_order)) in
+            messages.iter_mut().zip([(1, 10), (2, 10), (3, 4)])
+        {
+            message.created_at = created_at;
+            message.sort_order = sort_order;
+        }
+        let archive = ConversationArchive {
+            version: ARCHIVE_VERSION,
+            conversation: source.get_conversation("c1").unwrap(),
+            title_manual: false,
+            messages,
+            attachments: Vec::new(),
+        };
+        let dir = std::env::temp_dir().join(format!("zitong-import-order-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("c1.json");
+        std::fs::write(&src, serde_json::to_vec(&archive).unwrap()).unwrap();
+
+        let target = TempDb::new("import-order-target");
+        import_from_file(&target, &src).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let order: Vec<_> = target
+            .get_messages("c1")
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.content, m.sort_order))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("First".into(), 0),
+                ("Second".into(), 1),
+                ("Third".into(), 2)
+            ]
+        );
+    }
+}
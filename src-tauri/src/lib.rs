@@ -1,14 +1,22 @@
+mod attachments;
 mod commands;
 mod db;
+mod events;
+mod export;
+mod local_api;
+mod logging;
 mod providers;
+mod shortcuts;
+mod transcript;
+mod tray;
 mod updater;
 
 use db::Database;
 use std::path::PathBuf;
-use tauri::Manager;
-use tauri::Emitter;
 use tauri::menu::{MenuBuilder, MenuItemBuilder};
 use tauri::tray::TrayIconBuilder;
+use tauri::Emitter;
+use tauri::Manager;
 
 #[cfg(desktop)]
 use tauri_plugin_autostart::ManagerExt as AutostartManagerExt;
@@ -19,6 +27,24 @@ mod panel;
 
 mod clipboard;
 
+/// Show and focus the main window, restoring the Dock icon on macOS.
+fn show_main_window(app: &tauri::AppHandle) {
+    if let Some(win) = app.get_webview_window("main") {
+        // Restore Dock icon before showing window
+        #[cfg(target_os = "macos")]
+        {
+            use objc2::MainThreadMarker;
+            use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
+            if let Some(mtm) = MainThreadMarker::new() {
+                let ns_app = NSApplication::sharedApplication(mtm);
+                ns_app.setActivationPolicy(NSApplicationActivationPolicy::Regular);
+            }
+        }
+        let _ = win.show();
+        let _ = win.set_focus();
+    }
+}
+
 fn get_db_path(app: &tauri::App) -> PathBuf {
     let app_data_dir = app
         .path()
@@ -55,10 +81,25 @@ pub fn run() {
     builder
         .setup(|app| {
             let db_path = get_db_path(app);
-            let database =
-                Database::new(&db_path).expect("Failed to initialize database");
+            let database = Database::new(&db_path).expect("Failed to initialize database");
+
+            let settings = database.get_settings().unwrap_or_default();
+            match app.path().app_log_dir() {
+                Ok(log_dir) => {
+                    if let Err(e) = logging::init(&log_dir, &settings.log_level) {
+                        eprintln!("[zitong] Failed to initialize logging: {}", e);
+                    }
+                }
+                Err(e) => eprintln!("[zitong] Failed to resolve log dir: {}", e),
+            }
 
             app.manage(database);
+            app.manage(transcript::LastRequests::default());
+            if let Err(e) =
+                local_api::apply_settings(app.handle(), &app.state::<Database>(), &settings)
+            {
+                tracing::warn!("[local_api] {}", e);
+            }
 
             // --- macOS application menu (menu bar) ---
             #[cfg(target_os = "macos")]
@@ -118,7 +159,7 @@ pub fn run() {
                             let h = handle.clone();
                             tauri::async_runtime::spawn(async move {
                                 if let Err(e) = open_settings(h).await {
-                                    eprintln!("[menu] Failed to open settings: {}", e);
+                                    tracing::warn!("[menu] Failed to open settings: {}", e);
                                 }
                             });
                         }
@@ -142,55 +183,29 @@ pub fn run() {
             #[cfg(target_os = "macos")]
             {
                 if let Err(e) = panel::setup_overlay_panel(app.handle()) {
-                    eprintln!("[zitong] Failed to set up overlay panel: {}", e);
+                    tracing::error!("[zitong] Failed to set up overlay panel: {}", e);
                 }
             }
 
             // --- System tray icon ---
-            let show_main = MenuItemBuilder::with_id("show_main", "Show Zitong")
-                .build(app)?;
-            let command_palette = MenuItemBuilder::with_id("command_palette", "Command Palette")
-                .accelerator("CommandOrControl+Shift+Space")
-                .build(app)?;
-            let separator = tauri::menu::PredefinedMenuItem::separator(app)?;
-            let quit = MenuItemBuilder::with_id("quit", "Quit Zitong")
-                .accelerator("CommandOrControl+Q")
-                .build(app)?;
+            let tray_menu = tray::build_menu(app.handle())?;
 
-            let tray_menu = MenuBuilder::new(app)
-                .items(&[&show_main, &command_palette, &separator, &quit])
-                .build()?;
-
-            let _tray = TrayIconBuilder::new()
-                .icon(tauri::image::Image::from_bytes(include_bytes!("../icons/tray-icon.png")).expect("tray icon"))
-                .icon_as_template(true)
+            let tray_icon = tray::TrayIconVariant::for_theme(&settings.theme);
+            let _tray = TrayIconBuilder::with_id(tray::TRAY_ID)
+                .icon(tray_icon.image().expect("tray icon"))
+                .icon_as_template(tray_icon.is_template())
                 .menu(&tray_menu)
                 .tooltip("Zitong")
                 .on_menu_event(|app, event| {
                     match event.id().as_ref() {
-                        "show_main" => {
-                            if let Some(win) = app.get_webview_window("main") {
-                                // Restore Dock icon before showing window
-                                #[cfg(target_os = "macos")]
-                                {
-                                    use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
-                                    use objc2::MainThreadMarker;
-                                    if let Some(mtm) = MainThreadMarker::new() {
-                                        let ns_app = NSApplication::sharedApplication(mtm);
-                                        ns_app.setActivationPolicy(NSApplicationActivationPolicy::Regular);
-                                    }
-                                }
-                                let _ = win.show();
-                                let _ = win.set_focus();
-                            }
-                        }
+                        "show_main" => show_main_window(app),
                         "command_palette" => {
                             #[cfg(target_os = "macos")]
                             {
                                 let handle = app.clone();
                                 let _ = app.run_on_main_thread(move || {
                                     if let Err(e) = panel::toggle_overlay_panel(&handle) {
-                                        eprintln!("[tray] toggle overlay failed: {}", e);
+                                        tracing::warn!("[tray] toggle overlay failed: {}", e);
                                     }
                                 });
                             }
@@ -202,7 +217,10 @@ pub fn run() {
                                     } else {
                                         // Simulate Ctrl+C to copy selected text before showing
                                         if let Err(e) = clipboard::simulate_copy_sync() {
-                                            eprintln!("[tray] simulate_copy_sync failed: {}", e);
+                                            tracing::warn!(
+                                                "[tray] simulate_copy_sync failed: {}",
+                                                e
+                                            );
                                         }
                                         let _ = win.center();
                                         let _ = win.show();
@@ -215,27 +233,42 @@ pub fn run() {
                             // Save window position before quitting
                             if let Some(win) = app.get_webview_window("main") {
                                 let db: tauri::State<'_, Database> = app.state();
-                                if let (Ok(pos), Ok(size)) = (win.outer_position(), win.outer_size()) {
-                                    let _ = db.save_window_state(pos.x, pos.y, size.width, size.height);
+                                if let (Ok(pos), Ok(size)) =
+                                    (win.outer_position(), win.outer_size())
+                                {
+                                    let _ =
+                                        db.save_window_state(pos.x, pos.y, size.width, size.height);
                                 }
                             }
                             app.exit(0);
                         }
-                        _ => {}
+                        id => {
+                            if let Some(conversation_id) = tray::pinned_conversation_id(id) {
+                                show_main_window(app);
+                                commands::emit_open_conversation(app, conversation_id);
+                            }
+                        }
                     }
                 })
                 .build(app)?;
+            tray::watch_pins(app.handle());
 
             // --- Restore saved window position & size ---
             let main_window = app.get_webview_window("main").expect("no main window");
             {
                 let db: tauri::State<'_, Database> = app.state();
                 if let Some((x, y, w, h)) = db.get_window_state() {
-                    eprintln!("[window] restoring position: x={}, y={}, w={}, h={}", x, y, w, h);
+                    tracing::debug!(
+                        "[window] restoring position: x={}, y={}, w={}, h={}",
+                        x,
+                        y,
+                        w,
+                        h
+                    );
                     let _ = main_window.set_position(tauri::PhysicalPosition::new(x, y));
                     let _ = main_window.set_size(tauri::PhysicalSize::new(w, h));
                 } else {
-                    eprintln!("[window] no saved position found, using default");
+                    tracing::debug!("[window] no saved position found, using default");
                 }
             }
 
@@ -257,14 +290,19 @@ pub fn run() {
                             let _ = win.hide();
                             #[cfg(target_os = "macos")]
                             {
-                                use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
                                 use objc2::MainThreadMarker;
+                                use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
                                 if let Some(mtm) = MainThreadMarker::new() {
                                     let ns_app = NSApplication::sharedApplication(mtm);
-                                    ns_app.setActivationPolicy(NSApplicationActivationPolicy::Accessory);
+                                    ns_app.setActivationPolicy(
+                                        NSApplicationActivationPolicy::Accessory,
+                                    );
                                 }
                             }
                         }
+                        tauri::WindowEvent::Focused(true) => {
+                            commands::refresh_models_on_focus(&app_handle);
+                        }
                         tauri::WindowEvent::Moved(_) | tauri::WindowEvent::Resized(_) => {
                             // Throttle: save at most once per second during drag
                             let mut last = last_save.lock().unwrap();
@@ -272,8 +310,11 @@ pub fn run() {
                             if now.duration_since(*last).as_secs() >= 1 {
                                 *last = now;
                                 let db: tauri::State<'_, Database> = app_handle.state();
-                                if let (Ok(pos), Ok(size)) = (win.outer_position(), win.outer_size()) {
-                                    let _ = db.save_window_state(pos.x, pos.y, size.width, size.height);
+                                if let (Ok(pos), Ok(size)) =
+                                    (win.outer_position(), win.outer_size())
+                                {
+                                    let _ =
+                                        db.save_window_state(pos.x, pos.y, size.width, size.height);
                                 }
                             }
                         }
@@ -282,16 +323,22 @@ pub fn run() {
                 }
             });
 
-            // Always start with main window hidden — it will be shown only
-            // when the user explicitly triggers it (tray menu, hotkey, dock click).
-            #[cfg(target_os = "macos")]
-            {
-                use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
-                use objc2::MainThreadMarker;
-                if let Some(mtm) = MainThreadMarker::new() {
-                    let ns_app = NSApplication::sharedApplication(mtm);
-                    ns_app.setActivationPolicy(NSApplicationActivationPolicy::Accessory);
+            // `--hidden` launches (login items) and `start_as_background`
+            // stay in the tray; the window is then shown only when the user
+            // triggers it (tray menu, hotkey, dock click).
+            let launched_hidden = std::env::args().any(|arg| arg == "--hidden");
+            if commands::should_start_hidden(launched_hidden, &settings) {
+                #[cfg(target_os = "macos")]
+                {
+                    use objc2::MainThreadMarker;
+                    use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
+                    if let Some(mtm) = MainThreadMarker::new() {
+                        let ns_app = NSApplication::sharedApplication(mtm);
+                        ns_app.setActivationPolicy(NSApplicationActivationPolicy::Accessory);
+                    }
                 }
+            } else {
+                commands::reveal_main_window(app.handle());
             }
 
             // Background update check ~5s after launch. Rate-limited inside
@@ -307,7 +354,7 @@ pub fn run() {
                             let _ = handle.emit("update://available", info);
                         }
                         Ok(_) => {}
-                        Err(e) => eprintln!("[updater] silent check failed: {e}"),
+                        Err(e) => tracing::warn!("[updater] silent check failed: {e}"),
                     }
                 });
             }
@@ -318,50 +365,118 @@ pub fn run() {
             // Conversations
             commands::create_conversation,
             commands::list_conversations,
+            commands::list_conversations_in_folder,
+            commands::conversations_using_model,
+            commands::conversations_using_provider,
+            commands::list_conversation_summaries,
             commands::get_conversation,
+            commands::get_conversation_detail,
             commands::update_conversation_title,
+            commands::update_conversation_temperature,
+            commands::clear_conversation,
             commands::delete_conversation,
             commands::archive_conversation,
+            commands::set_conversation_pinned,
             commands::search_conversations,
+            commands::search_conversations_scoped,
+            commands::search_messages_in_conversation,
             // Messages
             commands::get_messages,
+            commands::get_messages_page,
             commands::delete_message,
+            commands::set_message_include_in_context,
+            commands::set_message_sticky,
+            commands::relabel_messages_model,
+            commands::add_attachment,
+            commands::append_message,
+            commands::fork_conversation,
+            commands::normalize_conversation_order,
             commands::send_message,
+            commands::get_last_request_debug,
+            commands::get_daily_usage,
+            commands::regenerate_message,
             // Providers
             commands::list_providers,
             commands::save_provider,
+            commands::change_provider_type,
             commands::delete_provider,
+            commands::delete_provider_with_data,
             commands::test_provider_connection,
             commands::list_models,
+            commands::list_models_with_metadata,
+            commands::list_models_for_all,
+            commands::validate_defaults,
+            commands::probe_capabilities,
+            commands::add_favorite_model,
+            commands::remove_favorite_model,
+            commands::list_favorite_models,
+            commands::add_provider_key,
+            commands::list_provider_keys,
+            commands::remove_provider_key,
+            commands::ollama_pull_model,
+            commands::ollama_cancel_pull,
+            commands::ollama_delete_model,
             // GitHub Copilot OAuth
             commands::copilot_start_device_flow,
             commands::copilot_poll_auth,
+            commands::copilot_poll_auth_state,
+            commands::copilot_check_auth,
             commands::copilot_exchange_token,
             // Settings
             commands::get_settings,
             commands::save_settings,
+            commands::set_log_level,
+            commands::set_db_journal_mode,
+            commands::reset_setting,
+            commands::set_default_model,
+            commands::import_config,
+            commands::export_config,
+            commands::export_providers_redacted,
+            commands::import_shared_providers,
+            commands::export_all,
+            commands::export_conversation_html,
+            commands::archive_to_file,
+            commands::import_from_file,
+            commands::diagnostics,
+            commands::find_orphans,
+            commands::delete_orphans,
+            commands::normalize_message_content,
             // Prompt Templates
             commands::list_prompt_templates,
+            commands::validate_template,
+            commands::render_prompt_template,
             commands::save_prompt_template,
             commands::delete_prompt_template,
             // Folders
             commands::list_folders,
+            commands::list_folders_with_counts,
             commands::create_folder,
             commands::delete_folder,
             // AI Commands
             commands::list_ai_commands,
             commands::save_ai_command,
+            commands::validate_accelerator,
             commands::delete_ai_command,
+            commands::restore_default_ai_commands,
             commands::execute_ai_command,
             commands::generate_conversation_title,
+            commands::backfill_titles,
+            commands::summarize_conversation,
             commands::open_in_new_chat,
+            commands::open_in_new_chat_streaming,
+            commands::apply_command_result,
             // Assistants
             commands::list_assistants,
             commands::save_assistant,
+            commands::duplicate_assistant,
             commands::delete_assistant,
+            commands::restore_default_assistants,
             // Clipboard (direct macOS)
             clipboard::read_clipboard_text,
             clipboard::write_clipboard_text,
+            clipboard::read_clipboard_image,
+            clipboard::write_clipboard_image,
+            clipboard::copy_response_to_clipboard,
             clipboard::simulate_copy,
             clipboard::simulate_paste,
             clipboard::check_accessibility,
@@ -389,16 +504,22 @@ pub fn run() {
             move |app, event| {
                 // Handle macOS Dock icon click (reopen)
                 #[cfg(target_os = "macos")]
-                if let tauri::RunEvent::Reopen { has_visible_windows, .. } = &event {
+                if let tauri::RunEvent::Reopen {
+                    has_visible_windows,
+                    ..
+                } = &event
+                {
                     if !has_visible_windows {
                         if let Some(win) = app.get_webview_window("main") {
                             // Restore Dock icon and show window
                             {
-                                use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
                                 use objc2::MainThreadMarker;
+                                use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
                                 if let Some(mtm) = MainThreadMarker::new() {
                                     let ns_app = NSApplication::sharedApplication(mtm);
-                                    ns_app.setActivationPolicy(NSApplicationActivationPolicy::Regular);
+                                    ns_app.setActivationPolicy(
+                                        NSApplicationActivationPolicy::Regular,
+                                    );
                                 }
                             }
                             let _ = win.show();
@@ -406,6 +527,20 @@ pub fn run() {
                         }
                     }
                 }
+
+                // Every quit path (tray item, app menu, app.exit) ends here.
+                // Fold the WAL back into the main file so a large `-wal` isn't
+                // left behind.
+                if let tauri::RunEvent::Exit = event {
+                    let db: tauri::State<'_, Database> = app.state();
+                    match db.close() {
+                        Ok(checkpoint) => tracing::info!(
+                            "[db] checkpointed {} WAL frames on exit",
+                            checkpoint.checkpointed_frames.max(0)
+                        ),
+                        Err(e) => tracing::warn!("[db] checkpoint on exit failed: {}", e),
+                    }
+                }
             }
         });
 }
@@ -415,12 +550,13 @@ pub fn run() {
 /// On other platforms, falls back to standard window show/hide.
 #[tauri::command]
 async fn hide_overlay(app: tauri::AppHandle) -> Result<(), String> {
+    commands::cancel_overlay_streams();
     #[cfg(target_os = "macos")]
     {
         let handle = app.clone();
         app.run_on_main_thread(move || {
             if let Err(e) = panel::hide_overlay_panel(&handle) {
-                eprintln!("[hide_overlay] panel hide failed: {}", e);
+                tracing::warn!("[hide_overlay] panel hide failed: {}", e);
             }
         })
         .map_err(|e| e.to_string())
@@ -441,7 +577,7 @@ async fn toggle_overlay(app: tauri::AppHandle) -> Result<(), String> {
         let handle = app.clone();
         app.run_on_main_thread(move || {
             if let Err(e) = panel::toggle_overlay_panel(&handle) {
-                eprintln!("[toggle_overlay] panel toggle failed: {}", e);
+                tracing::warn!("[toggle_overlay] panel toggle failed: {}", e);
             }
         })
         .map_err(|e| e.to_string())
@@ -457,7 +593,7 @@ async fn toggle_overlay(app: tauri::AppHandle) -> Result<(), String> {
                     .await
                     .map_err(|e| e.to_string())?
                 {
-                    eprintln!("[toggle_overlay] simulate_copy_sync failed: {}", e);
+                    tracing::warn!("[toggle_overlay] simulate_copy_sync failed: {}", e);
                 }
                 win.center().map_err(|e| e.to_string())?;
                 win.show().map_err(|e| e.to_string())?;
@@ -473,9 +609,13 @@ async fn toggle_overlay(app: tauri::AppHandle) -> Result<(), String> {
 fn set_launch_at_login(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
     let manager = app.autolaunch();
     if enabled {
-        manager.enable().map_err(|e| format!("Failed to enable autostart: {e}"))?;
+        manager
+            .enable()
+            .map_err(|e| format!("Failed to enable autostart: {e}"))?;
     } else {
-        manager.disable().map_err(|e| format!("Failed to disable autostart: {e}"))?;
+        manager
+            .disable()
+            .map_err(|e| format!("Failed to disable autostart: {e}"))?;
     }
     Ok(())
 }
@@ -490,7 +630,9 @@ fn set_launch_at_login(_enabled: bool) -> Result<(), String> {
 #[tauri::command]
 fn get_launch_at_login(app: tauri::AppHandle) -> Result<bool, String> {
     let manager = app.autolaunch();
-    manager.is_enabled().map_err(|e| format!("Failed to check autostart: {e}"))
+    manager
+        .is_enabled()
+        .map_err(|e| format!("Failed to check autostart: {e}"))
 }
 
 #[cfg(not(desktop))]
@@ -512,19 +654,23 @@ async fn open_settings(app: tauri::AppHandle) -> Result<(), String> {
 
     // Create a new settings window
     use tauri::WebviewWindowBuilder;
-    let _win = WebviewWindowBuilder::new(&app, "settings", tauri::WebviewUrl::App("/settings.html".into()))
-        .title("Settings")
-        .inner_size(800.0, 600.0)
-        .min_inner_size(600.0, 400.0)
-        .center()
-        .resizable(true)
-        .decorations(true)
-        .visible(true)
-        // Disable the OS-level file-drop handler so HTML5 drag-and-drop
-        // (e.g. reordering rows in the Commands tab) works inside the webview.
-        .disable_drag_drop_handler()
-        .build()
-        .map_err(|e| e.to_string())?;
+    let _win = WebviewWindowBuilder::new(
+        &app,
+        "settings",
+        tauri::WebviewUrl::App("/settings.html".into()),
+    )
+    .title("Settings")
+    .inner_size(800.0, 600.0)
+    .min_inner_size(600.0, 400.0)
+    .center()
+    .resizable(true)
+    .decorations(true)
+    .visible(true)
+    // Disable the OS-level file-drop handler so HTML5 drag-and-drop
+    // (e.g. reordering rows in the Commands tab) works inside the webview.
+    .disable_drag_drop_handler()
+    .build()
+    .map_err(|e| e.to_string())?;
 
     Ok(())
 }
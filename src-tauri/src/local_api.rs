@@ -0,0 +1,581 @@
+//! Optional OpenAI-compatible HTTP endpoint for other local tools.
+//!
+//! With the `local_api_enabled` setting on, `POST /v1/chat/completions` is
+//! served on `127.0.0.1:<local_api_port>`. Requests are answered by the
+//! default provider through `stream_chat`, so every provider type looks like
+//! an OpenAI endpoint; `stream: true` replies are sent back as SSE chunks.
+//! Clients authenticate with `Authorization: Bearer <local_api_token>`.
+//! Nothing is written to the conversation history.
+
+use crate::db::{AppSettings, Database};
+use crate::providers::{self, CancelToken, ChatMessage, ProviderConfig, StreamEvent};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tauri::Manager;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const DEFAULT_PORT: i64 = 11435;
+/// Largest request head (request line plus headers) accepted.
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+/// Largest request body accepted.
+const MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+/// Time a client has to send its whole request.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Resolves the provider config for a request's `model` (empty or missing
+/// means the provider's default model).
+pub type ResolveConfig = dyn Fn(Option<String>) -> Result<ProviderConfig, String> + Send + Sync;
+
+struct RunningServer {
+    port: u16,
+    /// Shared with the accept loop so a new token applies without rebinding.
+    token: Arc<Mutex<String>>,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+static SERVER: OnceLock<Mutex<Option<RunningServer>>> = OnceLock::new();
+
+fn server() -> std::sync::MutexGuard<'static, Option<RunningServer>> {
+    SERVER
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Validate a stored `local_api_port`.
+pub fn parse_port(port: i64) -> Result<u16, String> {
+    u16::try_from(port)
+        .ok()
+        .filter(|p| *p != 0)
+        .ok_or_else(|| format!("Local API port must be between 1 and 65535, got {}", port))
+}
+
+/// Start, stop or reconfigure the server to match `settings`. A missing
+/// token is generated and saved first. Errors if the port can't be bound.
+pub fn apply_settings(
+    app: &tauri::AppHandle,
+    db: &Database,
+    settings: &AppSettings,
+) -> Result<(), String> {
+    let mut server = server();
+    if !settings.local_api_enabled {
+        if let Some(running) = server.take() {
+            running.task.abort();
+            tracing::info!("[local_api] stopped");
+        }
+        return Ok(());
+    }
+
+    let port = parse_port(settings.local_api_port)?;
+    let token = match settings.local_api_token.trim() {
+        "" => {
+            let token = uuid::Uuid::new_v4().simple().to_string();
+            db.set_setting_raw("local_api_token", &token)
+                .map_err(|e| e.to_string())?;
+            token
+        }
+        token => token.to_string(),
+    };
+
+    if let Some(running) = server.as_ref().filter(|r| r.port == port) {
+        *running.token.lock().unwrap_or_else(|e| e.into_inner()) = token;
+        return Ok(());
+    }
+    if let Some(running) = server.take() {
+        running.task.abort();
+    }
+
+    let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .map_err(|e| format!("Local API could not listen on port {}: {}", port, e))?;
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    let token = Arc::new(Mutex::new(token));
+    let handle = app.clone();
+    let resolve: Arc<ResolveConfig> = Arc::new(move |model| {
+        crate::commands::default_provider_config(&handle.state::<Database>(), model)
+    });
+    let shared_token = token.clone();
+    let task = tauri::async_runtime::spawn(async move {
+        match TcpListener::from_std(listener) {
+            Ok(listener) => serve(listener, shared_token, resolve).await,
+            Err(e) => tracing::warn!("[local_api] failed to start: {}", e),
+        }
+    });
+    tracing::info!("[local_api] listening on 127.0.0.1:{}", port);
+    *server = Some(RunningServer { port, token, task });
+    Ok(())
+}
+
+/// Accept connections on `listener` until the task is aborted, handling
+/// each on its own task.
+pub async fn serve(listener: TcpListener, token: Arc<Mutex<String>>, resolve: Arc<ResolveConfig>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!("[local_api] accept failed: {}", e);
+                continue;
+            }
+        };
+        let token = token.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let resolve = resolve.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &token, resolve.as_ref()).await {
+                tracing::debug!("[local_api] connection ended: {}", e);
+            }
+        });
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Read one HTTP/1.1 request. Only `Content-Length` bodies are supported.
+async fn read_request(stream: &mut TcpStream) -> Result<Request, String> {
+    let mut reader = BufReader::new(stream);
+    let mut head = (&mut reader).take(MAX_HEAD_BYTES);
+
+    let mut line = String::new();
+    head.read_line(&mut line).await.map_err(|e| e.to_string())?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err("Malformed request line".to_string());
+    };
+    let method = method.to_string();
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let mut authorization = None;
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if head.read_line(&mut line).await.map_err(|e| e.to_string())? == 0 {
+            return Err("Request headers too large or incomplete".to_string());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(format!("Malformed header: {}", header));
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "authorization" => authorization = Some(value.to_string()),
+            "content-length" => {
+                content_length = value
+                    .parse()
+                    .map_err(|_| format!("Invalid Content-Length: {}", value))?
+            }
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(format!("Request body exceeds {} bytes", MAX_BODY_BYTES));
+    }
+
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Request {
+        method,
+        path,
+        authorization,
+        body,
+    })
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    token: &str,
+    resolve: &ResolveConfig,
+) -> std::io::Result<()> {
+    let request = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => request,
+        Ok(Err(e)) => return respond_error(&mut stream, "400 Bad Request", &e).await,
+        Err(_) => {
+            return respond_error(
+                &mut stream,
+                "408 Request Timeout",
+                "Timed out reading the request",
+            )
+            .await
+        }
+    };
+
+    let authorized = !token.is_empty()
+        && request
+            .authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| token_matches(given.trim(), token));
+    if !authorized {
+        return respond_error(
+            &mut stream,
+            "401 Unauthorized",
+            "Invalid or missing local API token",
+        )
+        .await;
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/v1/chat/completions") => {
+            chat_completions(&mut stream, &request.body, resolve).await
+        }
+        _ => {
+            let message = format!("No route for {} {}", request.method, request.path);
+            respond_error(&mut stream, "404 Not Found", &message).await
+        }
+    }
+}
+
+/// Compare a presented token with the configured one in time that doesn't
+/// depend on where they first differ.
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[derive(Deserialize)]
+struct CompletionRequest {
+    #[serde(default)]
+    model: Option<String>,
+    messages: Vec<IncomingMessage>,
+    #[serde(default)]
+    stream: bool,
+    #[serde(default)]
+    temperature: Option<f64>,
+    #[serde(default, alias = "max_completion_tokens")]
+    max_tokens: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct IncomingMessage {
+    role: String,
+    #[serde(default)]
+    content: Value,
+}
+
+/// Convert OpenAI-format messages. Content may be a string or a list of
+/// parts, of which only `text` parts are kept; `developer` is treated as
+/// `system` so the provider's `system_prompt_mode` still applies.
+fn chat_messages(messages: &[IncomingMessage]) -> Result<Vec<ChatMessage>, String> {
+    messages
+        .iter()
+        .map(|m| {
+            let role = match m.role.as_str() {
+                "system" | "developer" => "system",
+                "user" => "user",
+                "assistant" => "assistant",
+                other => return Err(format!("Unsupported message role '{}'", other)),
+            };
+            let content = match &m.content {
+                Value::String(text) => text.clone(),
+                Value::Array(parts) => parts
+                    .iter()
+                    .filter(|part| part["type"] == "text")
+                    .filter_map(|part| part["text"].as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                _ => String::new(),
+            };
+            Ok(ChatMessage {
+                role: role.to_string(),
+                content,
+            })
+        })
+        .collect()
+}
+
+async fn chat_completions(
+    stream: &mut TcpStream,
+    body: &[u8],
+    resolve: &ResolveConfig,
+) -> std::io::Result<()> {
+    let request: CompletionRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => {
+            let message = format!("Invalid request body: {}", e);
+            return respond_error(stream, "400 Bad Request", &message).await;
+        }
+    };
+    let messages = match chat_messages(&request.messages)
+        .and_then(|messages| providers::ensure_conversation_turn(&messages).map(|_| messages))
+    {
+        Ok(messages) => messages,
+        Err(e) => return respond_error(stream, "400 Bad Request", &e).await,
+    };
+    let mut config = match resolve(request.model) {
+        Ok(config) => config,
+        Err(e) => return respond_error(stream, "503 Service Unavailable", &e).await,
+    };
+    if request.temperature.is_some() {
+        config.temperature = request.temperature;
+    }
+    if request.max_tokens.is_some() {
+        config.max_tokens = request.max_tokens;
+    }
+
+    tracing::info!(
+        "[local_api] chat completion via {} / {} (stream: {})",
+        config.provider_type,
+        config.model,
+        request.stream
+    );
+    if request.stream {
+        stream_completion(stream, config, &messages).await
+    } else {
+        complete(stream, &config, &messages).await
+    }
+}
+
+/// Collect the whole reply and answer with one `chat.completion` object.
+async fn complete(
+    stream: &mut TcpStream,
+    config: &ProviderConfig,
+    messages: &[ChatMessage],
+) -> std::io::Result<()> {
+    let mut text = String::new();
+    let mut error = None;
+    let mut total_tokens = 0;
+    let result = providers::stream_chat(config, messages, |event| match event {
+        StreamEvent::Delta { content } => text.push_str(&content),
+        StreamEvent::Error { message, .. } => {
+            error.get_or_insert(message);
+        }
+        StreamEvent::Done {
+            total_tokens: total,
+        } => total_tokens = total,
+        _ => {}
+    })
+    .await;
+    if let Some(message) = error.or(result.err()) {
+        return respond_error(stream, "502 Bad Gateway", &message).await;
+    }
+
+    let body = json!({
+        "id": completion_id(),
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": config.model,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": text },
+            "finish_reason": "stop",
+        }],
+        "usage": { "prompt_tokens": 0, "completion_tokens": total_tokens, "total_tokens": total_tokens },
+    });
+    respond(
+        stream,
+        "200 OK",
+        "application/json",
+        body.to_string().as_bytes(),
+    )
+    .await
+}
+
+/// Forward the reply as `chat.completion.chunk` SSE events, ending with
+/// `data: [DONE]`. A provider error is sent as an `error` event. If the
+/// client goes away the provider stream is cancelled.
+async fn stream_completion(
+    stream: &mut TcpStream,
+    mut config: ProviderConfig,
+    messages: &[ChatMessage],
+) -> std::io::Result<()> {
+    let cancel = CancelToken::default();
+    config.cancel = Some(cancel.clone());
+    let id = completion_id();
+    let created = chrono::Utc::now().timestamp();
+    let chunk = |delta: Value, finish_reason: Option<&str>| {
+        json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": config.model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        })
+    };
+
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+    write_event(
+        stream,
+        &chunk(json!({ "role": "assistant", "content": "" }), None),
+    )
+    .await?;
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let produce = providers::stream_chat(&config, messages, move |event| {
+        let _ = tx.send(event);
+    });
+    let consume = async {
+        let mut error = None;
+        while let Some(event) = rx.recv().await {
+            match event {
+                StreamEvent::Delta { content } => {
+                    let written =
+                        write_event(stream, &chunk(json!({ "content": content }), None)).await;
+                    if let Err(e) = written {
+                        cancel.cancel();
+                        return Err(e);
+                    }
+                }
+                StreamEvent::Error { message, .. } => {
+                    error.get_or_insert(message);
+                }
+                _ => {}
+            }
+        }
+        Ok(error)
+    };
+    let (result, consumed) = tokio::join!(produce, consume);
+
+    match consumed?.or(result.err()) {
+        Some(message) => write_event(stream, &error_body(&message)).await?,
+        None => write_event(stream, &chunk(json!({}), Some("stop"))).await?,
+    }
+    stream.write_all(b"data: [DONE]\n\n").await?;
+    stream.shutdown().await
+}
+
+fn completion_id() -> String {
+    format!("chatcmpl-{}", uuid::Uuid::new_v4().simple())
+}
+
+fn error_body(message: &str) -> Value {
+    json!({ "error": { "message": message, "type": "zitong_error" } })
+}
+
+async fn write_event(stream: &mut TcpStream, data: &Value) -> std::io::Result<()> {
+    stream
+        .write_all(format!("data: {}\n\n", data).as_bytes())
+        .await
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await
+}
+
+async fn respond_error(stream: &mut TcpStream, status: &str, message: &str) -> std::io::Result<()> {
+    let body = error_body(message).to_string();
+    respond(stream, status, "application/json", body.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::tests::{http_response, mock_config, mock_server, openai_sse};
+
+    const TOKEN: &str = "local-token";
+
+    /// Serve the local API on a free port, backed by a mock provider that
+    /// replies "Hello there". Returns the local API's base URL.
+    async fn local_api() -> String {
+        let provider = mock_server(|request| {
+            assert!(request.contains("Bearer sk-test"));
+            let body = openai_sse(&["Hello", " there"]) + "data: [DONE]\n\n";
+            http_response("200 OK", "text/event-stream", &body)
+        })
+        .await;
+        let resolve: Arc<ResolveConfig> = Arc::new(move |model| {
+            let mut config = mock_config(provider.clone());
+            if let Some(model) = model {
+                config.model = model;
+            }
+            Ok(config)
+        });
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(Mutex::new(TOKEN.into())), resolve));
+        format!("http://{}", addr)
+    }
+
+    fn completion_request(url: &str, token: &str, stream: bool) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", url))
+            .bearer_auth(token)
+            .json(&json!({
+                "model": "mock-model",
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "stream": stream,
+            }))
+    }
+
+    #[test]
+    fn token_comparison() {
+        assert!(token_matches("local-token", "local-token"));
+        assert!(!token_matches("local-tokem", "local-token"));
+        assert!(!token_matches("local-token-", "local-token"));
+        assert!(!token_matches("", "local-token"));
+    }
+
+    #[tokio::test]
+    async fn completion_is_proxied_to_the_provider() {
+        let url = local_api().await;
+        let response = completion_request(&url, TOKEN, false).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["model"], "mock-model");
+        assert_eq!(body["choices"][0]["message"]["content"], "Hello there");
+    }
+
+    #[tokio::test]
+    async fn streamed_completion_is_proxied_as_sse() {
+        let url = local_api().await;
+        let response = completion_request(&url, TOKEN, true).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body = response.text().await.unwrap();
+        let content: String = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter(|data| *data != "[DONE]")
+            .filter_map(|data| serde_json::from_str::<Value>(data).ok())
+            .filter_map(|chunk| {
+                chunk["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .map(String::from)
+            })
+            .collect();
+        assert_eq!(content, "Hello there");
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+    }
+
+    #[tokio::test]
+    async fn wrong_token_is_rejected() {
+        let url = local_api().await;
+        let response = completion_request(&url, "not-the-token", false)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+    }
+}
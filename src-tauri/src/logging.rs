@@ -0,0 +1,222 @@
+//! Structured logging via `tracing`.
+//!
+//! Events are written to daily-rotated `zitong.*.log` files in the app's log
+//! directory (resolved via Tauri's path API; the directory is owner-only on
+//! Unix). The level comes from the `log_level` setting and can be changed at
+//! runtime with `set_log_level`; setting `ZITONG_DEBUG` forces debug output.
+//! Records from crates using the `log` facade are forwarded as well.
+//!
+//! Never pass API keys or tokens to a log event — not even at trace level.
+//! As a backstop, every formatted event goes through `redact_secrets` before
+//! it is written, since errors quote request URLs (Gemini's carry the key).
+
+use crate::transcript::{REDACTED, SECRET_HEADERS, SECRET_QUERY_PARAMS};
+use std::io::Write;
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+/// Set to any value other than `0` to force debug-level logging.
+pub const DEBUG_ENV_VAR: &str = "ZITONG_DEBUG";
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+pub const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+const LOG_FILE_PREFIX: &str = "zitong";
+const MAX_LOG_FILES: usize = 7;
+
+static LEVEL_HANDLE: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+// Keeps the background writer alive; dropping it would stop file output.
+static WRITER_GUARD: OnceLock<WorkerGuard> = OnceLock::new();
+
+fn debug_forced() -> bool {
+    std::env::var(DEBUG_ENV_VAR).is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Parse a `log_level` setting value (case-insensitive).
+pub fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    match level.trim().to_lowercase().as_str() {
+        "off" => Ok(LevelFilter::OFF),
+        "error" => Ok(LevelFilter::ERROR),
+        "warn" => Ok(LevelFilter::WARN),
+        "info" => Ok(LevelFilter::INFO),
+        "debug" => Ok(LevelFilter::DEBUG),
+        "trace" => Ok(LevelFilter::TRACE),
+        other => Err(format!(
+            "Unknown log level '{}'. Expected one of: {}",
+            other,
+            LOG_LEVELS.join(", ")
+        )),
+    }
+}
+
+/// Replace every value that follows a case-insensitive `marker` (up to the
+/// first character matching `is_end`) with `REDACTED`.
+fn redact_after(text: &str, marker: &str, is_end: impl Fn(char) -> bool) -> String {
+    // ASCII lowercasing keeps byte offsets, so matches index into `text`.
+    let lower = text.to_ascii_lowercase();
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, _) in lower.match_indices(marker) {
+        let value_start = start + marker.len();
+        if value_start < copied {
+            continue;
+        }
+        let value_len = text[value_start..]
+            .find(&is_end)
+            .unwrap_or(text.len() - value_start);
+        if value_len == 0 {
+            continue;
+        }
+        out.push_str(&text[copied..value_start]);
+        out.push_str(REDACTED);
+        copied = value_start + value_len;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Mask credentials in a log line: bearer tokens, credential headers and
+/// credential query parameters (e.g. `?key=` in a URL quoted by an error).
+pub fn redact_secrets(text: &str) -> String {
+    let url_end = |c: char| c == '&' || c == '#' || c == ')' || c == '"' || c.is_whitespace();
+    let token_end = |c: char| c == '"' || c == ',' || c == ')' || c.is_whitespace();
+    let header_end = |c: char| c == '"' || c == '\n';
+
+    let mut text = redact_after(text, "bearer ", token_end);
+    for param in SECRET_QUERY_PARAMS {
+        for separator in ['?', '&'] {
+            text = redact_after(&text, &format!("{}{}=", separator, param), url_end);
+        }
+    }
+    for header in SECRET_HEADERS {
+        text = redact_after(&text, &format!("{}: ", header), header_end);
+        text = redact_after(&text, &format!("\"{}\": \"", header), header_end);
+    }
+    text
+}
+
+/// Log writer that passes each formatted event through `redact_secrets`.
+pub(crate) struct Redacting<W>(W);
+
+impl<W: Write> Write for Redacting<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(redact_secrets(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Wrap a writer factory for `fmt::layer().with_writer` in `Redacting`.
+pub(crate) fn redacting<W: Write>(make_writer: impl Fn() -> W) -> impl Fn() -> Redacting<W> {
+    move || Redacting(make_writer())
+}
+
+fn restrict_dir_permissions(dir: &Path) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700));
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+/// Install the global subscriber. Call once during app setup.
+pub fn init(log_dir: &Path, level: &str) -> Result<(), String> {
+    std::fs::create_dir_all(log_dir).map_err(|e| format!("Failed to create log dir: {e}"))?;
+    restrict_dir_permissions(log_dir);
+
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(log_dir)
+        .map_err(|e| format!("Failed to open log file: {e}"))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+
+    let level = if debug_forced() {
+        LevelFilter::DEBUG
+    } else {
+        parse_level(level).unwrap_or(LevelFilter::INFO)
+    };
+    let (level_layer, handle) = reload::Layer::new(level);
+
+    tracing_subscriber::registry()
+        .with(level_layer)
+        .with(
+            fmt::layer()
+                .with_writer(redacting(move || writer.clone()))
+                .with_ansi(false),
+        )
+        .with(cfg!(debug_assertions).then(|| fmt::layer().with_writer(redacting(std::io::stderr))))
+        .try_init()
+        .map_err(|e| e.to_string())?;
+
+    let _ = LEVEL_HANDLE.set(handle);
+    let _ = WRITER_GUARD.set(guard);
+    Ok(())
+}
+
+/// Change the active log level without restarting. If logging failed to
+/// initialize, the level is only validated.
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = parse_level(level)?;
+    let Some(handle) = LEVEL_HANDLE.get() else {
+        return Ok(());
+    };
+    handle
+        .modify(|current| *current = filter)
+        .map_err(|e| format!("Failed to update log level: {e}"))?;
+    tracing::info!(level = %filter, "log level changed");
+    Ok(())
+}
+
+/// The level currently in effect, e.g. `"info"`.
+pub fn current_level() -> String {
+    LEVEL_HANDLE
+        .get()
+        .and_then(|handle| handle.clone_current())
+        .map(|filter| filter.to_string().to_lowercase())
+        .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted() {
+        let cases = [
+            (
+                "error sending request for url (https://generativelanguage.googleapis.com/v1beta/models?key=AIzaSecret&pageSize=100)",
+                "error sending request for url (https://generativelanguage.googleapis.com/v1beta/models?key=[REDACTED]&pageSize=100)",
+            ),
+            (
+                "GET /v1/models?pageToken=abc&access_token=ya29.secret",
+                "GET /v1/models?pageToken=abc&access_token=[REDACTED]",
+            ),
+            (
+                "Authorization: Bearer sk-secret",
+                "Authorization: [REDACTED]",
+            ),
+            (
+                r#"headers: {"x-api-key": "sk-ant-secret", "accept": "*/*"}"#,
+                r#"headers: {"x-api-key": "[REDACTED]", "accept": "*/*"}"#,
+            ),
+            ("token bearer abc123, next", "token bearer [REDACTED], next"),
+            ("[list_models] OK: 3 of 3 models", "[list_models] OK: 3 of 3 models"),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(redact_secrets(line), expected);
+        }
+    }
+}
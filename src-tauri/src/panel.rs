@@ -6,8 +6,8 @@
 use std::sync::Arc;
 use tauri::{Emitter, Manager};
 use tauri_nspanel::{
-    tauri_panel, CollectionBehavior, ManagerExt as NspanelManagerExt, PanelLevel, StyleMask,
-    WebviewWindowExt as NspanelWebviewWindowExt, Panel,
+    tauri_panel, CollectionBehavior, ManagerExt as NspanelManagerExt, Panel, PanelLevel, StyleMask,
+    WebviewWindowExt as NspanelWebviewWindowExt,
 };
 
 // Define our custom NSPanel subclass
@@ -37,12 +37,7 @@ fn configure_panel<R: tauri::Runtime>(panel: &Arc<dyn Panel<R>>) {
     panel.set_works_when_modal(true);
 
     // NonactivatingPanel: doesn't steal focus from the previous app
-    panel.set_style_mask(
-        StyleMask::empty()
-            .nonactivating_panel()
-            .resizable()
-            .into(),
-    );
+    panel.set_style_mask(StyleMask::empty().nonactivating_panel().resizable().into());
 
     // Collection behavior: show on all Spaces including fullscreen
     panel.set_collection_behavior(
@@ -133,8 +128,9 @@ pub fn setup_overlay_panel(handle: &tauri::AppHandle) -> Result<(), Box<dyn std:
     Ok(())
 }
 
-/// Hide the overlay panel.
+/// Hide the overlay panel, cancelling any AI command it is still streaming.
 pub fn hide_overlay_panel(handle: &tauri::AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+    crate::commands::cancel_overlay_streams();
     let panel = handle
         .get_webview_panel("overlay")
         .map_err(|e| format!("overlay panel not found: {:?}", e))?;
@@ -156,6 +152,7 @@ pub fn toggle_overlay_panel(handle: &tauri::AppHandle) -> Result<(), Box<dyn std
         .map_err(|e| format!("overlay panel not found: {:?}", e))?;
 
     if panel.is_visible() {
+        crate::commands::cancel_overlay_streams();
         panel.hide();
     } else {
         // Quick permission check — only use the instant AXIsProcessTrusted API.
@@ -163,7 +160,7 @@ pub fn toggle_overlay_panel(handle: &tauri::AppHandle) -> Result<(), Box<dyn std
         // with app focus, causing ⌘C to be sent to the wrong app.
         let has_accessibility = crate::clipboard::check_accessibility_permission(false);
         if !has_accessibility {
-            eprintln!("[panel] accessibility missing, showing permission guide");
+            tracing::info!("[panel] accessibility missing, showing permission guide");
             // Emit event to main window so frontend can show permission guide
             let _ = handle.emit("show-permission-guide", ());
             // Show and focus the main window
@@ -171,8 +168,8 @@ pub fn toggle_overlay_panel(handle: &tauri::AppHandle) -> Result<(), Box<dyn std
                 // Restore Dock icon
                 #[cfg(target_os = "macos")]
                 {
-                    use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
                     use objc2::MainThreadMarker;
+                    use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
                     if let Some(mtm) = MainThreadMarker::new() {
                         let ns_app = NSApplication::sharedApplication(mtm);
                         ns_app.setActivationPolicy(NSApplicationActivationPolicy::Regular);
@@ -188,7 +185,7 @@ pub fn toggle_overlay_panel(handle: &tauri::AppHandle) -> Result<(), Box<dyn std
         // The frontmost app still has keyboard focus at this point,
         // so the keystroke reaches it and copies the selected text.
         if let Err(e) = crate::clipboard::simulate_copy_sync() {
-            eprintln!("[panel] simulate_copy_sync failed: {}", e);
+            tracing::warn!("[panel] simulate_copy_sync failed: {}", e);
             // Still show the panel — the frontend will display an appropriate error
         }
 
@@ -1,6 +1,11 @@
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use reqwest_eventsource::{Event, EventSource};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::transcript::{RequestCapture, Transcript};
 
 // ============================================
 // Provider Message Types
@@ -22,7 +27,261 @@ pub enum StreamEvent {
     #[serde(rename = "done")]
     Done { total_tokens: i64 },
     #[serde(rename = "error")]
-    Error { message: String },
+    Error {
+        message: String,
+        /// What failed, for retry and fallback decisions; not sent to the UI.
+        #[serde(skip)]
+        kind: StreamErrorKind,
+    },
+    /// Non-fatal notice about the response in progress.
+    #[serde(rename = "warning")]
+    Warning { message: String },
+    /// A complete tool call requested by the model; `arguments` is JSON text.
+    #[serde(rename = "tool_call")]
+    ToolCall {
+        id: String,
+        name: String,
+        arguments: String,
+    },
+    /// The provider rejected `stream: true`, so the request is being retried
+    /// as one buffered request; `reason` is the provider's error.
+    #[serde(rename = "streaming_disabled")]
+    StreamingDisabled { reason: String },
+}
+
+impl StreamEvent {
+    /// An `Error` event with no more specific `StreamErrorKind`.
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::Error {
+            message: message.into(),
+            kind: StreamErrorKind::Other,
+        }
+    }
+}
+
+/// Why a stream failed, as reported by the provider layer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StreamErrorKind {
+    /// The provider answered with an error status; `body` is its response.
+    Status { code: u16, body: String },
+    /// The request couldn't be sent, or the connection closed before the
+    /// response was complete.
+    Connection,
+    /// No data arrived within the idle window.
+    Stalled,
+    /// A successful response that isn't an event stream, e.g. a plain JSON
+    /// reply to `stream: true`.
+    NotEventStream { content_type: String },
+    /// An error the provider reported in its response, a blocked prompt, or
+    /// a cancelled stream.
+    #[default]
+    Other,
+}
+
+impl StreamErrorKind {
+    /// The HTTP status of a rejected request.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::Status { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+/// Tracks how many tokens a response has produced so far and yields a
+/// single `StreamEvent::Warning` once it passes `warn_at`. Providers only
+/// report usage at the end of a stream, so deltas are estimated at roughly
+/// four characters per token until a real count arrives.
+#[derive(Debug, Default)]
+pub struct ResponseTokenMeter {
+    warn_at: Option<i64>,
+    estimated_chars: usize,
+    warned: bool,
+}
+
+impl ResponseTokenMeter {
+    /// `warn_at <= 0` disables the warning.
+    pub fn new(warn_at: i64) -> Self {
+        Self {
+            warn_at: (warn_at > 0).then_some(warn_at),
+            ..Self::default()
+        }
+    }
+
+    /// Account for `event`; returns the warning the first time the
+    /// threshold is crossed.
+    pub fn observe(&mut self, event: &StreamEvent) -> Option<StreamEvent> {
+        let warn_at = self.warn_at?;
+        let tokens = match event {
+            StreamEvent::Delta { content } => {
+                self.estimated_chars += content.chars().count();
+                self.estimated_chars.div_ceil(4) as i64
+            }
+            StreamEvent::Done { total_tokens } => *total_tokens,
+            _ => return None,
+        };
+        if self.warned || tokens < warn_at {
+            return None;
+        }
+        self.warned = true;
+        Some(StreamEvent::Warning {
+            message: format!(
+                "This response has used about {} tokens (warning threshold: {})",
+                tokens, warn_at
+            ),
+        })
+    }
+}
+
+/// How quickly a streamed response arrived. Times are from when the
+/// request was sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamMetrics {
+    /// Time to first token: until the first non-empty `Delta`. `None` when
+    /// the response had no text.
+    pub ttft_ms: Option<i64>,
+    /// Until the stream finished.
+    pub duration_ms: i64,
+    /// Output tokens per second after the first token. `None` when the
+    /// provider reported no usage or the text arrived all at once.
+    pub tokens_per_second: Option<f64>,
+}
+
+/// Records when a stream produced its first token, for `StreamMetrics`.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamTimer {
+    started: Instant,
+    first_token: Option<Instant>,
+}
+
+impl StreamTimer {
+    pub fn start(now: Instant) -> Self {
+        Self {
+            started: now,
+            first_token: None,
+        }
+    }
+
+    pub fn observe(&mut self, event: &StreamEvent, now: Instant) {
+        if let StreamEvent::Delta { content } = event {
+            if self.first_token.is_none() && !content.is_empty() {
+                self.first_token = Some(now);
+            }
+        }
+    }
+
+    /// Metrics for a stream that ended at `now` having produced
+    /// `total_tokens` (`0` if unknown).
+    pub fn finish(&self, now: Instant, total_tokens: i64) -> StreamMetrics {
+        let millis = |d: Duration| d.as_millis().min(i64::MAX as u128) as i64;
+        let generating = self
+            .first_token
+            .map(|first| now.saturating_duration_since(first).as_secs_f64());
+        StreamMetrics {
+            ttft_ms: self
+                .first_token
+                .map(|first| millis(first.saturating_duration_since(self.started))),
+            duration_ms: millis(now.saturating_duration_since(self.started)),
+            tokens_per_second: generating
+                .filter(|secs| *secs > 0.0 && total_tokens > 0)
+                .map(|secs| total_tokens as f64 / secs),
+        }
+    }
+}
+
+// ============================================
+// Tool calling
+// ============================================
+
+/// A tool the model may call. `parameters` is a JSON Schema object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolDef {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "empty_object_schema")]
+    pub parameters: serde_json::Value,
+}
+
+fn empty_object_schema() -> serde_json::Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+/// Assembles tool calls that stream in fragments (OpenAI `tool_calls`
+/// deltas, Anthropic `input_json_delta`s), keyed by their index in the
+/// response.
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+    calls: std::collections::BTreeMap<usize, ToolCall>,
+}
+
+impl ToolCallAccumulator {
+    /// Merge one fragment. `id` and `name` arrive once, with the first
+    /// fragment; `arguments` pieces are concatenated in order.
+    pub fn push(
+        &mut self,
+        index: usize,
+        id: Option<&str>,
+        name: Option<&str>,
+        arguments: Option<&str>,
+    ) {
+        let call = self.calls.entry(index).or_default();
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            call.id = id.to_string();
+        }
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            call.name = name.to_string();
+        }
+        if let Some(arguments) = arguments {
+            call.arguments.push_str(arguments);
+        }
+    }
+
+    /// Remove and return the call at `index` once its block has ended.
+    pub fn finish(&mut self, index: usize) -> Option<ToolCall> {
+        self.calls.remove(&index).map(Self::complete)
+    }
+
+    /// Remove and return every pending call, in index order.
+    pub fn drain(&mut self) -> Vec<ToolCall> {
+        std::mem::take(&mut self.calls)
+            .into_values()
+            .map(Self::complete)
+            .collect()
+    }
+
+    fn complete(mut call: ToolCall) -> ToolCall {
+        if call.arguments.trim().is_empty() {
+            call.arguments = "{}".to_string();
+        }
+        call
+    }
+
+    fn emit_all(&mut self, on_event: &mut impl FnMut(StreamEvent)) {
+        for call in self.drain() {
+            on_event(call.into());
+        }
+    }
+}
+
+impl From<ToolCall> for StreamEvent {
+    fn from(call: ToolCall) -> Self {
+        StreamEvent::ToolCall {
+            id: call.id,
+            name: call.name,
+            arguments: call.arguments,
+        }
+    }
 }
 
 // ============================================
@@ -35,33 +294,577 @@ pub struct ModelInfo {
     pub id: String,
     pub name: String,
     pub context_window: Option<i64>,
+    /// Set by `list_models` from the user's starred models; providers never set it.
+    #[serde(default)]
+    pub is_favorite: bool,
+    /// On-disk size of a locally installed model (Ollama only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+}
+
+/// A model list plus how it was arrived at, so the UI can explain an empty
+/// result instead of showing a blank picker.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelListing {
+    pub models: Vec<ModelInfo>,
+    /// Entries the API returned before non-chat models were filtered out.
+    pub fetched_count: usize,
+    /// Set when `models` is empty, describing why.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+impl ModelListing {
+    pub fn new(models: Vec<ModelInfo>, fetched_count: usize) -> Self {
+        let warning = if !models.is_empty() {
+            None
+        } else if fetched_count == 0 {
+            Some("The provider returned no models. Check that the account has access to at least one model.".to_string())
+        } else {
+            Some(format!(
+                "The provider returned {} model{}, but none are chat models (embedding, speech and image models are hidden). Enter a model id manually if yours is missing.",
+                fetched_count,
+                if fetched_count == 1 { "" } else { "s" }
+            ))
+        };
+        Self {
+            models,
+            fetched_count,
+            warning,
+        }
+    }
+}
+
+/// Order `list_models` returns models in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ModelSort {
+    /// By id, as the providers return them.
+    #[default]
+    IdAsc,
+    /// By display name, case-insensitively.
+    NameAsc,
+    /// Starred models first, each group by id.
+    FavoritesFirst,
+    /// Largest context window first; unknown windows last.
+    ContextDesc,
+}
+
+/// Sort `models` in place. Ties fall back to id order.
+pub fn sort_models(models: &mut [ModelInfo], sort: ModelSort) {
+    models.sort_by(|a, b| {
+        let primary = match sort {
+            ModelSort::IdAsc => std::cmp::Ordering::Equal,
+            ModelSort::NameAsc => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            ModelSort::FavoritesFirst => b.is_favorite.cmp(&a.is_favorite),
+            ModelSort::ContextDesc => b.context_window.cmp(&a.context_window),
+        };
+        primary.then_with(|| a.id.cmp(&b.id))
+    });
 }
 
 // ============================================
 // Provider Configuration
 // ============================================
 
+static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+/// Shared HTTP client for all provider traffic. Accepts gzip, deflate and
+/// brotli responses, which some gateways send even for SSE streams.
+pub fn http_client() -> reqwest::Client {
+    HTTP_CLIENT
+        .get_or_init(|| {
+            reqwest::Client::builder()
+                .gzip(true)
+                .deflate(true)
+                .brotli(true)
+                .build()
+                .unwrap_or_default()
+        })
+        .clone()
+}
+
 #[derive(Debug, Clone)]
 pub struct ProviderConfig {
     pub provider_type: String,
     pub api_key: Option<String>,
     pub base_url: Option<String>,
     pub model: String,
+    /// Abort the stream if no event arrives for this long. `None` disables
+    /// the stall detector.
+    pub idle_timeout: Option<Duration>,
+    /// When false, OpenAI-compatible providers are sent one buffered
+    /// (`stream: false`) request and the reply is emitted as a single delta.
+    pub supports_streaming: bool,
+    /// Raw request/response log, set when `log_raw_requests` is on.
+    pub transcript: Option<Arc<Transcript>>,
+    /// Keeps the last request sent, for `get_last_request_debug`.
+    pub capture: Option<Arc<RequestCapture>>,
+    /// How the key is attached on the OpenAI-compatible paths.
+    pub auth_style: AuthStyle,
+    /// Tools offered to the model (OpenAI-compatible and Anthropic only).
+    pub tools: Option<Vec<ToolDef>>,
+    /// Output token limit from the provider's `default_max_tokens`. `None`
+    /// leaves it to the API, except on Anthropic where it is required and
+    /// `anthropic_default_max_tokens` is used.
+    pub max_tokens: Option<i64>,
+    /// Sampling temperature, from the conversation's override. `None`
+    /// leaves it to the API.
+    pub temperature: Option<f64>,
+    /// Send a flattened prompt to the legacy `/completions` endpoint instead
+    /// of a message list (OpenAI-compatible providers only).
+    pub completion_mode: bool,
+    /// Stops the stream at the next event wait when cancelled.
+    pub cancel: Option<CancelToken>,
+    /// Extra headers sent with every request to the provider. Values may
+    /// reference environment variables as `${NAME}`.
+    pub custom_headers: BTreeMap<String, String>,
+    /// Provider-level JSON object merged into OpenAI-compatible and
+    /// Anthropic request bodies (see `merge_extra_body`).
+    pub extra_body: Option<serde_json::Value>,
+    /// `provider_keys` row `api_key` was taken from, if any; a 429 on this
+    /// config cools that key down.
+    pub key_id: Option<String>,
+    /// Times `stream_chat` resumes a stream whose connection drops before
+    /// `Done` (see `StreamResume`). `0` reports the drop as an error.
+    pub reconnect_attempts: u32,
+    /// How system messages are sent; applied before any provider builds
+    /// its request.
+    pub system_prompt_mode: SystemPromptMode,
+    /// Offline mode: requests to non-local providers fail immediately.
+    pub offline: bool,
+    /// Sent as `user` in OpenAI-style request bodies when set.
+    pub user: Option<String>,
+}
+
+fn trace_request(config: &ProviderConfig, builder: &reqwest::RequestBuilder) {
+    if let Some(transcript) = &config.transcript {
+        transcript.request(builder);
+    }
+    if let Some(capture) = &config.capture {
+        capture.request(builder);
+    }
+}
+
+fn trace_response(config: &ProviderConfig, line: &str) {
+    if let Some(transcript) = &config.transcript {
+        transcript.response_line(line);
+    }
+}
+
+pub const DEFAULT_AUTH_STYLE: &str = "bearer_header";
+const DEFAULT_AUTH_QUERY_PARAM: &str = "api-key";
+
+/// How an OpenAI-compatible provider expects its API key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AuthStyle {
+    /// `Authorization: Bearer <key>`
+    #[default]
+    BearerHeader,
+    /// `x-api-key: <key>`
+    XApiKeyHeader,
+    /// `?<param>=<key>`, `api-key` unless named explicitly.
+    QueryParam(String),
+}
+
+impl AuthStyle {
+    /// Parse a stored `auth_style`: `bearer_header`, `x_api_key_header`,
+    /// `query_param` or `query_param:<name>`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "" | "bearer_header" => Ok(Self::BearerHeader),
+            "x_api_key_header" => Ok(Self::XApiKeyHeader),
+            "query_param" => Ok(Self::QueryParam(DEFAULT_AUTH_QUERY_PARAM.to_string())),
+            other => match other.strip_prefix("query_param:").map(str::trim) {
+                Some(name) if !name.is_empty() => Ok(Self::QueryParam(name.to_string())),
+                _ => Err(format!(
+                    "Unknown auth style '{}'. Expected bearer_header, x_api_key_header or query_param[:name]",
+                    other
+                )),
+            },
+        }
+    }
+
+    pub fn apply(
+        &self,
+        builder: reqwest::RequestBuilder,
+        api_key: &str,
+    ) -> reqwest::RequestBuilder {
+        match self {
+            Self::BearerHeader => builder.header("Authorization", format!("Bearer {}", api_key)),
+            Self::XApiKeyHeader => builder.header("x-api-key", api_key),
+            Self::QueryParam(name) => builder.query(&[(name.as_str(), api_key)]),
+        }
+    }
+}
+
+pub const DEFAULT_SYSTEM_PROMPT_MODE: &str = "system_role";
+
+/// Where a provider wants the system prompt. Some local models ignore the
+/// `system` role, and newer OpenAI models expect `developer` instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SystemPromptMode {
+    /// Sent as `system` messages (Anthropic and Gemini lift them out of the
+    /// message list as usual).
+    #[default]
+    SystemRole,
+    /// Folded into the start of the first user message.
+    PrependUser,
+    /// Sent with the `developer` role.
+    DeveloperRole,
+}
+
+impl SystemPromptMode {
+    /// Parse a stored `system_prompt_mode`: `system_role`, `prepend_user` or
+    /// `developer_role`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "" | "system_role" => Ok(Self::SystemRole),
+            "prepend_user" => Ok(Self::PrependUser),
+            "developer_role" => Ok(Self::DeveloperRole),
+            other => Err(format!(
+                "Unknown system prompt mode '{}'. Expected system_role, prepend_user or developer_role",
+                other
+            )),
+        }
+    }
+
+    /// `messages` with their system messages placed for this mode.
+    pub fn apply<'a>(&self, messages: &'a [ChatMessage]) -> std::borrow::Cow<'a, [ChatMessage]> {
+        use std::borrow::Cow;
+
+        if !messages.iter().any(|m| m.role == "system") {
+            return Cow::Borrowed(messages);
+        }
+        match self {
+            Self::SystemRole => Cow::Borrowed(messages),
+            Self::DeveloperRole => Cow::Owned(
+                messages
+                    .iter()
+                    .map(|m| ChatMessage {
+                        role: if m.role == "system" {
+                            "developer".to_string()
+                        } else {
+                            m.role.clone()
+                        },
+                        content: m.content.clone(),
+                    })
+                    .collect(),
+            ),
+            Self::PrependUser => {
+                let system = messages
+                    .iter()
+                    .filter(|m| m.role == "system")
+                    .map(|m| m.content.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                let mut out: Vec<ChatMessage> = messages
+                    .iter()
+                    .filter(|m| m.role != "system")
+                    .cloned()
+                    .collect();
+                match out.iter_mut().find(|m| m.role == "user") {
+                    Some(first_user) => {
+                        first_user.content = format!("{}\n\n{}", system, first_user.content);
+                    }
+                    None => out.insert(
+                        0,
+                        ChatMessage {
+                            role: "user".to_string(),
+                            content: system,
+                        },
+                    ),
+                }
+                Cow::Owned(out)
+            }
+        }
+    }
+}
+
+/// Whether the provider's endpoint is on this machine: `localhost`, a
+/// `*.localhost` name or a loopback address. Ollama without a `base_url`
+/// counts, since it defaults to `localhost:11434`.
+pub fn is_local_provider(config: &ProviderConfig) -> bool {
+    let Ok(url) = reqwest::Url::parse(&config.get_endpoint()) else {
+        return false;
+    };
+    url.host_str().is_some_and(is_loopback_host)
+}
+
+/// Whether `host` names this machine.
+fn is_loopback_host(host: &str) -> bool {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase();
+    host == "localhost"
+        || host.ends_with(".localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Whether `host` is on the local network: a private or link-local address,
+/// or an mDNS `.local` name.
+fn is_lan_host(host: &str) -> bool {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_ascii_lowercase();
+    host.ends_with(".local")
+        || match host.parse::<std::net::IpAddr>() {
+            Ok(std::net::IpAddr::V4(ip)) => ip.is_private() || ip.is_link_local(),
+            Ok(std::net::IpAddr::V6(ip)) => {
+                let first = ip.segments()[0];
+                // fc00::/7 unique local, fe80::/10 link-local.
+                first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+            }
+            Err(_) => false,
+        }
+}
+
+/// Likely mistakes in a provider's `base_url` that would otherwise only show
+/// up as a connection error: a missing scheme, or an `ollama` URL that isn't
+/// plain http on this machine or the local network. Advisory only, since a
+/// remote Ollama behind a TLS proxy is legitimate.
+pub fn base_url_warnings(provider_type: &str, base_url: Option<&str>) -> Vec<String> {
+    let Some(base_url) = base_url.map(str::trim).filter(|u| !u.is_empty()) else {
+        return Vec::new();
+    };
+    if !base_url.contains("://") {
+        return vec![format!(
+            "Base URL '{}' has no scheme; start it with https:// (or http:// for a local server)",
+            base_url
+        )];
+    }
+    if provider_type != "ollama" {
+        return Vec::new();
+    }
+    let url = match reqwest::Url::parse(base_url) {
+        Ok(url) => url,
+        Err(e) => return vec![format!("Base URL '{}' is not a valid URL: {}", base_url, e)],
+    };
+    let mut warnings = Vec::new();
+    if url.scheme() != "http" {
+        warnings.push(format!(
+            "Ollama serves plain http, but the base URL uses {}://",
+            url.scheme()
+        ));
+    }
+    let host = url.host_str().unwrap_or_default();
+    if !is_loopback_host(host) && !is_lan_host(host) {
+        warnings.push(format!(
+            "Ollama usually runs on this machine (http://localhost:11434), but the base URL points at {}",
+            host
+        ));
+    }
+    warnings
+}
+
+/// Fail fast when offline mode is on and the provider isn't local.
+fn ensure_reachable(config: &ProviderConfig) -> Result<(), String> {
+    if config.offline && !is_local_provider(config) {
+        return Err(format!(
+            "Offline mode is on: the {} provider is not on this machine",
+            config.provider_type
+        ));
+    }
+    Ok(())
+}
+
+/// Expand `${NAME}` references in a custom header value from the process
+/// environment, so secrets like gateway tokens never need to be stored.
+pub fn resolve_header_value(name: &str, value: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("Header '{}' has an unterminated '${{' reference", name))?;
+        let var = after[..end].trim();
+        if var.is_empty() {
+            return Err(format!("Header '{}' has an empty '${{}}' reference", name));
+        }
+        let resolved = std::env::var(var).map_err(|_| {
+            format!(
+                "Header '{}' references environment variable '{}', which is not set",
+                name, var
+            )
+        })?;
+        out.push_str(&resolved);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Check stored custom headers without resolving them: names must be valid
+/// header names and every `${` must be closed.
+pub fn validate_custom_headers(headers: &BTreeMap<String, String>) -> Result<(), String> {
+    for (name, value) in headers {
+        reqwest::header::HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("Invalid header name '{}'", name))?;
+        let mut rest = value.as_str();
+        while let Some(start) = rest.find("${") {
+            let after = &rest[start + 2..];
+            let end = after
+                .find('}')
+                .ok_or_else(|| format!("Header '{}' has an unterminated '${{' reference", name))?;
+            rest = &after[end + 1..];
+        }
+    }
+    Ok(())
+}
+
+/// Add the keys of `extra` that `body` doesn't already set. Fields the app
+/// sets, like `model` and `messages`, always win.
+pub fn merge_extra_body(
+    mut body: serde_json::Value,
+    extra: Option<&serde_json::Value>,
+) -> serde_json::Value {
+    if let (Some(body), Some(serde_json::Value::Object(extra))) = (body.as_object_mut(), extra) {
+        for (key, value) in extra {
+            body.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+    body
+}
+
+/// `body` as JSON with the provider's `extra_body` merged in.
+fn request_body(
+    config: &ProviderConfig,
+    body: &impl Serialize,
+) -> Result<serde_json::Value, String> {
+    let body = serde_json::to_value(body).map_err(|e| e.to_string())?;
+    Ok(merge_extra_body(body, config.extra_body.as_ref()))
+}
+
+/// The provider's custom headers with environment references resolved.
+fn custom_headers(config: &ProviderConfig) -> Result<reqwest::header::HeaderMap, String> {
+    let mut map = reqwest::header::HeaderMap::new();
+    for (name, value) in &config.custom_headers {
+        let header = reqwest::header::HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("Invalid header name '{}'", name))?;
+        let value = reqwest::header::HeaderValue::from_str(&resolve_header_value(name, value)?)
+            .map_err(|_| format!("Header '{}' has an invalid value", name))?;
+        map.insert(header, value);
+    }
+    Ok(map)
+}
+
+/// Every `provider_type` the app knows how to talk to.
+pub const PROVIDER_TYPES: &[&str] = &[
+    "openai",
+    "anthropic",
+    "gemini",
+    "ollama",
+    "github_copilot",
+    "mistral",
+    "groq",
+    "deepseek",
+    "openrouter",
+    "xai",
+    "together",
+    "fireworks",
+    "openai_compatible",
+];
+
+/// Best-effort guess at whether `model` accepts image input, from its name.
+pub fn supports_vision(provider_type: &str, model: &str) -> bool {
+    let model = model.to_lowercase();
+    let any = |needles: &[&str]| needles.iter().any(|n| model.contains(n));
+    match provider_type {
+        "anthropic" => !model.starts_with("claude-2") && !model.starts_with("claude-instant"),
+        "gemini" => true,
+        "ollama" => any(&[
+            "llava",
+            "vision",
+            "moondream",
+            "minicpm-v",
+            "gemma3",
+            "qwen2.5vl",
+            "-vl",
+        ]),
+        "mistral" => any(&["pixtral", "mistral-medium", "mistral-small"]),
+        "groq" | "xai" => any(&["vision", "llama-4", "grok-4"]),
+        "deepseek" => false,
+        _ => any(&[
+            "gpt-4o",
+            "gpt-4.1",
+            "gpt-4-turbo",
+            "gpt-5",
+            "o1",
+            "o3",
+            "o4",
+            "vision",
+            "claude",
+            "gemini",
+            "-vl",
+        ]),
+    }
+}
+
+/// Provider types handled by the OpenAI-compatible request path.
+pub fn is_openai_compatible(provider_type: &str) -> bool {
+    !matches!(
+        provider_type,
+        "anthropic" | "gemini" | "ollama" | "github_copilot"
+    )
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        Self {
+            provider_type: String::new(),
+            api_key: None,
+            base_url: None,
+            model: String::new(),
+            idle_timeout: None,
+            supports_streaming: true,
+            transcript: None,
+            capture: None,
+            auth_style: AuthStyle::default(),
+            tools: None,
+            max_tokens: None,
+            temperature: None,
+            completion_mode: false,
+            cancel: None,
+            custom_headers: BTreeMap::new(),
+            extra_body: None,
+            key_id: None,
+            reconnect_attempts: 0,
+            system_prompt_mode: SystemPromptMode::default(),
+            offline: false,
+            user: None,
+        }
+    }
 }
 
 impl ProviderConfig {
     pub fn get_endpoint(&self) -> String {
         match self.provider_type.as_str() {
             "openai" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.openai.com/v1");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.openai.com/v1");
                 format!("{}/chat/completions", base)
             }
             "anthropic" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.anthropic.com");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.anthropic.com");
                 format!("{}/v1/messages", base)
             }
             "gemini" => {
-                let base = self.base_url.as_deref().unwrap_or("https://generativelanguage.googleapis.com");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://generativelanguage.googleapis.com");
                 format!(
                     "{}/v1beta/models/{}:streamGenerateContent?alt=sse",
                     base, self.model
@@ -72,32 +875,64 @@ impl ProviderConfig {
                 format!("{}/api/chat", base)
             }
             "github_copilot" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.individual.githubcopilot.com");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.individual.githubcopilot.com");
                 format!("{}/chat/completions", base)
             }
             "mistral" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.mistral.ai/v1");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.mistral.ai/v1");
                 format!("{}/chat/completions", base)
             }
             "groq" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.groq.com/openai/v1");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.groq.com/openai/v1");
                 format!("{}/chat/completions", base)
             }
             "deepseek" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.deepseek.com");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.deepseek.com");
                 format!("{}/chat/completions", base)
             }
             "openrouter" => {
-                let base = self.base_url.as_deref().unwrap_or("https://openrouter.ai/api/v1");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://openrouter.ai/api/v1");
                 format!("{}/chat/completions", base)
             }
             "xai" => {
                 let base = self.base_url.as_deref().unwrap_or("https://api.x.ai/v1");
                 format!("{}/chat/completions", base)
             }
+            "together" => {
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.together.xyz/v1");
+                format!("{}/chat/completions", base)
+            }
+            "fireworks" => {
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.fireworks.ai/inference/v1");
+                format!("{}/chat/completions", base)
+            }
             _ => {
                 // OpenAI-compatible
-                let base = self.base_url.as_deref().unwrap_or("https://api.openai.com/v1");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.openai.com/v1");
                 format!("{}/chat/completions", base)
             }
         }
@@ -107,15 +942,24 @@ impl ProviderConfig {
     fn get_models_endpoint(&self) -> String {
         match self.provider_type.as_str() {
             "openai" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.openai.com/v1");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.openai.com/v1");
                 format!("{}/models", base)
             }
             "anthropic" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.anthropic.com");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.anthropic.com");
                 format!("{}/v1/models", base)
             }
             "gemini" => {
-                let base = self.base_url.as_deref().unwrap_or("https://generativelanguage.googleapis.com");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://generativelanguage.googleapis.com");
                 format!("{}/v1beta/models", base)
             }
             "ollama" => {
@@ -123,30 +967,57 @@ impl ProviderConfig {
                 format!("{}/api/tags", base)
             }
             "github_copilot" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.individual.githubcopilot.com");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.individual.githubcopilot.com");
                 format!("{}/models", base)
             }
             "mistral" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.mistral.ai/v1");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.mistral.ai/v1");
                 format!("{}/models", base)
             }
             "groq" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.groq.com/openai/v1");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.groq.com/openai/v1");
                 format!("{}/models", base)
             }
             "deepseek" => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.deepseek.com");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.deepseek.com");
                 format!("{}/models", base)
             }
-            "openrouter" => {
-                "https://openrouter.ai/api/v1/models".to_string()
-            }
+            "openrouter" => "https://openrouter.ai/api/v1/models".to_string(),
             "xai" => {
                 let base = self.base_url.as_deref().unwrap_or("https://api.x.ai/v1");
                 format!("{}/models", base)
             }
+            "together" => {
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.together.xyz/v1");
+                format!("{}/models", base)
+            }
+            "fireworks" => {
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.fireworks.ai/inference/v1");
+                format!("{}/models", base)
+            }
             _ => {
-                let base = self.base_url.as_deref().unwrap_or("https://api.openai.com/v1");
+                let base = self
+                    .base_url
+                    .as_deref()
+                    .unwrap_or("https://api.openai.com/v1");
                 format!("{}/models", base)
             }
         }
@@ -212,6 +1083,7 @@ struct OllamaModelsResponse {
 struct OllamaModelEntry {
     name: Option<String>,
     model: Option<String>,
+    size: Option<u64>,
 }
 
 // OpenRouter format
@@ -234,20 +1106,113 @@ struct OpenRouterArchitecture {
     output_modalities: Option<Vec<String>>,
 }
 
+// Together format: a bare array rather than `{ "data": [...] }`
+#[derive(Deserialize)]
+struct TogetherModelEntry {
+    id: String,
+    /// `chat`, `language`, `code`, `embedding`, `image`, `rerank`, …
+    #[serde(rename = "type", default)]
+    model_type: Option<String>,
+    display_name: Option<String>,
+    context_length: Option<i64>,
+}
+
 // ============================================
 // Model Listing — Fetch Logic
 // ============================================
 
+/// Vendor prefixes aggregators (OpenRouter, gateways) put in front of model ids.
+const KNOWN_VENDOR_PREFIXES: &[&str] = &[
+    "openai/",
+    "anthropic/",
+    "google/",
+    "meta-llama/",
+    "mistralai/",
+    "x-ai/",
+    "deepseek/",
+    "cohere/",
+    "qwen/",
+    "microsoft/",
+    "nvidia/",
+    "perplexity/",
+];
+
+/// Lowercased model id with any known vendor prefix removed.
+fn normalized_model_id(id: &str) -> String {
+    let lower = id.to_lowercase();
+    KNOWN_VENDOR_PREFIXES
+        .iter()
+        .find_map(|prefix| lower.strip_prefix(prefix))
+        .map(str::to_string)
+        .unwrap_or(lower)
+}
+
+/// Collapse models that only differ by case or a vendor prefix
+/// (e.g. `openai/gpt-4o` and `gpt-4o`). Keeps the first entry's position but
+/// prefers whichever duplicate reports a context window.
+pub fn dedupe_models(models: Vec<ModelInfo>) -> Vec<ModelInfo> {
+    let mut deduped: Vec<ModelInfo> = Vec::with_capacity(models.len());
+    let mut seen: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+
+    for model in models {
+        let key = normalized_model_id(&model.id);
+        match seen.get(&key) {
+            Some(&idx) => {
+                if deduped[idx].context_window.is_none() && model.context_window.is_some() {
+                    deduped[idx] = model;
+                }
+            }
+            None => {
+                seen.insert(key, deduped.len());
+                deduped.push(model);
+            }
+        }
+    }
+
+    deduped
+}
+
 /// Fetch the list of available models from a provider's API.
 /// No fallbacks — if the API call fails, the error is returned directly.
-pub async fn list_provider_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>, String> {
+/// With `dedupe`, vendor-prefixed duplicates are collapsed (see `dedupe_models`).
+pub async fn list_provider_models(
+    config: &ProviderConfig,
+    dedupe: bool,
+) -> Result<Vec<ModelInfo>, String> {
+    list_provider_models_detailed(config, dedupe)
+        .await
+        .map(|listing| listing.models)
+}
+
+/// `list_provider_models`, also reporting how many models the API returned
+/// before filtering.
+pub async fn list_provider_models_detailed(
+    config: &ProviderConfig,
+    dedupe: bool,
+) -> Result<ModelListing, String> {
+    ensure_reachable(config)?;
+    let (models, fetched_count) = fetch_provider_models(config)
+        .await
+        .inspect_err(|e| note_rate_limit(config, http_status_in(e)))?;
+    let models = if dedupe {
+        dedupe_models(models)
+    } else {
+        models
+    };
+    Ok(ModelListing::new(models, fetched_count))
+}
+
+/// Chat models from the provider, plus the number of entries the API
+/// returned before filtering.
+async fn fetch_provider_models(config: &ProviderConfig) -> Result<(Vec<ModelInfo>, usize), String> {
     match config.provider_type.as_str() {
         "anthropic" => fetch_anthropic_models(config).await,
         "gemini" => fetch_gemini_models(config).await,
         "ollama" => fetch_ollama_models(config).await,
         "github_copilot" => fetch_copilot_models(config).await,
         "openrouter" => fetch_openrouter_models(config).await,
-        // OpenAI-compatible: openai, mistral, groq, deepseek, xai, github_copilot, and fallback
+        "together" => fetch_together_models(config).await,
+        // OpenAI-compatible: openai, mistral, groq, deepseek, xai, fireworks, and fallback
         provider_type => {
             let filter: Box<dyn Fn(&str) -> bool + Send + Sync> = match provider_type {
                 "openai" => Box::new(|id: &str| {
@@ -261,9 +1226,7 @@ pub async fn list_provider_models(config: &ProviderConfig) -> Result<Vec<ModelIn
                         && !id_lower.contains("davinci")
                         && !id_lower.starts_with("ft:")
                 }),
-                "mistral" => Box::new(|id: &str| {
-                    !id.to_lowercase().contains("embed")
-                }),
+                "mistral" => Box::new(|id: &str| !id.to_lowercase().contains("embed")),
                 "groq" => Box::new(|id: &str| {
                     let id_lower = id.to_lowercase();
                     !id_lower.contains("whisper")
@@ -271,8 +1234,13 @@ pub async fn list_provider_models(config: &ProviderConfig) -> Result<Vec<ModelIn
                         && !id_lower.contains("playai-tts")
                         && !id_lower.contains("distil-whisper")
                 }),
-                "xai" => Box::new(|id: &str| {
-                    !id.to_lowercase().contains("imagine")
+                "xai" => Box::new(|id: &str| !id.to_lowercase().contains("imagine")),
+                "fireworks" => Box::new(|id: &str| {
+                    let id_lower = id.to_lowercase();
+                    !id_lower.contains("embed")
+                        && !id_lower.contains("whisper")
+                        && !id_lower.contains("flux")
+                        && !id_lower.contains("stable-diffusion")
                 }),
                 _ => Box::new(|_: &str| true),
             };
@@ -285,8 +1253,8 @@ pub async fn list_provider_models(config: &ProviderConfig) -> Result<Vec<ModelIn
 async fn fetch_openai_compatible_models(
     config: &ProviderConfig,
     filter: &(dyn Fn(&str) -> bool + Send + Sync),
-) -> Result<Vec<ModelInfo>, String> {
-    let client = reqwest::Client::new();
+) -> Result<(Vec<ModelInfo>, usize), String> {
+    let client = http_client();
     let endpoint = config.get_models_endpoint();
 
     let api_key = config
@@ -294,9 +1262,12 @@ async fn fetch_openai_compatible_models(
         .as_deref()
         .ok_or_else(|| "API key not configured".to_string())?;
 
-    let response = client
-        .get(&endpoint)
-        .header("Authorization", format!("Bearer {}", api_key))
+    let response = config
+        .auth_style
+        .apply(
+            client.get(&endpoint).headers(custom_headers(config)?),
+            api_key,
+        )
         .send()
         .await
         .map_err(|e| format!("Failed to connect: {}", e))?;
@@ -312,6 +1283,7 @@ async fn fetch_openai_compatible_models(
         .await
         .map_err(|e| format!("Failed to parse model list: {}", e))?;
 
+    let fetched = resp.data.len();
     let mut models: Vec<ModelInfo> = resp
         .data
         .into_iter()
@@ -320,17 +1292,24 @@ async fn fetch_openai_compatible_models(
             name: m.id.clone(),
             id: m.id,
             context_window: None,
+            is_favorite: false,
+            size_bytes: None,
         })
         .collect();
 
     models.sort_by(|a, b| a.id.cmp(&b.id));
-    Ok(models)
+    Ok((models, fetched))
 }
 
 /// Fetch Anthropic models with pagination
-async fn fetch_anthropic_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>, String> {
-    let client = reqwest::Client::new();
-    let base = config.base_url.as_deref().unwrap_or("https://api.anthropic.com");
+async fn fetch_anthropic_models(
+    config: &ProviderConfig,
+) -> Result<(Vec<ModelInfo>, usize), String> {
+    let client = http_client();
+    let base = config
+        .base_url
+        .as_deref()
+        .unwrap_or("https://api.anthropic.com");
 
     let api_key = config
         .api_key
@@ -348,6 +1327,7 @@ async fn fetch_anthropic_models(config: &ProviderConfig) -> Result<Vec<ModelInfo
 
         let response = client
             .get(&url)
+            .headers(custom_headers(config)?)
             .header("x-api-key", api_key)
             .header("anthropic-version", "2023-06-01")
             .send()
@@ -370,6 +1350,8 @@ async fn fetch_anthropic_models(config: &ProviderConfig) -> Result<Vec<ModelInfo
                 name: m.display_name.unwrap_or_else(|| m.id.clone()),
                 id: m.id,
                 context_window: None,
+                is_favorite: false,
+                size_bytes: None,
             });
         }
 
@@ -381,13 +1363,17 @@ async fn fetch_anthropic_models(config: &ProviderConfig) -> Result<Vec<ModelInfo
     }
 
     all_models.sort_by(|a, b| a.id.cmp(&b.id));
-    Ok(all_models)
+    let fetched = all_models.len();
+    Ok((all_models, fetched))
 }
 
 /// Fetch Gemini models with pagination, filtering to chat-capable models
-async fn fetch_gemini_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>, String> {
-    let client = reqwest::Client::new();
-    let base = config.base_url.as_deref().unwrap_or("https://generativelanguage.googleapis.com");
+async fn fetch_gemini_models(config: &ProviderConfig) -> Result<(Vec<ModelInfo>, usize), String> {
+    let client = http_client();
+    let base = config
+        .base_url
+        .as_deref()
+        .unwrap_or("https://generativelanguage.googleapis.com");
 
     let api_key = config
         .api_key
@@ -395,6 +1381,7 @@ async fn fetch_gemini_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
         .ok_or_else(|| "API key not configured".to_string())?;
 
     let mut all_models: Vec<ModelInfo> = Vec::new();
+    let mut fetched = 0;
     let mut page_token: Option<String> = None;
 
     loop {
@@ -405,6 +1392,7 @@ async fn fetch_gemini_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
 
         let response = client
             .get(&url)
+            .headers(custom_headers(config)?)
             .send()
             .await
             .map_err(|e| format!("Failed to connect: {}", e))?;
@@ -421,6 +1409,7 @@ async fn fetch_gemini_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
             .map_err(|e| format!("Failed to parse model list: {}", e))?;
 
         if let Some(models) = resp.models {
+            fetched += models.len();
             for m in models {
                 // Only include models that support generateContent (chat)
                 let supports_chat = m
@@ -445,6 +1434,8 @@ async fn fetch_gemini_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
                     name: m.display_name.unwrap_or_else(|| id.clone()),
                     id,
                     context_window: m.input_token_limit,
+                    is_favorite: false,
+                    size_bytes: None,
                 });
             }
         }
@@ -456,16 +1447,17 @@ async fn fetch_gemini_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
     }
 
     all_models.sort_by(|a, b| a.id.cmp(&b.id));
-    Ok(all_models)
+    Ok((all_models, fetched))
 }
 
 /// Fetch locally available Ollama models
-async fn fetch_ollama_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>, String> {
-    let client = reqwest::Client::new();
+async fn fetch_ollama_models(config: &ProviderConfig) -> Result<(Vec<ModelInfo>, usize), String> {
+    let client = http_client();
     let endpoint = config.get_models_endpoint();
 
     let response = client
         .get(&endpoint)
+        .headers(custom_headers(config)?)
         .send()
         .await
         .map_err(|e| format!("Failed to connect to Ollama: {}. Is Ollama running?", e))?;
@@ -481,9 +1473,9 @@ async fn fetch_ollama_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
         .await
         .map_err(|e| format!("Failed to parse model list: {}", e))?;
 
-    let mut models: Vec<ModelInfo> = resp
-        .models
-        .unwrap_or_default()
+    let entries = resp.models.unwrap_or_default();
+    let fetched = entries.len();
+    let mut models: Vec<ModelInfo> = entries
         .into_iter()
         .filter_map(|m| {
             let id = m.model.or(m.name)?;
@@ -491,20 +1483,24 @@ async fn fetch_ollama_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
                 name: id.clone(),
                 id,
                 context_window: None,
+                is_favorite: false,
+                size_bytes: m.size,
             })
         })
         .collect();
 
     models.sort_by(|a, b| a.id.cmp(&b.id));
-    Ok(models)
+    Ok((models, fetched))
 }
 
 /// Fetch OpenRouter models with text output filtering
-async fn fetch_openrouter_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>, String> {
-    let client = reqwest::Client::new();
+async fn fetch_openrouter_models(
+    config: &ProviderConfig,
+) -> Result<(Vec<ModelInfo>, usize), String> {
+    let client = http_client();
     let endpoint = config.get_models_endpoint();
 
-    let mut req = client.get(&endpoint);
+    let mut req = client.get(&endpoint).headers(custom_headers(config)?);
     if let Some(api_key) = config.api_key.as_deref() {
         req = req.header("Authorization", format!("Bearer {}", api_key));
     }
@@ -525,6 +1521,7 @@ async fn fetch_openrouter_models(config: &ProviderConfig) -> Result<Vec<ModelInf
         .await
         .map_err(|e| format!("Failed to parse model list: {}", e))?;
 
+    let fetched = resp.data.len();
     let mut models: Vec<ModelInfo> = resp
         .data
         .into_iter()
@@ -540,11 +1537,62 @@ async fn fetch_openrouter_models(config: &ProviderConfig) -> Result<Vec<ModelInf
             name: m.name.unwrap_or_else(|| m.id.clone()),
             id: m.id,
             context_window: m.context_length,
+            is_favorite: false,
+            size_bytes: None,
+        })
+        .collect();
+
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok((models, fetched))
+}
+
+/// Fetch Together models. The listing mixes in embedding, image, rerank and
+/// moderation models, so only `chat` entries are kept.
+async fn fetch_together_models(config: &ProviderConfig) -> Result<(Vec<ModelInfo>, usize), String> {
+    let client = http_client();
+    let endpoint = config.get_models_endpoint();
+
+    let api_key = config
+        .api_key
+        .as_deref()
+        .ok_or_else(|| "API key not configured".to_string())?;
+
+    let response = config
+        .auth_style
+        .apply(
+            client.get(&endpoint).headers(custom_headers(config)?),
+            api_key,
+        )
+        .send()
+        .await
+        .map_err(|e| format!("Failed to connect: {}", e))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Together API error {}: {}", status, body));
+    }
+
+    let entries: Vec<TogetherModelEntry> = response
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse model list: {}", e))?;
+
+    let fetched = entries.len();
+    let mut models: Vec<ModelInfo> = entries
+        .into_iter()
+        .filter(|m| m.model_type.as_deref().is_none_or(|t| t == "chat"))
+        .map(|m| ModelInfo {
+            name: m.display_name.unwrap_or_else(|| m.id.clone()),
+            id: m.id,
+            context_window: m.context_length,
+            is_favorite: false,
+            size_bytes: None,
         })
         .collect();
 
     models.sort_by(|a, b| a.id.cmp(&b.id));
-    Ok(models)
+    Ok((models, fetched))
 }
 
 // ============================================
@@ -569,6 +1617,8 @@ struct OAuthTokenResponse {
     access_token: Option<String>,
     error: Option<String>,
     error_description: Option<String>,
+    /// New minimum polling interval, sent with `slow_down`.
+    interval: Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -587,15 +1637,12 @@ struct CopilotEndpoints {
 /// Step 1: Start GitHub Device OAuth flow.
 /// Returns device_code, user_code, and verification_uri for the user to complete in browser.
 pub async fn copilot_start_device_flow() -> Result<DeviceCodeResponse, String> {
-    let client = reqwest::Client::new();
+    let client = http_client();
 
     let response = client
         .post("https://github.com/login/device/code")
         .header("Accept", "application/json")
-        .form(&[
-            ("client_id", COPILOT_CLIENT_ID),
-            ("scope", "read:user"),
-        ])
+        .form(&[("client_id", COPILOT_CLIENT_ID), ("scope", "read:user")])
         .send()
         .await
         .map_err(|e| format!("Failed to start device flow: {}", e))?;
@@ -612,10 +1659,66 @@ pub async fn copilot_start_device_flow() -> Result<DeviceCodeResponse, String> {
         .map_err(|e| format!("Failed to parse device code response: {}", e))
 }
 
+/// Seconds GitHub adds to the polling interval on `slow_down`.
+const SLOW_DOWN_INCREMENT_SECS: u64 = 5;
+
+/// Outcome of one device-flow poll. Intervals are in seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(
+    tag = "state",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum PollState {
+    /// The user has not finished in the browser yet; poll again.
+    Pending {
+        next_interval: u64,
+    },
+    /// Polled too fast; poll again after the longer interval.
+    SlowDown {
+        next_interval: u64,
+    },
+    Denied,
+    Expired,
+    Authorized {
+        token: String,
+    },
+}
+
+impl OAuthTokenResponse {
+    fn into_poll_state(self, interval: u64) -> Result<PollState, String> {
+        if let Some(token) = self.access_token {
+            return Ok(PollState::Authorized { token });
+        }
+        let Some(error) = self.error else {
+            return Err("Unknown error during OAuth polling".to_string());
+        };
+        match error.as_str() {
+            "authorization_pending" => Ok(PollState::Pending {
+                next_interval: interval,
+            }),
+            // GitHub sends the new interval; fall back to its documented +5s.
+            "slow_down" => Ok(PollState::SlowDown {
+                next_interval: self.interval.unwrap_or(interval + SLOW_DOWN_INCREMENT_SECS),
+            }),
+            "access_denied" => Ok(PollState::Denied),
+            "expired_token" => Ok(PollState::Expired),
+            _ => Err(format!(
+                "{}:{}",
+                error,
+                self.error_description.unwrap_or_default()
+            )),
+        }
+    }
+}
+
 /// Step 2: Poll GitHub for the OAuth access token after user completes browser auth.
-/// Returns the GitHub access token on success, or an error describing the state.
-pub async fn copilot_poll_auth(device_code: &str) -> Result<String, String> {
-    let client = reqwest::Client::new();
+/// `interval` is the polling interval currently in use, in seconds.
+pub async fn copilot_poll_auth_state(
+    device_code: &str,
+    interval: u64,
+) -> Result<PollState, String> {
+    let client = http_client();
 
     let response = client
         .post("https://github.com/login/oauth/access_token")
@@ -640,27 +1743,53 @@ pub async fn copilot_poll_auth(device_code: &str) -> Result<String, String> {
         .await
         .map_err(|e| format!("Failed to parse token response: {}", e))?;
 
-    if let Some(access_token) = token_resp.access_token {
-        Ok(access_token)
-    } else if let Some(error) = token_resp.error {
-        // "authorization_pending" = user hasn't completed auth yet (keep polling)
-        // "slow_down" = polling too fast
-        // "expired_token" = device code expired
-        // "access_denied" = user denied
-        let desc = token_resp.error_description.unwrap_or_default();
-        Err(format!("{}:{}", error, desc))
+    token_resp.into_poll_state(interval)
+}
+
+/// Deprecated form of `copilot_poll_auth_state`: returns the token, or the
+/// GitHub error code as an `"<error>:<description>"` string.
+pub async fn copilot_poll_auth(device_code: &str) -> Result<String, String> {
+    match copilot_poll_auth_state(device_code, 0).await? {
+        PollState::Authorized { token } => Ok(token),
+        PollState::Pending { .. } => Err("authorization_pending:".to_string()),
+        PollState::SlowDown { .. } => Err("slow_down:".to_string()),
+        PollState::Denied => Err("access_denied:".to_string()),
+        PollState::Expired => Err("expired_token:".to_string()),
+    }
+}
+
+/// The error (and `StreamEvent::Error` message) when GitHub rejects the
+/// stored token in the Copilot token exchange: it was revoked or has expired,
+/// and the user must sign in again. The frontend matches on this text.
+pub const COPILOT_AUTH_EXPIRED: &str =
+    "GitHub Copilot sign-in has expired or was revoked. Sign in again in Settings → Providers.";
+
+/// The error for a failed Copilot token exchange. A 401 means the GitHub
+/// token itself is no longer valid, as opposed to e.g. no subscription (403).
+pub fn copilot_exchange_error(status: reqwest::StatusCode, body: &str) -> String {
+    if status == reqwest::StatusCode::UNAUTHORIZED {
+        COPILOT_AUTH_EXPIRED.to_string()
     } else {
-        Err("Unknown error during OAuth polling".to_string())
+        format!("Copilot token exchange error {}: {}", status, body)
     }
 }
 
+const COPILOT_TOKEN_URL: &str = "https://api.github.com/copilot_internal/v2/token";
+
 /// Step 3: Exchange the GitHub access token for a short-lived Copilot API token.
 /// Returns (copilot_token, api_base_url).
 pub async fn copilot_exchange_token(github_token: &str) -> Result<(String, String), String> {
-    let client = reqwest::Client::new();
+    exchange_copilot_token_at(COPILOT_TOKEN_URL, github_token).await
+}
+
+async fn exchange_copilot_token_at(
+    token_url: &str,
+    github_token: &str,
+) -> Result<(String, String), String> {
+    let client = http_client();
 
     let response = client
-        .get("https://api.github.com/copilot_internal/v2/token")
+        .get(token_url)
         .header("Authorization", format!("token {}", github_token))
         .header("User-Agent", "Zitong/1.0")
         .header("Accept", "application/json")
@@ -671,7 +1800,7 @@ pub async fn copilot_exchange_token(github_token: &str) -> Result<(String, Strin
     if !response.status().is_success() {
         let status = response.status();
         let body = response.text().await.unwrap_or_default();
-        return Err(format!("Copilot token exchange error {}: {}", status, body));
+        return Err(copilot_exchange_error(status, &body));
     }
 
     let token_resp: CopilotTokenResponse = response
@@ -704,33 +1833,56 @@ async fn stream_github_copilot(
         .as_deref()
         .ok_or_else(|| "GitHub Copilot not authenticated. Sign in first.".to_string())?;
 
-    // Exchange for a short-lived Copilot API token
-    let (copilot_token, base_url) = copilot_exchange_token(github_token).await?;
+    // Exchange for a short-lived Copilot API token. An expired sign-in is
+    // reported as a stream error like other rejected requests, so the UI can
+    // recognize it and ask the user to sign in again.
+    let (copilot_token, base_url) = match copilot_exchange_token(github_token).await {
+        Ok(exchanged) => exchanged,
+        Err(e) if e == COPILOT_AUTH_EXPIRED => {
+            on_event(StreamEvent::error(e));
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
 
-    let client = reqwest::Client::new();
+    let client = http_client();
     let endpoint = format!("{}/chat/completions", base_url);
 
-    let body = serde_json::json!({
+    let mut body = serde_json::json!({
         "model": config.model,
         "messages": messages,
         "stream": true,
     });
+    if let Some(max_tokens) = config.max_tokens {
+        body["max_tokens"] = max_tokens.into();
+    }
+    if let Some(temperature) = config.temperature {
+        body["temperature"] = temperature.into();
+    }
 
     let builder = client
         .post(&endpoint)
+        .headers(custom_headers(config)?)
         .header("Content-Type", "application/json")
         .header("Authorization", format!("Bearer {}", copilot_token))
         .header("Copilot-Integration-Id", "vscode-chat")
         .header("Editor-Version", "Zitong/1.0")
         .json(&body);
 
+    trace_request(config, &builder);
     let mut es = EventSource::new(builder).map_err(|e| e.to_string())?;
     let mut total_tokens: i64 = 0;
 
-    while let Some(event_result) = es.next().await {
+    loop {
+        let event_result = match next_event(&mut es, config, on_event).await {
+            Ok(Some(event_result)) => event_result,
+            Ok(None) => break,
+            Err(_) => return Ok(()),
+        };
         match event_result {
             Ok(Event::Open) => {}
             Ok(Event::Message(msg)) => {
+                trace_response(config, &msg.data);
                 if msg.data == "[DONE]" {
                     break;
                 }
@@ -742,19 +1894,15 @@ async fn stream_github_copilot(
                                 content: content.clone(),
                             });
                         }
-                        if choice.finish_reason.is_some() {
-                            if let Some(usage) = &chunk.usage {
-                                total_tokens = usage.total_tokens.unwrap_or(0);
-                            }
-                        }
+                    }
+                    if let Some(tokens) = chunk.usage.as_ref().and_then(|u| u.total_tokens) {
+                        total_tokens = tokens;
                     }
                 }
             }
             Err(err) => {
-                on_event(StreamEvent::Error {
-                    message: format!("Stream error: {}", err),
-                });
                 es.close();
+                on_event(event_source_error(config, err).await);
                 return Ok(());
             }
         }
@@ -765,22 +1913,23 @@ async fn stream_github_copilot(
 }
 
 /// Fetch models for GitHub Copilot — exchanges token first, then fetches models.
-async fn fetch_copilot_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>, String> {
+async fn fetch_copilot_models(config: &ProviderConfig) -> Result<(Vec<ModelInfo>, usize), String> {
     let github_token = config
         .api_key
         .as_deref()
         .ok_or_else(|| "GitHub Copilot not authenticated. Sign in first.".to_string())?;
 
-    eprintln!("[Copilot] Exchanging GitHub token ({} chars) for Copilot token...", github_token.len());
+    tracing::debug!("[Copilot] Exchanging GitHub token for Copilot token...");
     let (copilot_token, base_url) = copilot_exchange_token(github_token).await?;
-    eprintln!("[Copilot] Token exchanged OK, base_url={}", base_url);
+    tracing::debug!("[Copilot] Token exchanged OK, base_url={}", base_url);
 
-    let client = reqwest::Client::new();
+    let client = http_client();
     let endpoint = format!("{}/models", base_url);
-    eprintln!("[Copilot] Fetching models from {}", endpoint);
+    tracing::debug!("[Copilot] Fetching models from {}", endpoint);
 
     let response = client
         .get(&endpoint)
+        .headers(custom_headers(config)?)
         .header("Authorization", format!("Bearer {}", copilot_token))
         .header("Copilot-Integration-Id", "vscode-chat")
         .header("User-Agent", "Zitong/1.0")
@@ -790,21 +1939,28 @@ async fn fetch_copilot_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
         .map_err(|e| format!("Failed to fetch Copilot models: {}", e))?;
 
     let status = response.status();
-    eprintln!("[Copilot] Models response status={}", status);
+    tracing::debug!("[Copilot] Models response status={}", status);
 
     if !status.is_success() {
         let body = response.text().await.unwrap_or_default();
-        eprintln!("[Copilot] Models error body: {}", body);
+        tracing::debug!("[Copilot] Models error body: {}", body);
         return Err(format!("Copilot models error {}: {}", status, body));
     }
 
     // Try OpenAI-compatible format first
     let text = response.text().await.map_err(|e| e.to_string())?;
-    eprintln!("[Copilot] Models response length: {} bytes", text.len());
-    eprintln!("[Copilot] Models response (first 300 chars): {}", &text[..text.len().min(300)]);
+    tracing::debug!("[Copilot] Models response length: {} bytes", text.len());
+    tracing::debug!(
+        "[Copilot] Models response (first 300 chars): {}",
+        text.chars().take(300).collect::<String>()
+    );
 
     if let Ok(resp) = serde_json::from_str::<OpenAIModelsResponse>(&text) {
-        eprintln!("[Copilot] Parsed as OpenAI format, {} models", resp.data.len());
+        tracing::debug!(
+            "[Copilot] Parsed as OpenAI format, {} models",
+            resp.data.len()
+        );
+        let fetched = resp.data.len();
         let mut models: Vec<ModelInfo> = resp
             .data
             .into_iter()
@@ -816,14 +1972,17 @@ async fn fetch_copilot_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
                 name: m.id.clone(),
                 id: m.id,
                 context_window: None,
+                is_favorite: false,
+                size_bytes: None,
             })
             .collect();
         models.sort_by(|a, b| a.id.cmp(&b.id));
-        return Ok(models);
+        return Ok((models, fetched));
     }
 
     // Try as a JSON array
     if let Ok(entries) = serde_json::from_str::<Vec<serde_json::Value>>(&text) {
+        let fetched = entries.len();
         let mut models: Vec<ModelInfo> = entries
             .into_iter()
             .filter_map(|v| {
@@ -835,39 +1994,582 @@ async fn fetch_copilot_models(config: &ProviderConfig) -> Result<Vec<ModelInfo>,
                         .unwrap_or(&id)
                         .to_string(),
                     id,
-                    context_window: v
-                        .get("context_window")
-                        .and_then(|c| c.as_i64()),
+                    context_window: v.get("context_window").and_then(|c| c.as_i64()),
+                    is_favorite: false,
+                    size_bytes: None,
                 })
             })
             .collect();
         models.sort_by(|a, b| a.id.cmp(&b.id));
-        return Ok(models);
+        return Ok((models, fetched));
     }
 
-    Err(format!("Unexpected Copilot models response format: {}", &text[..text.len().min(200)]))
+    Err(format!(
+        "Unexpected Copilot models response format: {}",
+        &text[..text.len().min(200)]
+    ))
 }
 
 // ============================================
 // Streaming Chat Completion
 // ============================================
 
+/// Default idle window before a silent stream is considered stalled.
+pub const DEFAULT_STREAM_IDLE_TIMEOUT_SECS: u64 = 60;
+/// Lower bound for the idle window so a too-small setting can't trip on
+/// ordinary pauses (e.g. a reasoning model thinking before its first token).
+const MIN_STREAM_IDLE_TIMEOUT_SECS: u64 = 15;
+
+/// Convert the user-facing setting (seconds, `0` = disabled) into the idle
+/// window passed to `ProviderConfig`.
+pub fn stream_idle_timeout(secs: i64) -> Option<Duration> {
+    if secs <= 0 {
+        return None;
+    }
+    Some(Duration::from_secs(
+        (secs as u64).max(MIN_STREAM_IDLE_TIMEOUT_SECS),
+    ))
+}
+
+/// Cancels a stream from outside it. Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct CancelToken(Arc<tokio::sync::watch::Sender<bool>>);
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self(Arc::new(tokio::sync::watch::channel(false).0))
+    }
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once `cancel` has been called (immediately if it already was).
+    pub async fn cancelled(&self) {
+        let mut rx = self.0.subscribe();
+        // The sender lives as long as `self`, so this only returns on cancel.
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// Why `next_or_stall` stopped waiting for the stream.
+#[derive(Debug)]
+enum StreamInterrupted {
+    /// No event arrived within the idle window.
+    Stalled,
+    /// `ProviderConfig::cancel` fired.
+    Cancelled,
+}
+
+impl StreamInterrupted {
+    fn message(&self) -> String {
+        match self {
+            Self::Stalled => "stream stalled".to_string(),
+            Self::Cancelled => "Stream cancelled".to_string(),
+        }
+    }
+
+    fn kind(&self) -> StreamErrorKind {
+        match self {
+            Self::Stalled => StreamErrorKind::Stalled,
+            Self::Cancelled => StreamErrorKind::Other,
+        }
+    }
+}
+
+/// Await the next item of `stream`. The idle window restarts on every call,
+/// so any received event (including keep-alive pings) resets the timer.
+/// Returns early if `cancel` fires while waiting.
+async fn next_or_stall<S>(
+    stream: &mut S,
+    idle_timeout: Option<Duration>,
+    cancel: Option<&CancelToken>,
+) -> Result<Option<S::Item>, StreamInterrupted>
+where
+    S: Stream + Unpin,
+{
+    let next = async {
+        match idle_timeout {
+            Some(idle) => tokio::time::timeout(idle, stream.next())
+                .await
+                .map_err(|_| {
+                    tracing::warn!("[stream] no data for {}s", idle.as_secs());
+                    StreamInterrupted::Stalled
+                }),
+            None => Ok(stream.next().await),
+        }
+    };
+    match cancel {
+        Some(cancel) => tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err(StreamInterrupted::Cancelled),
+            next = next => next,
+        },
+        None => next.await,
+    }
+}
+
+/// `next_or_stall` for a provider's stream loop. An interruption has already
+/// been reported as an `Error` event when it is returned, so the loop only
+/// has to stop.
+async fn next_event<S>(
+    stream: &mut S,
+    config: &ProviderConfig,
+    on_event: &mut impl FnMut(StreamEvent),
+) -> Result<Option<S::Item>, StreamInterrupted>
+where
+    S: Stream + Unpin,
+{
+    next_or_stall(stream, config.idle_timeout, config.cancel.as_ref())
+        .await
+        .inspect_err(|interrupted| {
+            on_event(StreamEvent::Error {
+                message: interrupted.message(),
+                kind: interrupted.kind(),
+            })
+        })
+}
+
+/// The `Error` event for a failed event source. A rejected request's body is
+/// read, since it says why (e.g. that the gateway doesn't support `stream`).
+async fn event_source_error(
+    config: &ProviderConfig,
+    err: reqwest_eventsource::Error,
+) -> StreamEvent {
+    use reqwest_eventsource::Error as EsError;
+    let err = match err {
+        EsError::InvalidStatusCode(status, response) => {
+            let body = response.text().await.unwrap_or_default();
+            trace_response(config, &body);
+            return StreamEvent::Error {
+                message: format!(
+                    "Stream error: Invalid status code: {}: {}",
+                    status,
+                    body.trim()
+                ),
+                kind: StreamErrorKind::Status {
+                    code: status.as_u16(),
+                    body,
+                },
+            };
+        }
+        err => err,
+    };
+    let kind = match &err {
+        EsError::Transport(_) | EsError::StreamEnded => StreamErrorKind::Connection,
+        EsError::InvalidContentType(content_type, _) => StreamErrorKind::NotEventStream {
+            content_type: String::from_utf8_lossy(content_type.as_bytes()).into_owned(),
+        },
+        _ => StreamErrorKind::Other,
+    };
+    StreamEvent::Error {
+        message: format!("Stream error: {}", err),
+        kind,
+    }
+}
+
+/// The `Error` event for a request that couldn't be sent.
+fn send_error(err: reqwest::Error) -> StreamEvent {
+    StreamEvent::Error {
+        message: err.to_string(),
+        kind: StreamErrorKind::Connection,
+    }
+}
+
+/// Reject a history with no conversational turn (empty, or only a system
+/// prompt); several providers return an opaque 400 for such requests.
+pub fn ensure_conversation_turn(messages: &[ChatMessage]) -> Result<(), String> {
+    if messages
+        .iter()
+        .any(|m| m.role != "system" && !m.content.trim().is_empty())
+    {
+        Ok(())
+    } else if messages.is_empty() {
+        Err("Invalid input: there are no messages to send".to_string())
+    } else {
+        Err("Invalid input: a message is required besides the system prompt".to_string())
+    }
+}
+
+// ============================================
+// API key rotation
+// ============================================
+
+/// How long a key that got a 429 is passed over by key rotation.
+pub const KEY_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// `provider_keys` id → when its cooldown ends.
+fn key_cooldowns() -> &'static Mutex<HashMap<String, Instant>> {
+    static COOLDOWNS: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+    COOLDOWNS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Take a rotated key out of rotation for `KEY_COOLDOWN`.
+pub fn cool_down_key(key_id: &str) {
+    if let Ok(mut cooldowns) = key_cooldowns().lock() {
+        cooldowns.insert(key_id.to_string(), Instant::now() + KEY_COOLDOWN);
+    }
+}
+
+/// Ids of keys still cooling down.
+pub fn cooling_down_keys() -> Vec<String> {
+    let Ok(mut cooldowns) = key_cooldowns().lock() else {
+        return Vec::new();
+    };
+    let now = Instant::now();
+    cooldowns.retain(|_, until| *until > now);
+    cooldowns.keys().cloned().collect()
+}
+
+/// Cool down the key behind a request that was answered with `status`.
+fn note_rate_limit(config: &ProviderConfig, status: Option<u16>) {
+    if let (Some(key_id), Some(429)) = (&config.key_id, status) {
+        tracing::warn!("[providers] key {} rate limited, cooling down", key_id);
+        cool_down_key(key_id);
+    }
+}
+
+// ============================================
+// Capability probing
+// ============================================
+
+/// What a provider/model was found to support by `probe_capabilities`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    pub streaming: bool,
+    pub tools: bool,
+    /// Guessed from the model name (see `supports_vision`); not probed.
+    pub vision: bool,
+    /// From the provider's model list, when it reports one.
+    pub context_window: Option<i64>,
+}
+
+/// Tool offered by the tools probe; the model is never expected to call it.
+fn probe_tool() -> ToolDef {
+    ToolDef {
+        name: "get_time".to_string(),
+        description: "Get the current time.".to_string(),
+        parameters: empty_object_schema(),
+    }
+}
+
+/// Send a one-token "Hi" with `config`. Ok when the provider answered
+/// without an error event.
+async fn probe_request(config: &ProviderConfig) -> Result<(), String> {
+    let messages = [ChatMessage {
+        role: "user".to_string(),
+        content: "Hi".to_string(),
+    }];
+    let mut error = None;
+    stream_chat(config, &messages, |event| match event {
+        // A buffered retry succeeding doesn't make the streamed request work.
+        StreamEvent::Error { message, .. } | StreamEvent::StreamingDisabled { reason: message } => {
+            error.get_or_insert(message);
+        }
+        _ => {}
+    })
+    .await?;
+    error.map_or(Ok(()), Err)
+}
+
+/// Find out what `config.model` supports with a few one-token requests: one
+/// streamed, one buffered if streaming fails (OpenAI-compatible providers
+/// only), and one offering a tool where the app sends tools at all. Fails
+/// only when the model can't be reached at all.
+pub async fn probe_capabilities(config: &ProviderConfig) -> Result<Capabilities, String> {
+    let base = ProviderConfig {
+        max_tokens: Some(1),
+        tools: None,
+        supports_streaming: true,
+        ..config.clone()
+    };
+
+    let streaming = match probe_request(&base).await {
+        Ok(()) => true,
+        Err(e) if is_openai_compatible(&config.provider_type) && !config.completion_mode => {
+            let buffered = ProviderConfig {
+                supports_streaming: false,
+                ..base.clone()
+            };
+            probe_request(&buffered).await.map_err(|_| e)?;
+            false
+        }
+        Err(e) => return Err(e),
+    };
+
+    let sends_tools = config.provider_type == "anthropic"
+        || (is_openai_compatible(&config.provider_type) && !config.completion_mode);
+    let tools = sends_tools && {
+        let with_tools = ProviderConfig {
+            supports_streaming: streaming,
+            tools: Some(vec![probe_tool()]),
+            ..base.clone()
+        };
+        probe_request(&with_tools)
+            .await
+            .inspect_err(|e| tracing::debug!("[probe] tools rejected: {}", e))
+            .is_ok()
+    };
+
+    let context_window = list_provider_models(config, false)
+        .await
+        .ok()
+        .and_then(|models| models.into_iter().find(|m| m.id == config.model))
+        .and_then(|m| m.context_window);
+
+    Ok(Capabilities {
+        streaming,
+        tools,
+        vision: supports_vision(&config.provider_type, &config.model),
+        context_window,
+    })
+}
+
+/// First HTTP status code mentioned in a provider error message, in either
+/// the "API error 401 ..." or the "Invalid status code: 401 ..." form.
+pub fn http_status_in(message: &str) -> Option<u16> {
+    let lower = message.to_lowercase();
+    ["status code: ", "error "].iter().find_map(|marker| {
+        lower.match_indices(marker).find_map(|(i, _)| {
+            let rest = &lower[i + marker.len()..];
+            let digits = rest.get(..3)?;
+            let boundary = rest[3..].chars().next().is_none_or(|c| !c.is_ascii_digit());
+            (boundary && digits.bytes().all(|b| b.is_ascii_digit()))
+                .then(|| digits.parse().ok())
+                .flatten()
+        })
+    })
+}
+
+/// Whether a stream error means the endpoint doesn't accept `stream: true`:
+/// a client error whose body mentions streaming, or a plain JSON reply where
+/// an event stream was expected.
+pub fn rejects_streaming(kind: &StreamErrorKind) -> bool {
+    match kind {
+        StreamErrorKind::Status {
+            code: 400 | 415 | 422 | 501,
+            body,
+        } => body.to_lowercase().contains("stream"),
+        StreamErrorKind::NotEventStream { content_type } => {
+            content_type.to_lowercase().starts_with("application/json")
+        }
+        _ => false,
+    }
+}
+
+/// Stream a reply to `messages`. An OpenAI-compatible provider that turns
+/// out to reject streaming gets one buffered retry, announced with
+/// `StreamEvent::StreamingDisabled` before its reply.
 pub async fn stream_chat(
     config: &ProviderConfig,
     messages: &[ChatMessage],
     mut on_event: impl FnMut(StreamEvent),
 ) -> Result<(), String> {
+    ensure_reachable(config)?;
     let message_id = uuid::Uuid::new_v4().to_string();
     on_event(StreamEvent::Started {
         message_id: message_id.clone(),
     });
 
+    let mut on_event = |event: StreamEvent| {
+        if let StreamEvent::Error { kind, .. } = &event {
+            note_rate_limit(config, kind.status());
+        }
+        on_event(event);
+    };
+    let mut resume = StreamResume::new(config.reconnect_attempts);
+    let mut request = std::borrow::Cow::Borrowed(messages);
+    let mut buffered: Option<ProviderConfig> = None;
+    loop {
+        let attempt = buffered.as_ref().unwrap_or(config);
+        let can_buffer = buffered.is_none()
+            && config.supports_streaming
+            && !config.completion_mode
+            && is_openai_compatible(&config.provider_type);
+        let mut dropped = None;
+        let mut rejected = None;
+        let streamed = stream_once(attempt, &request, &mut |event| match event {
+            StreamEvent::Delta { content } => {
+                if let Some(content) = resume.delta(&content) {
+                    on_event(StreamEvent::Delta { content });
+                }
+            }
+            StreamEvent::Error { message, kind } if resume.can_resume(&kind) => {
+                dropped = Some(message);
+            }
+            StreamEvent::Error { message, kind }
+                if can_buffer && resume.text.is_empty() && rejects_streaming(&kind) =>
+            {
+                rejected = Some(message);
+            }
+            event => {
+                if let Some(content) = resume.release() {
+                    on_event(StreamEvent::Delta { content });
+                }
+                on_event(event);
+            }
+        })
+        .await;
+        if let Some(content) = resume.release() {
+            on_event(StreamEvent::Delta { content });
+        }
+        streamed.inspect_err(|e| note_rate_limit(config, http_status_in(e)))?;
+
+        if let Some(reason) = rejected {
+            tracing::warn!(
+                "[stream] {} rejected streaming, retrying buffered: {}",
+                config.provider_type,
+                reason
+            );
+            on_event(StreamEvent::StreamingDisabled { reason });
+            buffered = Some(ProviderConfig {
+                supports_streaming: false,
+                ..config.clone()
+            });
+            continue;
+        }
+
+        let Some(message) = dropped else {
+            return Ok(());
+        };
+        tracing::warn!(
+            "[stream] {} — resuming ({} attempt(s) left)",
+            message,
+            resume.attempts_left - 1
+        );
+        request = std::borrow::Cow::Owned(resume.continuation(messages));
+    }
+}
+
+async fn stream_once(
+    config: &ProviderConfig,
+    messages: &[ChatMessage],
+    on_event: &mut impl FnMut(StreamEvent),
+) -> Result<(), String> {
+    let messages = &*config.system_prompt_mode.apply(messages);
     match config.provider_type.as_str() {
-        "anthropic" => stream_anthropic(config, messages, &mut on_event).await,
-        "gemini" => stream_gemini(config, messages, &mut on_event).await,
-        "ollama" => stream_ollama(config, messages, &mut on_event).await,
-        "github_copilot" => stream_github_copilot(config, messages, &mut on_event).await,
-        _ => stream_openai_compatible(config, messages, &mut on_event).await,
+        "anthropic" => stream_anthropic(config, messages, on_event).await,
+        "gemini" => stream_gemini(config, messages, on_event).await,
+        "ollama" => stream_ollama(config, messages, on_event).await,
+        "github_copilot" => stream_github_copilot(config, messages, on_event).await,
+        _ if config.completion_mode => stream_openai_completion(config, messages, on_event).await,
+        _ if !config.supports_streaming => {
+            complete_openai_compatible(config, messages, on_event).await
+        }
+        _ => stream_openai_compatible(config, messages, on_event).await,
+    }
+}
+
+// ============================================
+// Stream resumption
+// ============================================
+
+/// Sent after the partial reply when a dropped stream is resumed.
+const CONTINUE_PROMPT: &str = "Your previous reply was cut off. Continue exactly where it \
+stopped, without repeating anything you already wrote.";
+
+/// Bytes of a resumed stream held back to find text the model repeated from
+/// before the drop.
+const RESUME_OVERLAP_WINDOW: usize = 200;
+
+/// Whether a stream error means the connection dropped mid-response (a
+/// transport failure, the stream ending early, a stall) rather than the
+/// provider rejecting the request.
+fn is_connection_drop(kind: &StreamErrorKind) -> bool {
+    matches!(kind, StreamErrorKind::Connection | StreamErrorKind::Stalled)
+}
+
+/// Reply text carried across the attempts of one `stream_chat` call.
+///
+/// A drop is resumed by resending the conversation with the partial reply
+/// and `CONTINUE_PROMPT` appended. Models often restate the last few words
+/// before going on, so the start of each continuation is held until
+/// `RESUME_OVERLAP_WINDOW` bytes have arrived (or the attempt ends) and
+/// whatever repeats the end of the reply so far is dropped. Resumes are
+/// bounded by `ProviderConfig::reconnect_attempts`.
+#[derive(Debug, Default)]
+struct StreamResume {
+    /// Text passed on so far, across attempts.
+    text: String,
+    /// Start of the current continuation; `None` outside one.
+    held: Option<String>,
+    attempts_left: u32,
+}
+
+impl StreamResume {
+    fn new(attempts: u32) -> Self {
+        Self {
+            attempts_left: attempts,
+            ..Self::default()
+        }
+    }
+
+    /// Whether an `Error` event of `kind` should be resumed instead of
+    /// reported.
+    fn can_resume(&self, kind: &StreamErrorKind) -> bool {
+        self.attempts_left > 0 && is_connection_drop(kind)
+    }
+
+    /// Messages for the next attempt, counting it against the budget.
+    fn continuation(&mut self, messages: &[ChatMessage]) -> Vec<ChatMessage> {
+        self.attempts_left = self.attempts_left.saturating_sub(1);
+        let mut messages = messages.to_vec();
+        if !self.text.is_empty() {
+            self.held = Some(String::new());
+            messages.push(ChatMessage {
+                role: "assistant".to_string(),
+                content: self.text.clone(),
+            });
+            messages.push(ChatMessage {
+                role: "user".to_string(),
+                content: CONTINUE_PROMPT.to_string(),
+            });
+        }
+        messages
+    }
+
+    /// The part of a delta to pass on now, if any.
+    fn delta(&mut self, content: &str) -> Option<String> {
+        match &mut self.held {
+            Some(held) => {
+                held.push_str(content);
+                if held.len() < RESUME_OVERLAP_WINDOW {
+                    return None;
+                }
+                self.release()
+            }
+            None => {
+                self.text.push_str(content);
+                Some(content.to_string())
+            }
+        }
+    }
+
+    /// End the hold on the current continuation, returning what it adds
+    /// after any text repeated from the reply so far.
+    fn release(&mut self) -> Option<String> {
+        let held = self.held.take()?;
+        let overlap = (1..=held.len().min(self.text.len()))
+            .rev()
+            .filter(|&n| held.is_char_boundary(n))
+            .find(|&n| {
+                let start = self.text.len() - n;
+                self.text.ends_with(&held[..n])
+                    && (held.starts_with(char::is_whitespace)
+                        || self.text[..start].is_empty()
+                        || self.text[..start].ends_with(char::is_whitespace))
+            })
+            .unwrap_or(0);
+        let added = &held[overlap..];
+        self.text.push_str(added);
+        (!added.is_empty()).then(|| added.to_string())
     }
 }
 
@@ -880,6 +2582,34 @@ struct OpenAIRequest<'a> {
     model: &'a str,
     messages: &'a [ChatMessage],
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<&'a str>,
+}
+
+/// `tools` in OpenAI's function-calling format.
+fn openai_tools(config: &ProviderConfig) -> Option<Vec<serde_json::Value>> {
+    let tools = config.tools.as_ref().filter(|tools| !tools.is_empty())?;
+    Some(
+        tools
+            .iter()
+            .map(|tool| {
+                serde_json::json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.parameters,
+                    }
+                })
+            })
+            .collect(),
+    )
 }
 
 #[derive(Deserialize)]
@@ -897,6 +2627,20 @@ struct OpenAIStreamChoice {
 #[derive(Deserialize)]
 struct OpenAIDelta {
     content: Option<String>,
+    tool_calls: Option<Vec<OpenAIToolCallDelta>>,
+}
+
+#[derive(Deserialize)]
+struct OpenAIToolCallDelta {
+    index: Option<usize>,
+    id: Option<String>,
+    function: Option<OpenAIFunctionDelta>,
+}
+
+#[derive(Deserialize)]
+struct OpenAIFunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -909,7 +2653,7 @@ async fn stream_openai_compatible(
     messages: &[ChatMessage],
     on_event: &mut impl FnMut(StreamEvent),
 ) -> Result<(), String> {
-    let client = reqwest::Client::new();
+    let client = http_client();
     let endpoint = config.get_endpoint();
 
     let api_key = config
@@ -917,26 +2661,44 @@ async fn stream_openai_compatible(
         .as_deref()
         .ok_or_else(|| "API key not configured".to_string())?;
 
-    let body = OpenAIRequest {
-        model: &config.model,
-        messages,
-        stream: true,
-    };
-
-    let builder = client
-        .post(&endpoint)
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&body);
-
+    let body = request_body(
+        config,
+        &OpenAIRequest {
+            model: &config.model,
+            messages,
+            stream: true,
+            tools: openai_tools(config),
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+            user: config.user.as_deref(),
+        },
+    )?;
+
+    let builder = config.auth_style.apply(
+        client
+            .post(&endpoint)
+            .headers(custom_headers(config)?)
+            .header("Content-Type", "application/json")
+            .json(&body),
+        api_key,
+    );
+
+    trace_request(config, &builder);
     let mut es = EventSource::new(builder).map_err(|e| e.to_string())?;
 
     let mut total_tokens: i64 = 0;
+    let mut tool_calls = ToolCallAccumulator::default();
 
-    while let Some(event_result) = es.next().await {
+    loop {
+        let event_result = match next_event(&mut es, config, on_event).await {
+            Ok(Some(event_result)) => event_result,
+            Ok(None) => break,
+            Err(_) => return Ok(()),
+        };
         match event_result {
             Ok(Event::Open) => {}
             Ok(Event::Message(msg)) => {
+                trace_response(config, &msg.data);
                 if msg.data == "[DONE]" {
                     break;
                 }
@@ -948,66 +2710,63 @@ async fn stream_openai_compatible(
                                 content: content.clone(),
                             });
                         }
+                        for call in choice.delta.tool_calls.iter().flatten() {
+                            let function = call.function.as_ref();
+                            tool_calls.push(
+                                call.index.unwrap_or(0),
+                                call.id.as_deref(),
+                                function.and_then(|f| f.name.as_deref()),
+                                function.and_then(|f| f.arguments.as_deref()),
+                            );
+                        }
                         if choice.finish_reason.is_some() {
-                            if let Some(usage) = &chunk.usage {
-                                total_tokens = usage.total_tokens.unwrap_or(0);
-                            }
+                            tool_calls.emit_all(on_event);
                         }
                     }
+                    // Usage comes with the finishing choice, or in a final
+                    // chunk of its own with no choices.
+                    if let Some(tokens) = chunk.usage.as_ref().and_then(|u| u.total_tokens) {
+                        total_tokens = tokens;
+                    }
                 }
             }
             Err(err) => {
-                on_event(StreamEvent::Error {
-                    message: format!("Stream error: {}", err),
-                });
                 es.close();
+                on_event(event_source_error(config, err).await);
                 return Ok(());
             }
         }
     }
 
+    tool_calls.emit_all(on_event);
     on_event(StreamEvent::Done { total_tokens });
     Ok(())
 }
 
-// ============================================
-// Anthropic streaming
-// ============================================
-
-#[derive(Serialize)]
-#[allow(dead_code)]
-struct AnthropicRequest<'a> {
-    model: &'a str,
-    messages: &'a [ChatMessage],
-    max_tokens: i64,
-    stream: bool,
-}
-
 #[derive(Deserialize)]
-struct AnthropicStreamEvent {
-    #[serde(rename = "type")]
-    event_type: String,
-    delta: Option<AnthropicDelta>,
-    usage: Option<AnthropicUsage>,
+struct OpenAICompletionResponse {
+    choices: Vec<OpenAICompletionChoice>,
+    usage: Option<OpenAIUsage>,
 }
 
 #[derive(Deserialize)]
-struct AnthropicDelta {
-    text: Option<String>,
+struct OpenAICompletionChoice {
+    message: OpenAICompletionMessage,
 }
 
 #[derive(Deserialize)]
-struct AnthropicUsage {
-    output_tokens: Option<i64>,
-    input_tokens: Option<i64>,
+struct OpenAICompletionMessage {
+    content: Option<String>,
 }
 
-async fn stream_anthropic(
+/// Buffered counterpart of `stream_openai_compatible` for gateways that
+/// reject `stream: true`. Emits the whole reply as one `Delta`.
+async fn complete_openai_compatible(
     config: &ProviderConfig,
     messages: &[ChatMessage],
     on_event: &mut impl FnMut(StreamEvent),
 ) -> Result<(), String> {
-    let client = reqwest::Client::new();
+    let client = http_client();
     let endpoint = config.get_endpoint();
 
     let api_key = config
@@ -1015,37 +2774,389 @@ async fn stream_anthropic(
         .as_deref()
         .ok_or_else(|| "API key not configured".to_string())?;
 
-    // Filter out system messages and extract system prompt
-    let system_messages: Vec<&ChatMessage> = messages.iter().filter(|m| m.role == "system").collect();
-    let chat_messages: Vec<&ChatMessage> = messages.iter().filter(|m| m.role != "system").collect();
-
-    let mut body = serde_json::json!({
-        "model": config.model,
-        "messages": chat_messages,
-        "max_tokens": 4096,
-        "stream": true,
-    });
-
-    if let Some(system_msg) = system_messages.first() {
-        body["system"] = serde_json::Value::String(system_msg.content.clone());
-    }
+    let body = request_body(
+        config,
+        &OpenAIRequest {
+            model: &config.model,
+            messages,
+            stream: false,
+            tools: openai_tools(config),
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+            user: config.user.as_deref(),
+        },
+    )?;
+
+    let builder = config.auth_style.apply(
+        client
+            .post(&endpoint)
+            .headers(custom_headers(config)?)
+            .header("Content-Type", "application/json")
+            .json(&body),
+        api_key,
+    );
+    trace_request(config, &builder);
+    let response = match builder.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            on_event(send_error(e));
+            return Ok(());
+        }
+    };
 
-    let builder = client
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    trace_response(config, &text);
+    if !status.is_success() {
+        on_event(StreamEvent::Error {
+            message: format!("API error {}: {}", status, text),
+            kind: StreamErrorKind::Status {
+                code: status.as_u16(),
+                body: text,
+            },
+        });
+        return Ok(());
+    }
+
+    let completion: OpenAICompletionResponse =
+        serde_json::from_str(&text).map_err(|e| format!("Failed to parse response: {}", e))?;
+    let content: String = completion
+        .choices
+        .into_iter()
+        .filter_map(|choice| choice.message.content)
+        .collect();
+    if !content.is_empty() {
+        on_event(StreamEvent::Delta { content });
+    }
+
+    let total_tokens = completion
+        .usage
+        .and_then(|usage| usage.total_tokens)
+        .unwrap_or(0);
+    on_event(StreamEvent::Done { total_tokens });
+    Ok(())
+}
+
+// ============================================
+// Legacy text completion (`/completions`)
+// ============================================
+
+/// Speaker label used for each role when flattening a chat into a prompt.
+fn completion_role_label(role: &str) -> &str {
+    match role {
+        "system" => "System",
+        "assistant" => "Assistant",
+        "user" => "User",
+        other => other,
+    }
+}
+
+/// Flatten a chat history into one role-labeled prompt for servers that only
+/// expose `/completions`. Turns are separated by a blank line and the prompt
+/// ends with an open `Assistant:` turn for the model to complete.
+pub fn flatten_completion_prompt(messages: &[ChatMessage]) -> String {
+    let mut prompt = String::new();
+    for message in messages {
+        prompt.push_str(completion_role_label(&message.role));
+        prompt.push_str(": ");
+        prompt.push_str(message.content.trim());
+        prompt.push_str("\n\n");
+    }
+    prompt.push_str("Assistant:");
+    prompt
+}
+
+#[derive(Serialize)]
+struct LegacyCompletionRequest<'a> {
+    model: &'a str,
+    prompt: String,
+    stream: bool,
+    /// Stop before the model starts writing the user's next turn.
+    stop: [&'a str; 1],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct LegacyCompletionChunk {
+    choices: Vec<LegacyCompletionChoice>,
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Deserialize)]
+struct LegacyCompletionChoice {
+    text: Option<String>,
+}
+
+/// Text and total token count (when reported) from one legacy completion
+/// stream chunk. `None` if the line is not a completion chunk.
+pub fn parse_completion_chunk(data: &str) -> Option<(String, Option<i64>)> {
+    let chunk: LegacyCompletionChunk = serde_json::from_str(data).ok()?;
+    let text = chunk
+        .choices
+        .into_iter()
+        .filter_map(|choice| choice.text)
+        .collect();
+    Some((text, chunk.usage.and_then(|usage| usage.total_tokens)))
+}
+
+async fn stream_openai_completion(
+    config: &ProviderConfig,
+    messages: &[ChatMessage],
+    on_event: &mut impl FnMut(StreamEvent),
+) -> Result<(), String> {
+    let client = http_client();
+    let chat_endpoint = config.get_endpoint();
+    let endpoint = match chat_endpoint.strip_suffix("/chat/completions") {
+        Some(base) => format!("{}/completions", base),
+        None => chat_endpoint,
+    };
+
+    let api_key = config
+        .api_key
+        .as_deref()
+        .ok_or_else(|| "API key not configured".to_string())?;
+
+    let body = request_body(
+        config,
+        &LegacyCompletionRequest {
+            model: &config.model,
+            prompt: flatten_completion_prompt(messages),
+            stream: true,
+            stop: ["\nUser:"],
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+            user: config.user.as_deref(),
+        },
+    )?;
+
+    let builder = config.auth_style.apply(
+        client
+            .post(&endpoint)
+            .headers(custom_headers(config)?)
+            .header("Content-Type", "application/json")
+            .json(&body),
+        api_key,
+    );
+
+    trace_request(config, &builder);
+    let mut es = EventSource::new(builder).map_err(|e| e.to_string())?;
+
+    let mut total_tokens: i64 = 0;
+
+    loop {
+        let event_result = match next_event(&mut es, config, on_event).await {
+            Ok(Some(event_result)) => event_result,
+            Ok(None) => break,
+            Err(_) => return Ok(()),
+        };
+        match event_result {
+            Ok(Event::Open) => {}
+            Ok(Event::Message(msg)) => {
+                trace_response(config, &msg.data);
+                if msg.data == "[DONE]" {
+                    break;
+                }
+
+                if let Some((text, tokens)) = parse_completion_chunk(&msg.data) {
+                    if !text.is_empty() {
+                        on_event(StreamEvent::Delta { content: text });
+                    }
+                    if let Some(tokens) = tokens {
+                        total_tokens = tokens;
+                    }
+                }
+            }
+            Err(err) => {
+                es.close();
+                on_event(event_source_error(config, err).await);
+                return Ok(());
+            }
+        }
+    }
+
+    on_event(StreamEvent::Done { total_tokens });
+    Ok(())
+}
+
+// ============================================
+// Anthropic streaming
+// ============================================
+
+#[derive(Serialize)]
+#[allow(dead_code)]
+struct AnthropicRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    max_tokens: i64,
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct AnthropicStreamEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    index: Option<usize>,
+    content_block: Option<AnthropicContentBlock>,
+    delta: Option<AnthropicDelta>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicContentBlock {
+    #[serde(rename = "type")]
+    block_type: String,
+    id: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicDelta {
+    text: Option<String>,
+    /// Fragment of a `tool_use` block's input (`input_json_delta`).
+    partial_json: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+    output_tokens: Option<i64>,
+    input_tokens: Option<i64>,
+}
+
+/// Output limit for Anthropic models when the provider sets none. The API
+/// requires `max_tokens`, so pick what each model generation supports.
+fn anthropic_default_max_tokens(model: &str) -> i64 {
+    if model.starts_with("claude-3-7") {
+        // Claude 3.7, 4 and later allow at least this much output.
+        32000
+    } else if model.starts_with("claude-3-5") {
+        8192
+    } else if ["claude-3-", "claude-2", "claude-instant"]
+        .iter()
+        .any(|prefix| model.starts_with(prefix))
+    {
+        4096
+    } else {
+        32000
+    }
+}
+
+/// Request body for Anthropic's Messages API.
+fn anthropic_request_body(config: &ProviderConfig, messages: &[ChatMessage]) -> serde_json::Value {
+    // Filter out system messages and extract system prompt
+    let system_messages: Vec<&ChatMessage> =
+        messages.iter().filter(|m| m.role == "system").collect();
+    let chat_messages: Vec<&ChatMessage> = messages.iter().filter(|m| m.role != "system").collect();
+
+    let mut body = serde_json::json!({
+        "model": config.model,
+        "messages": chat_messages,
+        "max_tokens": config
+            .max_tokens
+            .unwrap_or_else(|| anthropic_default_max_tokens(&config.model)),
+        "stream": true,
+    });
+    // The API rejects a final assistant turn ending in whitespace.
+    if let Some(prefill) = anthropic_prefill(messages) {
+        body["messages"][chat_messages.len() - 1]["content"] = prefill.into();
+    }
+    if let Some(temperature) = config.temperature {
+        body["temperature"] = temperature.into();
+    }
+
+    if let Some(system_msg) = system_messages.first() {
+        body["system"] = serde_json::Value::String(system_msg.content.clone());
+    }
+
+    if let Some(tools) = config.tools.as_ref().filter(|tools| !tools.is_empty()) {
+        body["tools"] = tools
+            .iter()
+            .map(|tool| {
+                serde_json::json!({
+                    "name": tool.name,
+                    "description": tool.description,
+                    "input_schema": tool.parameters,
+                })
+            })
+            .collect();
+    }
+
+    merge_extra_body(body, config.extra_body.as_ref())
+}
+
+/// Text of a trailing assistant message, which Anthropic treats as the start
+/// of its reply (a prefill) and continues from.
+fn anthropic_prefill(messages: &[ChatMessage]) -> Option<&str> {
+    let last = messages.iter().rev().find(|m| m.role != "system")?;
+    let prefill = last.content.trim_end();
+    (last.role == "assistant" && !prefill.is_empty()).then_some(prefill)
+}
+
+async fn stream_anthropic(
+    config: &ProviderConfig,
+    messages: &[ChatMessage],
+    on_event: &mut impl FnMut(StreamEvent),
+) -> Result<(), String> {
+    let client = http_client();
+    let endpoint = config.get_endpoint();
+
+    let api_key = config
+        .api_key
+        .as_deref()
+        .ok_or_else(|| "API key not configured".to_string())?;
+
+    let body = anthropic_request_body(config, messages);
+
+    let builder = client
         .post(&endpoint)
+        .headers(custom_headers(config)?)
         .header("Content-Type", "application/json")
         .header("x-api-key", api_key)
         .header("anthropic-version", "2023-06-01")
         .json(&body);
 
+    trace_request(config, &builder);
     let mut es = EventSource::new(builder).map_err(|e| e.to_string())?;
     let mut total_tokens: i64 = 0;
+    let mut tool_calls = ToolCallAccumulator::default();
+    // Shown as the start of the reply, since the API only sends what follows.
+    let mut prefill = anthropic_prefill(messages);
 
-    while let Some(event_result) = es.next().await {
+    loop {
+        let event_result = match next_event(&mut es, config, on_event).await {
+            Ok(Some(event_result)) => event_result,
+            Ok(None) => break,
+            Err(_) => return Ok(()),
+        };
         match event_result {
-            Ok(Event::Open) => {}
+            Ok(Event::Open) => {
+                if let Some(prefill) = prefill.take() {
+                    on_event(StreamEvent::Delta {
+                        content: prefill.to_string(),
+                    });
+                }
+            }
             Ok(Event::Message(msg)) => {
+                trace_response(config, &msg.data);
                 if let Ok(event) = serde_json::from_str::<AnthropicStreamEvent>(&msg.data) {
+                    let index = event.index.unwrap_or(0);
                     match event.event_type.as_str() {
+                        "content_block_start" => {
+                            if let Some(block) = &event.content_block {
+                                if block.block_type == "tool_use" {
+                                    tool_calls.push(
+                                        index,
+                                        block.id.as_deref(),
+                                        block.name.as_deref(),
+                                        None,
+                                    );
+                                }
+                            }
+                        }
                         "content_block_delta" => {
                             if let Some(delta) = &event.delta {
                                 if let Some(text) = &delta.text {
@@ -1053,6 +3164,14 @@ async fn stream_anthropic(
                                         content: text.clone(),
                                     });
                                 }
+                                if let Some(partial) = &delta.partial_json {
+                                    tool_calls.push(index, None, None, Some(partial));
+                                }
+                            }
+                        }
+                        "content_block_stop" => {
+                            if let Some(call) = tool_calls.finish(index) {
+                                on_event(call.into());
                             }
                         }
                         "message_delta" => {
@@ -1067,15 +3186,14 @@ async fn stream_anthropic(
                 }
             }
             Err(err) => {
-                on_event(StreamEvent::Error {
-                    message: format!("Stream error: {}", err),
-                });
                 es.close();
+                on_event(event_source_error(config, err).await);
                 return Ok(());
             }
         }
     }
 
+    tool_calls.emit_all(on_event);
     on_event(StreamEvent::Done { total_tokens });
     Ok(())
 }
@@ -1085,8 +3203,20 @@ async fn stream_anthropic(
 // ============================================
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiRequest {
     contents: Vec<GeminiContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GeminiGenerationConfig>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiGenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
 }
 
 #[derive(Serialize)]
@@ -1101,8 +3231,16 @@ struct GeminiPart {
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiStreamChunk {
     candidates: Option<Vec<GeminiCandidate>>,
+    prompt_feedback: Option<GeminiPromptFeedback>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiPromptFeedback {
+    block_reason: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -1120,12 +3258,71 @@ struct GeminiResponsePart {
     text: Option<String>,
 }
 
+/// Parse one Gemini payload into its chunks. The SSE endpoint sends one object
+/// per event, but the response can also arrive array-framed: a leading `[`, a
+/// trailing `]` and comma separators, either split across events or as one
+/// JSON array body. Anything that doesn't parse yields no chunks.
+fn gemini_chunks(data: &str) -> Vec<GeminiStreamChunk> {
+    let body = data
+        .trim_start_matches(|c: char| c == '[' || c == ',' || c.is_whitespace())
+        .trim_end_matches(|c: char| c == ']' || c == ',' || c.is_whitespace());
+    if body.is_empty() {
+        return Vec::new();
+    }
+    serde_json::from_str::<GeminiStreamChunk>(body)
+        .map(|chunk| vec![chunk])
+        .or_else(|_| serde_json::from_str::<Vec<GeminiStreamChunk>>(&format!("[{}]", body)))
+        .unwrap_or_default()
+}
+
+/// Forward a chunk's text parts as deltas, skipping empty ones so chunks that
+/// only carry `safetyRatings` or a `finishReason` emit nothing. Returns the
+/// block reason when Gemini refused the prompt outright.
+fn emit_gemini_chunk(
+    chunk: &GeminiStreamChunk,
+    on_event: &mut impl FnMut(StreamEvent),
+) -> Option<String> {
+    let texts = chunk
+        .candidates
+        .iter()
+        .flatten()
+        .filter_map(|candidate| candidate.content.as_ref())
+        .filter_map(|content| content.parts.as_ref())
+        .flatten()
+        .filter_map(|part| part.text.as_deref())
+        .filter(|text| !text.is_empty());
+    for text in texts {
+        on_event(StreamEvent::Delta {
+            content: text.to_string(),
+        });
+    }
+    chunk
+        .prompt_feedback
+        .as_ref()
+        .and_then(|feedback| feedback.block_reason.clone())
+}
+
+/// Emit every chunk in a Gemini payload. Returns false once the prompt was
+/// blocked, after reporting it as an error.
+fn emit_gemini_payload(data: &str, on_event: &mut impl FnMut(StreamEvent)) -> bool {
+    for chunk in gemini_chunks(data) {
+        if let Some(reason) = emit_gemini_chunk(&chunk, on_event) {
+            on_event(StreamEvent::error(format!(
+                "Gemini blocked the prompt: {}",
+                reason
+            )));
+            return false;
+        }
+    }
+    true
+}
+
 async fn stream_gemini(
     config: &ProviderConfig,
     messages: &[ChatMessage],
     on_event: &mut impl FnMut(StreamEvent),
 ) -> Result<(), String> {
-    let client = reqwest::Client::new();
+    let client = http_client();
     let api_key = config
         .api_key
         .as_deref()
@@ -1148,43 +3345,67 @@ async fn stream_gemini(
             }],
         })
         .collect();
+    // Gemini rejects an empty `contents`; system prompts are not sent to it.
+    if contents.is_empty() {
+        return Err("Invalid input: Gemini needs at least one user or model message".to_string());
+    }
 
-    let body = GeminiRequest { contents };
+    let body = GeminiRequest {
+        contents,
+        generation_config: (config.max_tokens.is_some() || config.temperature.is_some()).then_some(
+            GeminiGenerationConfig {
+                max_output_tokens: config.max_tokens,
+                temperature: config.temperature,
+            },
+        ),
+    };
 
     let builder = client
         .post(&endpoint)
+        .headers(custom_headers(config)?)
         .header("Content-Type", "application/json")
         .json(&body);
 
+    trace_request(config, &builder);
     let mut es = EventSource::new(builder).map_err(|e| e.to_string())?;
 
-    while let Some(event_result) = es.next().await {
+    loop {
+        let event_result = match next_event(&mut es, config, on_event).await {
+            Ok(Some(event_result)) => event_result,
+            Ok(None) => break,
+            Err(_) => return Ok(()),
+        };
         match event_result {
             Ok(Event::Open) => {}
             Ok(Event::Message(msg)) => {
-                if let Ok(chunk) = serde_json::from_str::<GeminiStreamChunk>(&msg.data) {
-                    if let Some(candidates) = &chunk.candidates {
-                        for candidate in candidates {
-                            if let Some(content) = &candidate.content {
-                                if let Some(parts) = &content.parts {
-                                    for part in parts {
-                                        if let Some(text) = &part.text {
-                                            on_event(StreamEvent::Delta {
-                                                content: text.clone(),
-                                            });
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+                trace_response(config, &msg.data);
+                if !emit_gemini_payload(&msg.data, on_event) {
+                    es.close();
+                    return Ok(());
+                }
+            }
+            // Without `alt=sse` Gemini answers with a plain JSON array rather
+            // than an event stream; read it whole instead of failing.
+            Err(reqwest_eventsource::Error::InvalidContentType(_, response))
+                if response.status().is_success() =>
+            {
+                es.close();
+                let data = response.text().await.map_err(|e| e.to_string())?;
+                trace_response(config, &data);
+                if !emit_gemini_payload(&data, on_event) {
+                    return Ok(());
                 }
+                break;
+            }
+            // Gemini has no terminal event; the server closing the stream is
+            // the end of the reply.
+            Err(reqwest_eventsource::Error::StreamEnded) => {
+                es.close();
+                break;
             }
             Err(err) => {
-                on_event(StreamEvent::Error {
-                    message: format!("Stream error: {}", err),
-                });
                 es.close();
+                on_event(event_source_error(config, err).await);
                 return Ok(());
             }
         }
@@ -1203,6 +3424,16 @@ struct OllamaRequest<'a> {
     model: &'a str,
     messages: &'a [ChatMessage],
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
 }
 
 #[derive(Deserialize)]
@@ -1216,77 +3447,1667 @@ struct OllamaMessage {
     content: Option<String>,
 }
 
+/// Incremental decoder for Ollama's NDJSON stream. Bytes are buffered until
+/// a newline arrives, so objects (and multi-byte characters) split across
+/// chunks survive. Each newline-terminated line is one complete object; a
+/// line that fails to parse is skipped.
+#[derive(Default)]
+struct NdjsonDecoder {
+    buffer: Vec<u8>,
+}
+
+impl NdjsonDecoder {
+    fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Next complete object, or `None` until more data arrives.
+    fn next_object<T: serde::de::DeserializeOwned>(&mut self) -> Option<T> {
+        loop {
+            let end = self.buffer.iter().position(|&b| b == b'\n')?;
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            // trim_ascii also strips the '\r' of CRLF line endings.
+            let line = line.trim_ascii();
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_slice::<T>(line) {
+                Ok(value) => return Some(value),
+                Err(e) => tracing::debug!("[ollama] skipping malformed stream line: {}", e),
+            }
+        }
+    }
+
+    /// Parse whatever is left once the stream has ended (a final object
+    /// without a trailing newline).
+    fn finish<T: serde::de::DeserializeOwned>(&mut self) -> Option<T> {
+        let value = serde_json::from_slice::<T>(self.buffer.trim_ascii()).ok();
+        self.buffer.clear();
+        value
+    }
+}
+
+/// Forward one Ollama chunk; returns true once the response is done.
+fn emit_ollama_chunk(chunk: OllamaStreamChunk, on_event: &mut impl FnMut(StreamEvent)) -> bool {
+    if let Some(content) = chunk.message.and_then(|m| m.content) {
+        on_event(StreamEvent::Delta { content });
+    }
+    if chunk.done == Some(true) {
+        on_event(StreamEvent::Done { total_tokens: 0 });
+        return true;
+    }
+    false
+}
+
 async fn stream_ollama(
     config: &ProviderConfig,
     messages: &[ChatMessage],
     on_event: &mut impl FnMut(StreamEvent),
 ) -> Result<(), String> {
-    let client = reqwest::Client::new();
+    let client = http_client();
     let endpoint = config.get_endpoint();
 
     let body = OllamaRequest {
         model: &config.model,
         messages,
         stream: true,
+        options: (config.max_tokens.is_some() || config.temperature.is_some()).then_some(
+            OllamaOptions {
+                num_predict: config.max_tokens,
+                temperature: config.temperature,
+            },
+        ),
     };
 
-    let response = client
+    let builder = client
         .post(&endpoint)
+        .headers(custom_headers(config)?)
         .header("Content-Type", "application/json")
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
+        .json(&body);
+    trace_request(config, &builder);
+    let response = match builder.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            on_event(send_error(e));
+            return Ok(());
+        }
+    };
 
     if !response.status().is_success() {
         let status = response.status();
         let body = response.text().await.unwrap_or_default();
+        trace_response(config, &body);
         on_event(StreamEvent::Error {
             message: format!("Ollama error {}: {}", status, body),
+            kind: StreamErrorKind::Status {
+                code: status.as_u16(),
+                body,
+            },
         });
         return Ok(());
     }
 
     let mut stream = response.bytes_stream();
-    let mut buffer = String::new();
+    let mut decoder = NdjsonDecoder::default();
 
-    while let Some(chunk_result) = stream.next().await {
+    loop {
+        let chunk_result = match next_event(&mut stream, config, on_event).await {
+            Ok(Some(chunk_result)) => chunk_result,
+            Ok(None) => break,
+            Err(_) => return Ok(()),
+        };
         match chunk_result {
             Ok(bytes) => {
-                buffer.push_str(&String::from_utf8_lossy(&bytes));
+                if config.transcript.is_some() {
+                    trace_response(config, &String::from_utf8_lossy(&bytes));
+                }
                 // Ollama sends NDJSON (one JSON per line)
-                while let Some(newline_pos) = buffer.find('\n') {
-                    let line = buffer[..newline_pos].trim().to_string();
-                    buffer = buffer[newline_pos + 1..].to_string();
-
-                    if line.is_empty() {
-                        continue;
-                    }
-
-                    if let Ok(chunk) = serde_json::from_str::<OllamaStreamChunk>(&line) {
-                        if let Some(msg) = &chunk.message {
-                            if let Some(content) = &msg.content {
-                                on_event(StreamEvent::Delta {
-                                    content: content.clone(),
-                                });
-                            }
-                        }
-                        if chunk.done == Some(true) {
-                            on_event(StreamEvent::Done { total_tokens: 0 });
-                            return Ok(());
-                        }
+                decoder.push(&bytes);
+                while let Some(chunk) = decoder.next_object::<OllamaStreamChunk>() {
+                    if emit_ollama_chunk(chunk, on_event) {
+                        return Ok(());
                     }
                 }
             }
             Err(err) => {
                 on_event(StreamEvent::Error {
                     message: format!("Stream error: {}", err),
+                    kind: StreamErrorKind::Connection,
                 });
                 return Ok(());
             }
         }
     }
 
+    if let Some(chunk) = decoder.finish::<OllamaStreamChunk>() {
+        if emit_ollama_chunk(chunk, on_event) {
+            return Ok(());
+        }
+    }
     on_event(StreamEvent::Done { total_tokens: 0 });
     Ok(())
 }
+
+// ============================================
+// Ollama model management
+// ============================================
+
+fn ollama_base_url(config: &ProviderConfig) -> &str {
+    config
+        .base_url
+        .as_deref()
+        .unwrap_or("http://localhost:11434")
+        .trim_end_matches('/')
+}
+
+/// One progress update from `/api/pull`, forwarded to the frontend.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PullProgress {
+    /// Ollama's status text, e.g. "pulling manifest" or "verifying sha256 digest".
+    pub status: String,
+    /// Layer digest being downloaded, when the status refers to one.
+    pub digest: Option<String>,
+    pub completed: Option<u64>,
+    pub total: Option<u64>,
+    /// 0–100 for the current layer; `None` when Ollama reports no sizes.
+    pub percent: Option<f64>,
+    /// Set on the terminal `status: "success"` line.
+    pub done: bool,
+}
+
+#[derive(Deserialize)]
+struct OllamaPullLine {
+    #[serde(default)]
+    status: String,
+    digest: Option<String>,
+    completed: Option<u64>,
+    total: Option<u64>,
+    error: Option<String>,
+}
+
+impl OllamaPullLine {
+    fn into_progress(self) -> Result<PullProgress, String> {
+        if let Some(error) = self.error {
+            return Err(format!("Ollama pull failed: {}", error));
+        }
+        let done = self.status == "success";
+        let percent = match (self.completed, self.total) {
+            _ if done => Some(100.0),
+            (Some(completed), Some(total)) if total > 0 => {
+                Some(completed.min(total) as f64 / total as f64 * 100.0)
+            }
+            _ => None,
+        };
+        Ok(PullProgress {
+            status: self.status,
+            digest: self.digest,
+            completed: self.completed,
+            total: self.total,
+            percent,
+            done,
+        })
+    }
+}
+
+/// Download `model` into the Ollama instance behind `config`, reporting
+/// progress as it goes. Setting `cancel` stops the download at the next
+/// chunk; Ollama keeps finished layers, so pulling again resumes.
+pub async fn pull_ollama_model(
+    config: &ProviderConfig,
+    model: &str,
+    cancel: &std::sync::atomic::AtomicBool,
+    mut on_progress: impl FnMut(PullProgress),
+) -> Result<(), String> {
+    let client = http_client();
+    let endpoint = format!("{}/api/pull", ollama_base_url(config));
+
+    let response = client
+        .post(&endpoint)
+        .headers(custom_headers(config)?)
+        .json(&serde_json::json!({ "model": model, "stream": true }))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to connect to Ollama: {}. Is Ollama running?", e))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Ollama error {}: {}", status, body));
+    }
+
+    let mut stream = response.bytes_stream();
+    let mut decoder = NdjsonDecoder::default();
+    let mut forward = |line: OllamaPullLine| -> Result<bool, String> {
+        let progress = line.into_progress()?;
+        let done = progress.done;
+        on_progress(progress);
+        Ok(done)
+    };
+
+    while let Some(chunk) = stream.next().await {
+        if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+            return Err("Pull cancelled".to_string());
+        }
+        decoder.push(&chunk.map_err(|e| format!("Stream error: {}", e))?);
+        while let Some(line) = decoder.next_object::<OllamaPullLine>() {
+            if forward(line)? {
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(line) = decoder.finish::<OllamaPullLine>() {
+        if forward(line)? {
+            return Ok(());
+        }
+    }
+    Err(format!(
+        "Ollama closed the connection before '{}' finished downloading",
+        model
+    ))
+}
+
+/// Remove a locally installed model from the Ollama instance behind `config`.
+pub async fn delete_ollama_model(config: &ProviderConfig, model: &str) -> Result<(), String> {
+    let client = http_client();
+    let endpoint = format!("{}/api/delete", ollama_base_url(config));
+
+    let response = client
+        .delete(&endpoint)
+        .headers(custom_headers(config)?)
+        .json(&serde_json::json!({ "model": model }))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to connect to Ollama: {}. Is Ollama running?", e))?;
+
+    match response.status() {
+        status if status.is_success() => Ok(()),
+        reqwest::StatusCode::NOT_FOUND => Err(format!("Model '{}' is not installed", model)),
+        status => {
+            let body = response.text().await.unwrap_or_default();
+            Err(format!("Ollama error {}: {}", status, body))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Serve HTTP on a local port, answering each request with the raw
+    /// response `respond` builds from the request text. Returns the base URL.
+    pub(crate) async fn mock_server(
+        respond: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let respond = Arc::new(respond);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let respond = respond.clone();
+                tokio::spawn(async move {
+                    let request = read_request(&mut socket).await;
+                    let _ = socket.write_all(respond(&request).as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap_or(0);
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some(head_end) = text.find("\r\n\r\n") {
+                let length = text[..head_end]
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse().ok())?
+                    })
+                    .unwrap_or(0);
+                if request.len() >= head_end + 4 + length {
+                    break;
+                }
+            }
+        }
+        String::from_utf8_lossy(&request).into_owned()
+    }
+
+    pub(crate) fn http_response(status: &str, content_type: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )
+    }
+
+    /// A listed model with an optional context window.
+    pub(crate) fn model_info(id: &str, context_window: Option<i64>) -> ModelInfo {
+        ModelInfo {
+            id: id.to_string(),
+            name: id.to_string(),
+            context_window,
+            is_favorite: false,
+            size_bytes: None,
+        }
+    }
+
+    /// An OpenAI-style event stream with one delta per text.
+    pub(crate) fn openai_sse(texts: &[&str]) -> String {
+        texts
+            .iter()
+            .map(|text| {
+                format!(
+                    "data: {}\n\n",
+                    serde_json::json!({ "choices": [{ "delta": { "content": text } }] })
+                )
+            })
+            .collect()
+    }
+
+    pub(crate) fn mock_config(base_url: String) -> ProviderConfig {
+        ProviderConfig {
+            provider_type: "openai_compatible".into(),
+            api_key: Some("sk-test".into()),
+            base_url: Some(base_url),
+            model: "mock-model".into(),
+            ..Default::default()
+        }
+    }
+
+    fn user_message(content: &str) -> Vec<ChatMessage> {
+        vec![ChatMessage {
+            role: "user".into(),
+            content: content.into(),
+        }]
+    }
+
+    fn delta_text(events: &[StreamEvent]) -> String {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::Delta { content } => Some(content.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn anthropic_default_max_tokens_by_model() {
+        for (model, expected) in [
+            ("claude-3-5-sonnet-20241022", 8192),
+            ("claude-3-5-haiku-latest", 8192),
+            ("claude-3-7-sonnet-20250219", 32000),
+            ("claude-sonnet-4-20250514", 32000),
+            ("claude-opus-4-1", 32000),
+            ("claude-3-opus-20240229", 4096),
+            ("claude-3-haiku-20240307", 4096),
+            ("claude-2.1", 4096),
+            ("claude-instant-1.2", 4096),
+        ] {
+            assert_eq!(anthropic_default_max_tokens(model), expected, "{}", model);
+        }
+    }
+
+    #[test]
+    fn anthropic_body_uses_configured_max_tokens() {
+        let messages = [ChatMessage {
+            role: "user".into(),
+            content: "hi".into(),
+        }];
+        let mut config = ProviderConfig {
+            provider_type: "anthropic".into(),
+            model: "claude-3-5-sonnet-20241022".into(),
+            max_tokens: Some(1234),
+            ..Default::default()
+        };
+        assert_eq!(
+            anthropic_request_body(&config, &messages)["max_tokens"],
+            1234
+        );
+
+        config.max_tokens = None;
+        assert_eq!(
+            anthropic_request_body(&config, &messages)["max_tokens"],
+            8192
+        );
+    }
+
+    #[tokio::test]
+    async fn dropped_stream_is_resumed_with_the_partial_reply() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = mock_server(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            if request.contains(CONTINUE_PROMPT) {
+                // The continuation restates the end of the partial reply.
+                let body = openai_sse(&["world", "!"]) + "data: [DONE]\n\n";
+                http_response("200 OK", "text/event-stream", &body)
+            } else {
+                // Promise more than is sent, then hang up mid-stream.
+                let body = openai_sse(&["Hello ", "wor"]);
+                http_response("200 OK", "text/event-stream", &body).replacen(
+                    &format!("content-length: {}", body.len()),
+                    &format!("content-length: {}", body.len() + 500),
+                    1,
+                )
+            }
+        })
+        .await;
+        let config = ProviderConfig {
+            reconnect_attempts: 2,
+            ..mock_config(url)
+        };
+
+        let mut events = Vec::new();
+        stream_chat(&config, &user_message("hi"), |event| events.push(event))
+            .await
+            .unwrap();
+
+        assert_eq!(delta_text(&events), "Hello world!");
+        assert!(matches!(events.last(), Some(StreamEvent::Done { .. })));
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, StreamEvent::Error { .. })));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains(r#"{"content":"Hello wor","role":"assistant"}"#));
+    }
+
+    #[tokio::test]
+    async fn dropped_stream_is_reported_without_reconnect_attempts() {
+        let url = mock_server(|_| {
+            let body = openai_sse(&["Hello"]);
+            http_response("200 OK", "text/event-stream", &body).replacen(
+                &format!("content-length: {}", body.len()),
+                "content-length: 9999",
+                1,
+            )
+        })
+        .await;
+
+        let mut events = Vec::new();
+        stream_chat(&mock_config(url), &user_message("hi"), |event| {
+            events.push(event)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(delta_text(&events), "Hello");
+        assert!(matches!(
+            events.last(),
+            Some(StreamEvent::Error {
+                kind: StreamErrorKind::Connection,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejected_request_is_not_resumed() {
+        let url = mock_server(|_| {
+            http_response(
+                "401 Unauthorized",
+                "application/json",
+                r#"{"error":"bad key"}"#,
+            )
+        })
+        .await;
+        let config = ProviderConfig {
+            reconnect_attempts: 2,
+            ..mock_config(url)
+        };
+
+        let mut events = Vec::new();
+        stream_chat(&config, &user_message("hi"), |event| events.push(event))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            events.last(),
+            Some(StreamEvent::Error {
+                kind: StreamErrorKind::Status { code: 401, body },
+                ..
+            }) if body.contains("bad key")
+        ));
+    }
+
+    #[test]
+    fn streaming_rejections() {
+        let status = |code, body: &str| StreamErrorKind::Status {
+            code,
+            body: body.into(),
+        };
+        let json = |content_type: &str| StreamErrorKind::NotEventStream {
+            content_type: content_type.into(),
+        };
+        assert!(rejects_streaming(&status(
+            400,
+            r#"{"error":"Streaming unsupported"}"#
+        )));
+        assert!(rejects_streaming(&status(
+            422,
+            "stream: extra field not permitted"
+        )));
+        assert!(rejects_streaming(&json("application/json; charset=utf-8")));
+        assert!(!rejects_streaming(&status(400, r#"{"error":"bad model"}"#)));
+        assert!(!rejects_streaming(&status(401, "stream")));
+        assert!(!rejects_streaming(&json("text/html")));
+        assert!(!rejects_streaming(&StreamErrorKind::Other));
+    }
+
+    #[tokio::test]
+    async fn plain_json_reply_to_a_stream_falls_back_to_buffered() {
+        let url = mock_server(|_| {
+            http_response(
+                "200 OK",
+                "application/json",
+                r#"{"choices":[{"message":{"role":"assistant","content":"Hi"}}]}"#,
+            )
+        })
+        .await;
+
+        let mut events = Vec::new();
+        stream_chat(&mock_config(url), &user_message("hi"), |event| {
+            events.push(event)
+        })
+        .await
+        .unwrap();
+
+        assert!(matches!(&events[1], StreamEvent::StreamingDisabled { .. }));
+        assert_eq!(delta_text(&events), "Hi");
+        assert!(matches!(events.last(), Some(StreamEvent::Done { .. })));
+    }
+
+    #[tokio::test]
+    async fn silent_stream_is_reported_as_stalled() {
+        let config = ProviderConfig {
+            idle_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let mut stream = futures::stream::pending::<()>();
+        let mut events = Vec::new();
+        let next = next_event(&mut stream, &config, &mut |event| events.push(event)).await;
+        assert!(matches!(next, Err(StreamInterrupted::Stalled)));
+        assert!(matches!(
+            events.as_slice(),
+            [StreamEvent::Error { message, kind: StreamErrorKind::Stalled }]
+                if message == "stream stalled"
+        ));
+    }
+
+    #[tokio::test]
+    async fn cancelled_stream_stops_waiting() {
+        let cancel = CancelToken::default();
+        let config = ProviderConfig {
+            cancel: Some(cancel.clone()),
+            ..Default::default()
+        };
+        cancel.cancel();
+        let mut stream = futures::stream::pending::<()>();
+        let next = next_event(&mut stream, &config, &mut |_| {}).await;
+        assert!(matches!(next, Err(StreamInterrupted::Cancelled)));
+    }
+
+    fn ollama_contents(decoder: &mut NdjsonDecoder) -> Vec<String> {
+        std::iter::from_fn(|| decoder.next_object::<OllamaStreamChunk>())
+            .filter_map(|chunk| chunk.message.and_then(|m| m.content))
+            .collect()
+    }
+
+    #[test]
+    fn ndjson_object_split_across_chunks() {
+        let mut decoder = NdjsonDecoder::default();
+        decoder.push(br#"{"message":{"content":"Hel"#);
+        assert!(ollama_contents(&mut decoder).is_empty());
+        // The split falls inside a multi-byte character.
+        let rest = "lo \u{00e9}\"},\"done\":false}\n".as_bytes();
+        decoder.push(&rest[..4]);
+        assert!(ollama_contents(&mut decoder).is_empty());
+        decoder.push(&rest[4..]);
+        assert_eq!(ollama_contents(&mut decoder), ["Hello \u{00e9}"]);
+    }
+
+    #[test]
+    fn ndjson_crlf_line_endings() {
+        let mut decoder = NdjsonDecoder::default();
+        decoder.push(
+            b"{\"message\":{\"content\":\"a\"}}\r\n\r\n{\"message\":{\"content\":\"b\"}}\r\n",
+        );
+        assert_eq!(ollama_contents(&mut decoder), ["a", "b"]);
+    }
+
+    #[test]
+    fn ndjson_malformed_line_is_skipped() {
+        let mut decoder = NdjsonDecoder::default();
+        decoder.push(b"{\"message\":{\"content\":\"trunc\n{\"message\":{\"content\":\"ok\"}}\n");
+        assert_eq!(ollama_contents(&mut decoder), ["ok"]);
+
+        decoder.push(b"{\"done\":true}");
+        let last: OllamaStreamChunk = decoder.finish().unwrap();
+        assert_eq!(last.done, Some(true));
+    }
+
+    #[test]
+    fn vendor_prefixed_models_are_deduped() {
+        let models = vec![
+            model_info("openai/gpt-4o", None),
+            model_info("gpt-4o-mini", None),
+            model_info("gpt-4o", Some(128_000)),
+            model_info("GPT-4O-MINI", None),
+            model_info("unknown/gpt-4o", None),
+        ];
+        let deduped: Vec<_> = dedupe_models(models)
+            .into_iter()
+            .map(|m| (m.id, m.context_window))
+            .collect();
+        assert_eq!(
+            deduped,
+            vec![
+                ("gpt-4o".to_string(), Some(128_000)),
+                ("gpt-4o-mini".to_string(), None),
+                ("unknown/gpt-4o".to_string(), None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn non_streaming_provider_uses_a_buffered_request() {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = mock_server(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            http_response(
+                "200 OK",
+                "application/json",
+                r#"{"choices":[{"message":{"role":"assistant","content":"Hi there"}}],"usage":{"total_tokens":5}}"#,
+            )
+        })
+        .await;
+        let config = ProviderConfig {
+            supports_streaming: false,
+            ..mock_config(url)
+        };
+        let mut events = Vec::new();
+        stream_chat(&config, &user_message("Hello"), |event| events.push(event))
+            .await
+            .unwrap();
+
+        assert_eq!(delta_text(&events), "Hi there");
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, StreamEvent::Done { .. })));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].contains(r#""stream":true"#), "{}", requests[0]);
+    }
+
+    #[test]
+    fn each_auth_style_places_the_key() {
+        let build = |style: &str| {
+            AuthStyle::parse(style)
+                .unwrap()
+                .apply(
+                    reqwest::Client::new().post("https://example.com/v1/chat/completions?x=1"),
+                    "sk-test",
+                )
+                .build()
+                .unwrap()
+        };
+        let header = |request: &reqwest::Request, name: &str| {
+            request
+                .headers()
+                .get(name)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        for style in ["", "bearer_header"] {
+            let request = build(style);
+            assert_eq!(
+                header(&request, "authorization").as_deref(),
+                Some("Bearer sk-test")
+            );
+            assert_eq!(request.url().query(), Some("x=1"));
+        }
+
+        let request = build("x_api_key_header");
+        assert_eq!(header(&request, "x-api-key").as_deref(), Some("sk-test"));
+        assert_eq!(header(&request, "authorization"), None);
+
+        let request = build("query_param");
+        assert_eq!(request.url().query(), Some("x=1&api-key=sk-test"));
+        assert_eq!(header(&request, "authorization"), None);
+
+        let request = build("query_param: key");
+        assert_eq!(request.url().query(), Some("x=1&key=sk-test"));
+
+        for bad in ["query_param:", "basic", "Bearer_Header"] {
+            assert!(AuthStyle::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn tool_call_fragments_are_accumulated_per_index() {
+        let mut calls = ToolCallAccumulator::default();
+        calls.push(1, Some("call_b"), Some("get_time"), None);
+        calls.push(0, Some("call_a"), Some("get_weather"), Some("{\"ci"));
+        calls.push(0, None, None, Some("ty\":"));
+        calls.push(0, Some(""), Some(""), Some("\"Oslo\"}"));
+
+        assert_eq!(
+            calls.finish(0),
+            Some(ToolCall {
+                id: "call_a".into(),
+                name: "get_weather".into(),
+                arguments: r#"{"city":"Oslo"}"#.into(),
+            })
+        );
+        assert_eq!(calls.finish(0), None);
+        // A call without arguments still gets a valid JSON object.
+        assert_eq!(
+            calls.drain(),
+            vec![ToolCall {
+                id: "call_b".into(),
+                name: "get_time".into(),
+                arguments: "{}".into(),
+            }]
+        );
+        assert!(calls.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn openai_tool_call_deltas_become_one_event() {
+        let chunks = [
+            serde_json::json!({ "choices": [{ "delta": { "tool_calls": [
+                { "index": 0, "id": "call_a", "function": { "name": "get_weather", "arguments": "" } }
+            ] } }] }),
+            serde_json::json!({ "choices": [{ "delta": { "tool_calls": [
+                { "index": 0, "function": { "arguments": "{\"city\":" } }
+            ] } }] }),
+            serde_json::json!({ "choices": [{ "delta": { "tool_calls": [
+                { "index": 0, "function": { "arguments": "\"Oslo\"}" } }
+            ] }, "finish_reason": "tool_calls" }] }),
+        ];
+        let body: String = chunks
+            .iter()
+            .map(|chunk| format!("data: {chunk}\n\n"))
+            .chain(["data: [DONE]\n\n".to_string()])
+            .collect();
+        let url = mock_server(move |_| http_response("200 OK", "text/event-stream", &body)).await;
+        let config = ProviderConfig {
+            tools: Some(vec![ToolDef {
+                name: "get_weather".into(),
+                description: String::new(),
+                parameters: empty_object_schema(),
+            }]),
+            ..mock_config(url)
+        };
+        let mut events = Vec::new();
+        stream_chat(&config, &user_message("Weather?"), |event| {
+            events.push(event)
+        })
+        .await
+        .unwrap();
+
+        let calls: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::ToolCall {
+                    id,
+                    name,
+                    arguments,
+                } => Some((id.as_str(), name.as_str(), arguments.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(calls, [("call_a", "get_weather", r#"{"city":"Oslo"}"#)]);
+    }
+
+    #[tokio::test]
+    async fn pull_progress_percentages_follow_the_stream() {
+        let body = [
+            r#"{"status":"pulling manifest"}"#,
+            r#"{"status":"pulling abc","digest":"sha256:abc","total":200,"completed":0}"#,
+            r#"{"status":"pulling abc","digest":"sha256:abc","total":200,"completed":50}"#,
+            r#"{"status":"pulling abc","digest":"sha256:abc","total":200,"completed":250}"#,
+            r#"{"status":"pulling def","digest":"sha256:def","total":0,"completed":0}"#,
+            r#"{"status":"verifying sha256 digest"}"#,
+            r#"{"status":"success"}"#,
+        ]
+        .join("\n");
+        let url =
+            mock_server(move |_| http_response("200 OK", "application/x-ndjson", &body)).await;
+        let config = ProviderConfig {
+            provider_type: "ollama".into(),
+            ..mock_config(url.trim_end_matches("/v1").to_string())
+        };
+        let cancel = std::sync::atomic::AtomicBool::new(false);
+        let mut updates = Vec::new();
+        pull_ollama_model(&config, "llama3", &cancel, |progress| {
+            updates.push(progress)
+        })
+        .await
+        .unwrap();
+
+        let percents: Vec<_> = updates.iter().map(|p| p.percent).collect();
+        assert_eq!(
+            percents,
+            [
+                None,
+                Some(0.0),
+                Some(25.0),
+                Some(100.0),
+                None,
+                None,
+                Some(100.0)
+            ]
+        );
+        assert_eq!(updates[2].digest.as_deref(), Some("sha256:abc"));
+        assert!(updates.iter().rev().skip(1).all(|p| !p.done));
+        assert!(updates.last().unwrap().done);
+    }
+
+    #[tokio::test]
+    async fn pull_reports_errors_and_early_close() {
+        let url = mock_server(|request| {
+            let body = if request.contains("bad-model") {
+                r#"{"error":"pull model manifest: file does not exist"}"#
+            } else {
+                r#"{"status":"pulling abc","total":10,"completed":5}"#
+            };
+            http_response("200 OK", "application/x-ndjson", body)
+        })
+        .await;
+        let config = ProviderConfig {
+            provider_type: "ollama".into(),
+            ..mock_config(url.trim_end_matches("/v1").to_string())
+        };
+        let cancel = std::sync::atomic::AtomicBool::new(false);
+
+        let err = pull_ollama_model(&config, "bad-model", &cancel, |_| {})
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Ollama pull failed: pull model manifest: file does not exist"
+        );
+        let err = pull_ollama_model(&config, "llama3", &cancel, |_| {})
+            .await
+            .unwrap_err();
+        assert!(err.contains("before 'llama3' finished"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn ollama_tags_carry_model_sizes() {
+        let url = mock_server(|request| {
+            assert!(request.starts_with("GET /api/tags "), "{request}");
+            http_response(
+                "200 OK",
+                "application/json",
+                r#"{"models":[
+                    {"name":"llama3:latest","model":"llama3:latest","size":4661224676},
+                    {"name":"tinyllama:latest","size":637700138},
+                    {"model":"nomic:latest"},
+                    {"size":1}
+                ]}"#,
+            )
+        })
+        .await;
+        let config = ProviderConfig {
+            provider_type: "ollama".into(),
+            ..mock_config(url.trim_end_matches("/v1").to_string())
+        };
+        let (models, fetched) = fetch_ollama_models(&config).await.unwrap();
+
+        assert_eq!(fetched, 4);
+        let sizes: Vec<_> = models
+            .iter()
+            .map(|m| (m.id.as_str(), m.size_bytes))
+            .collect();
+        assert_eq!(
+            sizes,
+            [
+                ("llama3:latest", Some(4_661_224_676)),
+                ("nomic:latest", None),
+                ("tinyllama:latest", Some(637_700_138)),
+            ]
+        );
+    }
+
+    #[test]
+    fn token_warning_is_emitted_exactly_once() {
+        let delta = || StreamEvent::Delta {
+            content: "abcd".into(),
+        };
+        let mut meter = ResponseTokenMeter::new(5);
+        let warnings: Vec<_> = (0..20)
+            .map(|_| delta())
+            .chain([StreamEvent::Done { total_tokens: 100 }])
+            .enumerate()
+            .filter_map(|(i, event)| meter.observe(&event).map(|warning| (i, warning)))
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        let (index, warning) = &warnings[0];
+        assert_eq!(*index, 4, "warns on the delta that reaches 5 tokens");
+        let StreamEvent::Warning { message } = warning else {
+            panic!("expected a warning, got {warning:?}");
+        };
+        assert!(message.contains("about 5 tokens"), "{message}");
+
+        // Usage reported only at the end still triggers it.
+        let mut meter = ResponseTokenMeter::new(50);
+        assert!(meter.observe(&delta()).is_none());
+        assert!(meter
+            .observe(&StreamEvent::Done { total_tokens: 60 })
+            .is_some());
+
+        let mut off = ResponseTokenMeter::new(0);
+        assert!(off
+            .observe(&StreamEvent::Done { total_tokens: 100 })
+            .is_none());
+    }
+
+    #[test]
+    fn histories_without_a_turn_are_rejected() {
+        let message = |role: &str, content: &str| ChatMessage {
+            role: role.into(),
+            content: content.into(),
+        };
+        assert_eq!(
+            ensure_conversation_turn(&[]),
+            Err("Invalid input: there are no messages to send".to_string())
+        );
+        let system_only = [message("system", "Be brief")];
+        assert_eq!(
+            ensure_conversation_turn(&system_only),
+            Err("Invalid input: a message is required besides the system prompt".to_string())
+        );
+        let blank_user = [message("system", "Be brief"), message("user", "  \n")];
+        assert!(ensure_conversation_turn(&blank_user).is_err());
+        let with_turn = [message("system", "Be brief"), message("user", "Hi")];
+        assert_eq!(ensure_conversation_turn(&with_turn), Ok(()));
+        assert_eq!(
+            ensure_conversation_turn(&[message("assistant", "Hello")]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn oauth_error_codes_map_to_poll_states() {
+        let poll = |json: &str| {
+            serde_json::from_str::<OAuthTokenResponse>(json)
+                .unwrap()
+                .into_poll_state(5)
+        };
+        assert_eq!(
+            poll(r#"{"access_token":"gho_abc"}"#),
+            Ok(PollState::Authorized {
+                token: "gho_abc".into()
+            })
+        );
+        assert_eq!(
+            poll(r#"{"error":"authorization_pending"}"#),
+            Ok(PollState::Pending { next_interval: 5 })
+        );
+        assert_eq!(
+            poll(r#"{"error":"slow_down","interval":15}"#),
+            Ok(PollState::SlowDown { next_interval: 15 })
+        );
+        assert_eq!(
+            poll(r#"{"error":"slow_down"}"#),
+            Ok(PollState::SlowDown { next_interval: 10 })
+        );
+        assert_eq!(poll(r#"{"error":"access_denied"}"#), Ok(PollState::Denied));
+        assert_eq!(poll(r#"{"error":"expired_token"}"#), Ok(PollState::Expired));
+        assert_eq!(
+            poll(r#"{"error":"incorrect_device_code","error_description":"bad code"}"#),
+            Err("incorrect_device_code:bad code".to_string())
+        );
+        assert!(poll("{}").is_err());
+    }
+
+    #[test]
+    fn chats_flatten_into_a_role_labeled_prompt() {
+        let message = |role: &str, content: &str| ChatMessage {
+            role: role.into(),
+            content: content.into(),
+        };
+        let prompt = flatten_completion_prompt(&[
+            message("system", "Be brief."),
+            message("user", "  Hi  \n"),
+            message("assistant", "Hello!"),
+            message("user", "Bye"),
+        ]);
+        assert_eq!(
+            prompt,
+            "System: Be brief.\n\nUser: Hi\n\nAssistant: Hello!\n\nUser: Bye\n\nAssistant:"
+        );
+        assert_eq!(flatten_completion_prompt(&[]), "Assistant:");
+    }
+
+    #[test]
+    fn legacy_completion_chunks_yield_text_and_usage() {
+        assert_eq!(
+            parse_completion_chunk(r#"{"choices":[{"text":"Hel","index":0}]}"#),
+            Some(("Hel".to_string(), None))
+        );
+        assert_eq!(
+            parse_completion_chunk(
+                r#"{"choices":[{"text":"lo"}],"usage":{"prompt_tokens":3,"completion_tokens":2,"total_tokens":5}}"#
+            ),
+            Some(("lo".to_string(), Some(5)))
+        );
+        assert_eq!(
+            parse_completion_chunk(r#"{"choices":[{"text":null}]}"#),
+            Some((String::new(), None))
+        );
+        assert_eq!(parse_completion_chunk("not json"), None);
+    }
+
+    #[tokio::test]
+    async fn gzipped_model_listings_are_decoded() {
+        use std::io::Write;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await.to_lowercase();
+            assert!(request.contains("accept-encoding: gzip"), "{request}");
+
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder
+                .write_all(br#"{"data":[{"id":"gpt-4o"},{"id":"gpt-4o-mini"}]}"#)
+                .unwrap();
+            let body = encoder.finish().unwrap();
+            let head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-encoding: gzip\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(&body).await.unwrap();
+            let _ = socket.shutdown().await;
+        });
+
+        let config = mock_config(format!("http://{}/v1", addr));
+        let models = list_provider_models(&config, false).await.unwrap();
+        let ids: Vec<_> = models.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["gpt-4o", "gpt-4o-mini"]);
+    }
+
+    #[tokio::test]
+    async fn header_values_resolve_from_the_environment() {
+        let var = format!("ZITONG_TEST_GATEWAY_TOKEN_{}", std::process::id());
+        std::env::set_var(&var, "gw-secret");
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = mock_server(move |request| {
+            seen.lock().unwrap().push(request.to_lowercase());
+            http_response("200 OK", "application/json", r#"{"data":[{"id":"m"}]}"#)
+        })
+        .await;
+        let mut config = mock_config(format!("{}/v1", url));
+        config
+            .custom_headers
+            .insert("X-Gateway-Token".into(), format!("Bearer ${{{}}}", var));
+
+        list_provider_models(&config, false).await.unwrap();
+        assert!(requests.lock().unwrap()[0].contains("x-gateway-token: bearer gw-secret"));
+        assert_eq!(
+            resolve_header_value("X-Plain", "no references"),
+            Ok("no references".to_string())
+        );
+
+        let unset = format!("ZITONG_TEST_UNSET_{}", std::process::id());
+        config
+            .custom_headers
+            .insert("X-Other".into(), format!("${{{}}}", unset));
+        let err = list_provider_models(&config, false).await.unwrap_err();
+        assert_eq!(
+            err,
+            format!(
+                "Header 'X-Other' references environment variable '{}', which is not set",
+                unset
+            )
+        );
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        std::env::remove_var(&var);
+    }
+
+    #[test]
+    fn together_and_fireworks_endpoints() {
+        let config = |provider_type: &str, base_url: Option<&str>| ProviderConfig {
+            provider_type: provider_type.into(),
+            base_url: base_url.map(String::from),
+            ..Default::default()
+        };
+        let together = config("together", None);
+        assert_eq!(
+            together.get_endpoint(),
+            "https://api.together.xyz/v1/chat/completions"
+        );
+        assert_eq!(
+            together.get_models_endpoint(),
+            "https://api.together.xyz/v1/models"
+        );
+        let fireworks = config("fireworks", None);
+        assert_eq!(
+            fireworks.get_endpoint(),
+            "https://api.fireworks.ai/inference/v1/chat/completions"
+        );
+        assert_eq!(
+            fireworks.get_models_endpoint(),
+            "https://api.fireworks.ai/inference/v1/models"
+        );
+        let proxied = config("together", Some("http://proxy.local/v1"));
+        assert_eq!(
+            proxied.get_endpoint(),
+            "http://proxy.local/v1/chat/completions"
+        );
+        assert_eq!(
+            proxied.get_models_endpoint(),
+            "http://proxy.local/v1/models"
+        );
+    }
+
+    #[tokio::test]
+    async fn together_and_fireworks_listings_drop_non_chat_models() {
+        let url = mock_server(|_| {
+            http_response(
+                "200 OK",
+                "application/json",
+                r#"[{"id":"meta/llama-3","type":"chat","display_name":"Llama 3","context_length":8192},
+                    {"id":"bge-large","type":"embedding"},
+                    {"id":"flux-schnell","type":"image"},
+                    {"id":"untyped-model"}]"#,
+            )
+        })
+        .await;
+        let together = ProviderConfig {
+            provider_type: "together".into(),
+            ..mock_config(format!("{}/v1", url))
+        };
+        let models = list_provider_models(&together, false).await.unwrap();
+        let ids: Vec<_> = models.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["meta/llama-3", "untyped-model"]);
+        assert_eq!(models[0].name, "Llama 3");
+        assert_eq!(models[0].context_window, Some(8192));
+
+        let url = mock_server(|_| {
+            http_response(
+                "200 OK",
+                "application/json",
+                r#"{"data":[{"id":"accounts/fireworks/models/llama-v3"},
+                    {"id":"accounts/fireworks/models/nomic-embed-text"},
+                    {"id":"accounts/fireworks/models/flux-1-dev"}]}"#,
+            )
+        })
+        .await;
+        let fireworks = ProviderConfig {
+            provider_type: "fireworks".into(),
+            ..mock_config(format!("{}/v1", url))
+        };
+        let models = list_provider_models(&fireworks, false).await.unwrap();
+        let ids: Vec<_> = models.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["accounts/fireworks/models/llama-v3"]);
+    }
+
+    #[tokio::test]
+    async fn extra_body_adds_keys_without_overriding_the_request() {
+        let bodies = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = bodies.clone();
+        let url = mock_server(move |request| {
+            let body = request.split_once("\r\n\r\n").unwrap().1;
+            seen.lock()
+                .unwrap()
+                .push(serde_json::from_str::<serde_json::Value>(body).unwrap());
+            http_response("200 OK", "text/event-stream", &openai_sse(&["Ok"]))
+        })
+        .await;
+        let config = ProviderConfig {
+            extra_body: Some(serde_json::json!({
+                "reasoning_effort": "high",
+                "model": "other-model",
+                "messages": [],
+            })),
+            ..mock_config(format!("{}/v1", url))
+        };
+
+        stream_chat(&config, &user_message("Hi"), |_| {})
+            .await
+            .unwrap();
+
+        let body = &bodies.lock().unwrap()[0];
+        assert_eq!(body["reasoning_effort"], "high");
+        assert_eq!(body["model"], "mock-model");
+        assert_eq!(body["messages"][0]["content"], "Hi");
+        assert_eq!(body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn probing_reports_each_capability() {
+        let url = mock_server(|request| {
+            if request.starts_with("GET") {
+                http_response(
+                    "200 OK",
+                    "application/json",
+                    r#"[{"id":"meta/llama-3.2-11b-vision","type":"chat","context_length":131072}]"#,
+                )
+            } else {
+                let stream = openai_sse(&["Hi"]) + "data: [DONE]\n\n";
+                http_response("200 OK", "text/event-stream", &stream)
+            }
+        })
+        .await;
+        let capable = ProviderConfig {
+            provider_type: "together".into(),
+            model: "meta/llama-3.2-11b-vision".into(),
+            ..mock_config(format!("{}/v1", url))
+        };
+        let capabilities = probe_capabilities(&capable).await.unwrap();
+        assert!(capabilities.streaming);
+        assert!(capabilities.tools);
+        assert!(capabilities.vision);
+        assert_eq!(capabilities.context_window, Some(131072));
+
+        let url = mock_server(|request| {
+            if request.starts_with("GET") {
+                http_response(
+                    "200 OK",
+                    "application/json",
+                    r#"{"data":[{"id":"mock-model"}]}"#,
+                )
+            } else if request.contains(r#""tools""#) {
+                http_response(
+                    "400 Bad Request",
+                    "application/json",
+                    r#"{"error":{"message":"tools are not supported"}}"#,
+                )
+            } else if request.contains(r#""stream":true"#) {
+                http_response(
+                    "400 Bad Request",
+                    "application/json",
+                    r#"{"error":{"message":"stream is not supported"}}"#,
+                )
+            } else {
+                http_response(
+                    "200 OK",
+                    "application/json",
+                    r#"{"choices":[{"message":{"role":"assistant","content":"Hi"}}]}"#,
+                )
+            }
+        })
+        .await;
+        let basic = mock_config(format!("{}/v1", url));
+        let capabilities = probe_capabilities(&basic).await.unwrap();
+        assert!(!capabilities.streaming);
+        assert!(!capabilities.tools);
+        assert!(!capabilities.vision);
+        assert_eq!(capabilities.context_window, None);
+
+        let unreachable = mock_config("http://127.0.0.1:9/v1".into());
+        assert!(probe_capabilities(&unreachable).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn anthropic_prefill_is_sent_and_starts_the_reply() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let url = mock_server(move |request| {
+            seen.lock().unwrap().push(request.to_string());
+            let body = [
+                serde_json::json!({ "type": "message_start" }),
+                serde_json::json!({
+                    "type": "content_block_delta",
+                    "index": 0,
+                    "delta": { "type": "text_delta", "text": "\"name\": \"Ada\"}" },
+                }),
+                serde_json::json!({ "type": "message_stop" }),
+            ]
+            .iter()
+            .map(|event| format!("data: {}\n\n", event))
+            .collect::<String>();
+            http_response("200 OK", "text/event-stream", &body)
+        })
+        .await;
+        let config = ProviderConfig {
+            provider_type: "anthropic".into(),
+            ..mock_config(url)
+        };
+        let messages = vec![
+            ChatMessage {
+                role: "user".into(),
+                content: "Reply with JSON.".into(),
+            },
+            ChatMessage {
+                role: "assistant".into(),
+                content: "{ ".into(),
+            },
+        ];
+
+        let mut events = Vec::new();
+        stream_chat(&config, &messages, |event| events.push(event))
+            .await
+            .unwrap();
+
+        assert_eq!(delta_text(&events), "{\"name\": \"Ada\"}");
+        let requests = requests.lock().unwrap();
+        let (_, body) = requests[0].split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        let sent = body["messages"].as_array().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1]["role"], "assistant");
+        assert_eq!(sent[1]["content"], "{");
+    }
+
+    fn gemini_text(payloads: &[&str]) -> (String, Vec<StreamEvent>) {
+        let mut events = Vec::new();
+        for payload in payloads {
+            assert!(emit_gemini_payload(payload, &mut |event| events.push(event)));
+        }
+        (delta_text(&events), events)
+    }
+
+    #[test]
+    fn gemini_object_framed_chunks() {
+        let chunk = |text: &str| {
+            serde_json::json!({
+                "candidates": [{ "content": { "parts": [{ "text": text }] } }]
+            })
+            .to_string()
+        };
+        let safety_only = r#"{"candidates":[{"finishReason":"STOP","safetyRatings":[{"category":"HARM_CATEGORY_HARASSMENT","probability":"NEGLIGIBLE"}]}]}"#;
+
+        let (text, events) =
+            gemini_text(&[&chunk("Hello "), &chunk(""), safety_only, &chunk("there")]);
+
+        assert_eq!(text, "Hello there");
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn gemini_array_framed_chunks() {
+        let first = r#"[{"candidates":[{"content":{"parts":[{"text":"Hello "}]}}]}"#;
+        let second = r#",{"candidates":[{"content":{"parts":[{"text":"there"}]}}]}"#;
+        let last = r#",{"candidates":[{"finishReason":"STOP","safetyRatings":[]}]}]"#;
+        assert_eq!(gemini_text(&[first, second, last, "]"]).0, "Hello there");
+
+        let whole = format!("{}\n{}\n{}", first, second, last);
+        assert_eq!(gemini_text(&[&whole]).0, "Hello there");
+    }
+
+    #[test]
+    fn gemini_blocked_prompt_is_reported() {
+        let mut events = Vec::new();
+        let blocked = r#"{"promptFeedback":{"blockReason":"SAFETY","safetyRatings":[]}}"#;
+
+        assert!(!emit_gemini_payload(blocked, &mut |event| events.push(event)));
+        assert!(matches!(
+            events.as_slice(),
+            [StreamEvent::Error { message, .. }] if message.contains("SAFETY")
+        ));
+    }
+
+    #[test]
+    fn system_prompt_modes_place_the_prompt() {
+        let message = |role: &str, content: &str| ChatMessage {
+            role: role.into(),
+            content: content.into(),
+        };
+        let messages = vec![
+            message("system", "Be brief."),
+            message("user", "Hi"),
+            message("assistant", "Hello"),
+            message("user", "Bye"),
+        ];
+        let placed = |mode: &str| -> Vec<(String, String)> {
+            SystemPromptMode::parse(mode)
+                .unwrap()
+                .apply(&messages)
+                .iter()
+                .map(|m| (m.role.clone(), m.content.clone()))
+                .collect()
+        };
+        let pairs = |expected: &[(&str, &str)]| -> Vec<(String, String)> {
+            expected
+                .iter()
+                .map(|(role, content)| (role.to_string(), content.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            placed("system_role"),
+            pairs(&[
+                ("system", "Be brief."),
+                ("user", "Hi"),
+                ("assistant", "Hello"),
+                ("user", "Bye")
+            ])
+        );
+        assert_eq!(
+            placed("developer_role"),
+            pairs(&[
+                ("developer", "Be brief."),
+                ("user", "Hi"),
+                ("assistant", "Hello"),
+                ("user", "Bye")
+            ])
+        );
+        assert_eq!(
+            placed("prepend_user"),
+            pairs(&[
+                ("user", "Be brief.\n\nHi"),
+                ("assistant", "Hello"),
+                ("user", "Bye")
+            ])
+        );
+        assert_eq!(
+            SystemPromptMode::parse("").unwrap(),
+            SystemPromptMode::SystemRole
+        );
+        assert!(SystemPromptMode::parse("user_role").is_err());
+    }
+
+    #[test]
+    fn model_sorts_order_a_sample_listing() {
+        let model =
+            |id: &str, name: &str, context_window: Option<i64>, is_favorite: bool| ModelInfo {
+                name: name.into(),
+                is_favorite,
+                ..model_info(id, context_window)
+            };
+        let sample = vec![
+            model("gpt-4o", "GPT-4o", Some(128_000), false),
+            model("claude-3-haiku", "claude 3 Haiku", Some(200_000), true),
+            model("o1", "Alpha", None, false),
+            model("gemini-pro", "Gemini Pro", Some(1_000_000), true),
+            model("babbage", "Babbage", Some(16_000), false),
+        ];
+        let sorted = |sort: ModelSort| {
+            let mut models = sample.clone();
+            sort_models(&mut models, sort);
+            models.into_iter().map(|m| m.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sorted(ModelSort::IdAsc),
+            ["babbage", "claude-3-haiku", "gemini-pro", "gpt-4o", "o1"]
+        );
+        assert_eq!(
+            sorted(ModelSort::NameAsc),
+            ["o1", "babbage", "claude-3-haiku", "gemini-pro", "gpt-4o"]
+        );
+        assert_eq!(
+            sorted(ModelSort::FavoritesFirst),
+            ["claude-3-haiku", "gemini-pro", "babbage", "gpt-4o", "o1"]
+        );
+        assert_eq!(
+            sorted(ModelSort::ContextDesc),
+            ["gemini-pro", "claude-3-haiku", "gpt-4o", "babbage", "o1"]
+        );
+        assert_eq!(ModelSort::default(), ModelSort::IdAsc);
+    }
+
+    #[test]
+    fn local_providers_are_detected_by_host() {
+        let local = |provider_type: &str, base_url: Option<&str>| {
+            is_local_provider(&ProviderConfig {
+                provider_type: provider_type.into(),
+                base_url: base_url.map(String::from),
+                ..Default::default()
+            })
+        };
+
+        assert!(local("openai_compatible", Some("http://localhost:1234/v1")));
+        assert!(local("openai_compatible", Some("http://LOCALHOST/v1")));
+        assert!(local("openai_compatible", Some("http://127.0.0.1:8080/v1")));
+        assert!(local("openai_compatible", Some("http://[::1]:8080/v1")));
+        assert!(local("openai_compatible", Some("http://llm.localhost/v1")));
+        assert!(local("ollama", None));
+        assert!(!local("ollama", Some("http://gpu-box.lan:11434")));
+        assert!(!local(
+            "openai_compatible",
+            Some("http://192.168.1.20:1234/v1")
+        ));
+        assert!(!local(
+            "openai_compatible",
+            Some("https://localhost.example.com/v1")
+        ));
+        assert!(!local("openai", None));
+    }
+
+    #[tokio::test]
+    async fn offline_mode_blocks_only_remote_providers() {
+        let config = ProviderConfig {
+            offline: true,
+            base_url: Some("https://api.example.com/v1".into()),
+            ..mock_config(String::new())
+        };
+        let error = stream_chat(&config, &user_message("hi"), |_| {})
+            .await
+            .unwrap_err();
+        assert!(error.contains("Offline mode"), "{}", error);
+
+        let url = mock_server(|_| {
+            let body = openai_sse(&["ok"]) + "data: [DONE]\n\n";
+            http_response("200 OK", "text/event-stream", &body)
+        })
+        .await;
+        let config = ProviderConfig {
+            offline: true,
+            ..mock_config(format!("{}/v1", url))
+        };
+        let mut events = Vec::new();
+        stream_chat(&config, &user_message("hi"), |event| events.push(event))
+            .await
+            .unwrap();
+        assert_eq!(delta_text(&events), "ok");
+    }
+
+    #[test]
+    fn base_url_mismatches_are_warned_about() {
+        assert!(base_url_warnings("ollama", None).is_empty());
+        assert!(base_url_warnings("ollama", Some("http://localhost:11434")).is_empty());
+        assert!(base_url_warnings("ollama", Some("http://192.168.1.20:11434")).is_empty());
+        assert!(base_url_warnings("ollama", Some("http://gpu-box.local:11434")).is_empty());
+        assert!(base_url_warnings("openai", Some("https://api.openai.com/v1")).is_empty());
+
+        let remote_https = base_url_warnings("ollama", Some("https://ollama.example.com"));
+        assert_eq!(remote_https.len(), 2, "{:?}", remote_https);
+        assert!(remote_https[0].contains("https://"));
+        assert!(remote_https[1].contains("ollama.example.com"));
+
+        let no_scheme = base_url_warnings("openai_compatible", Some("api.together.xyz/v1"));
+        assert_eq!(no_scheme.len(), 1);
+        assert!(no_scheme[0].contains("has no scheme"));
+        assert_eq!(
+            base_url_warnings("ollama", Some("localhost:11434")).len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_copilot_exchange_means_sign_in_again() {
+        let url = mock_server(|request| {
+            if request.contains("token revoked") {
+                http_response(
+                    "401 Unauthorized",
+                    "application/json",
+                    r#"{"message":"Bad credentials"}"#,
+                )
+            } else if request.contains("token unsubscribed") {
+                http_response(
+                    "403 Forbidden",
+                    "application/json",
+                    r#"{"message":"No access"}"#,
+                )
+            } else {
+                http_response(
+                    "200 OK",
+                    "application/json",
+                    r#"{"token":"tid=1","endpoints":{"api":"https://copilot.example"}}"#,
+                )
+            }
+        })
+        .await;
+
+        assert_eq!(
+            exchange_copilot_token_at(&url, "revoked")
+                .await
+                .unwrap_err(),
+            COPILOT_AUTH_EXPIRED
+        );
+        let error = exchange_copilot_token_at(&url, "unsubscribed")
+            .await
+            .unwrap_err();
+        assert_ne!(error, COPILOT_AUTH_EXPIRED);
+        assert!(error.contains("403"), "{}", error);
+        assert_eq!(
+            exchange_copilot_token_at(&url, "valid").await.unwrap(),
+            ("tid=1".to_string(), "https://copilot.example".to_string())
+        );
+    }
+
+    #[test]
+    fn stream_metrics_follow_the_event_timing() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let delta = |text: &str| StreamEvent::Delta {
+            content: text.into(),
+        };
+        let mut timer = StreamTimer::start(start);
+
+        timer.observe(
+            &StreamEvent::Started {
+                message_id: "m".into(),
+            },
+            at(50),
+        );
+        timer.observe(&delta(""), at(100));
+        timer.observe(&delta("Hello"), at(400));
+        timer.observe(&delta(" world"), at(900));
+        let metrics = timer.finish(at(2400), 100);
+
+        assert_eq!(metrics.ttft_ms, Some(400));
+        assert_eq!(metrics.duration_ms, 2400);
+        assert_eq!(metrics.tokens_per_second, Some(50.0));
+        // Without usage or text there is no speed to report.
+        assert_eq!(timer.finish(at(2400), 0).tokens_per_second, None);
+        let silent = StreamTimer::start(start).finish(at(300), 10);
+        assert_eq!((silent.ttft_ms, silent.tokens_per_second), (None, None));
+    }
+}
@@ -0,0 +1,245 @@
+//! Accelerator string validation for `AppSettings.global_hotkey` and
+//! `AiCommand.keyboard_shortcut`.
+//!
+//! Accelerators use the global-shortcut plugin's format: `+`-separated
+//! modifiers followed by exactly one key, e.g. `CommandOrControl+Shift+Space`.
+//! Token names are matched case-insensitively.
+
+/// Modifier aliases accepted by the plugin, grouped by the modifier they
+/// resolve to so duplicates like `Ctrl+Control+K` can be detected.
+const MODIFIERS: &[(&str, &[&str])] = &[
+    ("Alt", &["alt", "option"]),
+    ("Control", &["ctrl", "control"]),
+    ("Shift", &["shift"]),
+    ("Super", &["super", "cmd", "command"]),
+    (
+        "CommandOrControl",
+        &[
+            "commandorcontrol",
+            "commandorctrl",
+            "cmdorctrl",
+            "cmdorcontrol",
+        ],
+    ),
+];
+
+const NAMED_KEYS: &[&str] = &[
+    "backquote",
+    "backslash",
+    "bracketleft",
+    "bracketright",
+    "comma",
+    "equal",
+    "minus",
+    "period",
+    "quote",
+    "semicolon",
+    "slash",
+    "backspace",
+    "capslock",
+    "enter",
+    "return",
+    "space",
+    "tab",
+    "delete",
+    "end",
+    "home",
+    "insert",
+    "pagedown",
+    "pageup",
+    "printscreen",
+    "scrolllock",
+    "arrowdown",
+    "arrowleft",
+    "arrowright",
+    "arrowup",
+    "down",
+    "left",
+    "right",
+    "up",
+    "numlock",
+    "numpadadd",
+    "numadd",
+    "numpadplus",
+    "numplus",
+    "numpaddecimal",
+    "numdecimal",
+    "numpaddivide",
+    "numdivide",
+    "numpadenter",
+    "numenter",
+    "numpadequal",
+    "numequal",
+    "numpadmultiply",
+    "nummultiply",
+    "numpadsubtract",
+    "numsubtract",
+    "escape",
+    "esc",
+    "audiovolumedown",
+    "volumedown",
+    "audiovolumeup",
+    "volumeup",
+    "audiovolumemute",
+    "volumemute",
+    "mediaplay",
+    "mediapause",
+    "mediaplaypause",
+    "mediastop",
+    "mediatracknext",
+    "mediatrackprev",
+    "mediatrackprevious",
+];
+
+const PUNCTUATION_KEYS: &[char] = &['`', '\\', '[', ']', ',', '=', '-', '.', '\'', ';', '/'];
+
+fn modifier_name(token: &str) -> Option<&'static str> {
+    let lower = token.to_lowercase();
+    MODIFIERS
+        .iter()
+        .find(|(_, aliases)| aliases.contains(&lower.as_str()))
+        .map(|(name, _)| *name)
+}
+
+fn is_valid_key(token: &str) -> bool {
+    let lower = token.to_lowercase();
+    let key = lower
+        .strip_prefix("key")
+        .or_else(|| lower.strip_prefix("digit"))
+        .filter(|rest| rest.len() == 1)
+        .unwrap_or(&lower);
+
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return c.is_ascii_alphanumeric() || PUNCTUATION_KEYS.contains(&c);
+    }
+    if let Some(n) = key.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+        return (1..=24).contains(&n);
+    }
+    if let Some(d) = key
+        .strip_prefix("numpad")
+        .or_else(|| key.strip_prefix("num"))
+    {
+        if d.len() == 1 && d.chars().all(|c| c.is_ascii_digit()) {
+            return true;
+        }
+    }
+    NAMED_KEYS.contains(&key)
+}
+
+/// Check that `accel` is well formed: zero or more distinct modifiers
+/// followed by exactly one non-modifier key.
+pub fn validate_accelerator(accel: &str) -> Result<(), String> {
+    let accel = accel.trim();
+    if accel.is_empty() {
+        return Err("Shortcut is empty".to_string());
+    }
+
+    let tokens: Vec<&str> = accel.split('+').map(str::trim).collect();
+    if tokens.iter().any(|t| t.is_empty()) {
+        return Err(format!(
+            "Invalid shortcut '{}': empty key between '+' separators (use 'Equal' for the =/+ key)",
+            accel
+        ));
+    }
+
+    let (key, modifiers) = tokens
+        .split_last()
+        .expect("split yields at least one token");
+    let mut seen: Vec<&str> = Vec::new();
+    for token in modifiers {
+        let name = modifier_name(token).ok_or_else(|| {
+            if is_valid_key(token) {
+                format!(
+                    "Invalid shortcut '{}': only one non-modifier key is allowed",
+                    accel
+                )
+            } else {
+                format!("Invalid shortcut '{}': unknown modifier '{}'", accel, token)
+            }
+        })?;
+        if seen.contains(&name) {
+            return Err(format!(
+                "Invalid shortcut '{}': modifier '{}' is repeated",
+                accel, token
+            ));
+        }
+        seen.push(name);
+    }
+
+    if modifier_name(key).is_some() {
+        return Err(format!(
+            "Invalid shortcut '{}': must end with a non-modifier key",
+            accel
+        ));
+    }
+    if !is_valid_key(key) {
+        return Err(format!(
+            "Invalid shortcut '{}': unknown key '{}'",
+            accel, key
+        ));
+    }
+
+    // Final word goes to the plugin's own parser where it is available.
+    #[cfg(desktop)]
+    accel
+        .parse::<tauri_plugin_global_shortcut::Shortcut>()
+        .map_err(|e| format!("Invalid shortcut '{}': {}", accel, e))?;
+
+    Ok(())
+}
+
+/// Validate an optional accelerator field; blank means "no shortcut".
+pub fn validate_optional_accelerator(accel: Option<&str>) -> Result<(), String> {
+    match accel.map(str::trim) {
+        Some(a) if !a.is_empty() => validate_accelerator(a),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_accelerators() {
+        for accel in [
+            "CommandOrControl+Shift+Space",
+            "Alt+F12",
+            "ctrl+k",
+            "Super+Digit1",
+            "Shift+Equal",
+            "Control+Alt+ArrowUp",
+            "F5",
+            "Cmd+Period",
+        ] {
+            assert_eq!(validate_accelerator(accel), Ok(()), "{accel}");
+        }
+    }
+
+    #[test]
+    fn invalid_accelerators() {
+        let cases = [
+            ("", "empty"),
+            ("Ctrl+", "empty key"),
+            ("Ctrl++", "empty key"),
+            ("Ctrl+Shift", "must end with a non-modifier key"),
+            ("Ctrl+Control+K", "is repeated"),
+            ("Ctrl+A+B", "only one non-modifier key"),
+            ("Hyper+K", "unknown modifier 'Hyper'"),
+            ("Ctrl+F25", "unknown key 'F25'"),
+            ("Alt+Banana", "unknown key 'Banana'"),
+        ];
+        for (accel, expected) in cases {
+            let err = validate_accelerator(accel).unwrap_err();
+            assert!(err.contains(expected), "{accel}: {err}");
+        }
+    }
+
+    #[test]
+    fn blank_optional_accelerator_is_allowed() {
+        assert_eq!(validate_optional_accelerator(None), Ok(()));
+        assert_eq!(validate_optional_accelerator(Some("  ")), Ok(()));
+        assert!(validate_optional_accelerator(Some("Ctrl+")).is_err());
+    }
+}
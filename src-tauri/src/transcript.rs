@@ -0,0 +1,268 @@
+//! Raw provider traffic transcripts for debugging prompts.
+//!
+//! When the `log_raw_requests` setting is on, `send_message` attaches a
+//! `Transcript` to the provider config and every outgoing request and raw
+//! stream line is appended to `<app_log_dir>/transcripts/<conversation_id>.log`.
+//! Credentials in headers and query strings are redacted before writing.
+//!
+//! Independently of that setting, the last request of every send is kept in
+//! memory (`LastRequests`) for `get_last_request_debug`.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+/// Headers whose values are credentials.
+pub(crate) const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "x-api-key",
+    "api-key",
+    "x-goog-api-key",
+    "cookie",
+];
+
+/// Query parameters whose values are credentials.
+pub(crate) const SECRET_QUERY_PARAMS: &[&str] = &[
+    "key",
+    "api-key",
+    "api_key",
+    "apikey",
+    "access_token",
+    "token",
+];
+
+#[derive(Debug)]
+pub struct Transcript {
+    file: Mutex<File>,
+}
+
+impl Transcript {
+    pub fn open(log_dir: &Path, conversation_id: &str) -> Result<Self, String> {
+        let dir = log_dir.join("transcripts");
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create transcript dir: {e}"))?;
+        // Conversation IDs are UUIDs, but never let one escape the directory.
+        let name: String = conversation_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("{name}.log")))
+            .map_err(|e| format!("Failed to open transcript: {e}"))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(&self, text: &str) {
+        if let Ok(mut file) = self.file.lock() {
+            if let Err(e) = file.write_all(text.as_bytes()) {
+                tracing::warn!("[transcript] write failed: {}", e);
+            }
+        }
+    }
+
+    /// Record an outgoing request: method, URL, headers and body.
+    pub fn request(&self, builder: &reqwest::RequestBuilder) {
+        if let Some(text) = render_request(builder) {
+            self.write(&text);
+        }
+    }
+
+    /// Record one raw line of the response stream.
+    pub fn response_line(&self, line: &str) {
+        self.write(&format!("<<< {line}\n"));
+    }
+}
+
+/// The most recent request sent with one provider config, redacted.
+#[derive(Debug, Default)]
+pub struct RequestCapture {
+    last: Mutex<Option<String>>,
+}
+
+impl RequestCapture {
+    pub fn request(&self, builder: &reqwest::RequestBuilder) {
+        if let Some(text) = render_request(builder) {
+            if let Ok(mut last) = self.last.lock() {
+                *last = Some(text);
+            }
+        }
+    }
+
+    pub fn last(&self) -> Option<String> {
+        self.last.lock().ok()?.clone()
+    }
+}
+
+/// Last request captured per conversation, held as Tauri state.
+/// `execute_ai_command` has no conversation and uses `AI_COMMAND_KEY`.
+#[derive(Debug, Default)]
+pub struct LastRequests {
+    captures: Mutex<HashMap<String, Arc<RequestCapture>>>,
+}
+
+/// `LastRequests` key for the overlay's AI commands.
+pub const AI_COMMAND_KEY: &str = "ai_command";
+
+impl LastRequests {
+    /// Empty capture for `key`, replacing whatever the previous send left.
+    pub fn start(&self, key: &str) -> Arc<RequestCapture> {
+        let capture = Arc::new(RequestCapture::default());
+        if let Ok(mut captures) = self.captures.lock() {
+            captures.insert(key.to_string(), capture.clone());
+        }
+        capture
+    }
+
+    pub fn last(&self, key: &str) -> Option<String> {
+        self.captures.lock().ok()?.get(key)?.last()
+    }
+}
+
+/// Method, URL, headers and body of a request with credentials redacted.
+fn render_request(builder: &reqwest::RequestBuilder) -> Option<String> {
+    let request = builder.try_clone().and_then(|b| b.build().ok())?;
+    let headers: Vec<(String, String)> = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.as_str().to_string(),
+                value.to_str().unwrap_or("<binary>").to_string(),
+            )
+        })
+        .collect();
+    let body = request
+        .body()
+        .and_then(|b| b.as_bytes())
+        .map(|b| String::from_utf8_lossy(b).into_owned())
+        .unwrap_or_default();
+    Some(format_request(
+        request.method().as_str(),
+        request.url().as_str(),
+        &headers,
+        &body,
+    ))
+}
+
+/// Render a request for the transcript with credentials redacted.
+pub fn format_request(method: &str, url: &str, headers: &[(String, String)], body: &str) -> String {
+    let timestamp = chrono::Utc::now().to_rfc3339();
+    let mut out = format!("\n=== {timestamp}\n>>> {method} {}\n", redact_url(url));
+    for (name, value) in headers {
+        out.push_str(&format!(">>> {}: {}\n", name, redact_header(name, value)));
+    }
+    out.push_str(&format!(">>> {body}\n"));
+    out
+}
+
+pub fn redact_header(name: &str, value: &str) -> String {
+    if SECRET_HEADERS.contains(&name.to_lowercase().as_str()) {
+        // Keep the scheme so "Bearer" vs "Basic" is still visible.
+        match value.split_once(' ') {
+            Some((scheme, _)) if name.eq_ignore_ascii_case("authorization") => {
+                format!("{scheme} {REDACTED}")
+            }
+            _ => REDACTED.to_string(),
+        }
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn redact_url(url: &str) -> String {
+    let Some((base, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if SECRET_QUERY_PARAMS.contains(&key.to_lowercase().as_str()) => {
+                format!("{key}={REDACTED}")
+            }
+            _ => pair.to_string(),
+        })
+        .collect();
+    format!("{base}?{}", query.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_are_redacted() {
+        assert_eq!(
+            redact_header("Authorization", "Bearer sk-secret"),
+            "Bearer [REDACTED]"
+        );
+        assert_eq!(redact_header("x-api-key", "sk-secret"), REDACTED);
+        assert_eq!(
+            redact_header("content-type", "application/json"),
+            "application/json"
+        );
+        assert_eq!(
+            redact_url("https://x.io/v1/chat?key=sk-secret&alt=sse"),
+            "https://x.io/v1/chat?key=[REDACTED]&alt=sse"
+        );
+    }
+
+    #[test]
+    fn transcript_never_holds_the_api_key() {
+        let dir = std::env::temp_dir().join(format!("zitong-transcript-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let transcript = Transcript::open(&dir, "abc/../123").unwrap();
+        let request = reqwest::Client::new()
+            .post("https://x.io/v1/chat?key=sk-secret&alt=sse")
+            .header("Authorization", "Bearer sk-secret")
+            .json(&serde_json::json!({ "model": "m" }));
+        transcript.request(&request);
+        transcript.response_line("data: hi");
+
+        let text = std::fs::read_to_string(dir.join("transcripts/abc____123.log")).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(text.contains("authorization: Bearer [REDACTED]"), "{text}");
+        assert!(text.contains(r#">>> {"model":"m"}"#), "{text}");
+        assert!(text.contains("<<< data: hi"), "{text}");
+        assert!(!text.contains("sk-secret"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn last_request_is_stashed_without_credentials() {
+        use crate::providers::tests::{http_response, mock_config, mock_server, openai_sse};
+        use crate::providers::{stream_chat, ChatMessage};
+
+        let url =
+            mock_server(|_| http_response("200 OK", "text/event-stream", &openai_sse(&["Hi"])))
+                .await;
+        let store = LastRequests::default();
+        assert!(store.last("c1").is_none());
+        let mut config = mock_config(url);
+        config.capture = Some(store.start("c1"));
+        let messages = [ChatMessage {
+            role: "user".into(),
+            content: "hello there".into(),
+        }];
+        stream_chat(&config, &messages, |_| {}).await.unwrap();
+
+        let last = store.last("c1").unwrap();
+        assert!(last.contains("hello there"), "{last}");
+        assert!(last.contains("authorization: Bearer [REDACTED]"), "{last}");
+        assert!(!last.contains("sk-test"), "{last}");
+        assert!(store.last("c2").is_none());
+    }
+}
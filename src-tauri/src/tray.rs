@@ -0,0 +1,282 @@
+//! Tray icon variants chosen from the `theme` setting, and the tray menu.
+//!
+//! `system` keeps the monochrome template image so macOS tints it for the
+//! menu bar; the explicit themes use fixed-colour icons instead.
+//!
+//! The menu lists pinned conversations in a submenu and is rebuilt when a
+//! conversation change event changes that list.
+
+use std::sync::Mutex;
+
+use tauri::image::Image;
+use tauri::menu::{Menu, MenuBuilder, MenuItemBuilder, SubmenuBuilder};
+use tauri::{AppHandle, Listener, Manager};
+
+use crate::db::{Conversation, Database};
+use crate::events;
+
+pub const TRAY_ID: &str = "main";
+
+/// Most pinned conversations listed in the tray menu.
+pub const MAX_PINNED_ITEMS: usize = 10;
+
+const PINNED_LABEL_MAX_CHARS: usize = 40;
+
+/// Menu item id prefix for pinned conversations; the rest is the
+/// conversation id.
+const PINNED_ITEM_PREFIX: &str = "pinned:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayIconVariant {
+    /// Black glyph used as a macOS template image.
+    Monochrome,
+    /// White glyph for dark menu bars.
+    Dark,
+    /// Brand-violet glyph for light menu bars.
+    Colored,
+}
+
+impl TrayIconVariant {
+    pub fn for_theme(theme: &str) -> Self {
+        match theme {
+            "dark" => Self::Dark,
+            "light" => Self::Colored,
+            _ => Self::Monochrome,
+        }
+    }
+
+    pub fn bytes(self) -> &'static [u8] {
+        match self {
+            Self::Monochrome => include_bytes!("../icons/tray-icon.png"),
+            Self::Dark => include_bytes!("../icons/tray-icon-dark.png"),
+            Self::Colored => include_bytes!("../icons/tray-icon-color.png"),
+        }
+    }
+
+    /// Only the monochrome icon may be a template: macOS would flatten the
+    /// coloured ones to black.
+    pub fn is_template(self) -> bool {
+        self == Self::Monochrome
+    }
+
+    pub fn image(self) -> tauri::Result<Image<'static>> {
+        Image::from_bytes(self.bytes())
+    }
+}
+
+/// Swap the tray icon to match `theme`. A missing tray is not an error.
+pub fn apply_theme(app: &AppHandle, theme: &str) -> Result<(), String> {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return Ok(());
+    };
+    let variant = TrayIconVariant::for_theme(theme);
+    tray.set_icon(Some(variant.image().map_err(|e| e.to_string())?))
+        .map_err(|e| e.to_string())?;
+    tray.set_icon_as_template(variant.is_template())
+        .map_err(|e| e.to_string())
+}
+
+/// One entry of the tray's pinned-conversations submenu.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedMenuItem {
+    /// Menu item id; see `pinned_conversation_id`.
+    pub id: String,
+    pub label: String,
+}
+
+/// Submenu entries for the first `MAX_PINNED_ITEMS` pinned, unarchived
+/// conversations, in the order given.
+pub fn pinned_menu_items(conversations: &[Conversation]) -> Vec<PinnedMenuItem> {
+    conversations
+        .iter()
+        .filter(|c| c.is_pinned && !c.is_archived)
+        .take(MAX_PINNED_ITEMS)
+        .map(|c| {
+            let title = c.title.trim();
+            PinnedMenuItem {
+                id: format!("{PINNED_ITEM_PREFIX}{}", c.id),
+                label: if title.is_empty() {
+                    "Untitled".to_string()
+                } else {
+                    crate::commands::truncate_chars(title, PINNED_LABEL_MAX_CHARS)
+                },
+            }
+        })
+        .collect()
+}
+
+/// The conversation a pinned-conversation menu item opens.
+pub fn pinned_conversation_id(menu_id: &str) -> Option<&str> {
+    menu_id.strip_prefix(PINNED_ITEM_PREFIX)
+}
+
+/// Pinned items the tray menu was last built with.
+static MENU_PINS: Mutex<Vec<PinnedMenuItem>> = Mutex::new(Vec::new());
+
+fn current_pins(app: &AppHandle) -> Vec<PinnedMenuItem> {
+    match app.state::<Database>().list_conversations() {
+        Ok(conversations) => pinned_menu_items(&conversations),
+        Err(e) => {
+            tracing::warn!("[tray] failed to list pinned conversations: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Build the tray menu: show, command palette, pinned conversations (when
+/// there are any) and quit.
+pub fn build_menu(app: &AppHandle) -> tauri::Result<Menu<tauri::Wry>> {
+    let pins = current_pins(app);
+    let show_main = MenuItemBuilder::with_id("show_main", "Show Zitong").build(app)?;
+    let command_palette = MenuItemBuilder::with_id("command_palette", "Command Palette")
+        .accelerator("CommandOrControl+Shift+Space")
+        .build(app)?;
+    let quit = MenuItemBuilder::with_id("quit", "Quit Zitong")
+        .accelerator("CommandOrControl+Q")
+        .build(app)?;
+
+    let mut menu = MenuBuilder::new(app)
+        .item(&show_main)
+        .item(&command_palette);
+    if !pins.is_empty() {
+        let submenu = pins
+            .iter()
+            .fold(
+                SubmenuBuilder::new(app, "Pinned Conversations"),
+                |submenu, pin| submenu.text(&pin.id, &pin.label),
+            )
+            .build()?;
+        menu = menu.item(&submenu);
+    }
+    let menu = menu.separator().item(&quit).build()?;
+    *MENU_PINS.lock().unwrap_or_else(|e| e.into_inner()) = pins;
+    Ok(menu)
+}
+
+/// Rebuild the tray menu if the pinned conversations changed since it was
+/// last built.
+pub fn refresh_menu(app: &AppHandle) {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    if *MENU_PINS.lock().unwrap_or_else(|e| e.into_inner()) == current_pins(app) {
+        return;
+    }
+    let rebuilt = build_menu(app).and_then(|menu| tray.set_menu(Some(menu)));
+    if let Err(e) = rebuilt {
+        tracing::warn!("[tray] failed to rebuild menu: {}", e);
+    }
+}
+
+/// Keep the pinned-conversations submenu in sync with conversation change
+/// events (pins, renames, archives and deletions).
+pub fn watch_pins(app: &AppHandle) {
+    for event in [
+        events::CONVERSATION_CREATED,
+        events::CONVERSATION_UPDATED,
+        events::CONVERSATION_DELETED,
+        events::CONVERSATIONS_PRUNED,
+    ] {
+        let handle = app.clone();
+        app.listen_any(event, move |_| refresh_menu(&handle));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tray_icon_follows_the_theme() {
+        assert_eq!(TrayIconVariant::for_theme("dark"), TrayIconVariant::Dark);
+        assert_eq!(
+            TrayIconVariant::for_theme("light"),
+            TrayIconVariant::Colored
+        );
+        assert_eq!(
+            TrayIconVariant::for_theme("system"),
+            TrayIconVariant::Monochrome
+        );
+        assert_eq!(TrayIconVariant::for_theme(""), TrayIconVariant::Monochrome);
+
+        let variants = [
+            TrayIconVariant::Monochrome,
+            TrayIconVariant::Dark,
+            TrayIconVariant::Colored,
+        ];
+        for variant in variants {
+            assert!(
+                variant.bytes().starts_with(b"\x89PNG\r\n\x1a\n"),
+                "{:?}",
+                variant
+            );
+            assert_eq!(
+                variant.is_template(),
+                variant == TrayIconVariant::Monochrome
+            );
+        }
+        assert_ne!(
+            TrayIconVariant::Dark.bytes(),
+            TrayIconVariant::Colored.bytes()
+        );
+        assert_ne!(
+            TrayIconVariant::Dark.bytes(),
+            TrayIconVariant::Monochrome.bytes()
+        );
+    }
+
+    fn conversation(id: &str, title: &str, is_pinned: bool, is_archived: bool) -> Conversation {
+        Conversation {
+            id: id.into(),
+            title: title.into(),
+            model: "m".into(),
+            provider_id: "p".into(),
+            system_prompt: None,
+            created_at: 0,
+            updated_at: 0,
+            is_archived,
+            folder_id: None,
+            is_pinned,
+            temperature: None,
+        }
+    }
+
+    #[test]
+    fn pinned_menu_lists_the_top_pinned_conversations() {
+        let long_title = "a".repeat(PINNED_LABEL_MAX_CHARS + 5);
+        let mut conversations = vec![
+            conversation("c1", "Trip plan", true, false),
+            conversation("c2", "Not pinned", false, false),
+            conversation("c3", "Archived", true, true),
+            conversation("c4", "  ", true, false),
+            conversation("c5", &long_title, true, false),
+        ];
+        let items = pinned_menu_items(&conversations);
+
+        assert_eq!(
+            items[..2],
+            [
+                PinnedMenuItem {
+                    id: "pinned:c1".into(),
+                    label: "Trip plan".into(),
+                },
+                PinnedMenuItem {
+                    id: "pinned:c4".into(),
+                    label: "Untitled".into(),
+                },
+            ]
+        );
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items[2].label,
+            format!("{}…", "a".repeat(PINNED_LABEL_MAX_CHARS))
+        );
+        assert_eq!(pinned_conversation_id(&items[0].id), Some("c1"));
+        assert_eq!(pinned_conversation_id("quit"), None);
+
+        conversations.extend(
+            (0..MAX_PINNED_ITEMS).map(|i| conversation(&format!("x{}", i), "Extra", true, false)),
+        );
+        assert_eq!(pinned_menu_items(&conversations).len(), MAX_PINNED_ITEMS);
+    }
+}
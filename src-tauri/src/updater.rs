@@ -108,7 +108,10 @@ pub async fn get_last_update_check(app: AppHandle) -> Result<Option<u64>, String
     let db = app
         .try_state::<Database>()
         .ok_or_else(|| "database not ready".to_string())?;
-    match db.get_setting_raw(LAST_CHECK_KEY).map_err(|e| e.to_string())? {
+    match db
+        .get_setting_raw(LAST_CHECK_KEY)
+        .map_err(|e| e.to_string())?
+    {
         Some(s) => Ok(s.parse().ok()),
         None => Ok(None),
     }
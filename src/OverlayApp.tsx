@@ -94,12 +94,19 @@ export function OverlayApp() {
       setPhase({ kind: "loading", label: cmd.label });
       try {
         const { systemPrompt, userText } = renderPromptTemplate(cmd.systemPrompt, selectedText);
-        const result = await commands.executeAiCommand({
+        const { text: result, applied } = await commands.executeAiCommand({
           selectedText: userText,
           systemPrompt,
           providerId: cmd.providerId ?? undefined,
           model: cmd.model ?? undefined,
+          behavior: cmd.behavior,
+          commandId: cmd.id,
         });
+        // Live replace already pasted the result and hid the overlay.
+        if (applied) {
+          setPhase({ kind: "idle" });
+          return;
+        }
         setPhase({ kind: "result", result, selectedText, command: cmd });
         setFollowUp("");
         // Focus the follow-up input after render
@@ -206,7 +213,7 @@ export function OverlayApp() {
         const systemPrompt = phase.selectedText
           ? `The user's original text is:\n"""\n${phase.selectedText}\n"""\n\n${instruction} Preserve the user's original tone and style.`
           : instruction;
-        const result = await commands.executeAiCommand({
+        const { text: result } = await commands.executeAiCommand({
           selectedText: phase.result,
           systemPrompt,
           providerId: phase.command.providerId ?? undefined,
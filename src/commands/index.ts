@@ -1,16 +1,30 @@
-import { invoke } from "@tauri-apps/api/core";
+import { invoke, type Channel } from "@tauri-apps/api/core";
 import type {
   Conversation,
+  ConversationDetail,
   Message,
   CreateConversationRequest,
   Provider,
+  ProviderKey,
   AppSettings,
   PromptTemplate,
   Folder,
   ModelInfo,
+  ModelListing,
+  ProviderModels,
+  ModelSort,
+  ConnectionTestResult,
+  DefaultsStatus,
+  Capabilities,
   DeviceCodeResponse,
+  CopilotPollState,
   AiCommand,
+  AiCommandOutput,
   Assistant,
+  Attachment,
+  StreamEvent,
+  ClipboardImage,
+  DailyUsage,
 } from "@/types";
 
 // ============================================
@@ -27,10 +41,30 @@ export async function listConversations(): Promise<Conversation[]> {
   return invoke("list_conversations");
 }
 
+/** Conversations set to, or with replies from, `model`; archived included. */
+export async function conversationsUsingModel(
+  model: string
+): Promise<Conversation[]> {
+  return invoke("conversations_using_model", { model });
+}
+
+/** Conversations set to the provider; archived included. */
+export async function conversationsUsingProvider(
+  providerId: string
+): Promise<Conversation[]> {
+  return invoke("conversations_using_provider", { providerId });
+}
+
 export async function getConversation(id: string): Promise<Conversation> {
   return invoke("get_conversation", { id });
 }
 
+export async function getConversationDetail(
+  id: string
+): Promise<ConversationDetail> {
+  return invoke("get_conversation_detail", { id });
+}
+
 export async function updateConversationTitle(
   id: string,
   title: string
@@ -38,10 +72,29 @@ export async function updateConversationTitle(
   return invoke("update_conversation_title", { id, title });
 }
 
+export async function updateConversationTemperature(
+  id: string,
+  temperature: number | null
+): Promise<void> {
+  return invoke("update_conversation_temperature", { id, temperature });
+}
+
+/** Deletes every message but keeps the conversation; returns the count. */
+export async function clearConversation(id: string): Promise<number> {
+  return invoke("clear_conversation", { id });
+}
+
 export async function deleteConversation(id: string): Promise<void> {
   return invoke("delete_conversation", { id });
 }
 
+export async function setConversationPinned(
+  id: string,
+  pinned: boolean
+): Promise<void> {
+  return invoke("set_conversation_pinned", { id, pinned });
+}
+
 export async function archiveConversation(
   id: string,
   archived: boolean
@@ -55,6 +108,13 @@ export async function searchConversations(
   return invoke("search_conversations", { query });
 }
 
+export async function searchMessagesInConversation(
+  conversationId: string,
+  query: string
+): Promise<Message[]> {
+  return invoke("search_messages_in_conversation", { conversationId, query });
+}
+
 export async function generateConversationTitle(req: {
   conversationId: string;
   userMessage: string;
@@ -65,6 +125,16 @@ export async function generateConversationTitle(req: {
   return invoke("generate_conversation_title", { req });
 }
 
+/** Title every conversation still on its default title; returns the count. */
+export async function backfillTitles(): Promise<number> {
+  return invoke("backfill_titles");
+}
+
+/** A conversation as a standalone HTML page with Markdown rendered. */
+export async function exportConversationHtml(id: string): Promise<string> {
+  return invoke("export_conversation_html", { id });
+}
+
 // ============================================
 // Message Commands
 // ============================================
@@ -79,32 +149,138 @@ export async function deleteMessage(id: string): Promise<void> {
   return invoke("delete_message", { id });
 }
 
+export async function setMessageIncludeInContext(
+  messageId: string,
+  include: boolean
+): Promise<void> {
+  return invoke("set_message_include_in_context", { messageId, include });
+}
+
+export async function setMessageSticky(
+  messageId: string,
+  sticky: boolean
+): Promise<void> {
+  return invoke("set_message_sticky", { messageId, sticky });
+}
+
+/** Relabel messages' model (only those labeled `from`, if given); returns how many changed. */
+export async function relabelMessagesModel(
+  conversationId: string,
+  to: string,
+  from?: string
+): Promise<number> {
+  return invoke("relabel_messages_model", { conversationId, from, to });
+}
+
+export async function normalizeConversationOrder(
+  conversationId: string
+): Promise<number> {
+  return invoke("normalize_conversation_order", { conversationId });
+}
+
+/** The last request sent for a conversation, credentials redacted. */
+export async function getLastRequestDebug(
+  conversationId: string
+): Promise<string | null> {
+  return invoke("get_last_request_debug", { conversationId });
+}
+
+export async function addAttachment(
+  messageId: string,
+  filePath: string,
+  providerId: string,
+  model: string,
+  mimeType?: string
+): Promise<Attachment> {
+  return invoke("add_attachment", {
+    messageId,
+    filePath,
+    mimeType: mimeType ?? null,
+    providerId,
+    model,
+  });
+}
+
 // ============================================
 // Provider Commands
 // ============================================
 
+/** Today's token usage per provider, for comparing against `dailyTokenCap`. */
+export async function getDailyUsage(): Promise<DailyUsage[]> {
+  return invoke("get_daily_usage");
+}
+
 export async function listProviders(): Promise<Provider[]> {
   return invoke("list_providers");
 }
 
-export async function saveProvider(provider: Provider): Promise<void> {
-  return invoke("save_provider", { provider });
+/**
+ * Save a provider. Returns warnings about a suspicious base URL (e.g. an
+ * https:// Ollama URL); with `strict` they reject the save instead.
+ */
+export async function saveProvider(
+  provider: Provider,
+  strict?: boolean
+): Promise<string[]> {
+  return invoke("save_provider", { provider, strict });
 }
 
 export async function deleteProvider(id: string): Promise<void> {
   return invoke("delete_provider", { id });
 }
 
+export async function addProviderKey(
+  providerId: string,
+  key: string
+): Promise<ProviderKey> {
+  return invoke("add_provider_key", { providerId, key });
+}
+
+export async function listProviderKeys(
+  providerId: string
+): Promise<ProviderKey[]> {
+  return invoke("list_provider_keys", { providerId });
+}
+
+export async function removeProviderKey(id: string): Promise<void> {
+  return invoke("remove_provider_key", { id });
+}
+
 export async function testProviderConnection(
   id: string
-): Promise<{ success: boolean; error?: string }> {
+): Promise<ConnectionTestResult> {
   return invoke("test_provider_connection", { id });
 }
 
+export async function validateDefaults(): Promise<DefaultsStatus> {
+  return invoke("validate_defaults");
+}
+
+export async function probeCapabilities(
+  providerId: string,
+  model: string,
+  refresh?: boolean
+): Promise<Capabilities> {
+  return invoke("probe_capabilities", { providerId, model, refresh });
+}
+
 export async function listModels(
-  providerId: string
+  providerId: string,
+  sort?: ModelSort
 ): Promise<ModelInfo[]> {
-  return invoke("list_models", { providerId });
+  return invoke("list_models", { providerId, sort });
+}
+
+export async function listModelsWithMetadata(
+  providerId: string,
+  sort?: ModelSort
+): Promise<ModelListing> {
+  return invoke("list_models_with_metadata", { providerId, sort });
+}
+
+/** Model lists for every enabled provider; also broadcast as `models-updated`. */
+export async function listModelsForAll(): Promise<ProviderModels[]> {
+  return invoke("list_models_for_all");
 }
 
 // ============================================
@@ -115,6 +291,14 @@ export async function copilotStartDeviceFlow(): Promise<DeviceCodeResponse> {
   return invoke("copilot_start_device_flow");
 }
 
+export async function copilotPollAuthState(
+  deviceCode: string,
+  interval?: number
+): Promise<CopilotPollState> {
+  return invoke("copilot_poll_auth_state", { deviceCode, interval });
+}
+
+/** @deprecated Use `copilotPollAuthState`. */
 export async function copilotPollAuth(
   deviceCode: string
 ): Promise<string> {
@@ -127,6 +311,15 @@ export async function copilotExchangeToken(
   return invoke("copilot_exchange_token", { githubToken });
 }
 
+/** Stream error message sent when a Copilot sign-in has expired or was revoked. */
+export const COPILOT_AUTH_EXPIRED =
+  "GitHub Copilot sign-in has expired or was revoked. Sign in again in Settings → Providers.";
+
+/** Whether a Copilot provider's stored sign-in still works; `false` means sign in again. */
+export async function copilotCheckAuth(providerId: string): Promise<boolean> {
+  return invoke("copilot_check_auth", { providerId });
+}
+
 // ============================================
 // Settings Commands
 // ============================================
@@ -139,12 +332,34 @@ export async function saveSettings(settings: AppSettings): Promise<void> {
   return invoke("save_settings", { settings });
 }
 
+/**
+ * Set just the default provider and model (both empty clears them).
+ * `verifyModel` also checks the model is in the provider's model list.
+ */
+export async function setDefaultModel(
+  providerId: string,
+  model: string,
+  verifyModel?: boolean
+): Promise<void> {
+  return invoke("set_default_model", { providerId, model, verifyModel });
+}
+
 // ============================================
 // Prompt Template Commands
 // ============================================
 
-export async function listPromptTemplates(): Promise<PromptTemplate[]> {
-  return invoke("list_prompt_templates");
+export async function listPromptTemplates(
+  mostUsedFirst?: boolean
+): Promise<PromptTemplate[]> {
+  return invoke("list_prompt_templates", { mostUsedFirst });
+}
+
+/** Fill a template's `{{name}}` placeholders and count the use. */
+export async function renderPromptTemplate(
+  id: string,
+  values: Record<string, string>
+): Promise<string> {
+  return invoke("render_prompt_template", { id, values });
 }
 
 export async function savePromptTemplate(
@@ -153,6 +368,10 @@ export async function savePromptTemplate(
   return invoke("save_prompt_template", { template });
 }
 
+export async function validateTemplate(content: string): Promise<string[]> {
+  return invoke("validate_template", { content });
+}
+
 export async function deletePromptTemplate(id: string): Promise<void> {
   return invoke("delete_prompt_template", { id });
 }
@@ -177,8 +396,10 @@ export async function deleteFolder(id: string): Promise<void> {
 // AI Command Commands
 // ============================================
 
-export async function listAiCommands(): Promise<AiCommand[]> {
-  return invoke("list_ai_commands");
+export async function listAiCommands(
+  mostUsedFirst?: boolean
+): Promise<AiCommand[]> {
+  return invoke("list_ai_commands", { mostUsedFirst });
 }
 
 export async function saveAiCommand(command: AiCommand): Promise<void> {
@@ -189,15 +410,39 @@ export async function deleteAiCommand(id: string): Promise<void> {
   return invoke("delete_ai_command", { id });
 }
 
+/** Re-add the built-in AI commands; `overwrite` also resets existing ones. */
+export async function restoreDefaultAiCommands(
+  overwrite: boolean
+): Promise<number> {
+  return invoke("restore_default_ai_commands", { overwrite });
+}
+
 export async function executeAiCommand(req: {
   selectedText: string;
   systemPrompt: string;
   providerId?: string;
   model?: string;
-}): Promise<string> {
+  /** Lets `replace_selection`/`insert_after` stream into the target app when `live_replace` is on. */
+  behavior?: string;
+  /** The stored command being run; bumps its use count. */
+  commandId?: string;
+}): Promise<AiCommandOutput> {
   return invoke("execute_ai_command", { req });
 }
 
+/** Open a new chat for `userText` now and stream the reply into it. */
+export async function openInNewChatStreaming(
+  req: {
+    userText: string;
+    providerId: string;
+    model: string;
+    systemPrompt?: string;
+  },
+  onEvent: Channel<StreamEvent>
+): Promise<string> {
+  return invoke("open_in_new_chat_streaming", { req, onEvent });
+}
+
 // ============================================
 // Assistant Commands
 // ============================================
@@ -210,10 +455,36 @@ export async function saveAssistant(assistant: Assistant): Promise<void> {
   return invoke("save_assistant", { assistant });
 }
 
+/** Copy an assistant (never as the default) and return the copy. */
+export async function duplicateAssistant(id: string): Promise<Assistant> {
+  return invoke("duplicate_assistant", { id });
+}
+
 export async function deleteAssistant(id: string): Promise<void> {
   return invoke("delete_assistant", { id });
 }
 
+/** Re-add the built-in assistants; `overwrite` also resets existing ones. */
+export async function restoreDefaultAssistants(
+  overwrite: boolean
+): Promise<number> {
+  return invoke("restore_default_assistants", { overwrite });
+}
+
+// ============================================
+// Clipboard Commands
+// ============================================
+
+/** The clipboard image as PNG; rejects when the clipboard holds no image. */
+export async function readClipboardImage(): Promise<ClipboardImage> {
+  return invoke("read_clipboard_image");
+}
+
+/** Put a base64-encoded PNG on the clipboard. */
+export async function writeClipboardImage(data: string): Promise<void> {
+  return invoke("write_clipboard_image", { data });
+}
+
 // ============================================
 // Autostart Commands
 // ============================================
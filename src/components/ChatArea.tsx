@@ -12,6 +12,7 @@ import {
   ChevronDown,
   Ban,
   Sparkles,
+  AlertTriangle,
 } from "lucide-react";
 
 export function ChatArea() {
@@ -25,6 +26,7 @@ export function ChatArea() {
     createConversation,
     sendMessage,
     settings,
+    defaultsProblems,
     providers,
     assistants,
     activeAssistantId,
@@ -176,6 +178,16 @@ export function ChatArea() {
               Your cross-platform AI chat client. Start a new conversation or
               select one from the sidebar.
             </p>
+            {defaultsProblems.length > 0 && (
+              <div className="mt-4 max-w-md px-3 py-2 rounded-lg bg-amber-500/10 text-xs text-amber-500 space-y-1">
+                {defaultsProblems.map((problem) => (
+                  <p key={problem} className="flex items-start gap-1.5">
+                    <AlertTriangle className="w-3.5 h-3.5 mt-px shrink-0" />
+                    <span>{problem}</span>
+                  </p>
+                ))}
+              </div>
+            )}
             <div className="mt-8 grid grid-cols-2 gap-3 max-w-sm">
               <QuickAction
                 icon={<Lightbulb className="w-4 h-4 text-primary" />}
@@ -1,7 +1,8 @@
 import { useState, useRef, useCallback, useEffect } from "react";
 import TextareaAutosize from "react-textarea-autosize";
 import { useApp } from "@/context/AppContext";
-import type { SendMessageRequest, ModelInfo } from "@/types";
+import { listen } from "@tauri-apps/api/event";
+import type { SendMessageRequest, ModelInfo, ProviderModels } from "@/types";
 import { listModels } from "@/commands";
 import { ArrowUp, Square, ChevronDown } from "lucide-react";
 
@@ -89,6 +90,19 @@ export function ChatInput() {
     };
   }, [selectedProviderId]);
 
+  // Background refreshes (e.g. on window focus) replace the list in place
+  useEffect(() => {
+    const unlisten = listen<ProviderModels[]>("models-updated", (event) => {
+      const entry = event.payload.find((p) => p.providerId === selectedProviderId);
+      if (entry && !entry.error) {
+        setModels(entry.models);
+      }
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [selectedProviderId]);
+
   const handleSend = useCallback(async () => {
     const content = input.trim();
     if (!content || isStreaming) return;
@@ -169,6 +183,8 @@ export function ChatInput() {
                   <option value="deepseek">DeepSeek</option>
                   <option value="openrouter">OpenRouter</option>
                   <option value="xai">xAI (Grok)</option>
+                  <option value="together">Together AI</option>
+                  <option value="fireworks">Fireworks</option>
                 </>
               )}
             </select>
@@ -1,5 +1,5 @@
 import { useState, useEffect, useRef, useCallback } from "react";
-import { Plus, Trash2 } from "lucide-react";
+import { Copy, Plus, Trash2 } from "lucide-react";
 import type { Assistant, Provider } from "@/types";
 import * as commands from "@/commands";
 
@@ -67,6 +67,18 @@ export function AssistantsTab({ providers, onRefresh }: { providers: Provider[];
     }
   }
 
+  async function handleDuplicate(id: string) {
+    try {
+      const copy = await commands.duplicateAssistant(id);
+      // The copy shifts the sort order of everything after the original.
+      setAssistants(await commands.listAssistants());
+      setSelectedId(copy.id);
+      onRefresh?.();
+    } catch (e) {
+      console.error("Failed to duplicate assistant:", e);
+    }
+  }
+
   async function handleDelete(id: string) {
     try {
       await commands.deleteAssistant(id);
@@ -115,6 +127,16 @@ export function AssistantsTab({ providers, onRefresh }: { providers: Provider[];
                   DEFAULT
                 </span>
               )}
+              <button
+                onClick={(e) => {
+                  e.stopPropagation();
+                  handleDuplicate(a.id);
+                }}
+                title="Duplicate"
+                className="opacity-0 group-hover:opacity-100 p-1.5 rounded-lg glass-hover text-text-muted hover:text-text-primary transition-all"
+              >
+                <Copy className="w-3.5 h-3.5" />
+              </button>
               <button
                 onClick={(e) => {
                   e.stopPropagation();
@@ -15,6 +15,8 @@ const FALLBACK_PROVIDERS: Record<
   groq: { abbr: "Gr", bg: "bg-teal-500/15", text: "text-teal-500" },
   deepseek: { abbr: "Ds", bg: "bg-cyan-500/15", text: "text-cyan-500" },
   xai: { abbr: "xA", bg: "bg-slate-500/15", text: "text-slate-400" },
+  together: { abbr: "To", bg: "bg-blue-500/15", text: "text-blue-500" },
+  fireworks: { abbr: "Fw", bg: "bg-violet-500/15", text: "text-violet-500" },
   openai_compatible: { abbr: "OC", bg: "bg-gray-500/15", text: "text-gray-400" },
 };
 
@@ -30,6 +30,8 @@ export function ProvidersTab({
     { id: "deepseek", type: "deepseek", name: "DeepSeek", enabled: true },
     { id: "openrouter", type: "openrouter", name: "OpenRouter", enabled: true },
     { id: "xai", type: "xai", name: "xAI (Grok)", enabled: true },
+    { id: "together", type: "together", name: "Together AI", enabled: true },
+    { id: "fireworks", type: "fireworks", name: "Fireworks", enabled: true },
   ];
 
   const savedById = new Map(providers.map((p) => [p.id, p]));
@@ -41,7 +43,8 @@ export function ProvidersTab({
 
   const handleSave = async (provider: Provider) => {
     try {
-      await commands.saveProvider(provider);
+      const warnings = await commands.saveProvider(provider);
+      warnings.forEach((w) => console.warn(`Provider ${provider.id}: ${w}`));
       await onRefresh();
     } catch (err) {
       console.error("Failed to save provider:", err);
@@ -201,29 +204,40 @@ function ProviderDetail({
       try { await writeText(resp.user_code); } catch {}
       openUrl(resp.verification_uri);
       setCopilotStatus("polling");
-      const interval = (resp.interval || 5) * 1000;
+      let interval = resp.interval || 5;
       const poll = async () => {
         if (cancelledRef.current) return;
         try {
-          const token = await commands.copilotPollAuth(resp.device_code);
+          const result = await commands.copilotPollAuthState(resp.device_code, interval);
           if (cancelledRef.current) return;
-          setApiKey(token);
-          setCopilotStatus("success");
-          try {
-            await commands.saveProvider({ ...provider, apiKey: token, enabled: true });
-          } catch {}
+          switch (result.state) {
+            case "pending":
+            case "slowDown":
+              interval = result.nextInterval;
+              pollingRef.current = setTimeout(poll, interval * 1000);
+              return;
+            case "denied":
+              setCopilotError("Sign-in was denied on GitHub.");
+              setCopilotStatus("error");
+              return;
+            case "expired":
+              setCopilotError("The sign-in code expired. Please try again.");
+              setCopilotStatus("error");
+              return;
+            case "authorized":
+              setApiKey(result.token);
+              setCopilotStatus("success");
+              try {
+                await commands.saveProvider({ ...provider, apiKey: result.token, enabled: true });
+              } catch {}
+          }
         } catch (err) {
           if (cancelledRef.current) return;
-          const errStr = typeof err === "string" ? err : String(err);
-          if (errStr.includes("authorization_pending") || errStr.includes("slow_down")) {
-            pollingRef.current = setTimeout(poll, interval);
-            return;
-          }
-          setCopilotError(errStr);
+          setCopilotError(typeof err === "string" ? err : String(err));
           setCopilotStatus("error");
         }
       };
-      pollingRef.current = setTimeout(poll, interval);
+      pollingRef.current = setTimeout(poll, interval * 1000);
     } catch (err) {
       setCopilotError(typeof err === "string" ? err : String(err));
       setCopilotStatus("error");
@@ -9,6 +9,7 @@ export function TemplatesTab() {
   const [editName, setEditName] = useState("");
   const [editContent, setEditContent] = useState("");
   const [editCategory, setEditCategory] = useState("general");
+  const [contentError, setContentError] = useState<string | null>(null);
 
   const debounceRef = useRef<ReturnType<typeof setTimeout> | null>(null);
   const latestRef = useRef({ editName: "", editContent: "", editCategory: "general" });
@@ -36,7 +37,13 @@ export function TemplatesTab() {
         variables: vars,
         updatedAt: Date.now(),
       };
-      await commands.savePromptTemplate(updated);
+      try {
+        await commands.savePromptTemplate(updated);
+        setContentError(null);
+      } catch (e) {
+        setContentError(String(e));
+        return;
+      }
       await loadTemplates();
     }, 400);
   }, [editingId, templates]);
@@ -100,7 +107,10 @@ export function TemplatesTab() {
       variables: vars,
       updatedAt: Date.now(),
     };
-    commands.savePromptTemplate(updated).then(() => loadTemplates());
+    commands
+      .savePromptTemplate(updated)
+      .then(() => loadTemplates())
+      .catch((e) => console.warn("Template not saved:", e));
   }, [editingId, templates]);
 
   useEffect(() => {
@@ -214,6 +224,9 @@ export function TemplatesTab() {
                   placeholder="Enter your system prompt here. Use {{variable}} for placeholders..."
                   className="w-full px-3 py-2 text-sm rounded-lg glass-input text-text-primary resize-none font-mono"
                 />
+                {contentError && (
+                  <p className="text-xs text-red-400 mt-1">{contentError}</p>
+                )}
               </div>
               {/* Variables preview */}
               {editContent.match(/\{\{(\w+)\}\}/g) && (
@@ -16,6 +16,7 @@ import type {
   Message,
   StreamEvent,
   SendMessageRequest,
+  SendMessageStatus,
   CreateConversationRequest,
   Provider,
   AppSettings,
@@ -41,6 +42,9 @@ interface AppState {
   // Settings
   settings: AppSettings | null;
 
+  /** Problems with the default provider/model found at startup. */
+  defaultsProblems: string[];
+
   // UI state
   isStreaming: boolean;
   streamingContent: string;
@@ -97,6 +101,7 @@ export function AppProvider({ children }: { children: ReactNode }) {
   const [assistants, setAssistants] = useState<Assistant[]>([]);
   const [activeAssistantId, setActiveAssistantId] = useState<string | null>(null);
   const [settings, setSettings] = useState<AppSettings | null>(null);
+  const [defaultsProblems, setDefaultsProblems] = useState<string[]>([]);
   const [isStreaming, setIsStreaming] = useState(false);
   const [streamingContent, setStreamingContent] = useState("");
   const [sidebarOpen, setSidebarOpen] = useState(true);
@@ -211,8 +216,6 @@ export function AppProvider({ children }: { children: ReactNode }) {
       setIsStreaming(true);
       setStreamingContent("");
 
-      const isFirstMessage = messages.length === 0;
-
       // Add user message optimistically
       const userMessage: Message = {
         id: crypto.randomUUID(),
@@ -250,55 +253,52 @@ export function AppProvider({ children }: { children: ReactNode }) {
               setMessages((prev) => [...prev, assistantMessage]);
               setStreamingContent("");
               setIsStreaming(false);
-
-              // Generate a better title from the LLM after the first exchange,
-              // but only if the title is still the default (not manually renamed).
-              if (isFirstMessage) {
-                const currentConv = conversations.find(
-                  (c) => c.id === req.conversationId
-                );
-                if (!currentConv?.title || currentConv.title === "New Chat") {
-                  commands
-                    .generateConversationTitle({
-                      conversationId: req.conversationId,
-                      userMessage: req.content,
-                      assistantMessage: accumulated,
-                      providerId: req.providerId,
-                      model: req.model,
-                    })
-                    .then((title) => {
-                      setConversations((prev) =>
-                        prev.map((c) =>
-                          c.id === req.conversationId ? { ...c, title } : c
-                        )
-                      );
-                    })
-                    .catch((err) =>
-                      console.warn("Failed to generate title:", err)
-                    );
-                }
-              }
               break;
             }
             case "error":
-              console.error("Stream error:", event.data.message);
+              if (event.data.message === commands.COPILOT_AUTH_EXPIRED) {
+                console.warn("Copilot sign-in expired; re-authentication required");
+              } else {
+                console.error("Stream error:", event.data.message);
+              }
               setIsStreaming(false);
               setStreamingContent("");
               break;
+            case "streaming_disabled":
+              // The provider's streaming flag was turned off; the reply
+              // still arrives, in one piece.
+              console.warn("Provider rejected streaming; switched it to non-streaming:", event.data.reason);
+              loadProviders();
+              break;
           }
         };
 
-        await invoke("send_message", {
-          req,
+        const status = await invoke<SendMessageStatus>("send_message", {
+          req: { ...req, clientMessageId: req.clientMessageId ?? userMessage.id },
           onEvent: channel,
         });
+        if (status.duplicate) {
+          setMessages((prev) => prev.filter((m) => m.id !== userMessage.id));
+          if (!status.inProgress) {
+            setIsStreaming(false);
+          }
+          return;
+        }
+
+        // The backend may have auto-titled the conversation.
+        const updated = await commands.getConversation(req.conversationId);
+        setConversations((prev) =>
+          prev.map((c) =>
+            c.id === updated.id ? { ...c, title: updated.title } : c
+          )
+        );
       } catch (err) {
         console.error("Failed to send message:", err);
         setIsStreaming(false);
         setStreamingContent("");
       }
     },
-    [messages.length]
+    [messages.length, loadProviders]
   );
 
   const stopStreaming = useCallback(() => {
@@ -334,6 +334,14 @@ export function AppProvider({ children }: { children: ReactNode }) {
     loadAssistants();
   }, [loadConversations, loadProviders, loadSettings, loadAssistants]);
 
+  // Warn early when the default provider/model can't be used
+  useEffect(() => {
+    commands
+      .validateDefaults()
+      .then((status) => setDefaultsProblems(status.problems))
+      .catch((err) => console.error("Failed to validate defaults:", err));
+  }, []);
+
   // Listen for "settings-changed" event from the settings window
   useEffect(() => {
     type SettingsChangedPayload = {
@@ -357,6 +365,23 @@ export function AppProvider({ children }: { children: ReactNode }) {
     return () => { unlisten.then((fn) => fn()); };
   }, [loadProviders, loadSettings, loadAssistants]);
 
+  // Conversations archived/deleted to stay under max_conversations
+  useEffect(() => {
+    const unlisten = listen<{ conversationIds: string[] }>(
+      "conversations-pruned",
+      (event) => {
+        loadConversations();
+        if (activeConversationId && event.payload.conversationIds.includes(activeConversationId)) {
+          setActiveConversationId(null);
+          setMessages([]);
+        }
+      }
+    );
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [loadConversations, activeConversationId]);
+
   // Listen for "open-conversation" event from overlay (answer_in_new)
   useEffect(() => {
     const unlisten = listen<{ conversationId: string }>(
@@ -428,6 +453,7 @@ export function AppProvider({ children }: { children: ReactNode }) {
     setActiveAssistantId,
     loadAssistants,
     settings,
+    defaultsProblems,
     isStreaming,
     streamingContent,
     sidebarOpen,
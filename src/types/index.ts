@@ -26,14 +26,65 @@ export type ProviderType =
   | "deepseek"
   | "openrouter"
   | "xai"
+  | "together"
+  | "fireworks"
   | "openai_compatible";
 
+export type ConnectionTestReason =
+  | "ok"
+  | "authFailed"
+  | "unreachable"
+  | "badModel"
+  | "other";
+
+export interface ConnectionTestResult {
+  success: boolean;
+  reason: ConnectionTestReason;
+  error?: string;
+  latencyMs: number;
+  model: string;
+  suggestDisableStreaming: boolean;
+}
+
+export interface DefaultsStatus {
+  ok: boolean;
+  problems: string[];
+  /** False when the default model couldn't be checked (e.g. offline). */
+  modelChecked: boolean;
+}
+
+export interface Capabilities {
+  streaming: boolean;
+  tools: boolean;
+  /** Guessed from the model name, not probed. */
+  vision: boolean;
+  contextWindow?: number;
+}
+
 export interface ModelInfo {
   id: string;
   name: string;
   contextWindow?: number;
+  sizeBytes?: number; // installed size, Ollama only
+}
+
+/** `listModelsWithMetadata` result. */
+export interface ModelListing {
+  models: ModelInfo[];
+  fetchedCount: number; // models the API returned before filtering
+  warning?: string; // set when `models` is empty
 }
 
+/** One provider's entry in `listModelsForAll` and the `models-updated` event. */
+export interface ProviderModels {
+  providerId: string;
+  models: ModelInfo[];
+  error?: string; // set when the list couldn't be fetched
+}
+
+/** Order `listModels` returns models in; `idAsc` by default. */
+export type ModelSort = "idAsc" | "nameAsc" | "favoritesFirst" | "contextDesc";
+
 export interface DeviceCodeResponse {
   device_code: string;
   user_code: string;
@@ -42,6 +93,14 @@ export interface DeviceCodeResponse {
   interval: number;
 }
 
+/** Intervals are in seconds. */
+export type CopilotPollState =
+  | { state: "pending"; nextInterval: number }
+  | { state: "slowDown"; nextInterval: number }
+  | { state: "denied" }
+  | { state: "expired" }
+  | { state: "authorized"; token: string };
+
 export interface Provider {
   id: string;
   type: ProviderType;
@@ -50,6 +109,39 @@ export interface Provider {
   baseUrl?: string;
   defaultModel?: string;
   enabled: boolean;
+  supportsStreaming?: boolean;
+  authStyle?: string;
+  defaultMaxTokens?: number;
+  /** Use the legacy prompt-based `/completions` endpoint. */
+  completionMode?: boolean;
+  /** Extra request headers; values may use `${ENV_VAR}` references. */
+  customHeaders?: Record<string, string>;
+  /** JSON merged into chat request bodies; app-set fields win. */
+  extraBody?: Record<string, unknown>;
+  /** Where system prompts go; defaults to `system_role`. */
+  systemPromptMode?: "system_role" | "prepend_user" | "developer_role";
+  /** Tokens allowed per local day before sends are refused; unset is no cap. */
+  dailyTokenCap?: number;
+  /** Send the install's anonymous id as the OpenAI `user` field. Off by default. */
+  sendUserId?: boolean;
+}
+
+export interface DailyUsage {
+  providerId: string;
+  /** Local date, `YYYY-MM-DD`. */
+  day: string;
+  tokens: number;
+  requests: number;
+}
+
+/** One of several API keys a provider rotates through. */
+export interface ProviderKey {
+  id: string;
+  providerId: string;
+  key: string;
+  enabled: boolean;
+  lastUsed?: number;
+  createdAt: number;
 }
 
 export interface Model {
@@ -75,6 +167,16 @@ export interface Conversation {
   updatedAt: number;
   isArchived: boolean;
   folderId?: string;
+  /** Listed first and never auto-pruned. */
+  isPinned?: boolean;
+  /** Overrides the provider's default temperature for this conversation. */
+  temperature?: number | null;
+}
+
+export interface ConversationDetail extends Conversation {
+  messageCount: number;
+  totalTokens: number;
+  lastMessageAt?: number;
 }
 
 export interface Message {
@@ -87,6 +189,27 @@ export interface Message {
   createdAt: number;
   parentId?: string;
   sortOrder: number;
+  metadata?: { toolCalls?: ToolCall[] } & Record<string, unknown>;
+  /** False when the message is hidden from the model's context. */
+  includeInContext: boolean;
+  /** Always sent as context, even when older history is trimmed. */
+  sticky: boolean;
+  /** Streamed replies only: time to first token, total time, output speed. */
+  ttftMs?: number;
+  durationMs?: number;
+  tokensPerSecond?: number;
+}
+
+export interface ToolDef {
+  name: string;
+  description?: string;
+  parameters?: Record<string, unknown>; // JSON Schema
+}
+
+export interface ToolCall {
+  id: string;
+  name: string;
+  arguments: string; // JSON text
 }
 
 export interface Attachment {
@@ -109,6 +232,8 @@ export interface PromptTemplate {
   variables: string[];
   createdAt: number;
   updatedAt: number;
+  useCount?: number;
+  lastUsedAt?: number;
 }
 
 // --- Folder types ---
@@ -154,6 +279,17 @@ export interface AiCommand {
   keyboardShortcut?: string;
   enabled: boolean;
   sortOrder: number;
+  useCount?: number;
+  lastUsedAt?: number;
+}
+
+export interface AiCommandOutput {
+  text: string;
+  /** True when the provider failed mid-stream; `text` is what arrived. */
+  incomplete: boolean;
+  error?: string;
+  /** Already pasted into the originating app (live replace). */
+  applied: boolean;
 }
 
 // --- Assistant types ---
@@ -180,7 +316,10 @@ export type StreamEvent =
   | { event: "started"; data: { messageId: string } }
   | { event: "delta"; data: { content: string } }
   | { event: "done"; data: { totalTokens: number } }
-  | { event: "error"; data: { message: string } };
+  | { event: "error"; data: { message: string } }
+  | { event: "warning"; data: { message: string } }
+  | { event: "tool_call"; data: ToolCall }
+  | { event: "streaming_disabled"; data: { reason: string } };
 
 // --- API request/response types ---
 
@@ -191,6 +330,27 @@ export interface SendMessageRequest {
   providerId: string;
   attachments?: string[]; // file paths
   systemPrompt?: string;
+  tools?: ToolDef[];
+  /** Reused on retries so the backend sends the message only once. */
+  clientMessageId?: string;
+  /** Start of the reply for the model to continue (Anthropic only). */
+  prefill?: string;
+}
+
+export interface SendMessageStatus {
+  userMessageId: string;
+  /** A send with this `clientMessageId` was already made. */
+  duplicate: boolean;
+  /** The original send is still streaming. */
+  inProgress: boolean;
+}
+
+export interface ClipboardImage {
+  /** Base64-encoded PNG. */
+  data: string;
+  mimeType: string;
+  width: number;
+  height: number;
 }
 
 export interface CreateConversationRequest {